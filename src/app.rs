@@ -1,7 +1,10 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
     env,
     fmt::Write as _,
-    io::{self, Stdout},
+    fs,
+    io::{self, BufRead, Stdout},
+    path::PathBuf,
     sync::{Arc, mpsc as std_mpsc},
     time::{Duration, Instant},
 };
@@ -14,30 +17,71 @@ use crossterm::{
 };
 use ratatui::{Terminal, backend::CrosstermBackend};
 use serde_json::to_string_pretty;
-use tokio::{runtime::Runtime, sync::mpsc};
+use tokio::{runtime::Runtime, sync::mpsc, task::AbortHandle};
 use unicode_width::UnicodeWidthStr;
 
 use crate::{
-    config::{AppConfig, ProviderKind},
+    config::{
+        AppConfig, MAX_TEMPERATURE, MAX_TOP_P, ProviderKind, ToolOutputFormat, user_config_dir,
+    },
+    export,
     llm::{
-        ChatRequest, ChatResponse, LlmClient, LlmTool, StreamEvent, StubClient,
-        openai::{OpenAiClient, OpenAiConfig},
+        ChatRequest, ChatResponse, LlmClient, LlmTool, StreamEvent, StubClient, ToolCallProgress,
+        ToolChoice,
+        anthropic::{self, AnthropicClient, AnthropicConfig, DEFAULT_MAX_TOKENS},
+        ollama::{self, OllamaClient, OllamaConfig},
+        openai::{
+            DEFAULT_ERROR_BODY_LIMIT, DEFAULT_MAX_ATTEMPTS, DEFAULT_RETRY_BASE_DELAY, OpenAiClient,
+            OpenAiConfig,
+        },
     },
-    lua_tool::{LuaExecution, LuaExecutor},
+    lua_tool::{ExecutorIo, ExecutorOptions, LuaExecution, LuaExecutor},
     macros::MacroConfig,
-    session::SessionRecorder,
+    session::{self, Redactor, SessionRecorder},
     tui,
-    types::{Message, Role, ToolInvocation, ToolLogEntry, ToolStatus},
+    types::{
+        Glyphs, Message, Role, RolePrefixes, ToolIcons, ToolInvocation, ToolLogEntry, ToolStatus,
+    },
+    watch::LuaFileWatch,
 };
 
 use tracing::{info, instrument, warn};
 
-const LLM_LUA_TOOL_NAME: &str = "lua_run_script";
+pub(crate) const LLM_LUA_TOOL_NAME: &str = "lua_run_script";
+/// How often the plain line mode checks on a streaming response.
+const PLAIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// How long `/ping` and `/models` wait on the provider before giving up.
+const PROVIDER_CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+/// Keys `/config set` accepts.
+const SETTABLE_CONFIG_KEYS: &str = "allow_tool_writes, temperature, top_p, max_tokens";
+const PLAN_MODE_PROMPT: &str = r#"
+## Plan Mode
+Tool execution is suppressed for this turn. Describe your plan and propose the scripts you would run; they will be shown to the user for review but not executed.
+"#;
 
 #[derive(Debug, PartialEq)]
 enum LuaAction<'a> {
     Run(&'a str),
+    /// `/lua pretty <script>`: run and show a returned table indented.
+    Pretty(&'a str),
     Reset,
+    /// `/lua vars`: list globals defined by earlier scripts.
+    Vars,
+    /// `/lua clear <name>`: set one global to nil.
+    Clear(&'a str),
+}
+
+#[derive(Debug, PartialEq)]
+enum SessionsAction<'a> {
+    List,
+    Open(&'a str),
+}
+
+#[derive(Debug, PartialEq)]
+enum ThinkAction<'a> {
+    Enable,
+    Disable,
+    Prompt(&'a str),
 }
 
 pub struct App {
@@ -52,25 +96,77 @@ pub struct App {
     next_tool_id: usize,
     active_stream: Option<ActiveStream>,
     pending_lua_tools: Vec<PendingLuaTool>,
+    /// Persistent plan-only mode toggled with `/think on|off`.
+    plan_mode: bool,
+    /// Whether tool calls from the in-flight turn should only be shown.
+    suppress_tools: bool,
+    /// `/notools on`: ask the model to answer without calling tools.
+    tools_disabled: bool,
+    /// Runtime clamp from `/readonly on` that overrides `allow_tool_writes`.
+    read_only_override: bool,
+    /// Files written by Lua tools, summarized when the session ends.
+    modified_files: BTreeSet<PathBuf>,
+    /// Whether the session title has been recorded (or titling is disabled).
+    session_titled: bool,
+    /// `/watch-lua` dev mode: reruns a file read-only whenever it changes.
+    lua_watch: Option<ActiveLuaWatch>,
 }
 
 impl App {
     pub fn new() -> Result<Self> {
         let workspace = env::current_dir().context("failed to get current dir")?;
         let runtime = Runtime::new()?;
-        let config = AppConfig::load()?;
+        let (config, mut config_warnings) = AppConfig::load(&workspace)?;
+        let (redactor, pattern_warnings) = Redactor::new(&config.security.secret_patterns);
         let macros = MacroConfig::load()?;
-        let llm = build_llm_client(&config)?;
-        let mut state = AppState::default();
-        if !config.allow_tool_writes {
+        // A missing key is already flagged by validation; start anyway so the
+        // warning is readable, unless the config asks to be strict.
+        let llm = match build_llm_client(&config) {
+            Ok(llm) => llm,
+            Err(err) if !config.strict_config => {
+                config_warnings.push(format!(
+                    "{err:#} Using the offline stub provider until this is fixed."
+                ));
+                Arc::new(StubClient::new())
+            }
+            Err(err) => return Err(err),
+        };
+        let mut state = AppState::new(&config);
+        state.redactor = redactor.clone();
+        state
+            .input
+            .load_history(input_history_path(), redactor.clone());
+        for warning in config_warnings {
+            warn!("{warning}");
+            state.push_message(Message::new(Role::Assistant, format!("[config] {warning}")));
+        }
+        for warning in pattern_warnings {
+            warn!("{warning}");
             state.push_message(Message::new(
                 Role::Assistant,
-                "Lua helpers are running in read-only mode (enable writes in selenai.toml).",
+                format!("[security] {warning}"),
             ));
         }
-        let allow_writes = config.allow_tool_writes;
+        let lua_options = lua_options(&config, config.allow_tool_writes);
+        let lua_io = ExecutorIo {
+            http: reqwest::Client::builder().build()?,
+            runtime: runtime.handle().clone(),
+        };
         let log_root = config.resolve_log_dir(&workspace);
-        let session = SessionRecorder::new(&log_root, config.allow_tool_writes)?;
+        let project = workspace
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let session = SessionRecorder::with_template(
+            &log_root,
+            config.allow_tool_writes,
+            config
+                .session_dir_template
+                .as_deref()
+                .unwrap_or(session::DEFAULT_SESSION_DIR_TEMPLATE),
+            &project,
+        )?
+        .with_redactor(redactor);
         state.push_message(Message::new(
             Role::Assistant,
             format!(
@@ -78,51 +174,125 @@ impl App {
                 session.session_dir().display()
             ),
         ));
-        
+
+        let session_titled = !config.session_titles;
         let mut app = Self {
             config,
             macros,
             state,
             llm,
             runtime,
-            lua: LuaExecutor::new(workspace, allow_writes)?,
+            lua: LuaExecutor::with_options(workspace, lua_options, lua_io)?,
             session,
             should_quit: false,
             next_tool_id: 0,
             active_stream: None,
             pending_lua_tools: Vec::new(),
+            plan_mode: false,
+            suppress_tools: false,
+            tools_disabled: false,
+            read_only_override: false,
+            modified_files: BTreeSet::new(),
+            session_titled,
+            lua_watch: None,
         };
-        
-        app.check_first_run();
+
+        app.report_lua_warnings();
+        app.check_first_run(&user_config_dir().join(TOUR_MARKER_NAME));
         Ok(app)
     }
-    
-    fn check_first_run(&mut self) {
-        let home = env::var("HOME").unwrap_or_else(|_| ".".into());
-        let marker = std::path::Path::new(&home).join(".config/selenai/.seen_tour");
-        
-        if !marker.exists() {
+
+    /// Shows the welcome tour according to `show_tour`, recording that it was
+    /// seen in `marker`.
+    fn check_first_run(&mut self, marker: &std::path::Path) {
+        if self.config.show_tour.should_show(marker.exists()) {
              // Ensure directory exists
              if let Some(parent) = marker.parent() {
                  let _ = std::fs::create_dir_all(parent);
              }
              // Create marker
-             let _ = std::fs::write(&marker, "");
-             
-             self.state.push_message(Message::new(Role::Assistant, 
-                 "👋 **Welcome to SelenAI!** It looks like your first time here.\n\n\
-                  I am your terminal-based AI pair programmer. Here's a quick tour:\n\
-                  1. **Chat**: Type here to talk to me. I can read files, run tests, and edit code.\n\
-                  2. **Tools**: I execute Lua scripts to interact with your system. You'll see my plans and outputs in the right pane.\n\
-                  3. **Safety**: By default, I might be Read-Only. Check `/config show`.\n\
-                  4. **Commands**: Try `/review` to check git changes, or `/help` (conceptually) for more.\n\
-                  \n\
-                  Start by asking me to \"analyze the current project structure\"!"
-             ));
-        }
-    }
-
-    pub fn run(&mut self) -> Result<()> {
+            let _ = std::fs::write(marker, "");
+            self.show_tour();
+        }
+    }
+
+    /// Pushes the welcome tour into the chat; used on first run and by
+    /// `/reset-tour`.
+    fn show_tour(&mut self) {
+        let tour = welcome_tour(self.state.glyphs);
+        self.state.push_message(Message::new(Role::Assistant, tour));
+    }
+
+    /// Runs the TUI, or the plain line mode when `plain_reason` says the
+    /// terminal cannot host it (see `plain_mode_reason`).
+    pub fn run(&mut self, plain_reason: Option<&str>) -> Result<()> {
+        let result = match plain_reason {
+            Some(reason) => {
+                eprintln!("Running without the TUI ({reason}); reading prompts from stdin.");
+                for message in &self.state.messages {
+                    eprint!("{}", plain_message(message, &self.state.role_prefixes));
+                }
+                self.run_plain(io::stdin().lock(), &mut io::stdout())
+            }
+            None => self.run_tui(),
+        };
+
+        let persist_result = self
+            .session
+            .persist(&self.state.messages, &self.state.tool_logs);
+
+        if self.config.allow_tool_writes && !self.modified_files.is_empty() {
+            let paths: Vec<PathBuf> = self.modified_files.iter().cloned().collect();
+            let changes = session::collect_file_changes(self.lua.workspace_root(), &paths);
+            print!("{}", session::format_change_summary(&changes));
+        }
+
+        result.and(persist_result)
+    }
+
+    /// Line mode for pipes and captured output: each input line is submitted
+    /// like a prompt typed in the TUI, and the replies it produces are written
+    /// to `out` once the response finishes. Ends at end of input.
+    fn run_plain(&mut self, input: impl BufRead, out: &mut impl io::Write) -> Result<()> {
+        // Piped lines are not something to recall with Up in a later session.
+        self.state.input.detach_history_file();
+        let mut printed = self.state.messages.iter().map(|m| m.id).max().unwrap_or(0);
+        for line in input.lines() {
+            let line = line.context("failed to read stdin")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            self.state.input.set(&line);
+            self.submit_current_input();
+            loop {
+                self.poll_active_stream();
+                if self.active_stream.is_none() {
+                    break;
+                }
+                std::thread::sleep(PLAIN_POLL_INTERVAL);
+            }
+
+            for message in &self.state.messages {
+                if message.id > printed && message.role != Role::User {
+                    write!(out, "{}", plain_message(message, &self.state.role_prefixes))?;
+                }
+            }
+            printed = self
+                .state
+                .messages
+                .iter()
+                .map(|m| m.id)
+                .max()
+                .unwrap_or(printed);
+            out.flush()?;
+            if self.should_quit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn run_tui(&mut self) -> Result<()> {
         let mut stdout = io::stdout();
         enable_raw_mode()?;
         execute!(stdout, EnterAlternateScreen)?;
@@ -135,12 +305,7 @@ impl App {
         disable_raw_mode()?;
         execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
         terminal.show_cursor()?;
-
-        let persist_result = self
-            .session
-            .persist(&self.state.messages, &self.state.tool_logs);
-
-        result.and(persist_result)
+        result
     }
 
     fn event_loop(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
@@ -149,6 +314,8 @@ impl App {
 
         loop {
             self.poll_active_stream();
+            self.poll_lua_watch(Instant::now());
+            self.state.busy = self.active_stream.is_some();
             terminal.draw(|frame| tui::draw(frame, &self.state))?;
 
             if self.should_quit {
@@ -166,6 +333,7 @@ impl App {
 
             if last_tick.elapsed() >= tick_rate {
                 last_tick = Instant::now();
+                self.state.spinner_tick = self.state.spinner_tick.wrapping_add(1);
             }
         }
 
@@ -178,20 +346,38 @@ impl App {
             Event::Mouse(_) | Event::Resize(_, _) | Event::FocusGained | Event::FocusLost => {}
             Event::Paste(data) => {
                 if self.state.focus == FocusTarget::Input && !data.is_empty() {
-                    for ch in data.chars() {
-                        self.state.input.insert_char(ch);
-                    }
+                    self.handle_paste(&data);
                 }
             }
             _ => {}
         }
     }
 
+    /// Inserts pasted text; with `paste_submit_commands` on, a read-only command
+    /// pasted into an empty input is submitted straight away.
+    fn handle_paste(&mut self, data: &str) {
+        let command = paste_submit_command(data)
+            .filter(|_| self.config.paste_submit_commands && self.state.input.is_empty());
+        match command {
+            Some(line) => {
+                self.state.input.insert_str(line);
+                if self.state.input.dropped() == 0 {
+                    self.submit_current_input();
+                }
+            }
+            None => {
+                self.state.input.insert_str(data);
+            }
+        }
+    }
+
     fn handle_key_event(&mut self, key: KeyEvent) {
         if key.modifiers.contains(KeyModifiers::CONTROL) {
             match key.code {
                 KeyCode::Char('c') => {
-                    self.should_quit = true;
+                    if !self.cancel_active_stream() {
+                        self.should_quit = true;
+                    }
                     return;
                 }
                 KeyCode::Char('l') => {
@@ -203,8 +389,39 @@ impl App {
                     self.state.input.clear();
                     return;
                 }
+                KeyCode::Char('a') if self.state.focus == FocusTarget::Input => {
+                    self.state.input.move_to_start();
+                    return;
+                }
+                KeyCode::Char('e') if self.state.focus == FocusTarget::Input => {
+                    self.state.input.move_to_end();
+                    return;
+                }
+                KeyCode::Char('w') if self.state.focus == FocusTarget::Input => {
+                    self.state.input.delete_word_back();
+                    return;
+                }
+                KeyCode::Char('k') if self.state.focus == FocusTarget::Input => {
+                    self.state.input.kill_to_end();
+                    return;
+                }
+                KeyCode::Char('y') if self.state.focus == FocusTarget::Input => {
+                    self.state.input.yank();
+                    return;
+                }
+                KeyCode::Left if self.state.focus == FocusTarget::Input => {
+                    self.state.input.move_word_left();
+                    return;
+                }
+                KeyCode::Right if self.state.focus == FocusTarget::Input => {
+                    self.state.input.move_word_right();
+                    return;
+                }
                 KeyCode::Char('b') => {
                     self.state.copy_mode = !self.state.copy_mode;
+                    if let Err(err) = self.session.set_copy_mode(self.state.copy_mode) {
+                        warn!("failed to record copy mode: {err:#}");
+                    }
                     let status = if self.state.copy_mode {
                         "enabled"
                     } else {
@@ -221,13 +438,44 @@ impl App {
         }
 
         match key.code {
-            KeyCode::Esc => self.should_quit = true,
+            KeyCode::Esc => {
+                if !self.cancel_active_stream() {
+                    self.should_quit = true;
+                }
+            }
+            KeyCode::Tab
+                if self.state.focus == FocusTarget::Input
+                    && self.state.input.is_command_prefix() =>
+            {
+                self.state.input.complete_command(&slash_command_names());
+            }
             KeyCode::Tab => self.state.focus = self.state.focus.next(),
             KeyCode::BackTab => self.state.focus = self.state.focus.prev(),
+            // In multi-line input the arrows move between lines and only
+            // recall history from the first or last line.
+            KeyCode::Up if self.state.focus == FocusTarget::Input => {
+                if !self.state.input.move_up() {
+                    self.state.input.history_prev();
+                }
+            }
+            KeyCode::Down if self.state.focus == FocusTarget::Input => {
+                if !self.state.input.move_down() {
+                    self.state.input.history_next();
+                }
+            }
             KeyCode::Up => self.scroll_active(-1),
             KeyCode::Down => self.scroll_active(1),
             KeyCode::PageUp => self.scroll_active(-5),
             KeyCode::PageDown => self.scroll_active(5),
+            // Many terminals only report Alt+Enter, so both insert a newline.
+            KeyCode::Enter
+                if self.state.focus == FocusTarget::Input
+                    && key
+                        .modifiers
+                        .intersects(KeyModifiers::SHIFT | KeyModifiers::ALT) =>
+            {
+                self.state.input.insert_char('\n');
+            }
             KeyCode::Enter if self.state.focus == FocusTarget::Input => self.submit_current_input(),
             _ => {
                 if self.state.focus == FocusTarget::Input {
@@ -238,6 +486,23 @@ impl App {
     }
 
     fn handle_input_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::ALT) {
+            match key.code {
+                KeyCode::Backspace => {
+                    self.state.input.delete_word_back();
+                    return;
+                }
+                KeyCode::Char('b') | KeyCode::Left => {
+                    self.state.input.move_word_left();
+                    return;
+                }
+                KeyCode::Char('f') | KeyCode::Right => {
+                    self.state.input.move_word_right();
+                    return;
+                }
+                _ => {}
+            }
+        }
         match key.code {
             KeyCode::Char(ch) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
                 self.state.input.insert_char(ch);
@@ -273,14 +538,14 @@ impl App {
         if current.trim().is_empty() {
             return;
         }
-
         if self.active_stream.is_some() {
             self.state.push_message(Message::new(
                 Role::Assistant,
-                "Hang on, I'm still finishing the previous response.",
+                "Hang on, I'm still finishing the previous response (Esc cancels it).",
             ));
             return;
         }
+        self.state.input.push_history(&current);
         
         // Macro expansion
         if current.starts_with('@') {
@@ -300,6 +565,9 @@ impl App {
 
         self.state
             .push_message(Message::new(Role::User, text.clone()));
+        if !self.session_titled && !text.trim_start().starts_with('/') {
+            self.record_session_title(&text);
+        }
 
         if let Some(command) = parse_tool_command(&text) {
             self.handle_tool_command(command);
@@ -309,168 +577,735 @@ impl App {
              self.handle_review_command(target);
         } else if let Some((action, key, val)) = parse_config_command(&text) {
              self.handle_config_command(action, key, val);
+        } else if let Some(action) = parse_think_command(&text) {
+            self.handle_think_command(action);
+        } else if let Some(enable) = parse_readonly_command(&text) {
+            self.handle_readonly_command(enable);
+        } else if let Some(enable) = parse_notools_command(&text) {
+            self.handle_notools_command(enable);
+        } else if let Some(action) = parse_sessions_command(&text) {
+            self.handle_sessions_command(action);
+        } else if let Some(path) = parse_export_html_command(&text) {
+            self.handle_export_html(path);
+        } else if is_ping_command(&text) {
+            self.handle_ping_command();
+        } else if text.trim() == "/models" {
+            self.handle_models_command();
+        } else if matches!(text.trim(), "/regenerate" | "/retry") {
+            self.handle_regenerate_command();
+        } else if text.trim() == "/edit" {
+            self.handle_edit_command();
+        } else if let Some(arg) = parse_watch_lua_command(&text) {
+            self.handle_watch_lua_command(arg);
+        } else if text.trim() == "/help" {
+            self.state
+                .push_message(Message::new(Role::Assistant, help_text()));
+        } else if text.trim() == "/reset-tour" {
+            self.show_tour();
         } else {
             self.invoke_llm();
         }
     }
 
-    fn handle_review_command(&mut self, target: &str) {
-        let script = format!(
-            r#"
-            local status = rust.git_status().stdout
-            if status == "" and "{target}" == "" then
-                return "Working tree clean, nothing to review."
-            end
-            
-            local diff_cmd = {{ "diff" }}
-            if "{target}" ~= "" then
-                table.insert(diff_cmd, "{target}")
-            end
-            
-            local diff = rust.run_command("git", diff_cmd).stdout
-            if diff == "" then
-                return "No changes found for review."
-            end
-            
-            return "Here is the diff for review:\n" .. diff
-            "#
-        );
-        
-        let plan = format!("Reviewing changes in `{target}` (or staged/working if empty).");
-        self.state.push_message(Message::new(Role::User, format!("/review {target}")));
-        self.run_lua_script(plan, &script, None);
-    }
-
-    fn handle_config_command(&mut self, action: &str, key: Option<&str>, val: Option<&str>) {
-        match action {
-            "show" => {
-                let display = format!("{:#?}", self.config);
-                self.state.push_message(Message::new(Role::Assistant, format!("Current Config:\n```\n{display}\n```")));
+    fn handle_watch_lua_command(&mut self, arg: &str) {
+        match arg {
+            "" => {
+                let status = match &self.lua_watch {
+                    Some(active) => format!(
+                        "Watching {} (entry #{}). Use /watch-lua off to stop.",
+                        active.watch.path().display(),
+                        active.entry_id
+                    ),
+                    None => "Usage: /watch-lua <path> | /watch-lua off".to_string(),
+                };
+                self.state
+                    .push_message(Message::new(Role::Assistant, status));
             }
-            "set" => {
-                 if let Some(k) = key {
-                     if k == "allow_tool_writes" {
-                         if let Some(v) = val {
-                             let new_val = v == "true";
-                             self.config.allow_tool_writes = new_val;
-                             
-                             // Simple fix: recreate.
-                             match LuaExecutor::new(env::current_dir().unwrap(), new_val) {
-                                 Ok(executor) => {
-                                     self.lua = executor;
-                                     self.state.push_message(Message::new(Role::Assistant, format!("Config `{k}` set to `{new_val}`.")));
-                                 }
-                                 Err(e) => {
-                                     self.state.push_message(Message::new(Role::Assistant, format!("Failed to update config: {e}")));
-                                 }
-                             }
-                         } else {
-                             self.state.push_message(Message::new(Role::Assistant, "Missing value (true/false)."));
-                         }
-                     } else {
-                         self.state.push_message(Message::new(Role::Assistant, format!("Unknown config key `{k}`. Supported: allow_tool_writes")));
-                     }
-                 } else {
-                     self.state.push_message(Message::new(Role::Assistant, "Missing key."));
-                 }
+            "off" => {
+                let message = match self.lua_watch.take() {
+                    Some(active) => {
+                        format!("Stopped watching {}.", active.watch.path().display())
+                    }
+                    None => "No Lua file is being watched.".to_string(),
+                };
+                self.state
+                    .push_message(Message::new(Role::Assistant, message));
+            }
+            path => {
+                let target = self.lua.workspace_root().join(path);
+                if !target.is_file() {
+                    self.state.push_message(Message::new(
+                        Role::Assistant,
+                        format!("Cannot watch {}: not a file.", target.display()),
+                    ));
+                    return;
+                }
+                match LuaFileWatch::start(&target) {
+                    Ok(watch) => self.install_lua_watch(watch),
+                    Err(err) => self.state.push_message(Message::new(
+                        Role::Assistant,
+                        format!("Failed to watch {}: {err:#}", target.display()),
+                    )),
+                }
             }
-            _ => {}
         }
     }
 
-    #[instrument(skip(self))]
-    fn invoke_llm(&mut self) {
-        let system_prompt = Self::build_system_prompt(&self.config);
-        let lua_tool = Self::build_lua_tool(&self.config);
-        let mut request = ChatRequest::new(self.state.messages.clone())
-            .with_system_prompt(system_prompt)
-            .with_tool(lua_tool);
-        if self.config.streaming {
-            request = request.with_stream(true);
+    fn install_lua_watch(&mut self, watch: LuaFileWatch) {
+        // Reruns always use a read-only VM, whatever the session allows.
+        let options = lua_options(&self.config, false);
+        let io = self.lua.io().clone();
+        let lua = match LuaExecutor::with_options(self.lua.workspace_root(), options, io) {
+            Ok(lua) => lua,
+            Err(err) => {
+                self.state.push_message(Message::new(
+                    Role::Assistant,
+                    format!("Failed to start Lua watch: {err:#}"),
+                ));
+                return;
+            }
+        };
+        let path = watch.path().display().to_string();
+        let entry_id = self.create_tool_log_entry(format!("Watch: {path}"), String::new());
+        self.lua_watch = Some(ActiveLuaWatch {
+            watch,
+            lua,
+            entry_id,
+            runs: 0,
+        });
+        self.state.push_message(Message::new(
+            Role::Assistant,
+            format!(
+                "Watching {path}; it reruns read-only in entry #{entry_id} on every save. \
+                 /watch-lua off stops."
+            ),
+        ));
+        self.rerun_lua_watch();
+    }
+
+    fn poll_lua_watch(&mut self, now: Instant) {
+        if self
+            .lua_watch
+            .as_mut()
+            .is_some_and(|active| active.watch.poll(now))
+        {
+            self.rerun_lua_watch();
         }
+    }
 
-        info!("invoking LLM (streaming={})", self.config.streaming);
+    fn rerun_lua_watch(&mut self) {
+        let timeout = self.config.script_timeout();
+        let Some(active) = self.lua_watch.as_mut() else {
+            return;
+        };
+        active.runs += 1;
+        let started = Instant::now();
+        let result = fs::read_to_string(active.watch.path())
+            .with_context(|| format!("failed to read {}", active.watch.path().display()))
+            .and_then(|source| {
+                active.lua.reset()?;
+                run_lua_with_timeout(&active.lua, &source, timeout)
+            });
+        let header = format!("Run #{}", active.runs);
+        let entry_id = active.entry_id;
+        let (status, detail) = match result {
+            Ok(output) => (
+                ToolStatus::Success,
+                format!(
+                    "{header}\n{}",
+                    render_lua_execution(
+                        &output,
+                        self.config.tool_output_format,
+                        &self.state.redactor,
+                    )
+                ),
+            ),
+            Err(err) => (
+                ToolStatus::Error,
+                redact_tool_output(
+                    &format!("{header}\nLua error: {err:#}"),
+                    &self.state.redactor,
+                ),
+            ),
+        };
+        self.state.set_tool_duration(entry_id, started.elapsed());
+        self.state.update_tool_log(entry_id, status, detail);
+    }
 
-        if self.config.streaming && self.llm.supports_streaming() {
-            self.invoke_llm_streaming(request);
-        } else {
-            self.invoke_llm_unary(request);
+    fn record_session_title(&mut self, prompt: &str) {
+        let Some(title) = session::title_from_prompt(prompt) else {
+            return;
+        };
+        self.session_titled = true;
+        if let Err(err) = self.session.set_title(&title) {
+            warn!("failed to record session title: {err:#}");
         }
     }
 
-    fn invoke_llm_unary(&mut self, request: ChatRequest) {
-        let response = self.runtime.block_on(self.llm.chat(request));
-        match response {
-            Ok(chat_response) => self.handle_chat_response(chat_response),
-            Err(err) => self
-                .state
-                .push_message(Message::new(Role::Assistant, format!("LLM error: {err:#}"))),
+    /// Writes the export under the same rules as the Lua write helpers and
+    /// only replaces an existing file when `--force` is given.
+    fn handle_export_html(&mut self, args: &str) {
+        let (path, force) = match args.strip_suffix("--force") {
+            Some(path) => (path.trim_end(), true),
+            None => (args, false),
+        };
+        if path.is_empty() {
+            self.state.push_message(Message::new(
+                Role::Assistant,
+                "Usage: /export-html <file> [--force]",
+            ));
+            return;
         }
+        let target = match self.lua.resolve_write_target(std::path::Path::new(path)) {
+            Ok(target) => target,
+            Err(err) => {
+                self.state.push_message(Message::new(
+                    Role::Assistant,
+                    format!("Cannot export to {path}: {err:#}"),
+                ));
+                return;
+            }
+        };
+        let state = &self.state;
+        let html = export::render_session_html(&state.messages, &state.tool_logs, &state.redactor);
+        let written = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .create_new(!force)
+            .truncate(true)
+            .open(&target)
+            .and_then(|mut file| io::Write::write_all(&mut file, html.as_bytes()));
+        let message = match written {
+            Ok(()) => format!("Exported session to {}.", target.display()),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => format!(
+                "{} already exists; use `/export-html {path} --force` to replace it.",
+                target.display()
+            ),
+            Err(err) => format!("Failed to export {}: {err}", target.display()),
+        };
+        self.state
+            .push_message(Message::new(Role::Assistant, message));
     }
 
-    fn invoke_llm_streaming(&mut self, request: ChatRequest) {
-        let (tx, rx) = mpsc::unbounded_channel();
-        let placeholder_index = self
-            .state
-            .push_message_with_index(Message::new(Role::Assistant, String::new()));
+    fn handle_ping_command(&mut self) {
+        let provider = self.config.provider.name();
+        let started = Instant::now();
+        let result = self.block_on_provider(self.llm.health_check());
+        let elapsed = started.elapsed().as_millis();
+        let message = match result {
+            Ok(resolved) => format!(
+                "Provider `{provider}` is reachable ({elapsed} ms, model {}).",
+                resolved.as_deref().unwrap_or(&self.config.model_id)
+            ),
+            Err(err) => {
+                format!("Provider `{provider}` health check failed after {elapsed} ms: {err:#}")
+            }
+        };
+        self.state
+            .push_message(Message::new(Role::Assistant, message));
+    }
 
-        let llm = Arc::clone(&self.llm);
-        let (result_tx, result_rx) = std_mpsc::channel();
+    /// Runs a one-off provider request, giving up after
+    /// `PROVIDER_CHECK_TIMEOUT` so an unreachable host cannot freeze the UI.
+    fn block_on_provider<T>(&self, request: impl Future<Output = Result<T>>) -> Result<T> {
+        self.runtime
+            .block_on(async { tokio::time::timeout(PROVIDER_CHECK_TIMEOUT, request).await })
+            .unwrap_or_else(|_| {
+                Err(anyhow::anyhow!(
+                    "no response within {} s",
+                    PROVIDER_CHECK_TIMEOUT.as_secs()
+                ))
+            })
+    }
 
-        self.runtime.spawn(async move {
-            let result = llm.chat_stream(request, tx).await;
-            let _ = result_tx.send(result);
-        });
+    /// Index of the newest prompt that went to the model. Slash commands are
+    /// recorded as user messages too, but the model never answers them.
+    fn last_prompt_index(&self) -> Option<usize> {
+        self.state
+            .messages
+            .iter()
+            .rposition(|m| m.role == Role::User && !m.content.trim_start().starts_with('/'))
+    }
 
-        self.active_stream = Some(ActiveStream {
-            receiver: rx,
-            result_rx,
-            message_index: placeholder_index,
-        });
+    fn handle_regenerate_command(&mut self) {
+        // The slash command itself is not part of the conversation.
+        self.state
+            .remove_message(self.state.messages.len().saturating_sub(1));
+        let last_user = self.last_prompt_index();
+        let Some(last_user) = last_user.filter(|idx| idx + 1 < self.state.messages.len()) else {
+            self.state.push_message(Message::new(
+                Role::Assistant,
+                "Nothing to regenerate: there is no response after your last prompt.",
+            ));
+            return;
+        };
+
+        // Drop the previous answer along with any tool calls it left queued.
+        self.state.truncate_messages(last_user + 1);
+        self.cancel_pending_tools("Canceled: response regenerated.");
+        self.invoke_llm();
     }
 
-    #[instrument(skip(self))]
-    fn handle_chat_response(&mut self, response: ChatResponse) {
-        match response {
-            ChatResponse::Assistant(message) => {
-                 info!("received assistant message: {} chars", message.content.len());
-                 self.state.push_message(message);
-            }
-            ChatResponse::ToolCalls(invocations) => {
-                for invocation in invocations {
-                    self.handle_tool_call(invocation);
-                }
-            }
-        }
+    /// Moves the last prompt back into the input and forgets everything from
+    /// it onward. Dropped messages are not kept in the saved transcript.
+    fn handle_edit_command(&mut self) {
+        self.state
+            .remove_message(self.state.messages.len().saturating_sub(1));
+        let Some(last_user) = self.last_prompt_index() else {
+            self.state.push_message(Message::new(
+                Role::Assistant,
+                "Nothing to edit: no earlier prompt in this session.",
+            ));
+            return;
+        };
+
+        let prompt = self.state.messages[last_user].content.clone();
+        self.state.truncate_messages(last_user);
+        self.cancel_pending_tools("Canceled: prompt edited.");
+        self.state.input.set(&prompt);
+        self.state.focus = FocusTarget::Input;
     }
 
-    fn build_system_prompt(config: &AppConfig) -> String {
-        let mut prompt = format!(
-            r#"You are SelenAI, an advanced AI software engineer running in a CLI.
-Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which executes Lua code in a persistent environment.
+    fn cancel_pending_tools(&mut self, note: &str) {
+        for pending in std::mem::take(&mut self.pending_lua_tools) {
+            self.state
+                .update_tool_log(pending.entry_id, ToolStatus::Error, note);
+        }
+    }
 
-## Core Philosophy
-1. **Reasoning First**: Always analyze the request and state your plan before writing code.
-2. **Code as Action**: You do not just "talk" about code; you write Lua scripts to *do* things (explore, read, test, modify).
-3. **Persistence**: The Lua state is preserved. You can define functions or variables in one turn and use them in the next.
+    fn handle_models_command(&mut self) {
+        let provider = self.config.provider.name();
+        let message = match self.block_on_provider(self.llm.list_models()) {
+            Ok(models) if models.is_empty() => format!(
+                "Provider `{provider}` does not list models; using `{}`.",
+                self.config.model_id
+            ),
+            Ok(models) => render_model_list(&models, &self.config.model_id),
+            Err(err) => format!("Failed to list models from `{provider}`: {err:#}"),
+        };
+        self.state
+            .push_message(Message::new(Role::Assistant, message));
+    }
+
+    fn handle_sessions_command(&mut self, action: SessionsAction) {
+        let log_root = self.config.resolve_log_dir(self.lua.workspace_root());
+        let sessions = match session::list_sessions(&log_root) {
+            Ok(sessions) => sessions
+                .into_iter()
+                .filter(|s| s.dir != self.session.session_dir())
+                .collect::<Vec<_>>(),
+            Err(err) => {
+                self.state.push_message(Message::new(
+                    Role::Assistant,
+                    format!("Failed to list sessions: {err:#}"),
+                ));
+                return;
+            }
+        };
+
+        let selector = match action {
+            SessionsAction::List => {
+                self.state.push_message(Message::new(
+                    Role::Assistant,
+                    render_session_list(&sessions),
+                ));
+                return;
+            }
+            SessionsAction::Open(selector) => selector,
+        };
+
+        let found = selector
+            .parse::<usize>()
+            .ok()
+            .and_then(|idx| idx.checked_sub(1))
+            .and_then(|idx| sessions.get(idx))
+            .or_else(|| sessions.iter().find(|s| s.id == selector));
+        let Some(summary) = found else {
+            self.state.push_message(Message::new(
+                Role::Assistant,
+                format!("No session matches `{selector}`. Run /sessions to list them."),
+            ));
+            return;
+        };
+
+        match session::load_transcript(&summary.dir) {
+            Ok(messages) => {
+                let count = messages.len();
+                self.state.replace_messages(messages);
+                if let Some(copy_mode) = summary.copy_mode {
+                    self.state.copy_mode = copy_mode;
+                    if let Err(err) = self.session.set_copy_mode(copy_mode) {
+                        warn!("failed to record copy mode: {err:#}");
+                    }
+                }
+                self.state.push_message(Message::new(
+                    Role::Assistant,
+                    format!(
+                        "Resumed session `{}` ({count} messages). New turns continue from here.",
+                        summary.id
+                    ),
+                ));
+            }
+            Err(err) => self.state.push_message(Message::new(
+                Role::Assistant,
+                format!("Could not open session `{}`: {err:#}", summary.id),
+            )),
+        }
+    }
+
+    fn handle_readonly_command(&mut self, enable: Option<bool>) {
+        let Some(enable) = enable else {
+            let mode = if self.writes_enabled() { "off" } else { "on" };
+            self.state.push_message(Message::new(
+                Role::Assistant,
+                format!("Read-only override is {mode}. Usage: /readonly on|off."),
+            ));
+            return;
+        };
+
+        self.read_only_override = enable;
+        if let Err(e) = self.rebuild_lua_executor() {
+            self.state.push_message(Message::new(
+                Role::Assistant,
+                format!("Failed to rebuild Lua environment: {e}"),
+            ));
+            return;
+        }
+        let message = if enable {
+            "Read-only override on. Write helpers are disabled until `/readonly off`."
+        } else if self.config.allow_tool_writes {
+            "Read-only override off. Write helpers are enabled again."
+        } else {
+            "Read-only override off. Writes stay disabled because allow_tool_writes = false."
+        };
+        self.state
+            .push_message(Message::new(Role::Assistant, message));
+    }
+
+    fn handle_notools_command(&mut self, enable: Option<bool>) {
+        self.tools_disabled = enable.unwrap_or(!self.tools_disabled);
+        let message = if self.tools_disabled {
+            "Tools off. The model is asked to answer in text until `/notools off`."
+        } else {
+            "Tools on. The model may call `lua_run_script` again (per `tool_choice`)."
+        };
+        self.state
+            .push_message(Message::new(Role::Assistant, message));
+    }
+
+    /// Writes are allowed only when configured and not clamped by `/readonly on`.
+    fn writes_enabled(&self) -> bool {
+        self.config.allow_tool_writes && !self.read_only_override
+    }
+
+    fn rebuild_lua_executor(&mut self) -> Result<()> {
+        let allow_writes = self.writes_enabled();
+        let options = lua_options(&self.config, allow_writes);
+        let io = self.lua.io().clone();
+        self.lua = LuaExecutor::with_options(self.lua.workspace_root(), options, io)?;
+        self.state.writes_enabled = allow_writes;
+        self.report_lua_warnings();
+        Ok(())
+    }
+
+    fn report_lua_warnings(&mut self) {
+        for warning in self.lua.load_warnings() {
+            self.state
+                .push_message(Message::new(Role::Assistant, format!("Lua: {warning}")));
+        }
+    }
+
+    fn handle_think_command(&mut self, action: ThinkAction) {
+        match action {
+            ThinkAction::Enable => {
+                self.plan_mode = true;
+                self.state.push_message(Message::new(
+                    Role::Assistant,
+                    "Plan mode enabled. Tool calls will be shown but not executed.",
+                ));
+            }
+            ThinkAction::Disable => {
+                self.plan_mode = false;
+                self.state.push_message(Message::new(
+                    Role::Assistant,
+                    "Plan mode disabled. Tool calls follow the normal approval flow.",
+                ));
+            }
+            ThinkAction::Prompt("") => {
+                self.state.push_message(Message::new(
+                    Role::Assistant,
+                    "Usage: /think <prompt> or /think on|off.",
+                ));
+            }
+            ThinkAction::Prompt(prompt) => {
+                // Send the bare prompt to the model rather than the slash command.
+                if let Some(last) = self.state.messages.len().checked_sub(1) {
+                    self.state.set_message_content(last, prompt);
+                }
+                self.invoke_llm_with_plan(true);
+            }
+        }
+    }
+
+    fn handle_review_command(&mut self, target: &str) {
+        let script = review_script(target);
+        let plan = format!("Reviewing changes in `{target}` (or staged/working if empty).");
+        self.state
+            .push_message(Message::new(Role::User, format!("/review {target}")));
+        self.run_lua_script(plan, &script, None);
+    }
+
+    fn handle_config_command(&mut self, action: &str, key: Option<&str>, val: Option<&str>) {
+        match action {
+            "show" => {
+                let display = format!("{:#?}", self.config);
+                self.state.push_message(Message::new(
+                    Role::Assistant,
+                    format!("Current Config:\n```\n{display}\n```"),
+                ));
+            }
+            "set" => {
+                if let Some(k) = key {
+                    if matches!(k, "temperature" | "top_p" | "max_tokens") {
+                        let reply = self.set_sampling_config(k, val);
+                        self.state
+                            .push_message(Message::new(Role::Assistant, reply));
+                    } else if k == "allow_tool_writes" {
+                        if let Some(v) = val {
+                            let new_val = v == "true";
+                            self.config.allow_tool_writes = new_val;
+
+                            // Simple fix: recreate.
+                            match self.rebuild_lua_executor() {
+                                Ok(()) => {
+                                    self.state.push_message(Message::new(
+                                        Role::Assistant,
+                                        format!("Config `{k}` set to `{new_val}`."),
+                                    ));
+                                }
+                                Err(e) => {
+                                    self.state.push_message(Message::new(
+                                        Role::Assistant,
+                                        format!("Failed to update config: {e}"),
+                                    ));
+                                }
+                            }
+                        } else {
+                            self.state.push_message(Message::new(
+                                Role::Assistant,
+                                "Missing value (true/false).",
+                            ));
+                        }
+                    } else {
+                        self.state.push_message(Message::new(
+                            Role::Assistant,
+                            format!("Unknown config key `{k}`. Supported: {SETTABLE_CONFIG_KEYS}"),
+                        ));
+                    }
+                } else {
+                    self.state
+                        .push_message(Message::new(Role::Assistant, "Missing key."));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies `/config set temperature|top_p|max_tokens <value|default>` for
+    /// the rest of the session. The live value also replaces any `[openai]`
+    /// override so it takes effect whichever section set it.
+    fn set_sampling_config(&mut self, key: &str, value: Option<&str>) -> String {
+        let Some(value) = value else {
+            return format!("Missing value for `{key}` (a number, or `default`).");
+        };
+        let reset = matches!(value, "default" | "none");
+        match key {
+            "temperature" | "top_p" => {
+                let max = if key == "temperature" {
+                    MAX_TEMPERATURE
+                } else {
+                    MAX_TOP_P
+                };
+                let parsed = match value.parse::<f64>() {
+                    _ if reset => None,
+                    Ok(number) if (0.0..=max).contains(&number) => Some(number),
+                    _ => return format!("`{key}` must be a number from 0 to {max}."),
+                };
+                if key == "temperature" {
+                    self.config.temperature = parsed;
+                    self.config.openai.temperature = None;
+                } else {
+                    self.config.top_p = parsed;
+                    self.config.openai.top_p = None;
+                }
+            }
+            _ => {
+                let parsed = match value.parse::<u32>() {
+                    _ if reset => None,
+                    Ok(tokens) if tokens > 0 => Some(tokens),
+                    _ => return "`max_tokens` must be a positive whole number.".to_string(),
+                };
+                self.config.max_tokens = parsed;
+                self.config.openai.max_tokens = None;
+            }
+        }
+        if reset {
+            format!("Config `{key}` cleared; the provider default applies.")
+        } else {
+            format!("Config `{key}` set to `{value}`.")
+        }
+    }
+
+    fn invoke_llm(&mut self) {
+        self.invoke_llm_with_plan(false);
+    }
+
+    #[instrument(skip(self))]
+    fn invoke_llm_with_plan(&mut self, plan_only: bool) {
+        self.suppress_tools = self.plan_mode || plan_only;
+        let request = self.build_chat_request();
+
+        info!("invoking LLM (streaming={})", self.config.streaming);
+
+        if self.config.streaming && self.llm.supports_streaming() {
+            self.invoke_llm_streaming(request);
+        } else {
+            self.invoke_llm_unary(request);
+        }
+    }
+
+    /// The request for the next model turn: the whole conversation, the Lua
+    /// tool, and the current plan, tool, and sampling settings.
+    fn build_chat_request(&self) -> ChatRequest {
+        let mut system_prompt = Self::build_system_prompt(self.writes_enabled());
+        if self.suppress_tools {
+            system_prompt.push_str(PLAN_MODE_PROMPT);
+        }
+        let lua_tool = Self::build_lua_tool(self.writes_enabled());
+        let mut request = ChatRequest::new(self.state.messages.clone())
+            .with_system_prompt(system_prompt)
+            .with_tool(lua_tool)
+            .with_sampling(self.config.sampling())
+            .with_cache_prefix(true);
+        if self.tools_disabled {
+            request = request.with_tool_choice(ToolChoice::None);
+        } else if let Some(choice) = &self.config.tool_choice {
+            request = request.with_tool_choice(ToolChoice::from_setting(choice));
+        }
+        if self.config.streaming {
+            request = request.with_stream(true);
+        }
+        request
+    }
+
+    fn invoke_llm_unary(&mut self, request: ChatRequest) {
+        let response = self.runtime.block_on(self.llm.chat(request));
+        match response {
+            Ok(chat_response) => self.handle_chat_response(chat_response),
+            Err(err) => self
+                .state
+                .push_message(Message::new(Role::Assistant, format!("LLM error: {err:#}"))),
+        }
+    }
+
+    fn invoke_llm_streaming(&mut self, request: ChatRequest) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let placeholder = Message::new(Role::Assistant, String::new());
+        let message_id = placeholder.id;
+        self.state.push_message(placeholder);
+
+        let llm = Arc::clone(&self.llm);
+        let (result_tx, result_rx) = std_mpsc::channel();
+
+        let task = self.runtime.spawn(async move {
+            let result = llm.chat_stream(request, tx).await;
+            let _ = result_tx.send(result);
+        });
+
+        self.active_stream = Some(ActiveStream {
+            receiver: rx,
+            result_rx,
+            message_id,
+            tool_previews: BTreeMap::new(),
+            abort: task.abort_handle(),
+        });
+    }
+
+    /// Aborts the in-flight stream, keeping any text that already arrived and
+    /// marking the reply as canceled. Returns false when nothing is streaming.
+    fn cancel_active_stream(&mut self) -> bool {
+        let Some(mut active) = self.active_stream.take() else {
+            return false;
+        };
+        active.abort.abort();
+        self.clear_tool_call_previews(&mut active);
+        if let Some(index) = self.state.position_of(active.message_id) {
+            let marker = if self.state.message_is_empty(index) {
+                "(canceled)"
+            } else {
+                "\n\n(canceled)"
+            };
+            self.state.append_to_message(index, marker);
+        }
+        info!("canceled active LLM stream");
+        true
+    }
+
+    #[instrument(skip(self))]
+    fn handle_chat_response(&mut self, response: ChatResponse) {
+        match response {
+            ChatResponse::Assistant(message) => {
+                info!(
+                    "received assistant message: {} chars",
+                    message.content.len()
+                );
+                self.state.push_message(message);
+            }
+            ChatResponse::ToolCalls(invocations) => {
+                for invocation in invocations {
+                    self.handle_tool_call(invocation);
+                }
+            }
+        }
+    }
+
+    fn build_system_prompt(allow_writes: bool) -> String {
+        let mut prompt = format!(
+            r#"You are SelenAI, an advanced AI software engineer running in a CLI.
+Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which executes Lua code in a persistent environment.
+
+## Core Philosophy
+1. **Reasoning First**: Always analyze the request and state your plan before writing code.
+2. **Code as Action**: You do not just "talk" about code; you write Lua scripts to *do* things (explore, read, test, modify).
+3. **Persistence**: The Lua state is preserved. You can define functions or variables in one turn and use them in the next.
 
 ## The Lua Environment
 - **Stdlib**: Standard Lua 5.4 (math, table, string, etc.).
-- **Helpers**: `repr(obj)` (inspect data), `print(...)` (output), `warn(...)` (log to stderr).
+- **Helpers**: `repr(obj)` (inspect data), `print(...)` (output), `warn(...)` (log to stderr), `sorted_pairs(t)` (like `pairs`, in a stable key order; prefer it when output order matters).
+- **Tables**: `map(t, fn)` / `filter(t, fn)` (also `table.map`/`table.filter`), `table.reduce(t, fn, init?)`, `table.keys(t)` / `table.values(t)` (sorted by key), `table.merge(a, b)` (`b` wins), `table.contains(t, v)`.
+- **Strings**: `string.split(s, sep)` -> table (plain separator), `string.trim(s)`, `string.starts_with(s, prefix)` / `string.ends_with(s, suffix)`, `string.lines(s)` -> table; method syntax works too (`line:trim()`).
+- **JSON**: `json.encode(value, pretty?)` -> string, `json.decode(text)` -> table (`null` becomes `nil`), or `nil, err` on invalid JSON.
+- **Environment (`selenai` table, read-only)**: `workspace_root`, `allow_writes`, `model`, `version`.
 - **Rust API (`rust` table)**:
-  - `rust.list_dir(path)` -> table of `{{name, is_dir}}`
+  - `rust.list_dir(path)` -> table of `{{name, is_dir, is_symlink, size, modified}}`
+  - `rust.walk_dir(path, opts?)` -> array of `{{path, is_dir, size}}` (Recursive, skips `.gitignore`d paths; `opts`: `max_depth`, `max_entries` (default 1000, max 10000; sets `truncated = true` when hit), `respect_gitignore`). Prefer this over shelling out to `find`.
   - `rust.read_file(path)` -> string
-  - `rust.search(pattern, dir?)` -> `{{stdout, stderr, status}}` (Recursive grep)
+  - `rust.pretty(value)` -> string (Indented multi-line rendering of tables)
+  - `rust.workspace_root()` / `rust.join(a, b, ...)` / `rust.relpath(path)` -> string (Pure path helpers; use these instead of string concat)
+  - `rust.search(pattern, dir?, opts?)` -> array of `{{file, line_number, text}}` (Recursive regex search that skips `.gitignore`d and binary files; `opts`: `case_insensitive`, `max_results`)
   - `rust.git_status()` -> `{{stdout, status}}`
-  - `rust.http_request({{url=..., method=..., headers=..., body=...}})` -> `{{status, body, headers}}`
+  - `rust.git_diff(opts?)` / `rust.git_log(opts?)` -> `{{stdout, stderr, status}}` (Read-only; `opts`: `target` revision or range, `paths` list, `stat`; `git_diff` also takes `staged`, `git_log` takes `max_count` (default 20) and `oneline` (default true))
+  - `rust.http_request({{url=..., method=..., headers=..., query=..., body=..., timeout_ms=..., follow_redirects=...}})` -> `{{status, body, headers}}` (default timeout 30000 ms; redirects are followed unless `follow_redirects = false`)
+  - `rust.preview_patch(path, unified_diff)` -> string (Contents after the patch; nothing is written)
 "#
         );
 
-        if config.allow_tool_writes {
+        if allow_writes {
             prompt.push_str(
                 r#"  - `rust.write_file(path, content)` -> nil
   - `rust.patch_file(path, unified_diff)` -> nil (Preferred for small edits)
-  - `rust.run_command(cmd, {args...})` -> `{status, stdout, stderr}`
+  - `rust.delete_file(path, recursive?)` -> nil (Directories need `recursive = true`)
+  - `rust.run_command(cmd, {args...}, opts?)` -> `{status, stdout, stderr}` (`opts`: `stdin` string piped to the command, `env` table of extra variables, `cwd` workspace-relative directory, `timeout_ms` limit where 0 = none; a timed-out command returns `status = -1`, `timed_out = true` and its partial output)
 
 ## Safety & Permissions
 - **Write Mode**: ENABLED. You can modify files and run commands.
@@ -480,7 +1315,7 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
             );
         } else {
             prompt.push_str(
-                r#"  - **Note**: `write_file`, `patch_file`, and `run_command` are currently **DISABLED** (Read-Only Mode).
+                r#"  - **Note**: `write_file`, `patch_file`, `delete_file`, and `run_command` are currently **DISABLED** (Read-Only Mode).
 
 ## Safety & Permissions
 - **Write Mode**: READ-ONLY. You cannot modify files or run commands.
@@ -493,7 +1328,7 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
             r#"
 ## Usage Patterns
 - **Exploration**: `local files = rust.list_dir("."); print(repr(files))`
-- **Searching**: `print(rust.search("TODO", "src").stdout)`
+- **Searching**: `for _, hit in ipairs(rust.search("TODO", "src")) do print(hit.file .. ":" .. hit.line_number .. ": " .. hit.text) end`
 - **Editing**:
   1. Read file: `local src = rust.read_file("main.rs")`
   2. Plan change: "I need to change X to Y..."
@@ -510,11 +1345,11 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
         prompt
     }
 
-    fn build_lua_tool(config: &AppConfig) -> LlmTool {
+    fn build_lua_tool(allow_writes: bool) -> LlmTool {
         let mut description = format!(
             "Execute Lua code inside the user's workspace using the injected helpers (`io.*`, `fs.*`, and the lower-level `rust.*` functions for read_file, list_dir, write_file, http_request, log, etc.). Use `{LLM_LUA_TOOL_NAME}` when you need to inspect files, gather context, and apply verified edits. Always explain why you need the script and summarize results afterward."
         );
-        if !config.allow_tool_writes {
+        if !allow_writes {
             description
                 .push_str(" File writes are disabled; limit scripts to read-only inspection.");
         }
@@ -551,37 +1386,52 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
         while let Ok(event) = active.receiver.try_recv() {
             match event {
                 StreamEvent::Delta(chunk) => {
-                    self.state.append_to_message(active.message_index, &chunk);
+                    if let Some(index) = self.state.position_of(active.message_id) {
+                        self.state.append_to_message(index, &chunk);
+                    }
+                }
+                StreamEvent::ToolCallProgress(progress) => {
+                    self.show_tool_call_progress(&mut active, progress);
+                }
+                StreamEvent::ToolCall(invocation) => {
+                    self.clear_tool_call_previews(&mut active);
+                    self.handle_tool_call(invocation);
                 }
-                StreamEvent::ToolCall(invocation) => self.handle_tool_call(invocation),
                 StreamEvent::Completed => {}
             }
         }
 
+        let placeholder = self.state.position_of(active.message_id);
         match active.result_rx.try_recv() {
             Ok(result) => {
                 finished = true;
-                match result {
-                    Ok(()) => {
-                        if self.state.message_is_empty(active.message_index) {
-                            self.state.remove_message(active.message_index);
+                match (result, placeholder) {
+                    (Ok(()), Some(index)) => {
+                        if self.state.message_is_empty(index) {
+                            self.state.remove_message(index);
                         }
                     }
-                    Err(err) => {
-                        self.state.remove_message(active.message_index);
+                    (Ok(()), None) => {}
+                    (Err(err), _) => {
+                        if let Some(index) = placeholder {
+                            self.state.remove_message(index);
+                        }
                         error_message = Some(format!("LLM error: {err:#}"));
                     }
                 }
             }
             Err(std_mpsc::TryRecvError::Disconnected) => {
                 finished = true;
-                self.state.remove_message(active.message_index);
+                if let Some(index) = placeholder {
+                    self.state.remove_message(index);
+                }
                 error_message = Some("LLM stream ended unexpectedly.".to_string());
             }
             Err(std_mpsc::TryRecvError::Empty) => {}
         }
 
         if finished {
+            self.clear_tool_call_previews(&mut active);
             self.active_stream = None;
         } else {
             self.active_stream = Some(active);
@@ -593,6 +1443,41 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
         }
     }
 
+    /// Shows a streaming tool call in the tool log so a long script is visible
+    /// while it arrives instead of only after the stream completes.
+    fn show_tool_call_progress(&mut self, active: &mut ActiveStream, progress: ToolCallProgress) {
+        let name = progress.name.as_deref().unwrap_or("tool call");
+        let title = format!("Receiving {name}... ({} chars)", progress.received_chars);
+        let detail = progress.source.unwrap_or_default();
+        let existing = active.tool_previews.get(&progress.index).and_then(|id| {
+            self.state
+                .tool_logs
+                .iter_mut()
+                .find(|entry| entry.id == *id)
+        });
+        match existing {
+            Some(entry) => {
+                entry.title = title;
+                entry.detail = detail;
+            }
+            None => {
+                let entry_id = self.create_tool_log_entry(title, detail);
+                active.tool_previews.insert(progress.index, entry_id);
+            }
+        }
+    }
+
+    /// Drops preview entries once the real tool calls (or an error) arrive.
+    fn clear_tool_call_previews(&mut self, active: &mut ActiveStream) {
+        let previews = std::mem::take(&mut active.tool_previews);
+        if previews.is_empty() {
+            return;
+        }
+        self.state
+            .tool_logs
+            .retain(|entry| !previews.values().any(|id| *id == entry.id));
+    }
+
     #[instrument(skip(self))]
     fn invoke_lua(&mut self, action: LuaAction) {
         match action {
@@ -604,16 +1489,56 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
                 }
                 self.run_lua_script("Lua script", script, None);
             }
-            LuaAction::Reset => {
-                match self.lua.reset() {
-                    Ok(()) => {
-                         self.state.push_message(Message::new(Role::Assistant, "Lua environment reset. Global variables cleared."));
+            LuaAction::Pretty(script) => {
+                if script.is_empty() {
+                    self.state
+                        .push_message(Message::new(Role::Assistant, "Lua command needs a script."));
+                    return;
+                }
+                let entry_id = self.create_tool_log_entry("Lua script (pretty)", script);
+                self.execute_lua_entry_as(entry_id, script, None, ToolOutputFormat::Pretty);
+            }
+            LuaAction::Vars => {
+                let globals = self.lua.list_user_globals();
+                let text = if globals.is_empty() {
+                    "No user-defined Lua globals yet.".to_string()
+                } else {
+                    let mut text = format!("Lua globals ({}):", globals.len());
+                    for (name, description) in globals {
+                        text.push_str(&format!("\n- `{name}`: {description}"));
                     }
-                    Err(e) => {
-                         self.state.push_message(Message::new(Role::Assistant, format!("Failed to reset Lua environment: {e}")));
+                    text
+                };
+                self.state.push_message(Message::new(Role::Assistant, text));
+            }
+            LuaAction::Clear(name) => {
+                let reply = if name.is_empty() {
+                    "Usage: /lua clear <name>".to_string()
+                } else {
+                    match self.lua.clear_global(name) {
+                        Ok(true) => format!("Cleared Lua global `{name}`."),
+                        Ok(false) => format!("Lua global `{name}` was not set."),
+                        Err(err) => format!("Could not clear `{name}`: {err}"),
                     }
-                }
+                };
+                self.state
+                    .push_message(Message::new(Role::Assistant, reply));
             }
+            LuaAction::Reset => match self.lua.reset() {
+                Ok(()) => {
+                    self.state.push_message(Message::new(
+                        Role::Assistant,
+                        "Lua environment reset. Global variables cleared.",
+                    ));
+                    self.report_lua_warnings();
+                }
+                Err(e) => {
+                    self.state.push_message(Message::new(
+                        Role::Assistant,
+                        format!("Failed to reset Lua environment: {e}"),
+                    ));
+                }
+            },
         }
     }
 
@@ -638,9 +1563,24 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
     }
 
     fn execute_lua_entry(&mut self, entry_id: usize, script: &str, call_id: Option<String>) {
-        match self.lua.run_script(script) {
+        let format = self.config.tool_output_format;
+        self.execute_lua_entry_as(entry_id, script, call_id, format);
+    }
+
+    fn execute_lua_entry_as(
+        &mut self,
+        entry_id: usize,
+        script: &str,
+        call_id: Option<String>,
+        format: ToolOutputFormat,
+    ) {
+        let started = Instant::now();
+        let result = run_lua_with_timeout(&self.lua, script, self.config.script_timeout());
+        self.state.set_tool_duration(entry_id, started.elapsed());
+        self.modified_files.extend(self.lua.take_modified_files());
+        match result {
             Ok(output) => {
-                let rendered = render_lua_execution(&output);
+                let rendered = render_lua_execution(&output, format, &self.state.redactor);
                 self.state.push_message(match call_id.as_deref() {
                     Some(tool_call_id) => Message::new_tool(tool_call_id, rendered.clone()),
                     None => Message::new(Role::Tool, rendered.clone()),
@@ -649,7 +1589,7 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
                     .update_tool_log(entry_id, ToolStatus::Success, rendered);
             }
             Err(err) => {
-                let msg = format!("Lua error: {err:#}");
+                let msg = redact_tool_output(&format!("Lua error: {err:#}"), &self.state.redactor);
                 self.state.push_message(match call_id.as_deref() {
                     Some(tool_call_id) => Message::new_tool(tool_call_id, msg.clone()),
                     None => Message::new(Role::Tool, msg.clone()),
@@ -681,11 +1621,26 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
                 } else {
                     let _ = writeln!(summary, "LLM requested `{LLM_LUA_TOOL_NAME}`.");
                 }
-                let _ = writeln!(summary, "Script:\n```lua\n{}\n```", request.script);
-                if self.config.allow_tool_writes {
+                let _ = writeln!(
+                    summary,
+                    "Script:\n{}",
+                    session::fence_code_block("lua", &request.script)
+                );
+                let needs_approval = self
+                    .config
+                    .tool_approval
+                    .requires_approval(self.writes_enabled());
+                if self.suppress_tools {
+                    let _ = writeln!(summary, "Tool execution suppressed (plan mode).");
+                } else if needs_approval {
+                    let _ = writeln!(
+                        summary,
+                        "This run is queued for approval. Use `/tool run` to approve or `/tool skip` to cancel."
+                    );
+                } else if self.writes_enabled() {
                     let _ = writeln!(
                         summary,
-                        "Writes are enabled, so this run is queued. Use `/tool run` to approve or `/tool skip` to cancel."
+                        "Tool approval is set to auto; executing immediately."
                     );
                 } else {
                     let _ = writeln!(summary, "Sandbox is read-only; executing immediately.");
@@ -697,7 +1652,9 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
                     .as_ref()
                     .map(|r| format!("LLM {LLM_LUA_TOOL_NAME}: {}", truncate_summary(r)))
                     .unwrap_or_else(|| format!("LLM {LLM_LUA_TOOL_NAME}"));
-                if self.config.allow_tool_writes {
+                if self.suppress_tools {
+                    self.propose_lua_tool(title, request, invocation.call_id.clone());
+                } else if needs_approval {
                     self.queue_lua_tool(title, request, invocation.call_id.clone());
                 } else {
                     self.run_lua_script(title, &request.script, invocation.call_id.clone());
@@ -726,6 +1683,30 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
         }
     }
 
+    fn propose_lua_tool(
+        &mut self,
+        title: String,
+        request: LuaToolRequest,
+        call_id: Option<String>,
+    ) {
+        let mut detail = String::new();
+        if let Some(reason) = request.reason.as_deref() {
+            let _ = writeln!(detail, "Reason: {reason}");
+        }
+        let _ = writeln!(detail, "Script:\n{}", request.script);
+        let _ = writeln!(detail, "\nTool execution suppressed (plan mode).");
+        // Left pending: the script was proposed, not run, and did not fail.
+        self.create_tool_log_entry(format!("Plan: {title}"), detail);
+
+        // Answer the call so the conversation stays valid for the provider.
+        let note =
+            "Tool execution suppressed (plan mode). The script was shown to the user but not run.";
+        self.state.push_message(match call_id.as_deref() {
+            Some(tool_call_id) => Message::new_tool(tool_call_id, note),
+            None => Message::new(Role::Tool, note),
+        });
+    }
+
     fn queue_lua_tool(&mut self, title: String, request: LuaToolRequest, call_id: Option<String>) {
         let mut detail = String::new();
         if let Some(reason) = request.reason.as_deref() {
@@ -772,7 +1753,8 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
             self.state.push_message(Message::new(
                 Role::Assistant,
                 format!(
-                    "Approved queued {LLM_LUA_TOOL_NAME} (`{label}`) — executing now (entry #{})",
+                    "Approved queued {LLM_LUA_TOOL_NAME} (`{label}`) {} executing now (entry #{})",
+                    self.state.glyphs.dash(),
                     pending.entry_id
                 ),
             ));
@@ -788,7 +1770,7 @@ Your primary method of interaction is the `{LLM_LUA_TOOL_NAME}` tool, which exec
     fn current_stream_message_index(&self) -> Option<usize> {
         self.active_stream
             .as_ref()
-            .map(|stream| stream.message_index)
+            .and_then(|stream| self.state.position_of(stream.message_id))
     }
 
     fn skip_pending_tool(&mut self, entry_id: Option<usize>) {
@@ -864,13 +1846,28 @@ fn render_tool_invocation(invocation: ToolInvocation) -> Message {
     Message::new(Role::Assistant, content)
 }
 
-fn render_lua_execution(output: &LuaExecution) -> String {
+fn render_lua_execution(
+    output: &LuaExecution,
+    format: ToolOutputFormat,
+    redactor: &Redactor,
+) -> String {
     let mut content = String::new();
     let _ = writeln!(content, "Lua value:");
-    if output.value.is_empty() {
+    let value = match (&output.structured, format) {
+        (Some(json), ToolOutputFormat::Auto) => {
+            render_aligned_rows(json).unwrap_or_else(|| pretty_json(json))
+        }
+        (Some(json), ToolOutputFormat::Json) => pretty_json(json),
+        (_, ToolOutputFormat::Pretty) => output
+            .pretty
+            .clone()
+            .unwrap_or_else(|| output.value.clone()),
+        _ => output.value.clone(),
+    };
+    if value.is_empty() {
         let _ = writeln!(content, "<empty>");
     } else {
-        for line in output.value.split('\n') {
+        for line in value.split('\n') {
             let _ = writeln!(content, "{line}");
         }
     }
@@ -878,17 +1875,68 @@ fn render_lua_execution(output: &LuaExecution) -> String {
     append_section(&mut content, "Stdout", &output.stdout);
     append_section(&mut content, "Stderr", &output.stderr);
     append_section(&mut content, "Logs", &output.logs);
-    content
+    redact_tool_output(&content, redactor)
 }
 
-fn append_section(buffer: &mut String, label: &str, lines: &[String]) {
-    if lines.is_empty() {
-        return;
+/// Masks secrets (built-in API key shapes plus `[security] secret_patterns`)
+/// before tool output reaches the screen or the model, and says so, since a
+/// script that printed one has likely read a secrets file.
+fn redact_tool_output(content: &str, redactor: &Redactor) -> String {
+    let mut redacted = redactor.redact(content);
+    if redacted != content {
+        if !redacted.ends_with('\n') {
+            redacted.push('\n');
+        }
+        redacted.push_str("Warning: secrets were detected and redacted from this output.\n");
     }
-    let _ = writeln!(buffer);
-    let _ = writeln!(buffer, "{label}:");
-    for line in lines {
-        let _ = writeln!(buffer, "{line}");
+    redacted
+}
+
+fn pretty_json(value: &serde_json::Value) -> String {
+    to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+}
+
+/// Lays out a flat table as `key  value` rows with keys padded to one width.
+/// Returns `None` for nested or empty tables.
+fn render_aligned_rows(value: &serde_json::Value) -> Option<String> {
+    let rows: Vec<(String, &serde_json::Value)> = match value {
+        serde_json::Value::Object(map) => map.iter().map(|(k, v)| (k.clone(), v)).collect(),
+        serde_json::Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(idx, v)| ((idx + 1).to_string(), v))
+            .collect(),
+        _ => return None,
+    };
+    if rows.is_empty() || rows.iter().any(|(_, v)| v.is_object() || v.is_array()) {
+        return None;
+    }
+    let width = rows
+        .iter()
+        .map(|(k, _)| k.chars().count())
+        .max()
+        .unwrap_or(0);
+    let lines: Vec<String> = rows
+        .iter()
+        .map(|(key, value)| {
+            let shown = match value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            format!("{key:<width$}  {shown}")
+        })
+        .collect();
+    Some(lines.join("\n"))
+}
+
+fn append_section(buffer: &mut String, label: &str, lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+    let _ = writeln!(buffer);
+    let _ = writeln!(buffer, "{label}:");
+    for line in lines {
+        let _ = writeln!(buffer, "{line}");
     }
 }
 
@@ -900,9 +1948,63 @@ fn build_llm_client(config: &AppConfig) -> Result<Arc<dyn LlmClient>> {
             let client = OpenAiClient::new(openai_cfg)?;
             Ok(Arc::new(client))
         }
+        ProviderKind::Anthropic => {
+            let anthropic_cfg = build_anthropic_config(config)?;
+            let client = AnthropicClient::new(anthropic_cfg)?;
+            Ok(Arc::new(client))
+        }
+        ProviderKind::Ollama => {
+            let client = OllamaClient::new(build_ollama_config(config))?;
+            Ok(Arc::new(client))
+        }
+    }
+}
+
+/// `[ollama] base_url`, then `OLLAMA_HOST` (as the Ollama CLI reads it, with or
+/// without a scheme), then the default local port.
+fn build_ollama_config(config: &AppConfig) -> OllamaConfig {
+    let base_url = config
+        .ollama
+        .base_url
+        .clone()
+        .or_else(|| {
+            env::var("OLLAMA_HOST").ok().map(|host| {
+                if host.contains("://") {
+                    host
+                } else {
+                    format!("http://{host}")
+                }
+            })
+        })
+        .unwrap_or_else(|| ollama::DEFAULT_BASE_URL.to_string());
+    OllamaConfig {
+        model: config.model_id.clone(),
+        base_url,
     }
 }
 
+fn build_anthropic_config(config: &AppConfig) -> Result<AnthropicConfig> {
+    let anthropic = &config.anthropic;
+    let api_key = env::var("ANTHROPIC_API_KEY").context(
+        "Anthropic provider selected but no API key configured. Set ANTHROPIC_API_KEY (for example in your .env file).",
+    )?;
+    let base_url = anthropic
+        .base_url
+        .clone()
+        .or_else(|| env::var("ANTHROPIC_BASE_URL").ok())
+        .unwrap_or_else(|| anthropic::DEFAULT_BASE_URL.to_string());
+
+    Ok(AnthropicConfig {
+        api_key,
+        model: config.model_id.clone(),
+        base_url,
+        max_tokens: anthropic.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        error_body_limit: anthropic
+            .error_body_limit
+            .unwrap_or(DEFAULT_ERROR_BODY_LIMIT),
+    })
+}
+
 fn build_openai_config(config: &AppConfig) -> Result<OpenAiConfig> {
     let openai = &config.openai;
     let api_key = env::var("OPENAI_API_KEY").context(
@@ -921,6 +2023,11 @@ fn build_openai_config(config: &AppConfig) -> Result<OpenAiConfig> {
         .project
         .clone()
         .or_else(|| env::var("OPENAI_PROJECT").ok());
+    let user = openai
+        .user
+        .clone()
+        .or_else(|| env::var("SELENAI_USER").ok())
+        .filter(|user| !user.trim().is_empty());
 
     Ok(OpenAiConfig {
         api_key,
@@ -928,6 +2035,15 @@ fn build_openai_config(config: &AppConfig) -> Result<OpenAiConfig> {
         base_url,
         organization,
         project,
+        user,
+        parallel_tool_calls: openai.parallel_tool_calls,
+        error_body_limit: openai.error_body_limit.unwrap_or(DEFAULT_ERROR_BODY_LIMIT),
+        headers: openai.headers.clone(),
+        max_attempts: openai.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS),
+        retry_base_delay: openai
+            .retry_base_delay_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_RETRY_BASE_DELAY),
     })
 }
 
@@ -985,8 +2101,22 @@ fn parse_lua_command(input: &str) -> Option<LuaAction> {
     }
 
     let rest = &trimmed[4..];
-    if rest.trim() == "reset" {
-        return Some(LuaAction::Reset);
+    match rest.trim() {
+        "reset" => return Some(LuaAction::Reset),
+        "vars" => return Some(LuaAction::Vars),
+        _ => {}
+    }
+    if let Some(name) = rest.trim_start().strip_prefix("clear")
+        && rest.starts_with(char::is_whitespace)
+        && (name.is_empty() || name.starts_with(char::is_whitespace))
+    {
+        return Some(LuaAction::Clear(name.trim()));
+    }
+    if let Some(script) = rest.trim_start().strip_prefix("pretty")
+        && rest.starts_with(char::is_whitespace)
+        && (script.is_empty() || script.starts_with(char::is_whitespace))
+    {
+        return Some(LuaAction::Pretty(script.trim_start()));
     }
     
     if rest.is_empty() {
@@ -1000,6 +2130,305 @@ fn parse_lua_command(input: &str) -> Option<LuaAction> {
     None
 }
 
+fn parse_think_command(input: &str) -> Option<ThinkAction<'_>> {
+    let trimmed = input.trim_start();
+    let rest = trimmed.strip_prefix("/think")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    match rest.trim() {
+        "on" => Some(ThinkAction::Enable),
+        "off" => Some(ThinkAction::Disable),
+        prompt => Some(ThinkAction::Prompt(prompt)),
+    }
+}
+
+fn welcome_tour(glyphs: Glyphs) -> String {
+    format!(
+        "{}**Welcome to SelenAI!** It looks like your first time here.\n\n\
+         I am your terminal-based AI pair programmer. Here's a quick tour:\n\
+         1. **Chat**: Type here to talk to me. I can read files, run tests, and edit code.\n\
+         2. **Tools**: I execute Lua scripts to interact with your system. You'll see my plans and outputs in the right pane.\n\
+         3. **Safety**: By default, I might be Read-Only. Check `/config show`, or run `selenai init` to write a commented `selenai.toml`.\n\
+         4. **Commands**: Try `/review` to check git changes, or `/help` for the full list (Tab completes a `/command`).\n\
+         \n\
+         Start by asking me to \"analyze the current project structure\"!",
+        glyphs.wave()
+    )
+}
+
+struct SlashCommand {
+    name: &'static str,
+    aliases: &'static [&'static str],
+    usage: &'static str,
+    summary: &'static str,
+}
+
+/// Every slash command, in `/help` order; also the Tab completion candidates.
+const SLASH_COMMANDS: &[SlashCommand] = &[
+    SlashCommand {
+        name: "/help",
+        aliases: &[],
+        usage: "/help",
+        summary: "Show this list.",
+    },
+    SlashCommand {
+        name: "/lua",
+        aliases: &[],
+        usage: "/lua <script> | pretty <script> | vars | clear <name> | reset",
+        summary: "Run Lua in the persistent sandbox, or inspect and reset its globals.",
+    },
+    SlashCommand {
+        name: "/watch-lua",
+        aliases: &[],
+        usage: "/watch-lua <path> | off",
+        summary: "Re-run a Lua file read-only whenever it is saved.",
+    },
+    SlashCommand {
+        name: "/tool",
+        aliases: &[],
+        usage: "/tool run [id] | skip [id]",
+        summary: "Approve or cancel queued tool runs (oldest first without an id).",
+    },
+    SlashCommand {
+        name: "/review",
+        aliases: &[],
+        usage: "/review [focus]",
+        summary: "Review the uncommitted git changes.",
+    },
+    SlashCommand {
+        name: "/think",
+        aliases: &[],
+        usage: "/think <prompt> | on | off",
+        summary: "Plan without running tools, once or until turned off.",
+    },
+    SlashCommand {
+        name: "/config",
+        aliases: &[],
+        usage: "/config show | set <key> <value>",
+        summary: "Show the settings or change sampling and write access live.",
+    },
+    SlashCommand {
+        name: "/readonly",
+        aliases: &[],
+        usage: "/readonly on | off",
+        summary: "Clamp the session to read-only helpers.",
+    },
+    SlashCommand {
+        name: "/notools",
+        aliases: &[],
+        usage: "/notools [on | off]",
+        summary: "Ask the model to answer without calling tools (toggles without an argument).",
+    },
+    SlashCommand {
+        name: "/regenerate",
+        aliases: &["/retry"],
+        usage: "/regenerate",
+        summary: "Drop the last response and ask again.",
+    },
+    SlashCommand {
+        name: "/edit",
+        aliases: &[],
+        usage: "/edit",
+        summary: "Pull the last prompt back into the input to fix and resend.",
+    },
+    SlashCommand {
+        name: "/ping",
+        aliases: &["/health"],
+        usage: "/ping",
+        summary: "Check the provider connection and report latency.",
+    },
+    SlashCommand {
+        name: "/models",
+        aliases: &[],
+        usage: "/models",
+        summary: "List the models the provider offers.",
+    },
+    SlashCommand {
+        name: "/sessions",
+        aliases: &[],
+        usage: "/sessions [open <n|id>]",
+        summary: "List past sessions or load one back into the chat.",
+    },
+    SlashCommand {
+        name: "/export-html",
+        aliases: &[],
+        usage: "/export-html <file> [--force]",
+        summary: "Write the chat and tool log as a shareable HTML page.",
+    },
+    SlashCommand {
+        name: "/reset-tour",
+        aliases: &[],
+        usage: "/reset-tour",
+        summary: "Replay the welcome tour.",
+    },
+];
+
+fn slash_command_names() -> Vec<&'static str> {
+    SLASH_COMMANDS
+        .iter()
+        .flat_map(|command| std::iter::once(command.name).chain(command.aliases.iter().copied()))
+        .collect()
+}
+
+fn help_text() -> String {
+    let mut out = String::from("**Commands** (Tab completes a `/command` in the input):\n");
+    for command in SLASH_COMMANDS {
+        let _ = write!(out, "- `{}`", command.usage);
+        if !command.aliases.is_empty() {
+            let _ = write!(out, " (alias `{}`)", command.aliases.join("`, `"));
+        }
+        let _ = writeln!(out, " - {}", command.summary);
+    }
+    out.push_str(
+        "\nAnything else goes to the model; `@name` expands a macro from \
+         `~/.config/selenai/macros.toml`.",
+    );
+    out
+}
+
+fn parse_watch_lua_command(input: &str) -> Option<&str> {
+    let rest = input.trim_start().strip_prefix("/watch-lua")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(rest.trim())
+}
+
+/// Applies `script_timeout_secs` so a runaway script cannot freeze the UI.
+fn run_lua_with_timeout(
+    lua: &LuaExecutor,
+    script: &str,
+    timeout: Option<Duration>,
+) -> Result<LuaExecution> {
+    match timeout {
+        Some(timeout) => lua.run_script_with_timeout(script, timeout),
+        None => lua.run_script(script),
+    }
+}
+
+fn lua_options(config: &AppConfig, allow_writes: bool) -> ExecutorOptions {
+    ExecutorOptions {
+        allow_writes,
+        write_scope: config.write_scope.clone(),
+        extensions_dir: config.lua_extensions_dir.clone(),
+        prelude_file: config.lua_prelude_file.clone(),
+        model: Some(config.model_id.clone()),
+        command_timeout: config.command_timeout(),
+    }
+}
+
+fn render_model_list(models: &[String], current: &str) -> String {
+    let mut out = format!("Available models ({}):\n", models.len());
+    for model in models {
+        let marker = if model == current { "*" } else { " " };
+        out.push_str(&format!("{marker} {model}\n"));
+    }
+    out.push_str("\n`*` marks the configured `model_id`.");
+    out
+}
+
+/// Returns the command line when a paste is a single line holding a command
+/// that only reads state, so submitting it without Enter cannot change anything.
+fn paste_submit_command(data: &str) -> Option<&str> {
+    let line = data.trim_end_matches(['\r', '\n']);
+    if line.contains(['\r', '\n']) || !line.starts_with('/') {
+        return None;
+    }
+    let read_only = parse_review_command(line).is_some()
+        || matches!(parse_config_command(line), Some(("show", None, None)))
+        || is_ping_command(line)
+        || line.trim() == "/models";
+    read_only.then_some(line)
+}
+
+fn is_ping_command(input: &str) -> bool {
+    matches!(input.trim(), "/ping" | "/health")
+}
+
+fn parse_export_html_command(input: &str) -> Option<&str> {
+    let rest = input.trim_start().strip_prefix("/export-html")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(rest.trim())
+}
+
+fn parse_sessions_command(input: &str) -> Option<SessionsAction<'_>> {
+    let trimmed = input.trim_start();
+    let rest = trimmed.strip_prefix("/sessions")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    match rest.trim().strip_prefix("open") {
+        Some(id) if !id.trim().is_empty() => Some(SessionsAction::Open(id.trim())),
+        _ => Some(SessionsAction::List),
+    }
+}
+
+fn render_session_list(sessions: &[session::SessionSummary]) -> String {
+    const LIMIT: usize = 20;
+    if sessions.is_empty() {
+        return "No past sessions found.".to_string();
+    }
+    let mut out = String::from("Recent sessions (newest first):\n");
+    for (idx, summary) in sessions.iter().take(LIMIT).enumerate() {
+        let started = summary
+            .started_unix_ms
+            .and_then(|ms| chrono::DateTime::from_timestamp_millis(ms as i64))
+            .map(|ts| {
+                ts.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "unknown start".to_string());
+        let messages = summary
+            .message_count
+            .map(|count| format!("{count} messages"))
+            .unwrap_or_else(|| "no transcript".to_string());
+        let name = match &summary.title {
+            Some(title) => format!("{title} ({})", summary.id),
+            None => summary.id.clone(),
+        };
+        let _ = writeln!(out, "{}. {started} - {messages} - {name}", idx + 1);
+    }
+    if sessions.len() > LIMIT {
+        let _ = writeln!(
+            out,
+            "... {} older sessions not shown.",
+            sessions.len() - LIMIT
+        );
+    }
+    out.push_str("Use /sessions open <number|id> to resume one.");
+    out
+}
+
+fn parse_readonly_command(input: &str) -> Option<Option<bool>> {
+    let trimmed = input.trim_start();
+    let rest = trimmed.strip_prefix("/readonly")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    match rest.trim() {
+        "on" => Some(Some(true)),
+        "off" => Some(Some(false)),
+        _ => Some(None),
+    }
+}
+
+/// `/notools` toggles; `on`/`off` set the state explicitly.
+fn parse_notools_command(input: &str) -> Option<Option<bool>> {
+    let rest = input.trim_start().strip_prefix("/notools")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    match rest.trim() {
+        "on" => Some(Some(true)),
+        "off" => Some(Some(false)),
+        _ => Some(None),
+    }
+}
+
 fn parse_tool_command(input: &str) -> Option<ToolCommand> {
     let trimmed = input.trim_start();
     if !trimmed.starts_with("/tool") {
@@ -1032,6 +2461,32 @@ fn parse_tool_command(input: &str) -> Option<ToolCommand> {
     }
 }
 
+/// Lua that collects the diff for `/review`. The target is user input, so it
+/// goes in as an escaped string literal rather than spliced into the source.
+fn review_script(target: &str) -> String {
+    format!(
+        r#"
+        local target = {target:?}
+        local status = rust.git_status().stdout
+        if status == "" and target == "" then
+            return "Working tree clean, nothing to review."
+        end
+
+        local diff
+        if target ~= "" then
+            diff = rust.git_diff({{ target = target }}).stdout
+        else
+            diff = rust.git_diff({{ staged = true }}).stdout .. rust.git_diff().stdout
+        end
+        if diff == "" then
+            return "No changes found for review."
+        end
+
+        return "Here is the diff for review:\n" .. diff
+        "#
+    )
+}
+
 fn parse_review_command(input: &str) -> Option<&str> {
     let trimmed = input.trim_start();
     if !trimmed.starts_with("/review") {
@@ -1072,11 +2527,114 @@ pub struct AppState {
     pub chat_scroll: u16,
     pub tool_scroll: u16,
     pub copy_mode: bool,
+    /// Effective write mode shown in the status hint.
+    pub writes_enabled: bool,
+    pub tool_icons: ToolIcons,
+    pub glyphs: Glyphs,
+    /// Render relative message ages next to role headers.
+    pub show_timestamps: bool,
+    /// Trim leading whitespace on wrapped chat rows.
+    pub chat_wrap_trim: bool,
+    /// Split chat lines holding a token wider than the pane into full rows.
+    pub chat_hard_wrap: bool,
+    /// Show message, line, and entry counts in the pane titles.
+    pub show_pane_stats: bool,
+    /// Labels shown before chat messages.
+    pub role_prefixes: RolePrefixes,
+    /// Secret patterns masked in tool output and exports.
+    pub redactor: Redactor,
+    /// Put single-line messages on the same row as their label.
+    pub chat_compact: bool,
+    /// Blank lines after each chat message.
+    pub message_spacing: usize,
+    /// A response is streaming; the status line shows a spinner.
+    pub busy: bool,
+    /// Advanced once per UI tick to animate the spinner.
+    pub spinner_tick: usize,
+    /// Wrapped message heights reused across redraws.
+    pub chat_heights: tui::ChatHeights,
+    /// Characters across `messages`, kept up to date by the methods that
+    /// change them so the status line does not recount every frame.
+    message_chars: usize,
+}
+
+/// Why the TUI should not start, if it should not: it needs a terminal on
+/// both stdin (key events) and stdout (the alternate screen).
+pub fn plain_mode_reason(forced: bool, stdin_tty: bool, stdout_tty: bool) -> Option<&'static str> {
+    if forced {
+        Some("--plain was given")
+    } else if !stdout_tty {
+        Some("stdout is not a terminal")
+    } else if !stdin_tty {
+        Some("stdin is not a terminal")
+    } else {
+        None
+    }
+}
+
+/// A chat message as printed by the plain line mode.
+fn plain_message(message: &Message, prefixes: &RolePrefixes) -> String {
+    format!(
+        "{}: {}\n\n",
+        prefixes.prefix(message.role),
+        message.content.trim_end()
+    )
+}
+
+fn welcome_message(glyphs: Glyphs) -> String {
+    format!(
+        "Welcome to SelenAI {} Tab moves focus, Ctrl+C exits.",
+        glyphs.dash()
+    )
 }
 
 impl Default for AppState {
     fn default() -> Self {
+        Self::with_glyphs(Glyphs::default())
+    }
+}
+
+impl AppState {
+    /// Initial state with the display preferences from `selenai.toml` applied
+    /// and startup notes (copy mode, read-only helpers) already in the chat.
+    pub fn new(config: &AppConfig) -> Self {
+        let glyphs = config.glyphs();
         let mut state = Self {
+            writes_enabled: config.allow_tool_writes,
+            tool_icons: config.tool_icons(),
+            copy_mode: config.copy_mode_default,
+            show_timestamps: config.message_timestamps,
+            chat_wrap_trim: config.chat_wrap_trim,
+            chat_hard_wrap: config.chat_hard_wrap,
+            show_pane_stats: config.pane_stats,
+            role_prefixes: config.role_prefixes(),
+            chat_compact: config.chat_compact,
+            message_spacing: config.message_spacing,
+            input: InputState::with_max_chars(config.input_char_limit()),
+            ..Self::empty(glyphs)
+        };
+        let mut welcome = welcome_message(glyphs);
+        if state.copy_mode {
+            welcome.push_str(" Copy-friendly mode is on; Ctrl+B brings the borders back.");
+        }
+        state.push_message(Message::new(Role::Assistant, welcome));
+        if !config.allow_tool_writes {
+            state.push_message(Message::new(
+                Role::Assistant,
+                "Lua helpers are running in read-only mode (enable writes in selenai.toml).",
+            ));
+        }
+        state
+    }
+
+    pub fn with_glyphs(glyphs: Glyphs) -> Self {
+        let mut state = Self::empty(glyphs);
+        state.push_message(Message::new(Role::Assistant, welcome_message(glyphs)));
+        state
+    }
+
+    fn empty(glyphs: Glyphs) -> Self {
+        Self {
             messages: Vec::new(),
             tool_logs: Vec::new(),
             input: InputState::default(),
@@ -1084,25 +2642,55 @@ impl Default for AppState {
             chat_scroll: 0,
             tool_scroll: 0,
             copy_mode: false,
-        };
-        state.push_message(Message::new(
-            Role::Assistant,
-            "Welcome to SelenAI — Tab moves focus, Ctrl+C exits.",
-        ));
-        state
+            writes_enabled: false,
+            tool_icons: glyphs.tool_icons(),
+            glyphs,
+            show_timestamps: false,
+            chat_wrap_trim: false,
+            chat_hard_wrap: true,
+            show_pane_stats: false,
+            role_prefixes: RolePrefixes::default(),
+            redactor: Redactor::default(),
+            chat_compact: false,
+            message_spacing: 1,
+            busy: false,
+            spinner_tick: 0,
+            chat_heights: tui::ChatHeights::default(),
+            message_chars: 0,
+        }
+    }
+
+    /// Rough size of the conversation in tokens (about four characters each),
+    /// for the status line; providers do not report usage here.
+    pub fn estimated_tokens(&self) -> usize {
+        self.message_chars.div_ceil(4)
     }
-}
 
-impl AppState {
     pub fn push_message(&mut self, message: Message) {
+        self.message_chars += message.content.chars().count();
         self.messages.push(message);
         self.chat_scroll = 0;
     }
 
-    pub fn push_message_with_index(&mut self, message: Message) -> usize {
-        let index = self.messages.len();
-        self.push_message(message);
-        index
+    /// Swaps in a whole history, e.g. a resumed session.
+    pub fn replace_messages(&mut self, messages: Vec<Message>) {
+        self.message_chars = messages.iter().map(|m| m.content.chars().count()).sum();
+        self.messages = messages;
+        self.chat_scroll = 0;
+    }
+
+    pub fn set_message_content(&mut self, index: usize, text: &str) {
+        if let Some(message) = self.messages.get_mut(index) {
+            self.message_chars -= message.content.chars().count();
+            self.message_chars += text.chars().count();
+            message.content = text.to_string();
+        }
+    }
+
+    pub fn set_tool_duration(&mut self, id: usize, elapsed: Duration) {
+        if let Some(entry) = self.tool_logs.iter_mut().find(|entry| entry.id == id) {
+            entry.duration_ms = Some(elapsed.as_millis() as u64);
+        }
     }
 
     pub fn update_tool_log(&mut self, id: usize, status: ToolStatus, detail: impl Into<String>) {
@@ -1116,6 +2704,7 @@ impl AppState {
     pub fn append_to_message(&mut self, index: usize, text: &str) {
         if let Some(message) = self.messages.get_mut(index) {
             message.content.push_str(text);
+            self.message_chars += text.chars().count();
             self.chat_scroll = 0;
         }
     }
@@ -1133,9 +2722,23 @@ impl AppState {
             .unwrap_or(true)
     }
 
+    /// Current position of the message with `id`, if it is still present.
+    pub fn position_of(&self, id: u64) -> Option<usize> {
+        self.messages.iter().position(|message| message.id == id)
+    }
+
+    /// Drops every message from `len` onward.
+    pub fn truncate_messages(&mut self, len: usize) {
+        for message in self.messages.drain(len.min(self.messages.len())..) {
+            self.message_chars -= message.content.chars().count();
+        }
+        self.chat_scroll = 0;
+    }
+
     pub fn remove_message(&mut self, index: usize) {
         if index < self.messages.len() {
-            self.messages.remove(index);
+            let message = self.messages.remove(index);
+            self.message_chars -= message.content.chars().count();
             self.chat_scroll = 0;
         }
     }
@@ -1170,17 +2773,88 @@ impl FocusTarget {
 pub struct InputState {
     buffer: String,
     cursor: usize, // cursor position in characters
+    /// Cap on buffer length in characters; `None` means unlimited.
+    max_chars: Option<usize>,
+    /// Characters refused by the last insert that hit `max_chars`.
+    dropped: usize,
+    /// Text removed by the last Ctrl+W / Ctrl+K, restored by Ctrl+Y.
+    killed: String,
+    /// Submitted entries, oldest first, recalled with Up/Down.
+    history: Vec<String>,
+    /// Entry shown while browsing history; `None` means the draft is showing.
+    history_pos: Option<usize>,
+    /// Text in progress when browsing started, restored past the newest entry.
+    draft: String,
+    /// File new entries are appended to, when history persists across runs.
+    history_file: Option<PathBuf>,
+    /// Masks secrets in entries before they reach `history_file`.
+    history_redactor: Redactor,
+    /// Lines currently in `history_file`; past the limit it is rewritten.
+    history_file_lines: usize,
+    /// Last Tab completion: the typed prefix, the match index, and the text it
+    /// produced. Tab cycles only while the buffer still holds that text.
+    completion: Option<(String, usize, String)>,
 }
 
 impl InputState {
+    pub fn with_max_chars(max_chars: Option<usize>) -> Self {
+        Self {
+            max_chars,
+            ..Self::default()
+        }
+    }
+
     pub fn buffer(&self) -> String {
         self.buffer.clone()
     }
 
-    pub fn insert_char(&mut self, ch: char) {
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Inserts `ch` at the cursor unless the buffer is full; returns whether
+    /// it was inserted.
+    pub fn insert_char(&mut self, ch: char) -> bool {
+        if self.remaining() == Some(0) {
+            self.dropped += 1;
+            return false;
+        }
         let idx = self.byte_index(self.cursor);
         self.buffer.insert(idx, ch);
         self.cursor += 1;
+        self.dropped = 0;
+        true
+    }
+
+    /// Inserts as much of `text` as fits (used for pastes) and returns how
+    /// many characters were cut off.
+    pub fn insert_str(&mut self, text: &str) -> usize {
+        let total = text.chars().count();
+        let keep = self.remaining().map_or(total, |room| room.min(total));
+        let kept: String = text.chars().take(keep).collect();
+        let idx = self.byte_index(self.cursor);
+        self.buffer.insert_str(idx, &kept);
+        self.cursor += keep;
+        self.dropped = total - keep;
+        self.dropped
+    }
+
+    pub fn char_count(&self) -> usize {
+        self.len_chars()
+    }
+
+    pub fn max_chars(&self) -> Option<usize> {
+        self.max_chars
+    }
+
+    /// Characters refused by the most recent insert, cleared by the next edit.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        self.max_chars
+            .map(|max| max.saturating_sub(self.len_chars()))
     }
 
     pub fn backspace(&mut self) {
@@ -1191,6 +2865,7 @@ impl InputState {
         let end = self.byte_index(self.cursor);
         self.buffer.replace_range(start..end, "");
         self.cursor -= 1;
+        self.dropped = 0;
     }
 
     pub fn delete_char(&mut self) {
@@ -1214,6 +2889,101 @@ impl InputState {
         }
     }
 
+    /// Deletes back to the start of the previous word (Ctrl+W / Alt+Backspace),
+    /// taking any whitespace between the cursor and that word with it.
+    pub fn delete_word_back(&mut self) {
+        let target = self.word_start_before(self.cursor);
+        if target == self.cursor {
+            return;
+        }
+        let start = self.byte_index(target);
+        let end = self.byte_index(self.cursor);
+        self.killed = self.buffer.drain(start..end).collect();
+        self.cursor = target;
+        self.dropped = 0;
+    }
+
+    /// Deletes from the cursor to the end of the buffer (Ctrl+K).
+    pub fn kill_to_end(&mut self) {
+        let start = self.byte_index(self.cursor);
+        if start == self.buffer.len() {
+            return;
+        }
+        self.killed = self.buffer.split_off(start);
+        self.dropped = 0;
+    }
+
+    /// Inserts the most recently killed text at the cursor (Ctrl+Y).
+    pub fn yank(&mut self) {
+        let killed = self.killed.clone();
+        self.insert_str(&killed);
+    }
+
+    pub fn move_word_left(&mut self) {
+        self.cursor = self.word_start_before(self.cursor);
+    }
+
+    pub fn move_word_right(&mut self) {
+        let chars: Vec<char> = self.buffer.chars().collect();
+        let mut pos = self.cursor;
+        while pos < chars.len() && chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        while pos < chars.len() && !chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        self.cursor = pos;
+    }
+
+    /// Character index where the word ending at or before `cursor` starts.
+    fn word_start_before(&self, cursor: usize) -> usize {
+        let chars: Vec<char> = self.buffer.chars().take(cursor).collect();
+        let mut pos = chars.len();
+        while pos > 0 && chars[pos - 1].is_whitespace() {
+            pos -= 1;
+        }
+        while pos > 0 && !chars[pos - 1].is_whitespace() {
+            pos -= 1;
+        }
+        pos
+    }
+
+    /// Moves to the same column on the previous line, or to its end when it
+    /// is shorter. Returns false on the first line.
+    pub fn move_up(&mut self) -> bool {
+        let chars: Vec<char> = self.buffer.chars().collect();
+        let Some(newline) = chars[..self.cursor].iter().rposition(|&c| c == '\n') else {
+            return false;
+        };
+        let column = self.cursor - newline - 1;
+        let prev_start = chars[..newline]
+            .iter()
+            .rposition(|&c| c == '\n')
+            .map_or(0, |pos| pos + 1);
+        self.cursor = prev_start + column.min(newline - prev_start);
+        true
+    }
+
+    /// Moves to the same column on the next line, or to its end when it is
+    /// shorter. Returns false on the last line.
+    pub fn move_down(&mut self) -> bool {
+        let chars: Vec<char> = self.buffer.chars().collect();
+        let Some(offset) = chars[self.cursor..].iter().position(|&c| c == '\n') else {
+            return false;
+        };
+        let line_start = chars[..self.cursor]
+            .iter()
+            .rposition(|&c| c == '\n')
+            .map_or(0, |pos| pos + 1);
+        let next_start = self.cursor + offset + 1;
+        let next_len = chars[next_start..]
+            .iter()
+            .position(|&c| c == '\n')
+            .unwrap_or(chars.len() - next_start);
+        self.cursor = next_start + (self.cursor - line_start).min(next_len);
+        true
+    }
+
     pub fn move_to_start(&mut self) {
         self.cursor = 0;
     }
@@ -1224,59 +2994,242 @@ impl InputState {
 
     pub fn take(&mut self) -> String {
         self.cursor = 0;
+        self.dropped = 0;
         std::mem::take(&mut self.buffer)
     }
 
     pub fn clear(&mut self) {
         self.buffer.clear();
         self.cursor = 0;
+        self.dropped = 0;
     }
 
-    pub fn cursor_display_offset(&self) -> u16 {
-        let idx = self.byte_index(self.cursor);
-        let slice = &self.buffer[..idx];
-        UnicodeWidthStr::width(slice) as u16
+    /// Replaces the buffer and places the cursor at the end.
+    pub fn set(&mut self, text: &str) {
+        self.buffer = text.to_string();
+        self.cursor = self.len_chars();
     }
 
-    fn len_chars(&self) -> usize {
-        self.buffer.chars().count()
+    /// Loads entries saved by earlier runs (one JSON string per line) and
+    /// appends future ones to the same file.
+    pub fn load_history(&mut self, path: PathBuf, redactor: Redactor) {
+        self.history_file_lines = 0;
+        if let Ok(contents) = fs::read_to_string(&path) {
+            self.history_file_lines = contents.lines().count();
+            self.history = contents
+                .lines()
+                .filter_map(|line| serde_json::from_str::<String>(line).ok())
+                .collect();
+            let excess = self.history.len().saturating_sub(INPUT_HISTORY_LIMIT);
+            self.history.drain(..excess);
+        }
+        self.history_file = Some(path);
+        self.history_redactor = redactor;
     }
 
-    fn byte_index(&self, cursor: usize) -> usize {
-        if cursor == 0 {
-            return 0;
-        }
+    /// Stops saving entries to disk; later ones are kept in memory only.
+    pub fn detach_history_file(&mut self) {
+        self.history_file = None;
+    }
 
-        if cursor >= self.len_chars() {
-            return self.buffer.len();
+    /// Records a submitted entry, skipping blanks and repeats of the newest
+    /// one, and stops any history browsing.
+    pub fn push_history(&mut self, entry: &str) {
+        self.history_pos = None;
+        self.draft.clear();
+        if entry.trim().is_empty() || self.history.last().is_some_and(|last| last == entry) {
+            return;
+        }
+        self.history.push(entry.to_string());
+        if self.history.len() > INPUT_HISTORY_LIMIT {
+            self.history.remove(0);
+        }
+        let Some(path) = &self.history_file else {
+            return;
+        };
+        let saved = if self.history_file_lines < INPUT_HISTORY_LIMIT {
+            append_history_entry(path, &self.history_redactor.redact(entry))
+        } else {
+            rewrite_history_file(path, &self.history, &self.history_redactor)
+        };
+        match saved {
+            Ok(()) => {
+                self.history_file_lines = (self.history_file_lines + 1).min(self.history.len());
+            }
+            Err(err) => warn!("failed to save input history: {err:#}"),
         }
+    }
 
-        self.buffer
-            .char_indices()
-            .nth(cursor)
-            .map(|(idx, _)| idx)
-            .unwrap_or_else(|| self.buffer.len())
+    /// Shows the next older entry (Up), saving the draft on the first step.
+    pub fn history_prev(&mut self) {
+        let pos = match self.history_pos {
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.buffer.clone();
+                self.history.len() - 1
+            }
+            Some(0) => return,
+            Some(pos) => pos - 1,
+        };
+        self.history_pos = Some(pos);
+        let entry = self.history[pos].clone();
+        self.set(&entry);
     }
-}
 
-struct PendingLuaTool {
-    entry_id: usize,
+    /// Shows the next newer entry (Down), restoring the draft past the newest.
+    pub fn history_next(&mut self) {
+        let Some(pos) = self.history_pos else {
+            return;
+        };
+        if pos + 1 < self.history.len() {
+            self.history_pos = Some(pos + 1);
+            let entry = self.history[pos + 1].clone();
+            self.set(&entry);
+        } else {
+            self.history_pos = None;
+            let draft = std::mem::take(&mut self.draft);
+            self.set(&draft);
+        }
+    }
+
+    /// True for a bare leading `/token`, the only input Tab completes.
+    pub fn is_command_prefix(&self) -> bool {
+        self.buffer.starts_with('/') && !self.buffer.contains(char::is_whitespace)
+    }
+
+    /// Replaces a `/token` with the next command starting with what was typed,
+    /// cycling through the matches on repeated calls.
+    pub fn complete_command(&mut self, commands: &[&str]) {
+        let (prefix, next) = match &self.completion {
+            Some((prefix, index, shown)) if *shown == self.buffer => (prefix.clone(), index + 1),
+            _ => (self.buffer.clone(), 0),
+        };
+        let matches: Vec<&str> = commands
+            .iter()
+            .copied()
+            .filter(|command| command.starts_with(prefix.as_str()))
+            .collect();
+        if matches.is_empty() {
+            self.completion = None;
+            return;
+        }
+        let index = next % matches.len();
+        self.set(matches[index]);
+        self.completion = Some((prefix, index, matches[index].to_string()));
+    }
+
+    /// Display column of the cursor within its line.
+    pub fn cursor_display_offset(&self) -> u16 {
+        let idx = self.byte_index(self.cursor);
+        let slice = &self.buffer[..idx];
+        let line_start = slice.rfind('\n').map_or(0, |pos| pos + 1);
+        UnicodeWidthStr::width(&slice[line_start..]) as u16
+    }
+
+    /// Zero-based line the cursor is on.
+    pub fn cursor_line(&self) -> u16 {
+        let idx = self.byte_index(self.cursor);
+        self.buffer[..idx].matches('\n').count() as u16
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.buffer.split('\n').count()
+    }
+
+    fn len_chars(&self) -> usize {
+        self.buffer.chars().count()
+    }
+
+    fn byte_index(&self, cursor: usize) -> usize {
+        if cursor == 0 {
+            return 0;
+        }
+
+        if cursor >= self.len_chars() {
+            return self.buffer.len();
+        }
+
+        self.buffer
+            .char_indices()
+            .nth(cursor)
+            .map(|(idx, _)| idx)
+            .unwrap_or_else(|| self.buffer.len())
+    }
+}
+
+/// Written to `user_config_dir()` once the welcome tour has been shown.
+const TOUR_MARKER_NAME: &str = ".seen_tour";
+/// Input history kept in memory and on disk.
+const INPUT_HISTORY_LIMIT: usize = 500;
+
+fn input_history_path() -> PathBuf {
+    user_config_dir().join("history")
+}
+
+/// Appends one (already redacted) entry, JSON-encoded so multi-line prompts
+/// stay on a single line.
+fn append_history_entry(path: &std::path::Path, entry: &str) -> Result<()> {
+    use std::io::Write as _;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Replaces the history file with `entries`, keeping it at the in-memory
+/// limit instead of growing with every append.
+fn rewrite_history_file(
+    path: &std::path::Path,
+    entries: &[String],
+    redactor: &Redactor,
+) -> Result<()> {
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&serde_json::to_string(&redactor.redact(entry))?);
+        contents.push('\n');
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+struct PendingLuaTool {
+    entry_id: usize,
     title: String,
     script: String,
     reason: Option<String>,
     call_id: Option<String>,
 }
 
+struct ActiveLuaWatch {
+    watch: LuaFileWatch,
+    /// Dedicated read-only VM, reset before every run.
+    lua: LuaExecutor,
+    entry_id: usize,
+    runs: usize,
+}
+
 struct ActiveStream {
     receiver: mpsc::UnboundedReceiver<StreamEvent>,
     result_rx: std_mpsc::Receiver<Result<()>>,
-    message_index: usize,
+    /// Tracked by id so removals elsewhere in the history cannot redirect deltas.
+    message_id: u64,
+    /// Tool log entries previewing tool calls still streaming in, by call index.
+    tool_previews: BTreeMap<usize, usize>,
+    /// Stops the spawned `chat_stream` task when the user cancels.
+    abort: AbortHandle,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::tempdir;
+    use crate::config::{ToolApproval, TourSetting};
+    use tempfile::{TempDir, tempdir};
 
     #[test]
     fn lua_tool_request_parses_fields() {
@@ -1319,31 +3272,24 @@ mod tests {
         }
     }
 
-    #[allow(clippy::field_reassign_with_default)]
     #[test]
     fn build_system_prompt_mentions_write_policy() {
-        let mut cfg = AppConfig::default();
-        cfg.allow_tool_writes = false;
-        let prompt = App::build_system_prompt(&cfg);
+        let prompt = App::build_system_prompt(false);
         assert!(
             prompt.contains("READ-ONLY"),
             "prompt should mention read-only mode:\n{prompt}"
         );
 
-        cfg.allow_tool_writes = true;
-        let prompt = App::build_system_prompt(&cfg);
+        let prompt = App::build_system_prompt(true);
         assert!(
             prompt.contains("ENABLED"),
             "prompt should mention write access:\n{prompt}"
         );
     }
 
-    #[allow(clippy::field_reassign_with_default)]
     #[test]
     fn build_lua_tool_reflects_config() {
-        let mut cfg = AppConfig::default();
-        cfg.allow_tool_writes = false;
-        let tool = App::build_lua_tool(&cfg);
+        let tool = App::build_lua_tool(false);
         assert_eq!(tool.name, LLM_LUA_TOOL_NAME);
         assert!(
             tool.description.contains("read-only inspection"),
@@ -1351,8 +3297,7 @@ mod tests {
         );
         assert_eq!(tool.parameters["required"], serde_json::json!(["source"]));
 
-        cfg.allow_tool_writes = true;
-        let tool = App::build_lua_tool(&cfg);
+        let tool = App::build_lua_tool(true);
         assert!(
             !tool.description.contains("read-only inspection"),
             "write-enabled configs should not append read-only warning"
@@ -1363,6 +3308,32 @@ mod tests {
     fn parse_lua_command_handles_whitespace() {
         assert_eq!(parse_lua_command("   /lua   return 1"), Some(LuaAction::Run("return 1")));
         assert_eq!(parse_lua_command("/lua"), Some(LuaAction::Run("")));
+        assert_eq!(
+            parse_lua_command("/lua pretty return {1}"),
+            Some(LuaAction::Pretty("return {1}"))
+        );
+        assert_eq!(
+            parse_lua_command("/lua pretty"),
+            Some(LuaAction::Pretty(""))
+        );
+        assert_eq!(
+            parse_lua_command("/lua pretty_print(x)"),
+            Some(LuaAction::Run("pretty_print(x)"))
+        );
+        assert_eq!(parse_lua_command("/lua vars "), Some(LuaAction::Vars));
+        assert_eq!(
+            parse_lua_command("/lua clear  x "),
+            Some(LuaAction::Clear("x"))
+        );
+        assert_eq!(parse_lua_command("/lua clear"), Some(LuaAction::Clear("")));
+        assert_eq!(
+            parse_lua_command("/lua clearAll()"),
+            Some(LuaAction::Run("clearAll()"))
+        );
+        assert_eq!(
+            parse_lua_command("/lua variables"),
+            Some(LuaAction::Run("variables"))
+        );
         assert_eq!(parse_lua_command("lua return 1"), None);
     }
 
@@ -1407,6 +3378,291 @@ mod tests {
         assert!(input.cursor_display_offset() > 0);
     }
 
+    #[test]
+    fn tab_completes_and_cycles_slash_commands() {
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.state.focus = FocusTarget::Input;
+        let tab = KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE);
+        app.state.input.insert_str("/re");
+        let mut seen = Vec::new();
+        for _ in 0..6 {
+            app.handle_key_event(tab);
+            seen.push(app.state.input.buffer());
+        }
+        assert_eq!(
+            seen,
+            [
+                "/review",
+                "/readonly",
+                "/regenerate",
+                "/retry",
+                "/reset-tour",
+                "/review"
+            ]
+        );
+        assert_eq!(app.state.focus, FocusTarget::Input);
+
+        app.state.input.set("/he");
+        app.handle_key_event(tab);
+        assert_eq!(app.state.input.buffer(), "/help");
+        app.handle_key_event(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        let help = &app.state.messages.last().unwrap().content;
+        for command in SLASH_COMMANDS {
+            assert!(help.contains(command.usage), "{} missing", command.name);
+        }
+        assert!(help.contains("(alias `/health`)"), "{help}");
+
+        app.state.input.set("/xyz");
+        app.handle_key_event(tab);
+        assert_eq!(app.state.input.buffer(), "/xyz");
+        app.state.input.set("/lua re");
+        app.handle_key_event(tab);
+        assert_eq!(app.state.input.buffer(), "/lua re");
+        assert_eq!(
+            app.state.focus,
+            FocusTarget::Chat,
+            "Tab moves focus after a space"
+        );
+    }
+
+    #[test]
+    fn cursor_position_tracks_lines() {
+        let mut input = InputState::default();
+        input.insert_str("local x = 1\nreturn 好x");
+        assert_eq!(input.line_count(), 2);
+        assert_eq!(input.cursor_line(), 1);
+        assert_eq!(input.cursor_display_offset(), 10);
+        input.move_to_start();
+        assert_eq!((input.cursor_line(), input.cursor_display_offset()), (0, 0));
+    }
+
+    #[test]
+    fn shift_or_alt_enter_inserts_newline_and_enter_submits() {
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.state.focus = FocusTarget::Input;
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        app.state.input.insert_str("/lua local x = 1");
+        app.handle_key_event(key(KeyCode::Enter, KeyModifiers::SHIFT));
+        app.state.input.insert_str("x = x + 1");
+        app.handle_key_event(key(KeyCode::Enter, KeyModifiers::ALT));
+        app.state.input.insert_str("return x");
+        assert_eq!(app.state.input.line_count(), 3);
+
+        app.handle_key_event(key(KeyCode::Enter, KeyModifiers::NONE));
+        assert!(app.state.input.is_empty());
+        let script = "/lua local x = 1\nx = x + 1\nreturn x";
+        assert!(
+            app.state
+                .messages
+                .iter()
+                .any(|m| m.role == Role::User && m.content == script)
+        );
+    }
+
+    #[test]
+    fn up_and_down_move_between_lines_before_recalling_history() {
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.state.focus = FocusTarget::Input;
+        let up = KeyEvent::new(KeyCode::Up, KeyModifiers::NONE);
+        let down = KeyEvent::new(KeyCode::Down, KeyModifiers::NONE);
+        app.state.input.push_history("older");
+        app.state.input.insert_str("first line\nxy\nthird");
+        let position = |app: &App| {
+            let input = &app.state.input;
+            (input.cursor_line(), input.cursor_display_offset())
+        };
+
+        app.handle_key_event(up);
+        assert_eq!(position(&app), (1, 2), "clamped to the shorter line");
+        app.handle_key_event(up);
+        assert_eq!(position(&app), (0, 2));
+        app.handle_key_event(down);
+        app.handle_key_event(down);
+        assert_eq!(position(&app), (2, 2));
+        app.handle_key_event(down);
+        assert_eq!(app.state.input.buffer(), "first line\nxy\nthird");
+
+        app.handle_key_event(up);
+        app.handle_key_event(up);
+        app.handle_key_event(up);
+        assert_eq!(
+            app.state.input.buffer(),
+            "older",
+            "recalls from the first line"
+        );
+        app.handle_key_event(down);
+        assert_eq!(app.state.input.buffer(), "first line\nxy\nthird");
+    }
+
+    #[test]
+    fn delete_word_back_handles_multibyte_and_whitespace() {
+        let mut input = InputState::default();
+        input.set("héllo  wörld 你好 ");
+        input.delete_word_back();
+        assert_eq!(input.buffer(), "héllo  wörld ");
+        input.delete_word_back();
+        assert_eq!(input.buffer(), "héllo  ");
+        input.delete_word_back();
+        assert_eq!(input.buffer(), "");
+        input.delete_word_back();
+        assert_eq!(input.buffer(), "");
+
+        input.set("one two three");
+        input.move_word_left();
+        input.move_word_left();
+        input.delete_word_back();
+        assert_eq!(input.buffer(), "two three");
+        input.move_word_right();
+        input.insert_char('!');
+        assert_eq!(input.buffer(), "two! three");
+        input.kill_to_end();
+        assert_eq!(input.buffer(), "two!");
+        input.move_word_left();
+        input.delete_word_back();
+        assert_eq!(input.buffer(), "two!", "nothing before the first word");
+    }
+
+    #[test]
+    fn yank_restores_killed_text_at_cursor() {
+        let mut input = InputState::default();
+        input.set("héllo wörld");
+        input.move_word_left();
+        input.kill_to_end();
+        assert_eq!(input.buffer(), "héllo ");
+        input.move_to_start();
+        input.yank();
+        assert_eq!(input.buffer(), "wörldhéllo ");
+        input.insert_char(' ');
+        assert_eq!(input.buffer(), "wörld héllo ");
+
+        input.move_to_end();
+        input.delete_word_back();
+        assert_eq!(input.buffer(), "wörld ");
+        input.move_to_start();
+        input.yank();
+        assert_eq!(input.buffer(), "héllo wörld ");
+
+        input.move_to_end();
+        input.kill_to_end();
+        input.yank();
+        assert_eq!(
+            input.buffer(),
+            "héllo wörld héllo ",
+            "empty kill keeps the ring"
+        );
+    }
+
+    #[test]
+    fn token_estimate_follows_every_message_change() {
+        fn recounted(state: &AppState) -> usize {
+            let chars: usize = state
+                .messages
+                .iter()
+                .map(|m| m.content.chars().count())
+                .sum();
+            chars.div_ceil(4)
+        }
+
+        let mut state = AppState::default();
+        state.push_message(Message::new(Role::User, "h\u{e9}llo there"));
+        state.push_message(Message::new(Role::Assistant, "partial"));
+        let steps: [fn(&mut AppState); 5] = [
+            |state| state.append_to_message(2, " reply \u{2713}"),
+            |state| state.set_message_content(1, "/think shorter"),
+            |state| state.remove_message(0),
+            |state| state.truncate_messages(1),
+            |state| state.replace_messages(vec![Message::new(Role::User, "x".repeat(9))]),
+        ];
+        for step in steps {
+            step(&mut state);
+            assert_eq!(state.estimated_tokens(), recounted(&state));
+        }
+        assert_eq!(state.estimated_tokens(), 3);
+    }
+
+    #[test]
+    fn input_history_cycles_entries_and_restores_draft() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("selenai/history");
+        let mut input = InputState::default();
+        input.load_history(path.clone(), Redactor::default());
+        for entry in ["first", "second", "second", "  ", "multi\nline"] {
+            input.push_history(entry);
+        }
+
+        input.insert_str("dra");
+        input.history_next();
+        assert_eq!(
+            input.buffer(),
+            "dra",
+            "Down without browsing keeps the draft"
+        );
+        input.history_prev();
+        assert_eq!(input.buffer(), "multi\nline");
+        input.history_prev();
+        input.history_prev();
+        input.history_prev();
+        assert_eq!(input.buffer(), "first", "stops at the oldest entry");
+        input.history_next();
+        assert_eq!(input.buffer(), "second");
+        input.history_next();
+        input.history_next();
+        assert_eq!(input.buffer(), "dra");
+        input.insert_char('f');
+        assert_eq!(input.buffer(), "draf");
+
+        let mut reloaded = InputState::default();
+        reloaded.load_history(path, Redactor::default());
+        reloaded.history_prev();
+        assert_eq!(reloaded.buffer(), "multi\nline");
+        reloaded.history_prev();
+        reloaded.history_prev();
+        assert_eq!(reloaded.buffer(), "first");
+    }
+
+    #[test]
+    fn input_history_file_is_capped_when_saving() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("history");
+        let old: String = (0..INPUT_HISTORY_LIMIT + 3)
+            .map(|i| format!("\"old {i}\"\n"))
+            .collect();
+        fs::write(&path, old).unwrap();
+
+        let mut input = InputState::default();
+        input.load_history(path.clone(), Redactor::default());
+        input.push_history("new 1");
+        input.push_history("new 2");
+
+        let saved = fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = saved.lines().collect();
+        assert_eq!(lines.len(), INPUT_HISTORY_LIMIT);
+        assert_eq!(lines[0], "\"old 5\"");
+        assert_eq!(lines[INPUT_HISTORY_LIMIT - 1], "\"new 2\"");
+    }
+
+    #[test]
+    fn input_state_caps_typing_and_pastes_at_max_chars() {
+        let mut input = InputState::with_max_chars(Some(5));
+        assert_eq!(input.insert_str("abc"), 0);
+        assert_eq!(input.insert_str("defgh"), 3);
+        assert_eq!(input.buffer(), "abcde");
+        assert_eq!(input.dropped(), 3);
+        assert!(!input.insert_char('x'));
+        assert_eq!(input.buffer(), "abcde");
+
+        input.backspace();
+        assert_eq!(input.dropped(), 0);
+        input.move_to_start();
+        assert!(input.insert_char('你'));
+        assert_eq!(input.buffer(), "你abcd");
+        assert_eq!(input.char_count(), 5);
+
+        let mut unlimited = InputState::default();
+        assert_eq!(unlimited.insert_str(&"x".repeat(50_000)), 0);
+        assert_eq!(unlimited.char_count(), 50_000);
+    }
+
 
 
     #[test]
@@ -1421,11 +3677,23 @@ mod tests {
     }
 
     #[test]
-    fn push_message_with_index_tracks_position() {
+    fn message_ids_stay_stable_across_removals() {
         let mut state = AppState::default();
-        let idx = state.push_message_with_index(Message::new(Role::User, "next"));
-        assert_eq!(idx, 1, "default state starts with welcome message");
-        assert_eq!(state.messages[idx].content, "next");
+        let a = Message::new(Role::User, "a");
+        let b = Message::new(Role::Assistant, "b");
+        let (a_id, b_id) = (a.id, b.id);
+        state.push_message(a);
+        state.push_message(b);
+        assert_eq!(state.position_of(b_id), Some(2));
+
+        state.remove_message(0);
+        assert_eq!(state.position_of(a_id), Some(0));
+        assert_eq!(state.position_of(b_id), Some(1));
+        assert_eq!(state.messages[1].id, b_id);
+
+        state.remove_message(0);
+        assert_eq!(state.position_of(a_id), None);
+        assert_eq!(state.position_of(b_id), Some(0));
     }
 
     #[allow(clippy::field_reassign_with_default)]
@@ -1442,7 +3710,8 @@ mod tests {
     #[test]
     fn append_tool_call_appends_invocation() {
         let mut state = AppState::default();
-        let idx = state.push_message_with_index(Message::new(Role::Assistant, "running tool"));
+        state.push_message(Message::new(Role::Assistant, "running tool"));
+        let idx = state.messages.len() - 1;
         let invocation =
             ToolInvocation::from_parts("demo", serde_json::json!({"value": 1}), Some("abc".into()));
         state.append_tool_call(idx, invocation.clone());
@@ -1453,7 +3722,8 @@ mod tests {
     #[test]
     fn message_is_empty_checks_bounds() {
         let mut state = AppState::default();
-        let idx = state.push_message_with_index(Message::new(Role::Assistant, ""));
+        state.push_message(Message::new(Role::Assistant, ""));
+        let idx = state.messages.len() - 1;
         assert!(state.message_is_empty(idx));
         assert!(state.message_is_empty(usize::MAX));
     }
@@ -1470,30 +3740,32 @@ mod tests {
         assert!(message.content.contains("lua_run_script"));
     }
 
+    /// Abort handle for streams assembled by hand, with no task behind them.
+    fn idle_abort_handle() -> AbortHandle {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.spawn(async {}).abort_handle()
+    }
+
     #[test]
     fn stream_robustness_handles_partial_chunks() {
         let mut state = AppState::default();
-        let idx = state.push_message_with_index(Message::new(Role::Assistant, ""));
+        state.push_message(Message::new(Role::Assistant, ""));
+        let idx = state.messages.len() - 1;
+        let message_id = state.messages[idx].id;
         let (tx, rx) = mpsc::unbounded_channel();
         let (res_tx, res_rx) = std_mpsc::channel();
 
-        let mut app = App {
-            config: AppConfig::default(),
-            macros: MacroConfig::default(),
-            state,
-            llm: Arc::new(StubClient::new()),
-            runtime: Runtime::new().unwrap(),
-            lua: LuaExecutor::new(".", false).unwrap(),
-            session: SessionRecorder::new(tempdir().unwrap().path(), false).unwrap(),
-            should_quit: false,
-            next_tool_id: 0,
-            active_stream: Some(ActiveStream {
-                receiver: rx,
-                result_rx: res_rx,
-                message_index: idx,
-            }),
-            pending_lua_tools: Vec::new(),
-        };
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.state = state;
+        app.active_stream = Some(ActiveStream {
+            receiver: rx,
+            result_rx: res_rx,
+            message_id,
+            tool_previews: BTreeMap::new(),
+            abort: idle_abort_handle(),
+        });
 
         // Send chunks
         tx.send(StreamEvent::Delta("Hello".into())).unwrap();
@@ -1514,31 +3786,22 @@ mod tests {
     #[test]
     fn multi_tool_queuing_works() {
         let mut state = AppState::default();
-        let idx = state.push_message_with_index(Message::new(Role::Assistant, ""));
+        state.push_message(Message::new(Role::Assistant, ""));
+        let idx = state.messages.len() - 1;
+        let message_id = state.messages[idx].id;
         let (tx, rx) = mpsc::unbounded_channel();
         let (res_tx, res_rx) = std_mpsc::channel();
 
-        // Config with writes enabled to trigger queuing
-        let mut config = AppConfig::default();
-        config.allow_tool_writes = true;
-
-        let mut app = App {
-            config,
-            macros: MacroConfig::default(),
-            state,
-            llm: Arc::new(StubClient::new()),
-            runtime: Runtime::new().unwrap(),
-            lua: LuaExecutor::new(".", false).unwrap(),
-            session: SessionRecorder::new(tempdir().unwrap().path(), false).unwrap(),
-            should_quit: false,
-            next_tool_id: 0,
-            active_stream: Some(ActiveStream {
-                receiver: rx,
-                result_rx: res_rx,
-                message_index: idx,
-            }),
-            pending_lua_tools: Vec::new(),
-        };
+        // Writes enabled to trigger queuing
+        let (mut app, _workspace) = app_with_policy(true, ToolApproval::WritesOnly);
+        app.state = state;
+        app.active_stream = Some(ActiveStream {
+            receiver: rx,
+            result_rx: res_rx,
+            message_id,
+            tool_previews: BTreeMap::new(),
+            abort: idle_abort_handle(),
+        });
 
         // Simulate receiving two tool calls
         let call1 = ToolInvocation::from_parts("lua_run_script", serde_json::json!({"source": "print(1)"}), Some("id1".into()));
@@ -1557,4 +3820,893 @@ mod tests {
         // Check that tool log entries were created
         assert_eq!(app.state.tool_logs.len(), 2);
     }
+
+    #[test]
+    fn review_script_quotes_the_target() {
+        let target = r#"main" .. os.exit() .. "\"#;
+        let lua = mlua::Lua::new();
+        lua.load(
+            r#"
+            rust = {
+                git_status = function() return { stdout = "" } end,
+                git_diff = function(opts) return { stdout = opts.target } end,
+            }
+            "#,
+        )
+        .exec()
+        .unwrap();
+        let output: String = lua.load(review_script(target)).eval().unwrap();
+        assert_eq!(output, format!("Here is the diff for review:\n{target}"));
+    }
+
+    #[test]
+    fn parse_think_command_handles_modes_and_prompts() {
+        assert_eq!(parse_think_command("/think on"), Some(ThinkAction::Enable));
+        assert_eq!(
+            parse_think_command("/think off"),
+            Some(ThinkAction::Disable)
+        );
+        assert_eq!(
+            parse_think_command("/think  plan a refactor"),
+            Some(ThinkAction::Prompt("plan a refactor"))
+        );
+        assert_eq!(parse_think_command("/thinking"), None);
+    }
+
+    #[test]
+    fn notools_command_sets_tool_choice_none() {
+        assert_eq!(parse_notools_command("/notools"), Some(None));
+        assert_eq!(parse_notools_command("/notools on"), Some(Some(true)));
+        assert_eq!(parse_notools_command("/notoolsy"), None);
+
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        assert_eq!(app.build_chat_request().tool_choice, None);
+        app.state.input.set("/notools");
+        app.submit_current_input();
+        let request = app.build_chat_request();
+        assert_eq!(request.tool_choice, Some(ToolChoice::None));
+        assert_eq!(request.tools.len(), 1);
+
+        app.state.input.set("/notools off");
+        app.submit_current_input();
+        assert_eq!(app.build_chat_request().tool_choice, None);
+
+        app.config.tool_choice = Some("lua_run_script".into());
+        let forced = app.build_chat_request().tool_choice;
+        assert_eq!(forced, Some(ToolChoice::Tool("lua_run_script".into())));
+        assert_eq!(ToolChoice::from_setting("required"), ToolChoice::Required);
+        assert_eq!(ToolChoice::from_setting(" auto "), ToolChoice::Auto);
+    }
+
+    #[test]
+    fn plan_mode_shows_tool_calls_without_running_them() {
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.plan_mode = true;
+        app.suppress_tools = true;
+
+        let call = ToolInvocation::from_parts(
+            LLM_LUA_TOOL_NAME,
+            serde_json::json!({"source": "marker = 1; return marker"}),
+            Some("call_plan".into()),
+        );
+        app.handle_tool_call(call);
+
+        assert!(app.pending_lua_tools.is_empty());
+        assert_eq!(app.state.tool_logs.len(), 1);
+        assert!(app.state.tool_logs[0].detail.contains("marker = 1"));
+        assert_eq!(app.state.tool_logs[0].status, ToolStatus::Pending);
+        let last = app.state.messages.last().expect("tool result");
+        assert_eq!(last.tool_call_id.as_deref(), Some("call_plan"));
+        assert!(last.content.contains("suppressed (plan mode)"));
+        let output = app.lua.run_script("return marker").unwrap();
+        assert_eq!(output.value, "nil", "script must not have executed");
+    }
+
+    /// App whose workspace and session logs live in the returned directory,
+    /// which must outlive it.
+    fn app_with_policy(allow_tool_writes: bool, tool_approval: ToolApproval) -> (App, TempDir) {
+        let tmp = tempdir().unwrap();
+        let config = AppConfig {
+            allow_tool_writes,
+            tool_approval,
+            ..AppConfig::default()
+        };
+        let app = App {
+            config,
+            macros: MacroConfig::default(),
+            state: AppState::default(),
+            llm: Arc::new(StubClient::new()),
+            runtime: Runtime::new().unwrap(),
+            lua: LuaExecutor::new(tmp.path(), allow_tool_writes).unwrap(),
+            session: SessionRecorder::new(tmp.path().join("logs"), false).unwrap(),
+            should_quit: false,
+            next_tool_id: 0,
+            active_stream: None,
+            pending_lua_tools: Vec::new(),
+            plan_mode: false,
+            suppress_tools: false,
+            tools_disabled: false,
+            read_only_override: false,
+            modified_files: BTreeSet::new(),
+            session_titled: false,
+            lua_watch: None,
+        };
+        (app, tmp)
+    }
+
+    fn last_reply(app: &App) -> &str {
+        &app.state.messages.last().unwrap().content
+    }
+
+    #[test]
+    fn export_html_stays_in_the_workspace() {
+        let tmp = tempdir().unwrap();
+        let (mut app, _workspace) = app_with_policy(true, ToolApproval::WritesOnly);
+        app.lua = LuaExecutor::new(tmp.path(), true).unwrap();
+
+        app.handle_export_html("../escape.html");
+        assert!(last_reply(&app).starts_with("Cannot export to ../escape.html"));
+        assert!(!tmp.path().parent().unwrap().join("escape.html").exists());
+
+        app.lua = LuaExecutor::new(tmp.path(), false).unwrap();
+        app.handle_export_html("chat.html");
+        assert!(last_reply(&app).contains("write helpers are disabled"));
+        assert!(!tmp.path().join("chat.html").exists());
+    }
+
+    #[test]
+    fn export_html_overwrites_only_with_force() {
+        let tmp = tempdir().unwrap();
+        let target = tmp.path().join("chat.html");
+        fs::write(&target, "keep me").unwrap();
+        let (mut app, _workspace) = app_with_policy(true, ToolApproval::WritesOnly);
+        app.lua = LuaExecutor::new(tmp.path(), true).unwrap();
+
+        app.handle_export_html("chat.html");
+        assert!(
+            last_reply(&app).contains("already exists"),
+            "{}",
+            last_reply(&app)
+        );
+        assert_eq!(fs::read_to_string(&target).unwrap(), "keep me");
+
+        app.handle_export_html("chat.html --force");
+        assert!(last_reply(&app).starts_with("Exported session to"));
+        assert!(fs::read_to_string(&target).unwrap().contains("<html"));
+    }
+
+    #[test]
+    fn plain_mode_is_used_without_a_terminal() {
+        assert_eq!(plain_mode_reason(false, true, true), None);
+        assert_eq!(
+            plain_mode_reason(false, true, false),
+            Some("stdout is not a terminal")
+        );
+        assert_eq!(
+            plain_mode_reason(false, false, true),
+            Some("stdin is not a terminal")
+        );
+        assert_eq!(
+            plain_mode_reason(true, true, true),
+            Some("--plain was given")
+        );
+    }
+
+    #[test]
+    fn plain_mode_prints_replies_for_each_input_line() {
+        let tmp = tempdir().unwrap();
+        let history = tmp.path().join("history");
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.state
+            .input
+            .load_history(history.clone(), Redactor::default());
+        let input = io::Cursor::new("hello there\n\n/lua return 1 + 2\n");
+        let mut out = Vec::new();
+        app.run_plain(input, &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "Assistant: Stub agent turn 1 heard: \"hello there\"\n\nTool: Lua value:\n3\n\n"
+        );
+        assert!(app.active_stream.is_none());
+        assert!(
+            !history.exists(),
+            "piped input stays out of the saved history"
+        );
+    }
+
+    #[test]
+    fn tool_approval_policy_decides_queue_or_run() {
+        let cases = [
+            (false, ToolApproval::Auto, false),
+            (true, ToolApproval::Auto, false),
+            (false, ToolApproval::WritesOnly, false),
+            (true, ToolApproval::WritesOnly, true),
+            (false, ToolApproval::Always, true),
+            (true, ToolApproval::Always, true),
+        ];
+        for (allow_writes, policy, queued) in cases {
+            let (mut app, _workspace) = app_with_policy(allow_writes, policy);
+            app.handle_tool_call(ToolInvocation::from_parts(
+                LLM_LUA_TOOL_NAME,
+                serde_json::json!({"source": "return 1"}),
+                Some("call_policy".into()),
+            ));
+            assert_eq!(
+                app.pending_lua_tools.len(),
+                usize::from(queued),
+                "policy {policy:?} with writes={allow_writes}"
+            );
+            let status = app.state.tool_logs[0].status;
+            if queued {
+                assert_eq!(status, ToolStatus::Pending);
+            } else {
+                assert_eq!(status, ToolStatus::Success);
+            }
+        }
+    }
+
+    #[test]
+    fn paste_submits_only_single_line_read_only_commands() {
+        assert_eq!(paste_submit_command("/config show\n"), Some("/config show"));
+        assert_eq!(
+            paste_submit_command("/review src/app.rs"),
+            Some("/review src/app.rs")
+        );
+        assert_eq!(
+            paste_submit_command("/config set allow_tool_writes true"),
+            None
+        );
+        assert_eq!(paste_submit_command("/lua rust.delete_file(\"x\")"), None);
+        assert_eq!(paste_submit_command("/config show\n/readonly off"), None);
+        assert_eq!(paste_submit_command("config show"), None);
+
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.handle_paste("/config show\n");
+        assert_eq!(app.state.input.buffer(), "/config show\n", "off by default");
+
+        app.config.paste_submit_commands = true;
+        app.state.input.clear();
+        app.handle_paste("/config show\n");
+        assert!(app.state.input.is_empty());
+        let reply = &app.state.messages.last().unwrap().content;
+        assert!(reply.starts_with("Current Config:"), "{reply}");
+
+        let before = app.state.messages.len();
+        app.handle_paste("/readonly on");
+        assert_eq!(app.state.input.buffer(), "/readonly on");
+        assert_eq!(app.state.messages.len(), before);
+    }
+
+    #[test]
+    fn app_state_new_applies_config_to_startup() {
+        let plain = AppState::new(&AppConfig::default());
+        assert!(!plain.copy_mode);
+        assert_eq!(plain.focus, FocusTarget::Input);
+        assert!(!plain.messages[0].content.contains("Copy-friendly"));
+        assert!(plain.messages[1].content.contains("read-only mode"));
+
+        let config = AppConfig {
+            copy_mode_default: true,
+            allow_tool_writes: true,
+            ..AppConfig::default()
+        };
+        let state = AppState::new(&config);
+        assert!(state.copy_mode);
+        assert!(state.writes_enabled);
+        assert_eq!(state.messages.len(), 1);
+        assert!(
+            state.messages[0]
+                .content
+                .contains("Copy-friendly mode is on")
+        );
+    }
+
+    #[test]
+    fn config_set_updates_sampling_live() {
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.config.openai.temperature = Some(0.9);
+        app.config.provider = ProviderKind::OpenAi;
+
+        app.handle_config_command("set", Some("temperature"), Some("0.2"));
+        assert_eq!(app.config.sampling().temperature, Some(0.2));
+        app.handle_config_command("set", Some("max_tokens"), Some("256"));
+        assert_eq!(app.config.sampling().max_tokens, Some(256));
+
+        app.handle_config_command("set", Some("top_p"), Some("1.5"));
+        let reply = &app.state.messages.last().unwrap().content;
+        assert!(reply.contains("from 0 to 1"), "{reply}");
+        assert_eq!(app.config.sampling().top_p, None);
+
+        app.handle_config_command("set", Some("temperature"), Some("default"));
+        assert_eq!(app.config.sampling().temperature, None);
+    }
+
+    #[test]
+    fn readonly_override_disables_write_helpers() {
+        let (mut app, _workspace) = app_with_policy(true, ToolApproval::WritesOnly);
+        assert!(App::build_system_prompt(app.writes_enabled()).contains("ENABLED"));
+
+        assert_eq!(parse_readonly_command("/readonly on"), Some(Some(true)));
+        app.handle_readonly_command(Some(true));
+        assert!(!app.writes_enabled());
+        assert!(!app.state.writes_enabled);
+        assert!(App::build_system_prompt(app.writes_enabled()).contains("READ-ONLY"));
+        let err = app
+            .lua
+            .run_script(r#"rust.write_file("blocked.txt", "x")"#)
+            .unwrap_err();
+        assert!(err.to_string().contains("write helpers are disabled"));
+
+        app.handle_readonly_command(Some(false));
+        assert!(app.writes_enabled());
+        assert!(app.state.writes_enabled);
+    }
+
+    #[test]
+    fn parse_sessions_command_handles_list_and_open() {
+        assert_eq!(
+            parse_sessions_command("/sessions"),
+            Some(SessionsAction::List)
+        );
+        assert_eq!(
+            parse_sessions_command("/sessions open 2"),
+            Some(SessionsAction::Open("2"))
+        );
+        assert_eq!(
+            parse_sessions_command("/sessions open"),
+            Some(SessionsAction::List)
+        );
+        assert!(parse_sessions_command("/sessionsx").is_none());
+        assert_eq!(render_session_list(&[]), "No past sessions found.");
+    }
+
+    #[test]
+    fn first_run_tour_writes_marker_and_honors_show_tour() {
+        let tmp = tempdir().unwrap();
+        let marker = tmp.path().join("config").join(TOUR_MARKER_NAME);
+        let tours = |app: &App| {
+            let tour = welcome_tour(app.state.glyphs);
+            app.state
+                .messages
+                .iter()
+                .filter(|m| m.content == tour)
+                .count()
+        };
+
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.check_first_run(&marker);
+        assert_eq!(tours(&app), 1);
+        assert!(marker.exists(), "marker created with its directory");
+        app.check_first_run(&marker);
+        assert_eq!(tours(&app), 1, "seen once");
+
+        app.config.show_tour = TourSetting::Always;
+        app.check_first_run(&marker);
+        assert_eq!(tours(&app), 2);
+
+        fs::remove_file(&marker).unwrap();
+        app.config.show_tour = TourSetting::Never;
+        app.check_first_run(&marker);
+        assert_eq!(tours(&app), 2);
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn reset_tour_command_replays_the_tour() {
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.state.input.insert_str("/reset-tour");
+        app.submit_current_input();
+
+        let last = app.state.messages.last().expect("tour message");
+        assert_eq!(last.role, Role::Assistant);
+        assert_eq!(last.content, welcome_tour(app.state.glyphs));
+        assert!(help_text().contains("/reset-tour"));
+    }
+
+    #[test]
+    fn ascii_mode_welcome_has_no_unicode() {
+        let ascii = Glyphs { unicode: false };
+        assert!(welcome_tour(ascii).is_ascii());
+        assert!(welcome_tour(Glyphs::default()).starts_with("👋 "));
+
+        let state = AppState::with_glyphs(ascii);
+        assert!(state.messages.iter().all(|m| m.content.is_ascii()));
+        assert_eq!(state.tool_icons, ToolIcons::ascii());
+    }
+
+    #[test]
+    fn ping_reports_stub_provider_health() {
+        assert!(is_ping_command("/ping"));
+        assert!(is_ping_command(" /health "));
+        assert!(!is_ping_command("/pingu"));
+
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.handle_ping_command();
+        let reply = &app.state.messages.last().expect("reply").content;
+        assert!(
+            reply.starts_with("Provider `stub` is reachable ("),
+            "{reply}"
+        );
+        assert!(reply.contains(&app.config.model_id), "{reply}");
+    }
+
+    #[test]
+    fn models_command_marks_configured_model() {
+        let models = vec!["gpt-4o".to_string(), "gpt-4o-mini".to_string()];
+        let rendered = render_model_list(&models, "gpt-4o-mini");
+        assert!(rendered.contains("  gpt-4o\n"));
+        assert!(rendered.contains("* gpt-4o-mini\n"));
+
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.handle_models_command();
+        let reply = &app.state.messages.last().expect("reply").content;
+        assert!(reply.contains("does not list models"), "{reply}");
+    }
+
+    #[test]
+    fn removing_earlier_message_mid_stream_keeps_deltas_on_placeholder() {
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.state
+            .push_message(Message::new(Role::Assistant, "LLM error: earlier turn"));
+        let placeholder = Message::new(Role::Assistant, "");
+        let message_id = placeholder.id;
+        app.state.push_message(placeholder);
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (res_tx, res_rx) = std_mpsc::channel();
+        app.active_stream = Some(ActiveStream {
+            receiver: rx,
+            result_rx: res_rx,
+            message_id,
+            tool_previews: BTreeMap::new(),
+            abort: idle_abort_handle(),
+        });
+
+        tx.send(StreamEvent::Delta("Hello".into())).unwrap();
+        app.poll_active_stream();
+        app.state.remove_message(1);
+        tx.send(StreamEvent::Delta(" World".into())).unwrap();
+        app.poll_active_stream();
+
+        let index = app.state.position_of(message_id).expect("placeholder");
+        assert_eq!(app.state.messages[index].content, "Hello World");
+        assert!(
+            app.state
+                .messages
+                .iter()
+                .all(|m| m.id == message_id || !m.content.contains("World"))
+        );
+
+        drop(tx);
+        res_tx.send(Ok(())).unwrap();
+        app.poll_active_stream();
+        assert!(app.active_stream.is_none());
+        assert_eq!(app.state.messages.last().unwrap().content, "Hello World");
+    }
+
+    #[test]
+    fn streaming_tool_call_arguments_preview_in_tool_log() {
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (_res_tx, res_rx) = std_mpsc::channel();
+        app.active_stream = Some(ActiveStream {
+            receiver: rx,
+            result_rx: res_rx,
+            message_id: 0,
+            tool_previews: BTreeMap::new(),
+            abort: idle_abort_handle(),
+        });
+
+        let progress = |chars, source: Option<&str>| {
+            StreamEvent::ToolCallProgress(ToolCallProgress {
+                index: 0,
+                name: Some(LLM_LUA_TOOL_NAME.into()),
+                received_chars: chars,
+                source: source.map(str::to_string),
+            })
+        };
+        tx.send(progress(5, None)).unwrap();
+        tx.send(progress(24, Some("return 1 +"))).unwrap();
+        app.poll_active_stream();
+        assert_eq!(app.state.tool_logs.len(), 1);
+        let preview = &app.state.tool_logs[0];
+        assert_eq!(preview.title, "Receiving lua_run_script... (24 chars)");
+        assert_eq!(preview.detail, "return 1 +");
+
+        let call = ToolInvocation::from_parts(
+            LLM_LUA_TOOL_NAME,
+            serde_json::json!({"source": "return 1 + 1"}),
+            Some("call-1".into()),
+        );
+        tx.send(StreamEvent::ToolCall(call)).unwrap();
+        app.poll_active_stream();
+        assert_eq!(app.state.tool_logs.len(), 1);
+        assert!(!app.state.tool_logs[0].title.starts_with("Receiving"));
+    }
+
+    /// Streams one delta, then waits forever; flags when its task is dropped.
+    struct HangingClient {
+        dropped: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    struct DropFlag(Arc<std::sync::atomic::AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl LlmClient for HangingClient {
+        async fn chat(&self, _: ChatRequest) -> Result<ChatResponse> {
+            unreachable!("tests run with streaming enabled")
+        }
+
+        async fn chat_stream(
+            &self,
+            _: ChatRequest,
+            sender: crate::llm::StreamEventSender,
+        ) -> Result<()> {
+            let _flag = DropFlag(Arc::clone(&self.dropped));
+            let _ = sender.send(StreamEvent::Delta("partial".into()));
+            std::future::pending::<()>().await;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn esc_cancels_active_stream_before_quitting() {
+        let dropped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.config.streaming = true;
+        app.llm = Arc::new(HangingClient {
+            dropped: Arc::clone(&dropped),
+        });
+        app.state
+            .push_message(Message::new(Role::User, "write forever"));
+        app.invoke_llm();
+
+        let started = Instant::now();
+        while !app
+            .state
+            .messages
+            .last()
+            .unwrap()
+            .content
+            .starts_with("partial")
+        {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "no delta arrived"
+            );
+            app.poll_active_stream();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let streamed = app.active_stream.as_ref().unwrap().message_id;
+        app.state.input.set("too soon");
+        app.submit_current_input();
+        assert!(last_reply(&app).starts_with("Hang on"));
+        assert!(
+            app.state.input.history.is_empty(),
+            "refused input is not recorded"
+        );
+        assert_eq!(app.state.input.buffer(), "too soon");
+        app.state.input.clear();
+
+        let esc = KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE);
+        app.handle_key_event(esc);
+        assert!(!app.should_quit);
+        assert!(app.active_stream.is_none());
+        let streamed = app
+            .state
+            .messages
+            .iter()
+            .find(|m| m.id == streamed)
+            .unwrap();
+        assert_eq!(streamed.content, "partial\n\n(canceled)");
+        while !dropped.load(std::sync::atomic::Ordering::SeqCst) {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "task not aborted"
+            );
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        app.handle_key_event(esc);
+        assert!(app.should_quit);
+    }
+
+    /// Replies "reply N" and records the latest user prompt of each call.
+    struct RecordingClient {
+        prompts: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl LlmClient for RecordingClient {
+        async fn chat(&self, request: ChatRequest) -> Result<ChatResponse> {
+            let mut prompts = self.prompts.lock().unwrap();
+            prompts.push(request.latest_user_prompt().unwrap_or_default().to_string());
+            Ok(ChatResponse::assistant_text(format!(
+                "reply {}",
+                prompts.len()
+            )))
+        }
+
+        async fn chat_stream(
+            &self,
+            _: ChatRequest,
+            _: crate::llm::StreamEventSender,
+        ) -> Result<()> {
+            unreachable!("tests run with streaming disabled")
+        }
+
+        fn supports_streaming(&self) -> bool {
+            false
+        }
+    }
+
+    /// Answers every prompt with a single `lua_run_script` call.
+    struct ToolCallingClient;
+
+    #[async_trait::async_trait]
+    impl LlmClient for ToolCallingClient {
+        async fn chat(&self, _: ChatRequest) -> Result<ChatResponse> {
+            Ok(ChatResponse::ToolCalls(vec![ToolInvocation::from_parts(
+                LLM_LUA_TOOL_NAME,
+                serde_json::json!({"source": "return 40 + 2"}),
+                Some("call_plain".into()),
+            )]))
+        }
+
+        async fn chat_stream(
+            &self,
+            _: ChatRequest,
+            _: crate::llm::StreamEventSender,
+        ) -> Result<()> {
+            unreachable!("tests run with streaming disabled")
+        }
+
+        fn supports_streaming(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn plain_mode_respects_tool_approval() {
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::Always);
+        app.llm = Arc::new(ToolCallingClient);
+        let mut out = Vec::new();
+        app.run_plain(io::Cursor::new("check the answer\n"), &mut out)
+            .unwrap();
+        let queued = String::from_utf8(out).unwrap();
+        assert!(queued.contains("queued for approval"), "{queued}");
+        assert!(!queued.contains("Tool: Lua value"), "{queued}");
+        assert_eq!(app.pending_lua_tools.len(), 1);
+
+        let mut out = Vec::new();
+        app.run_plain(io::Cursor::new("/tool run\n"), &mut out)
+            .unwrap();
+        let approved = String::from_utf8(out).unwrap();
+        assert!(approved.contains("Tool: Lua value:\n42"), "{approved}");
+        assert!(app.pending_lua_tools.is_empty());
+
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::Auto);
+        app.llm = Arc::new(ToolCallingClient);
+        let mut out = Vec::new();
+        app.run_plain(io::Cursor::new("check the answer\n"), &mut out)
+            .unwrap();
+        let ran = String::from_utf8(out).unwrap();
+        assert!(ran.contains("executing immediately"), "{ran}");
+        assert!(ran.contains("Tool: Lua value:\n42"), "{ran}");
+        assert!(app.pending_lua_tools.is_empty());
+    }
+
+    fn app_with_recording_client() -> (App, Arc<RecordingClient>, TempDir) {
+        let client = Arc::new(RecordingClient {
+            prompts: std::sync::Mutex::new(Vec::new()),
+        });
+        let (mut app, workspace) = app_with_policy(false, ToolApproval::WritesOnly);
+        app.llm = client.clone();
+        (app, client, workspace)
+    }
+
+    #[test]
+    fn regenerate_replaces_last_response_with_fresh_call() {
+        let (mut app, client, _workspace) = app_with_recording_client();
+        app.state
+            .push_message(Message::new(Role::User, "tell me a joke"));
+        app.invoke_llm();
+        assert_eq!(app.state.messages.last().unwrap().content, "reply 1");
+
+        app.state
+            .push_message(Message::new(Role::User, "/regenerate"));
+        app.handle_regenerate_command();
+
+        let prompts = client.prompts.lock().unwrap().clone();
+        assert_eq!(prompts, vec!["tell me a joke", "tell me a joke"]);
+        let tail: Vec<_> = app.state.messages[app.state.messages.len() - 2..]
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect();
+        assert_eq!(tail, vec!["tell me a joke", "reply 2"]);
+        assert!(!app.state.messages.iter().any(|m| m.content == "reply 1"));
+    }
+
+    #[test]
+    fn regenerate_skips_slash_commands_after_the_prompt() {
+        let (mut app, client, _workspace) = app_with_recording_client();
+        app.state
+            .push_message(Message::new(Role::User, "tell me a joke"));
+        app.invoke_llm();
+        for line in ["/help", "/regenerate"] {
+            app.state.input.set(line);
+            app.submit_current_input();
+        }
+
+        let prompts = client.prompts.lock().unwrap().clone();
+        assert_eq!(prompts, vec!["tell me a joke", "tell me a joke"]);
+        let contents: Vec<_> = app
+            .state
+            .messages
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect();
+        assert!(!contents.contains(&"/help"), "{contents:?}");
+        assert_eq!(
+            contents[contents.len() - 2..],
+            ["tell me a joke", "reply 2"]
+        );
+    }
+
+    #[test]
+    fn regenerate_without_response_reports_nothing_to_do() {
+        let (mut app, client, _workspace) = app_with_recording_client();
+        app.state
+            .push_message(Message::new(Role::User, "pending prompt"));
+        app.state.push_message(Message::new(Role::User, "/retry"));
+        app.handle_regenerate_command();
+
+        assert!(client.prompts.lock().unwrap().is_empty());
+        let last = app.state.messages.last().unwrap();
+        assert!(last.content.starts_with("Nothing to regenerate"));
+        assert!(!app.state.messages.iter().any(|m| m.content == "/retry"));
+    }
+
+    #[test]
+    fn edit_truncates_history_before_last_prompt() {
+        let (mut app, _client, _workspace) = app_with_recording_client();
+        app.state.push_message(Message::new(Role::User, "first"));
+        app.invoke_llm();
+        app.state
+            .push_message(Message::new(Role::User, "secnd tyop"));
+        app.invoke_llm();
+        let kept = app.state.messages.len() - 2;
+
+        app.state.push_message(Message::new(Role::User, "/edit"));
+        app.handle_edit_command();
+
+        assert_eq!(app.state.messages.len(), kept);
+        assert_eq!(app.state.messages.last().unwrap().content, "reply 1");
+        assert_eq!(app.state.input.buffer(), "secnd tyop");
+        app.state.input.insert_char('!');
+        assert_eq!(app.state.input.buffer(), "secnd tyop!");
+    }
+
+    #[test]
+    fn edit_skips_slash_commands_after_the_prompt() {
+        let (mut app, _client, _workspace) = app_with_recording_client();
+        app.state
+            .push_message(Message::new(Role::User, "secnd tyop"));
+        app.invoke_llm();
+        let kept = app.state.messages.len() - 2;
+        for line in ["/help", "/edit"] {
+            app.state.input.set(line);
+            app.submit_current_input();
+        }
+
+        assert_eq!(app.state.input.buffer(), "secnd tyop");
+        assert_eq!(app.state.messages.len(), kept);
+        assert!(!app.state.messages.iter().any(|m| m.content == "secnd tyop"));
+    }
+
+    #[test]
+    fn returned_table_renders_as_aligned_rows() {
+        let tmp = tempdir().unwrap();
+        let lua = LuaExecutor::new(tmp.path(), false).unwrap();
+        let output = lua
+            .run_script(r#"return { name = "selenai", version = 3, ok = true }"#)
+            .unwrap();
+
+        let auto = render_lua_execution(&output, ToolOutputFormat::Auto, &Redactor::default());
+        assert!(
+            auto.starts_with("Lua value:\nname     selenai\nok       true\nversion  3\n"),
+            "{auto}"
+        );
+
+        let json = render_lua_execution(&output, ToolOutputFormat::Json, &Redactor::default());
+        assert!(json.contains("  \"name\": \"selenai\","), "{json}");
+
+        let raw = render_lua_execution(&output, ToolOutputFormat::Raw, &Redactor::default());
+        assert_eq!(raw, format!("Lua value:\n{}\n", output.value));
+
+        let nested = lua.run_script(r#"return { files = { "a.rs" } }"#).unwrap();
+        let auto = render_lua_execution(&nested, ToolOutputFormat::Auto, &Redactor::default());
+        assert!(auto.contains("\"files\": ["), "{auto}");
+
+        let list = lua.run_script(r#"return { "x", "y" }"#).unwrap();
+        let auto = render_lua_execution(&list, ToolOutputFormat::Auto, &Redactor::default());
+        assert!(auto.starts_with("Lua value:\n1  x\n2  y\n"), "{auto}");
+    }
+
+    #[test]
+    fn tool_output_secrets_are_redacted_before_display_and_history() {
+        let (mut app, _workspace) = app_with_policy(false, ToolApproval::Auto);
+        let key = "sk-abcdefghijklmnopqrstuvwxyz123456";
+        let script = format!(r#"print("OPENAI_API_KEY={key}")"#);
+        let entry_id = app.create_tool_log_entry("lua", script.clone());
+        app.execute_lua_entry(entry_id, &script, Some("call-1".into()));
+
+        let message = app.state.messages.last().unwrap();
+        assert!(
+            message.content.contains("OPENAI_API_KEY=[REDACTED]"),
+            "{}",
+            message.content
+        );
+        assert!(!message.content.contains(key));
+        assert!(
+            message
+                .content
+                .contains("secrets were detected and redacted")
+        );
+        let entry = app.state.tool_logs.last().unwrap();
+        assert!(!entry.detail.contains(key), "{}", entry.detail);
+    }
+
+    #[test]
+    fn watch_lua_reruns_after_debounced_change() {
+        let dir = tempdir().unwrap();
+        let script = dir.path().join("tool.lua");
+        fs::write(&script, "return 1 + 1").unwrap();
+        let (mut app, _workspace) = app_with_policy(true, ToolApproval::Auto);
+        assert_eq!(parse_watch_lua_command("/watch-lua off"), Some("off"));
+        assert!(parse_watch_lua_command("/watch-luax").is_none());
+
+        let (tx, rx) = std_mpsc::channel();
+        app.install_lua_watch(LuaFileWatch::from_channel(&script, rx));
+        let entry_id = app.lua_watch.as_ref().unwrap().entry_id;
+        let entry = |app: &App| {
+            app.state
+                .tool_logs
+                .iter()
+                .find(|e| e.id == entry_id)
+                .cloned()
+                .unwrap()
+        };
+        assert_eq!(entry(&app).status, ToolStatus::Success);
+        assert!(entry(&app).detail.starts_with("Run #1\nLua value:\n2"));
+
+        fs::write(&script, "rust.write_file('x.txt', 'no')").unwrap();
+        tx.send(()).unwrap();
+        let now = Instant::now();
+        app.poll_lua_watch(now);
+        assert!(
+            entry(&app).detail.starts_with("Run #1"),
+            "debounce holds the rerun"
+        );
+        app.poll_lua_watch(now + crate::watch::DEBOUNCE);
+        let rerun = entry(&app);
+        assert_eq!(rerun.status, ToolStatus::Error);
+        assert!(
+            rerun.detail.starts_with("Run #2\nLua error:"),
+            "{}",
+            rerun.detail
+        );
+        assert!(rerun.detail.contains("write helpers are disabled"));
+
+        app.handle_watch_lua_command("off");
+        assert!(app.lua_watch.is_none());
+    }
 }
@@ -1,42 +1,194 @@
 use std::{
-    fs,
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write as _},
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use anyhow::{Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{
+    app::LLM_LUA_TOOL_NAME,
+    llm::Sampling,
+    types::{Glyphs, RolePrefixes, ToolIcons},
+};
 
 const DEFAULT_CONFIG_BASENAME: &str = "selenai.toml";
 const DEFAULT_MODEL_ID: &str = "gpt-4o-mini";
+const DEFAULT_MAX_INPUT_CHARS: usize = 20_000;
+const DEFAULT_SCRIPT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_COMMAND_TIMEOUT_SECS: u64 = 300;
+const SEARCH_IGNORE_BASENAME: &str = ".selenai-ignore";
+/// Commented config listing every option at its default, for `selenai init`.
+const STARTER_CONFIG: &str = include_str!("starter_config.toml");
+const STARTER_SEARCH_IGNORE: &str = "\
+# Paths `rust.search` skips in addition to .gitignore, using the same syntax.
+# Session transcripts and tool logs:
+.selenai/
+# Generated or vendored files that only add noise to searches, for example:
+# *.min.js
+# vendor/
+";
+/// Environment variables with this prefix override config fields.
+const ENV_OVERRIDE_PREFIX: &str = "SELENAI_";
+/// Upper bounds accepted for the sampling knobs, here and by `/config set`.
+pub const MAX_TEMPERATURE: f64 = 2.0;
+pub const MAX_TOP_P: f64 = 1.0;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct AppConfig {
     pub provider: ProviderKind,
     pub model_id: String,
     pub streaming: bool,
+    /// Sampling knobs sent to the provider; unset ones are left out.
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<u32>,
     pub allow_tool_writes: bool,
+    pub tool_approval: ToolApproval,
+    /// Sent as the request's tool choice: "auto", "none", "required", or a
+    /// tool name the model must call. Unset leaves it to the provider.
+    pub tool_choice: Option<String>,
+    pub tool_output_format: ToolOutputFormat,
+    /// Seconds a Lua script may run before it is aborted (0 = no limit).
+    pub script_timeout_secs: u64,
+    /// Seconds a `rust.run_command` child may run before it is killed (0 = no limit).
+    pub command_timeout_secs: u64,
+    pub write_scope: Option<PathBuf>,
+    pub lua_extensions_dir: Option<PathBuf>,
+    pub lua_prelude_file: Option<PathBuf>,
     pub log_dir: Option<PathBuf>,
+    pub session_dir_template: Option<String>,
+    /// Record the first prompt as the session title shown by `/sessions`.
+    pub session_titles: bool,
+    /// Use unicode glyphs in the UI; set false for ASCII-only terminals.
+    pub unicode: bool,
+    /// Start in copy-friendly mode (no panel borders); Ctrl+B still toggles it.
+    pub copy_mode_default: bool,
+    /// Show a dim "(2m ago)" next to each chat message header.
+    pub message_timestamps: bool,
+    /// Drop leading whitespace on wrapped chat rows (indentation is kept when false).
+    pub chat_wrap_trim: bool,
+    /// Break tokens wider than the chat pane (long URLs, hashes) into full rows.
+    pub chat_hard_wrap: bool,
+    /// Blank lines between chat messages (0 packs them together).
+    pub message_spacing: usize,
+    /// Render single-line chat messages on one row after their role prefix.
+    pub chat_compact: bool,
+    /// Show message/line counts in the chat title and entry counts in the tool title.
+    pub pane_stats: bool,
+    /// Longest prompt the input box accepts, in characters (0 = unlimited).
+    pub max_input_chars: usize,
+    /// Submit a pasted single-line read-only command (`/review`, `/config show`)
+    /// without waiting for Enter.
+    pub paste_submit_commands: bool,
+    /// When the welcome tour is shown; `SELENAI_SHOW_TOUR=1` / `=0` also work.
+    pub show_tour: TourSetting,
+    /// Refuse to start on a config that fails to load instead of falling back
+    /// to defaults. Only honored when the file is at least valid TOML.
+    pub strict_config: bool,
+    pub tool_icons: ToolIconsSection,
+    pub role_prefixes: RolePrefixesSection,
     pub openai: OpenAiSection,
+    pub anthropic: AnthropicSection,
+    pub ollama: OllamaSection,
+    pub security: SecuritySection,
 }
 
 impl AppConfig {
-    pub fn load() -> Result<Self> {
+    /// Loads and validates the config file, returning warnings for the chat.
+    /// `SELENAI_*` environment variables override the file (see
+    /// `env_override_path`). A config that fails to load yields the
+    /// defaults plus a warning, unless it sets `strict_config = true`.
+    pub fn load(workspace_root: &Path) -> Result<(Self, Vec<String>)> {
         let path = config_path_from_env();
-        Self::load_from_path(&path)
+        let overrides: Vec<(String, String)> = std::env::vars()
+            .filter(|(name, _)| name.starts_with(ENV_OVERRIDE_PREFIX))
+            .collect();
+        let (mut cfg, mut warnings) = Self::load_or_fallback(&path, &overrides)?;
+        warnings.extend(cfg.validate(workspace_root, &|name| {
+            std::env::var_os(name).is_some_and(|value| !value.is_empty())
+        }));
+        Ok((cfg, warnings))
     }
 
-    fn load_from_path(path: &Path) -> Result<Self> {
-        if !path.exists() {
-            return Ok(Self::default());
+    fn load_or_fallback(
+        path: &Path,
+        overrides: &[(String, String)],
+    ) -> Result<(Self, Vec<String>)> {
+        match Self::load_from_path(path, overrides) {
+            Ok(loaded) => Ok(loaded),
+            Err(err) if strict_config_requested(path, overrides) => Err(err),
+            Err(err) => {
+                let warning = format!(
+                    "{} could not be loaded, so SelenAI started with default settings. \
+                     Fix the file and restart (set `strict_config = true` to refuse to \
+                     start instead).\n\n```\n{}\n```",
+                    path.display(),
+                    format!("{:#}", err).trim_end()
+                );
+                Ok((Self::default(), vec![warning]))
+            }
         }
+    }
 
-        let data = fs::read_to_string(path)
-            .with_context(|| format!("failed to read config file {}", path.display()))?;
+    /// Parses the file, then layers the overrides one variable at a time. A
+    /// variable whose value does not fit is skipped with a warning (or fails
+    /// the load under `strict_config`), so it never costs the file's values.
+    fn load_from_path(path: &Path, overrides: &[(String, String)]) -> Result<(Self, Vec<String>)> {
+        let data = if path.exists() {
+            fs::read_to_string(path)
+                .with_context(|| format!("failed to read config file {}", path.display()))?
+        } else {
+            String::new()
+        };
+        // The file is parsed on its own first so its errors keep line numbers.
         let mut cfg: AppConfig = toml::from_str(&data)
             .with_context(|| format!("invalid config format in {}", path.display()))?;
+
+        let mut table: toml::Table = data.parse()?;
+        let mut warnings = Vec::new();
+        for (name, raw) in overrides {
+            let Some(field) = env_override_path(name) else {
+                continue;
+            };
+            // `SELENAI_OPENAI__USER=12345` parses as an integer; a string
+            // field still accepts it as the text it was written as.
+            let typed = parse_env_value(raw);
+            let retry = (!typed.is_str()).then(|| toml::Value::String(raw.clone()));
+            let mut first_err = None;
+            for value in std::iter::once(typed).chain(retry) {
+                let mut candidate = table.clone();
+                set_env_override(&mut candidate, &field, value);
+                match toml::Value::Table(candidate.clone()).try_into::<AppConfig>() {
+                    Ok(layered) => {
+                        cfg = layered;
+                        table = candidate;
+                        first_err = None;
+                        break;
+                    }
+                    Err(err) => {
+                        first_err.get_or_insert(err);
+                    }
+                }
+            }
+            if let Some(err) = first_err {
+                if strict_config_requested(path, overrides) {
+                    return Err(err)
+                        .with_context(|| format!("invalid environment override ({name})"));
+                }
+                warnings.push(format!(
+                    "Ignored invalid environment override {name}: {err}"
+                ));
+            }
+        }
         cfg.normalize();
-        Ok(cfg)
+        Ok((cfg, warnings))
     }
 
     fn normalize(&mut self) {
@@ -45,6 +197,199 @@ impl AppConfig {
         }
     }
 
+    /// Flags values that load fine but cannot work: a provider without its API
+    /// key, a `log_dir` that is a file, out-of-range sampling knobs and an
+    /// unknown `tool_choice` (dropped so the provider default applies), and
+    /// base URLs without a scheme.
+    /// `has_env` reports whether an environment variable is set.
+    fn validate(&mut self, workspace_root: &Path, has_env: &dyn Fn(&str) -> bool) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        let key_var = match self.provider {
+            ProviderKind::OpenAi => Some(("openai", "OPENAI_API_KEY")),
+            ProviderKind::Anthropic => Some(("anthropic", "ANTHROPIC_API_KEY")),
+            ProviderKind::Stub | ProviderKind::Ollama => None,
+        };
+        if let Some((provider, var)) = key_var
+            && !has_env(var)
+        {
+            warnings.push(format!(
+                "provider = \"{provider}\" but {var} is not set; export it or add it to .env."
+            ));
+        }
+
+        if self
+            .log_dir
+            .as_ref()
+            .is_some_and(|dir| dir.as_os_str().is_empty())
+        {
+            warnings.push("log_dir is empty; using the default .selenai/logs.".into());
+            self.log_dir = None;
+        }
+        let log_dir = self.resolve_log_dir(workspace_root);
+        if log_dir.exists() && !log_dir.is_dir() {
+            warnings.push(format!(
+                "log_dir {} is a file, so session logs cannot be saved there.",
+                log_dir.display()
+            ));
+        }
+
+        check_unit_range(
+            &mut warnings,
+            "temperature",
+            &mut self.temperature,
+            MAX_TEMPERATURE,
+        );
+        check_unit_range(&mut warnings, "top_p", &mut self.top_p, MAX_TOP_P);
+        check_positive(&mut warnings, "max_tokens", &mut self.max_tokens);
+        let openai = &mut self.openai;
+        check_unit_range(
+            &mut warnings,
+            "[openai] temperature",
+            &mut openai.temperature,
+            MAX_TEMPERATURE,
+        );
+        check_unit_range(
+            &mut warnings,
+            "[openai] top_p",
+            &mut openai.top_p,
+            MAX_TOP_P,
+        );
+        check_positive(&mut warnings, "[openai] max_tokens", &mut openai.max_tokens);
+        check_positive(
+            &mut warnings,
+            "[openai] max_attempts",
+            &mut openai.max_attempts,
+        );
+        check_positive(
+            &mut warnings,
+            "[anthropic] max_tokens",
+            &mut self.anthropic.max_tokens,
+        );
+
+        if let Some(choice) = &self.tool_choice
+            && !matches!(
+                choice.trim(),
+                "auto" | "none" | "required" | LLM_LUA_TOOL_NAME
+            )
+        {
+            warnings.push(format!(
+                "tool_choice \"{choice}\" is not auto, none, required, or a tool name \
+                 ({LLM_LUA_TOOL_NAME}); using the provider default."
+            ));
+            self.tool_choice = None;
+        }
+
+        let base_urls = [
+            ("[openai] base_url", &self.openai.base_url),
+            ("[anthropic] base_url", &self.anthropic.base_url),
+            ("[ollama] base_url", &self.ollama.base_url),
+        ];
+        for (name, url) in base_urls {
+            if let Some(url) = url
+                && !url.starts_with("http://")
+                && !url.starts_with("https://")
+            {
+                warnings.push(format!(
+                    "{name} \"{url}\" should start with http:// or https://."
+                ));
+            }
+        }
+
+        warnings
+    }
+
+    /// Renders the fully merged config as TOML for `--print-config`, headed by
+    /// comments naming the config file, workspace, and resolved log directory.
+    pub fn to_effective_toml(&self, config_path: &Path, workspace_root: &Path) -> Result<String> {
+        let file_state = if config_path.exists() {
+            "loaded"
+        } else {
+            "not found, defaults used"
+        };
+        let body = toml::to_string(self).context("failed to serialize config")?;
+        Ok(format!(
+            "# Effective SelenAI config (environment > file > defaults)\n\
+             # config file: {} ({file_state})\n\
+             # workspace: {}\n\
+             # log dir: {}\n\n{body}",
+            config_path.display(),
+            workspace_root.display(),
+            self.resolve_log_dir(workspace_root).display(),
+        ))
+    }
+
+    /// `script_timeout_secs` as a duration, treating 0 as "no limit".
+    pub fn script_timeout(&self) -> Option<Duration> {
+        (self.script_timeout_secs > 0).then(|| Duration::from_secs(self.script_timeout_secs))
+    }
+
+    /// `command_timeout_secs` as a duration, treating 0 as "no limit".
+    pub fn command_timeout(&self) -> Option<Duration> {
+        (self.command_timeout_secs > 0).then(|| Duration::from_secs(self.command_timeout_secs))
+    }
+
+    /// Sampling for chat requests; `[openai]` values win over the top-level
+    /// ones when the OpenAI provider is selected.
+    pub fn sampling(&self) -> Sampling {
+        let base = Sampling {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+        };
+        match self.provider {
+            ProviderKind::OpenAi => Sampling {
+                temperature: self.openai.temperature.or(base.temperature),
+                top_p: self.openai.top_p.or(base.top_p),
+                max_tokens: self.openai.max_tokens.or(base.max_tokens),
+            },
+            _ => base,
+        }
+    }
+
+    /// `max_input_chars` as an optional cap, treating 0 as "no limit".
+    pub fn input_char_limit(&self) -> Option<usize> {
+        (self.max_input_chars > 0).then_some(self.max_input_chars)
+    }
+
+    pub fn glyphs(&self) -> Glyphs {
+        Glyphs {
+            unicode: self.unicode,
+        }
+    }
+
+    /// Status glyphs for the tool log, honoring `unicode` and any overrides.
+    pub fn tool_icons(&self) -> ToolIcons {
+        let mut icons = self.glyphs().tool_icons();
+        let overrides = &self.tool_icons;
+        if let Some(icon) = &overrides.pending {
+            icons.pending = icon.clone();
+        }
+        if let Some(icon) = &overrides.success {
+            icons.success = icon.clone();
+        }
+        if let Some(icon) = &overrides.error {
+            icons.error = icon.clone();
+        }
+        icons
+    }
+
+    /// Chat message labels, with any `[role_prefixes]` overrides applied.
+    pub fn role_prefixes(&self) -> RolePrefixes {
+        let mut prefixes = RolePrefixes::default();
+        let overrides = &self.role_prefixes;
+        if let Some(prefix) = &overrides.user {
+            prefixes.user = prefix.clone();
+        }
+        if let Some(prefix) = &overrides.assistant {
+            prefixes.assistant = prefix.clone();
+        }
+        if let Some(prefix) = &overrides.tool {
+            prefixes.tool = prefix.clone();
+        }
+        prefixes
+    }
+
     pub fn resolve_log_dir(&self, workspace_root: &Path) -> PathBuf {
         let configured = self
             .log_dir
@@ -64,29 +409,423 @@ impl Default for AppConfig {
             provider: ProviderKind::default(),
             model_id: DEFAULT_MODEL_ID.to_string(),
             streaming: true,
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
             allow_tool_writes: false,
+            tool_approval: ToolApproval::default(),
+            tool_choice: None,
+            tool_output_format: ToolOutputFormat::default(),
+            script_timeout_secs: DEFAULT_SCRIPT_TIMEOUT_SECS,
+            command_timeout_secs: DEFAULT_COMMAND_TIMEOUT_SECS,
+            write_scope: None,
+            lua_extensions_dir: None,
+            lua_prelude_file: None,
             log_dir: None,
+            session_dir_template: None,
+            session_titles: true,
+            unicode: true,
+            copy_mode_default: false,
+            message_timestamps: false,
+            chat_wrap_trim: false,
+            chat_hard_wrap: true,
+            message_spacing: 1,
+            chat_compact: false,
+            pane_stats: false,
+            max_input_chars: DEFAULT_MAX_INPUT_CHARS,
+            paste_submit_commands: false,
+            show_tour: TourSetting::FirstRun,
+            strict_config: false,
+            tool_icons: ToolIconsSection::default(),
+            role_prefixes: RolePrefixesSection::default(),
             openai: OpenAiSection::default(),
+            anthropic: AnthropicSection::default(),
+            ollama: OllamaSection::default(),
+            security: SecuritySection::default(),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Default)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum ProviderKind {
     #[default]
     Stub,
     OpenAi,
+    Anthropic,
+    Ollama,
+}
+
+impl ProviderKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ProviderKind::Stub => "stub",
+            ProviderKind::OpenAi => "openai",
+            ProviderKind::Anthropic => "anthropic",
+            ProviderKind::Ollama => "ollama",
+        }
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+/// When LLM-requested tool runs must wait for `/tool run`.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolApproval {
+    /// Run every script immediately, even when writes are enabled.
+    Auto,
+    /// Queue scripts for approval only when writes are enabled.
+    #[default]
+    WritesOnly,
+    /// Queue every script, including read-only ones.
+    Always,
+}
+
+impl ToolApproval {
+    pub fn requires_approval(self, allow_tool_writes: bool) -> bool {
+        match self {
+            ToolApproval::Auto => false,
+            ToolApproval::WritesOnly => allow_tool_writes,
+            ToolApproval::Always => true,
+        }
+    }
+}
+
+/// When `check_first_run` shows the welcome tour. Besides the names, `true`
+/// / `1` mean `always` and `false` / `0` mean `never`, so
+/// `SELENAI_SHOW_TOUR=1` re-shows it for one launch.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", try_from = "TourSettingValue")]
+pub enum TourSetting {
+    /// Only while the marker file in `user_config_dir()` is missing.
+    #[default]
+    FirstRun,
+    Always,
+    Never,
+}
+
+impl TourSetting {
+    pub fn should_show(self, seen_before: bool) -> bool {
+        match self {
+            TourSetting::FirstRun => !seen_before,
+            TourSetting::Always => true,
+            TourSetting::Never => false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TourSettingValue {
+    Flag(bool),
+    Number(i64),
+    Name(String),
+}
+
+impl TryFrom<TourSettingValue> for TourSetting {
+    type Error = String;
+
+    fn try_from(value: TourSettingValue) -> Result<Self, String> {
+        match value {
+            TourSettingValue::Flag(true) | TourSettingValue::Number(1) => Ok(Self::Always),
+            TourSettingValue::Flag(false) | TourSettingValue::Number(0) => Ok(Self::Never),
+            TourSettingValue::Name(name) => match name.as_str() {
+                "first_run" => Ok(Self::FirstRun),
+                "always" => Ok(Self::Always),
+                "never" => Ok(Self::Never),
+                _ => Err(format!(
+                    "unknown show_tour value {name:?}; expected first_run, always, or never"
+                )),
+            },
+            TourSettingValue::Number(other) => Err(format!(
+                "show_tour = {other} is not allowed; use 1 (always) or 0 (never)"
+            )),
+        }
+    }
+}
+
+/// How a table returned from a Lua script is shown in tool results.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolOutputFormat {
+    /// Aligned key/value rows for flat tables, pretty JSON for nested ones.
+    #[default]
+    Auto,
+    /// Always pretty-printed JSON.
+    Json,
+    /// The `{k: v, ...}` one-liner.
+    Raw,
+    /// Lua-style table literal, one entry per line (same as `rust.pretty`).
+    Pretty,
+}
+
+/// Optional `[tool_icons]` overrides for the tool log status glyphs.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct ToolIconsSection {
+    pub pending: Option<String>,
+    pub success: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Optional `[role_prefixes]` overrides for the chat message labels.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct RolePrefixesSection {
+    pub user: Option<String>,
+    pub assistant: Option<String>,
+    pub tool: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct OpenAiSection {
     pub base_url: Option<String>,
     pub organization: Option<String>,
     pub project: Option<String>,
+    /// End-user id sent as the request's `user` field (else `SELENAI_USER`).
+    pub user: Option<String>,
+    /// Set false to have the model call one tool at a time (OpenAI default: true).
+    pub parallel_tool_calls: Option<bool>,
+    /// Characters of an API error body to keep (default 500).
+    pub error_body_limit: Option<usize>,
+    /// Extra headers for OpenAI-compatible gateways (`[openai.headers]`).
+    pub headers: ExtraHeaders,
+    /// Total tries for a chat request hitting 429/500/502/503 (default 3).
+    pub max_attempts: Option<u32>,
+    /// Delay before the first retry in milliseconds (default 500).
+    pub retry_base_delay_ms: Option<u64>,
+    /// Per-provider overrides of the top-level sampling knobs.
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<u32>,
+}
+
+/// `[anthropic]` settings; the API key always comes from `ANTHROPIC_API_KEY`.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct AnthropicSection {
+    pub base_url: Option<String>,
+    /// Largest response the model may write per turn (default 4096 tokens).
+    pub max_tokens: Option<u32>,
+    /// Characters of an API error body to keep (default 500).
+    pub error_body_limit: Option<usize>,
+}
+
+/// `[ollama]` settings for a local server; no API key is needed.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct OllamaSection {
+    pub base_url: Option<String>,
 }
 
-fn config_path_from_env() -> PathBuf {
+/// `[security]` policy shared by every redaction path.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct SecuritySection {
+    /// Extra regexes redacted as `[REDACTED]`, on top of the built-in API key
+    /// patterns.
+    pub secret_patterns: Vec<String>,
+}
+
+/// Header name -> value pairs added to every provider request. `Debug` and
+/// `Serialize` mask values of credential-like headers so `/config show` and
+/// `--print-config` do not leak them.
+#[derive(Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct ExtraHeaders(pub BTreeMap<String, String>);
+
+impl ExtraHeaders {
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    fn masked(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.iter().map(|(name, value)| {
+            let shown = if is_sensitive_header(name) {
+                "<redacted>"
+            } else {
+                value
+            };
+            (name, shown)
+        })
+    }
+}
+
+impl fmt::Debug for ExtraHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.masked()).finish()
+    }
+}
+
+impl Serialize for ExtraHeaders {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.masked())
+    }
+}
+
+/// Whether a header likely carries a credential.
+pub fn is_sensitive_header(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    ["auth", "key", "token", "secret", "cookie", "password"]
+        .iter()
+        .any(|marker| lower.contains(marker))
+}
+
+/// Drops a sampling value outside `0..=max` with a warning.
+fn check_unit_range(warnings: &mut Vec<String>, name: &str, value: &mut Option<f64>, max: f64) {
+    if let Some(number) = *value
+        && !(0.0..=max).contains(&number)
+    {
+        warnings.push(format!(
+            "{name} = {number} is outside 0 to {max}; using the provider default."
+        ));
+        *value = None;
+    }
+}
+
+/// Drops a zero count with a warning so the built-in default applies.
+fn check_positive(warnings: &mut Vec<String>, name: &str, value: &mut Option<u32>) {
+    if *value == Some(0) {
+        warnings.push(format!("{name} = 0 is not allowed; using the default."));
+        *value = None;
+    }
+}
+
+/// Reads `strict_config` straight from the TOML (or `SELENAI_STRICT_CONFIG`)
+/// so a config whose values fail to deserialize can still ask to abort. A
+/// syntax error in the file hides the flag.
+fn strict_config_requested(path: &Path, overrides: &[(String, String)]) -> bool {
+    let mut table = fs::read_to_string(path)
+        .ok()
+        .and_then(|data| data.parse::<toml::Table>().ok())
+        .unwrap_or_default();
+    apply_env_overrides(&mut table, overrides);
+    table
+        .get("strict_config")
+        .and_then(toml::Value::as_bool)
+        .unwrap_or(false)
+}
+
+/// Layers `SELENAI_*` variables over the parsed table without checking
+/// that the values fit their fields.
+fn apply_env_overrides(table: &mut toml::Table, overrides: &[(String, String)]) {
+    for (name, raw) in overrides {
+        if let Some(field) = env_override_path(name) {
+            set_env_override(table, &field, parse_env_value(raw));
+        }
+    }
+}
+
+/// Maps a `SELENAI_*` variable to the config key it overrides:
+/// `SELENAI_MODEL_ID` sets `model_id`, and a double underscore descends into a
+/// section, so `SELENAI_OPENAI__BASE_URL` sets `openai.base_url`. Variables
+/// that are not config fields (`SELENAI_CONFIG`, `SELENAI_TRACE`,
+/// `SELENAI_USER`, `SELENAI_DEBUG_*`) map to `None`.
+fn env_override_path(name: &str) -> Option<Vec<String>> {
+    let field = name.strip_prefix(ENV_OVERRIDE_PREFIX)?;
+    let not_a_field = matches!(field, "CONFIG" | "TRACE" | "USER");
+    if field.is_empty() || not_a_field || field.starts_with("DEBUG_") {
+        return None;
+    }
+    Some(field.split("__").map(str::to_ascii_lowercase).collect())
+}
+
+fn set_env_override(table: &mut toml::Table, path: &[String], value: toml::Value) {
+    let Some((key, sections)) = path.split_last() else {
+        return;
+    };
+    let mut target = table;
+    for section in sections {
+        let entry = target
+            .entry(section.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if !entry.is_table() {
+            *entry = toml::Value::Table(toml::Table::new());
+        }
+        let toml::Value::Table(inner) = entry else {
+            unreachable!("entry was just made a table");
+        };
+        target = inner;
+    }
+    target.insert(key.clone(), value);
+}
+
+/// Values are read as TOML (`true`, `0.2`, `["a"]`, `{ X-Org = "acme" }`) and
+/// otherwise taken as plain strings.
+fn parse_env_value(raw: &str) -> toml::Value {
+    format!("value = {raw}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+/// `selenai init`: writes a commented `selenai.toml` and a starter
+/// `.selenai-ignore` into `workspace_root`, leaving files that already exist
+/// untouched. Returns one line per file saying what happened.
+pub fn init_workspace(workspace_root: &Path) -> Result<Vec<String>> {
+    let mut report = Vec::new();
+    for (name, contents) in [
+        (DEFAULT_CONFIG_BASENAME, STARTER_CONFIG),
+        (SEARCH_IGNORE_BASENAME, STARTER_SEARCH_IGNORE),
+    ] {
+        let path = workspace_root.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(contents.as_bytes())
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+                report.push(format!("created {}", path.display()));
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                report.push(format!("kept existing {}", path.display()));
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to create {}", path.display()));
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Per-user directory for the tour marker, input history, and macros:
+/// `%APPDATA%\selenai` on Windows, otherwise `$XDG_CONFIG_HOME/selenai` or
+/// `~/.config/selenai`. Falls back to `./.config/selenai` when none is set.
+pub fn user_config_dir() -> PathBuf {
+    user_config_dir_from(
+        cfg!(windows),
+        &|name| std::env::var_os(name).filter(|value| !value.is_empty()),
+        &|dir| dir.is_dir(),
+    )
+}
+
+fn user_config_dir_from(
+    windows: bool,
+    var: &dyn Fn(&str) -> Option<OsString>,
+    exists: &dyn Fn(&Path) -> bool,
+) -> PathBuf {
+    const APP_DIR: &str = "selenai";
+    let base = if windows {
+        var("APPDATA").map(PathBuf::from).or_else(|| {
+            var("USERPROFILE").map(|home| PathBuf::from(home).join("AppData").join("Roaming"))
+        })
+    } else {
+        // The XDG spec says relative values are invalid and must be ignored.
+        let xdg = var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute());
+        let home = var("HOME").map(|home| PathBuf::from(home).join(".config"));
+        match (xdg, home) {
+            // Earlier releases always used `~/.config/selenai`; keep reading the
+            // history and tour marker there until the XDG directory exists.
+            (Some(xdg), Some(legacy))
+                if !exists(&xdg.join(APP_DIR)) && exists(&legacy.join(APP_DIR)) =>
+            {
+                Some(legacy)
+            }
+            (xdg, home) => xdg.or(home),
+        }
+    };
+    base.unwrap_or_else(|| PathBuf::from(".config"))
+        .join(APP_DIR)
+}
+
+/// The config file to read: `SELENAI_CONFIG`, else `./selenai.toml`.
+pub fn config_path_from_env() -> PathBuf {
     std::env::var("SELENAI_CONFIG")
         .map(PathBuf::from)
         .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_BASENAME))
@@ -109,12 +848,421 @@ mod tests {
     #[test]
     fn load_returns_defaults_when_missing() {
         with_temp_config(None, |path| {
-            let cfg = AppConfig::load_from_path(path).expect("default config");
+            let cfg = AppConfig::load_from_path(path, &[])
+                .expect("default config")
+                .0;
             assert_eq!(cfg.model_id, DEFAULT_MODEL_ID);
             assert!(matches!(cfg.provider, ProviderKind::Stub));
         });
     }
 
+    #[test]
+    fn invalid_config_falls_back_to_defaults_with_warning() {
+        with_temp_config(Some("model_id = \"gpt-4o\"\nstreaming = maybe\n"), |path| {
+            let (cfg, warning) = AppConfig::load_or_fallback(path, &[]).expect("fallback");
+            assert_eq!(cfg.model_id, DEFAULT_MODEL_ID);
+            let warning = &warning[0];
+            assert!(
+                warning.contains("started with default settings"),
+                "{warning}"
+            );
+            assert!(warning.contains("line 2"), "{warning}");
+            assert!(warning.contains("streaming = maybe"), "{warning}");
+        });
+
+        with_temp_config(Some("streaming = false\n"), |path| {
+            let (cfg, warning) = AppConfig::load_or_fallback(path, &[]).expect("config");
+            assert!(!cfg.streaming);
+            assert!(warning.is_empty());
+        });
+
+        with_temp_config(
+            Some("strict_config = true\nstreaming = \"yes\"\n"),
+            |path| {
+                let err = AppConfig::load_or_fallback(path, &[]).expect_err("strict aborts");
+                assert!(
+                    format!("{err:#}").contains("invalid config format"),
+                    "{err:#}"
+                );
+            },
+        );
+    }
+
+    fn env(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn env_overrides_take_precedence_over_file_values() {
+        let file = "model_id = \"gpt-4o\"\nstreaming = true\n[openai]\nbase_url = \"https://a\"\n";
+        with_temp_config(Some(file), |path| {
+            let overrides = env(&[
+                ("SELENAI_MODEL_ID", "gpt-4.1-mini"),
+                ("SELENAI_STREAMING", "false"),
+                ("SELENAI_TEMPERATURE", "1"),
+                ("SELENAI_OPENAI__BASE_URL", "http://localhost:8080/v1"),
+                ("SELENAI_OPENAI__HEADERS", "{ X-Org = \"acme\" }"),
+                ("SELENAI_CONFIG", "elsewhere.toml"),
+                ("SELENAI_DEBUG_OPENAI", "1"),
+            ]);
+            let cfg = AppConfig::load_from_path(path, &overrides)
+                .expect("config")
+                .0;
+            assert_eq!(cfg.model_id, "gpt-4.1-mini");
+            assert!(!cfg.streaming);
+            assert_eq!(cfg.temperature, Some(1.0));
+            assert_eq!(
+                cfg.openai.base_url.as_deref(),
+                Some("http://localhost:8080/v1")
+            );
+            assert_eq!(
+                cfg.openai.headers.iter().collect::<Vec<_>>(),
+                vec![("X-Org", "acme")]
+            );
+
+            let cfg = AppConfig::load_from_path(path, &[]).expect("config").0;
+            assert_eq!(cfg.model_id, "gpt-4o");
+            assert!(cfg.streaming);
+        });
+
+        with_temp_config(None, |path| {
+            let overrides = env(&[("SELENAI_ALLOW_TOOL_WRITES", "true")]);
+            let cfg = AppConfig::load_from_path(path, &overrides)
+                .expect("config")
+                .0;
+            assert!(cfg.allow_tool_writes);
+        });
+    }
+
+    #[test]
+    fn numeric_looking_env_override_still_sets_a_string_field() {
+        let file = "model_id = \"gpt-4o\"\n[openai]\nuser = \"ci\"\n";
+        with_temp_config(Some(file), |path| {
+            let overrides = env(&[
+                ("SELENAI_OPENAI__USER", "12345"),
+                ("SELENAI_MODEL_ID", "2024"),
+                ("SELENAI_TEMPERATURE", "0.5"),
+            ]);
+            let (cfg, warnings) = AppConfig::load_from_path(path, &overrides).expect("config");
+            assert!(warnings.is_empty(), "{warnings:?}");
+            assert_eq!(cfg.openai.user.as_deref(), Some("12345"));
+            assert_eq!(cfg.model_id, "2024");
+            assert_eq!(cfg.temperature, Some(0.5));
+        });
+    }
+
+    #[test]
+    fn effective_toml_reflects_env_overrides_and_masks_secrets() {
+        let file = concat!(
+            "model_id = \"gpt-4o\"\nlog_dir = \"logs\"\n",
+            "[openai.headers]\nAuthorization = \"Bearer sk-secret\"\nX-Title = \"SelenAI\"\n",
+        );
+        with_temp_config(Some(file), |path| {
+            let overrides = env(&[("SELENAI_MODEL_ID", "gpt-4.1-mini")]);
+            let cfg = AppConfig::load_from_path(path, &overrides)
+                .expect("config")
+                .0;
+            let workspace = Path::new("/work/repo");
+            let dump = cfg.to_effective_toml(path, workspace).expect("dump");
+
+            assert!(dump.contains("model_id = \"gpt-4.1-mini\""), "{dump}");
+            assert!(!dump.contains("gpt-4o\""), "{dump}");
+            assert!(dump.contains(&format!("# config file: {} (loaded)", path.display())));
+            assert!(dump.contains("# workspace: /work/repo"), "{dump}");
+            assert!(dump.contains("# log dir: /work/repo/logs"), "{dump}");
+            assert!(dump.contains("Authorization = \"<redacted>\""), "{dump}");
+            assert!(dump.contains("X-Title = \"SelenAI\""), "{dump}");
+            assert!(!dump.contains("sk-secret"), "{dump}");
+
+            let reparsed: toml::Table = dump.parse().expect("dump is valid TOML");
+            assert_eq!(reparsed["streaming"].as_bool(), Some(true));
+        });
+    }
+
+    #[test]
+    fn init_writes_starter_files_once() {
+        let dir = tempdir().expect("temp dir");
+        let report = init_workspace(dir.path()).expect("init");
+        assert_eq!(report.len(), 2);
+        assert!(
+            report.iter().all(|line| line.starts_with("created ")),
+            "{report:?}"
+        );
+
+        let path = dir.path().join(DEFAULT_CONFIG_BASENAME);
+        let written = fs::read_to_string(&path).expect("config written");
+        let defaults = toml::Table::try_from(AppConfig::default()).expect("serialize");
+        for key in defaults.keys() {
+            assert!(
+                written.contains(&format!("{key} =")) || written.contains(&format!("[{key}]")),
+                "starter config is missing `{key}`"
+            );
+        }
+        assert!(written.is_ascii(), "glyphs are written as TOML escapes");
+        let cfg = AppConfig::load_from_path(&path, &[])
+            .expect("starter config loads")
+            .0;
+        assert_eq!(cfg.model_id, DEFAULT_MODEL_ID);
+        let icons: toml::Table = written
+            .lines()
+            .filter_map(|line| line.strip_prefix("# "))
+            .filter(|line| line.starts_with("success =") || line.starts_with("error ="))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .parse()
+            .expect("icon examples parse");
+        assert_eq!(icons["success"].as_str(), Some("\u{2713}"));
+        assert_eq!(icons["error"].as_str(), Some("\u{2717}"));
+        assert_eq!(cfg.command_timeout_secs, DEFAULT_COMMAND_TIMEOUT_SECS);
+        let default_logs = AppConfig::default().resolve_log_dir(dir.path());
+        assert_eq!(cfg.resolve_log_dir(dir.path()), default_logs);
+        let ignore = fs::read_to_string(dir.path().join(SEARCH_IGNORE_BASENAME)).expect("ignore");
+        assert!(ignore.lines().any(|line| line == ".selenai/"), "{ignore}");
+
+        fs::write(&path, "model_id = \"mine\"\n").expect("edit config");
+        let report = init_workspace(dir.path()).expect("second init");
+        assert!(
+            report.iter().all(|line| line.starts_with("kept existing ")),
+            "{report:?}"
+        );
+        assert_eq!(
+            fs::read_to_string(&path).expect("config"),
+            "model_id = \"mine\"\n"
+        );
+    }
+
+    #[test]
+    fn user_config_dir_follows_platform_conventions() {
+        let vars = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| OsString::from(value))
+            }
+        };
+        let none = |_: &Path| false;
+        let unix = vars(&[("HOME", "/home/ada")]);
+        assert_eq!(
+            user_config_dir_from(false, &unix, &none),
+            PathBuf::from("/home/ada/.config/selenai")
+        );
+        let xdg = vars(&[("HOME", "/home/ada"), ("XDG_CONFIG_HOME", "/xdg")]);
+        assert_eq!(
+            user_config_dir_from(false, &xdg, &none),
+            PathBuf::from("/xdg/selenai")
+        );
+        let legacy_only = |dir: &Path| dir == Path::new("/home/ada/.config/selenai");
+        assert_eq!(
+            user_config_dir_from(false, &xdg, &legacy_only),
+            PathBuf::from("/home/ada/.config/selenai"),
+            "an existing ~/.config/selenai wins until the XDG one is created"
+        );
+        let both = |dir: &Path| dir.ends_with("selenai");
+        assert_eq!(
+            user_config_dir_from(false, &xdg, &both),
+            PathBuf::from("/xdg/selenai")
+        );
+        let relative_xdg = vars(&[("HOME", "/home/ada"), ("XDG_CONFIG_HOME", "xdg")]);
+        assert_eq!(
+            user_config_dir_from(false, &relative_xdg, &none),
+            PathBuf::from("/home/ada/.config/selenai")
+        );
+        let windows = vars(&[("APPDATA", r"C:\Users\ada\AppData\Roaming"), ("HOME", "/x")]);
+        assert_eq!(
+            user_config_dir_from(true, &windows, &none),
+            PathBuf::from(r"C:\Users\ada\AppData\Roaming").join("selenai")
+        );
+        let profile = vars(&[("USERPROFILE", r"C:\Users\ada")]);
+        assert_eq!(
+            user_config_dir_from(true, &profile, &none),
+            PathBuf::from(r"C:\Users\ada")
+                .join("AppData")
+                .join("Roaming")
+                .join("selenai")
+        );
+        assert_eq!(
+            user_config_dir_from(true, &vars(&[]), &none),
+            PathBuf::from(".config/selenai")
+        );
+    }
+
+    #[test]
+    fn show_tour_accepts_names_and_flags() {
+        let parse = |raw: &str| {
+            let overrides = env(&[("SELENAI_SHOW_TOUR", raw)]);
+            show_tour_with_env(&overrides)
+        };
+        assert_eq!(parse("1"), (TourSetting::Always, vec![]));
+        assert_eq!(parse("0"), (TourSetting::Never, vec![]));
+        assert_eq!(parse("true"), (TourSetting::Always, vec![]));
+        assert_eq!(parse("never"), (TourSetting::Never, vec![]));
+        assert_eq!(AppConfig::default().show_tour, TourSetting::FirstRun);
+        let (tour, warnings) = parse("sometimes");
+        assert_eq!(tour, TourSetting::FirstRun);
+        assert!(
+            warnings[0].contains("unknown show_tour value"),
+            "{warnings:?}"
+        );
+
+        assert!(TourSetting::FirstRun.should_show(false));
+        assert!(!TourSetting::FirstRun.should_show(true));
+        assert!(TourSetting::Always.should_show(true));
+        assert!(!TourSetting::Never.should_show(false));
+    }
+
+    fn show_tour_with_env(overrides: &[(String, String)]) -> (TourSetting, Vec<String>) {
+        let dir = tempdir().expect("temp dir");
+        let path = dir.path().join("missing.toml");
+        let (cfg, warnings) = AppConfig::load_from_path(&path, overrides).expect("config");
+        (cfg.show_tour, warnings)
+    }
+
+    #[test]
+    fn invalid_env_override_names_the_variable() {
+        with_temp_config(Some("streaming = false\n"), |path| {
+            let overrides = env(&[
+                ("SELENAI_MODEL_ID", "gpt-4.1-mini"),
+                ("SELENAI_STREAMING", "sometimes"),
+            ]);
+            let (cfg, warnings) = AppConfig::load_or_fallback(path, &overrides).expect("config");
+            assert!(!cfg.streaming, "the file value is kept");
+            assert_eq!(cfg.model_id, "gpt-4.1-mini", "valid overrides still apply");
+            assert_eq!(warnings.len(), 1, "{warnings:?}");
+            assert!(warnings[0].contains("SELENAI_STREAMING"), "{}", warnings[0]);
+
+            let overrides = env(&[
+                ("SELENAI_STREAMING", "sometimes"),
+                ("SELENAI_STRICT_CONFIG", "true"),
+            ]);
+            let err = AppConfig::load_or_fallback(path, &overrides).expect_err("strict aborts");
+            assert!(
+                format!("{err:#}").contains("invalid environment override"),
+                "{err:#}"
+            );
+        });
+    }
+
+    #[test]
+    fn validate_warns_when_provider_key_is_missing() {
+        let tmp = tempdir().expect("temp dir");
+        let mut cfg = AppConfig {
+            provider: ProviderKind::OpenAi,
+            ..AppConfig::default()
+        };
+        let warnings = cfg.validate(tmp.path(), &|_| false);
+        assert_eq!(
+            warnings,
+            ["provider = \"openai\" but OPENAI_API_KEY is not set; export it or add it to .env."]
+        );
+        assert!(
+            cfg.validate(tmp.path(), &|name| name == "OPENAI_API_KEY")
+                .is_empty()
+        );
+
+        cfg.provider = ProviderKind::Anthropic;
+        let warnings = cfg.validate(tmp.path(), &|name| name == "OPENAI_API_KEY");
+        assert!(warnings[0].contains("ANTHROPIC_API_KEY"), "{warnings:?}");
+        cfg.provider = ProviderKind::Ollama;
+        assert!(cfg.validate(tmp.path(), &|_| false).is_empty());
+    }
+
+    #[test]
+    fn validate_flags_unusable_log_dir() {
+        let tmp = tempdir().expect("temp dir");
+        fs::write(tmp.path().join("logs"), "not a directory").expect("write");
+        let mut cfg = AppConfig {
+            log_dir: Some(PathBuf::from("logs")),
+            ..AppConfig::default()
+        };
+        let warnings = cfg.validate(tmp.path(), &|_| true);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].ends_with("is a file, so session logs cannot be saved there."));
+
+        cfg.log_dir = Some(PathBuf::new());
+        let warnings = cfg.validate(tmp.path(), &|_| true);
+        assert_eq!(
+            warnings,
+            ["log_dir is empty; using the default .selenai/logs."]
+        );
+        assert!(cfg.log_dir.is_none());
+    }
+
+    #[test]
+    fn validate_drops_out_of_range_numbers() {
+        let tmp = tempdir().expect("temp dir");
+        let mut cfg = AppConfig {
+            temperature: Some(3.5),
+            top_p: Some(0.9),
+            max_tokens: Some(0),
+            ..AppConfig::default()
+        };
+        cfg.openai.top_p = Some(-0.1);
+        cfg.openai.max_attempts = Some(0);
+        cfg.anthropic.max_tokens = Some(0);
+        let warnings = cfg.validate(tmp.path(), &|_| true);
+        assert_eq!(
+            warnings,
+            [
+                "temperature = 3.5 is outside 0 to 2; using the provider default.",
+                "max_tokens = 0 is not allowed; using the default.",
+                "[openai] top_p = -0.1 is outside 0 to 1; using the provider default.",
+                "[openai] max_attempts = 0 is not allowed; using the default.",
+                "[anthropic] max_tokens = 0 is not allowed; using the default.",
+            ]
+        );
+        assert_eq!(cfg.temperature, None);
+        assert_eq!(cfg.top_p, Some(0.9));
+        assert_eq!(cfg.max_tokens, None);
+        assert!(
+            cfg.validate(tmp.path(), &|_| true).is_empty(),
+            "fixed values stay quiet"
+        );
+    }
+
+    #[test]
+    fn validate_drops_unknown_tool_choice() {
+        let tmp = tempdir().expect("temp dir");
+        for known in ["auto", "none", "required", "lua_run_script"] {
+            let mut cfg = AppConfig {
+                tool_choice: Some(known.into()),
+                ..AppConfig::default()
+            };
+            assert!(cfg.validate(tmp.path(), &|_| true).is_empty(), "{known}");
+            assert_eq!(cfg.tool_choice.as_deref(), Some(known));
+        }
+
+        let mut cfg = AppConfig {
+            tool_choice: Some("any".into()),
+            ..AppConfig::default()
+        };
+        let warnings = cfg.validate(tmp.path(), &|_| true);
+        assert_eq!(
+            warnings,
+            [concat!(
+                "tool_choice \"any\" is not auto, none, required, or a tool name ",
+                "(lua_run_script); using the provider default."
+            )]
+        );
+        assert_eq!(cfg.tool_choice, None);
+    }
+
+    #[test]
+    fn validate_flags_base_urls_without_scheme() {
+        let tmp = tempdir().expect("temp dir");
+        let mut cfg = AppConfig::default();
+        cfg.openai.base_url = Some("https://gateway.example/v1".into());
+        cfg.ollama.base_url = Some("localhost:11434".into());
+        let warnings = cfg.validate(tmp.path(), &|_| true);
+        assert_eq!(
+            warnings,
+            ["[ollama] base_url \"localhost:11434\" should start with http:// or https://."]
+        );
+    }
+
     #[test]
     fn load_normalizes_blank_model_id() {
         with_temp_config(
@@ -126,7 +1274,7 @@ streaming = false
 "#,
             ),
             |path| {
-                let cfg = AppConfig::load_from_path(path).expect("config");
+                let cfg = AppConfig::load_from_path(path, &[]).expect("config").0;
                 assert_eq!(cfg.model_id, DEFAULT_MODEL_ID);
                 assert!(
                     !cfg.streaming,
@@ -136,6 +1284,104 @@ streaming = false
         );
     }
 
+    #[test]
+    fn openai_headers_parse_and_redact_in_debug() {
+        with_temp_config(
+            Some(
+                r#"
+[openai.headers]
+"HTTP-Referer" = "https://example.com"
+"X-Api-Key" = "super-secret"
+"#,
+            ),
+            |path| {
+                let cfg = AppConfig::load_from_path(path, &[]).expect("config").0;
+                let headers = &cfg.openai.headers;
+                assert_eq!(headers.0["HTTP-Referer"], "https://example.com");
+                assert_eq!(headers.0["X-Api-Key"], "super-secret");
+                let debug = format!("{:?}", cfg);
+                assert!(debug.contains("https://example.com"));
+                assert!(!debug.contains("super-secret"));
+            },
+        );
+    }
+
+    #[test]
+    fn tool_approval_parses_and_decides_queueing() {
+        with_temp_config(Some("tool_approval = \"always\"\n"), |path| {
+            let cfg = AppConfig::load_from_path(path, &[]).expect("config").0;
+            assert_eq!(cfg.tool_approval, ToolApproval::Always);
+        });
+        assert_eq!(AppConfig::default().tool_approval, ToolApproval::WritesOnly);
+
+        assert!(!ToolApproval::Auto.requires_approval(true));
+        assert!(!ToolApproval::Auto.requires_approval(false));
+        assert!(ToolApproval::WritesOnly.requires_approval(true));
+        assert!(!ToolApproval::WritesOnly.requires_approval(false));
+        assert!(ToolApproval::Always.requires_approval(true));
+        assert!(ToolApproval::Always.requires_approval(false));
+    }
+
+    #[test]
+    fn tool_icons_follow_unicode_flag_and_overrides() {
+        with_temp_config(
+            Some("unicode = false\n[tool_icons]\nsuccess = \"OK\"\n"),
+            |path| {
+                let cfg = AppConfig::load_from_path(path, &[]).expect("config").0;
+                let icons = cfg.tool_icons();
+                assert_eq!(icons.success, "OK");
+                assert_eq!(icons.error, "[error]");
+            },
+        );
+        assert_eq!(AppConfig::default().tool_icons(), ToolIcons::unicode());
+    }
+
+    #[test]
+    fn role_prefixes_apply_overrides_over_display_names() {
+        with_temp_config(
+            Some("chat_compact = true\n[role_prefixes]\nuser = \"\u{276F} You\"\n"),
+            |path| {
+                let cfg = AppConfig::load_from_path(path, &[]).expect("config").0;
+                let prefixes = cfg.role_prefixes();
+                assert!(cfg.chat_compact);
+                assert_eq!(prefixes.user, "\u{276F} You");
+                assert_eq!(prefixes.assistant, "Assistant");
+            },
+        );
+    }
+
+    #[test]
+    fn load_keeps_secret_patterns_for_startup_validation() {
+        with_temp_config(
+            Some("[security]\nsecret_patterns = [\"ghp_[A-Za-z0-9]{36}\", \"(unclosed\"]\n"),
+            |path| {
+                let cfg = AppConfig::load_from_path(path, &[])
+                    .expect("bad patterns do not fail load")
+                    .0;
+                assert_eq!(
+                    cfg.security.secret_patterns,
+                    ["ghp_[A-Za-z0-9]{36}", "(unclosed"]
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn sampling_prefers_openai_overrides_for_openai() {
+        with_temp_config(
+            Some("temperature = 0.7\nmax_tokens = 800\n[openai]\ntemperature = 0.2\n"),
+            |path| {
+                let mut cfg = AppConfig::load_from_path(path, &[]).expect("config").0;
+                assert_eq!(cfg.sampling().temperature, Some(0.7));
+                cfg.provider = ProviderKind::OpenAi;
+                let sampling = cfg.sampling();
+                assert_eq!(sampling.temperature, Some(0.2));
+                assert_eq!(sampling.max_tokens, Some(800));
+                assert_eq!(sampling.top_p, None);
+            },
+        );
+    }
+
     #[test]
     fn resolve_log_dir_honors_defaults_and_overrides() {
         let workspace = tempdir().expect("workspace");
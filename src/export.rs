@@ -0,0 +1,187 @@
+use std::fmt::Write as _;
+
+use crate::{
+    session::{Redactor, escape_html},
+    types::{DiffLineKind, Message, Role, ToolLogEntry},
+};
+
+const STYLE: &str = r#"
+body { font-family: -apple-system, "Segoe UI", sans-serif; max-width: 960px; margin: 2rem auto; padding: 0 1rem; color: #1f2328; }
+h1 { font-size: 1.4rem; }
+h2 { font-size: 1.1rem; border-bottom: 1px solid #d0d7de; padding-bottom: .3rem; }
+.message { border-left: 4px solid #d0d7de; margin: 1rem 0; padding: .25rem .75rem; }
+.message .role { font-weight: bold; margin-bottom: .25rem; }
+.role-user { border-color: #0969da; }
+.role-assistant { border-color: #8250df; }
+.role-tool { border-color: #1a7f37; }
+.text { white-space: pre-wrap; margin: .25rem 0; }
+pre { background: #f6f8fa; padding: .5rem; overflow-x: auto; border-radius: 4px; }
+details { margin: .5rem 0; border: 1px solid #d0d7de; border-radius: 4px; padding: .25rem .5rem; }
+summary { cursor: pointer; font-weight: bold; }
+.status-ok { color: #1a7f37; }
+.status-error { color: #cf222e; }
+.status-pending { color: #9a6700; }
+.diff-add { color: #1a7f37; }
+.diff-del { color: #cf222e; }
+.diff-hunk { color: #0550ae; }
+.diff-file { font-weight: bold; }
+"#;
+
+/// Renders the chat transcript and tool log as a self-contained HTML page,
+/// masking secrets with `redactor`.
+pub fn render_session_html(
+    messages: &[Message],
+    tool_logs: &[ToolLogEntry],
+    redactor: &Redactor,
+) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>SelenAI session</title>\n<style>");
+    out.push_str(STYLE);
+    out.push_str("</style>\n</head>\n<body>\n<h1>SelenAI session</h1>\n");
+
+    out.push_str("<section id=\"transcript\">\n<h2>Transcript</h2>\n");
+    for message in messages {
+        let class = match message.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        };
+        let _ = writeln!(
+            out,
+            "<div class=\"message role-{class}\">\n<div class=\"role\">{}</div>",
+            message.role.display_name()
+        );
+        render_markdown_blocks(&mut out, &redactor.redact(&message.content));
+        out.push_str("</div>\n");
+    }
+    out.push_str("</section>\n");
+
+    out.push_str("<section id=\"tool-logs\">\n<h2>Tool activity</h2>\n");
+    for entry in tool_logs {
+        let status = entry.status.as_str();
+        let _ = writeln!(
+            out,
+            "<details>\n<summary><span class=\"status-{status}\">[{status}]</span> {}</summary>",
+            escape_html(&redactor.redact(&entry.title))
+        );
+        render_diff_block(&mut out, &redactor.redact(&entry.detail));
+        out.push_str("</details>\n");
+    }
+    out.push_str("</section>\n</body>\n</html>\n");
+    out
+}
+
+/// Splits message text into prose and fenced code blocks.
+fn render_markdown_blocks(out: &mut String, text: &str) {
+    let mut prose = String::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let opener = line.trim_start();
+        let fence_len = opener.chars().take_while(|&ch| ch == '`').count();
+        if fence_len < 3 {
+            prose.push_str(line);
+            prose.push('\n');
+            continue;
+        }
+        let lang = &opener[fence_len..];
+        let fence = &opener[..fence_len];
+        flush_prose(out, &mut prose);
+        let mut code = String::new();
+        for inner in lines.by_ref() {
+            if inner.trim().starts_with(fence) && inner.trim().trim_start_matches('`').is_empty() {
+                break;
+            }
+            code.push_str(inner);
+            code.push('\n');
+        }
+        if lang.trim() == "diff" {
+            render_diff_block(out, &code);
+        } else {
+            let _ = writeln!(out, "<pre><code>{}</code></pre>", escape_html(&code));
+        }
+    }
+    flush_prose(out, &mut prose);
+}
+
+fn flush_prose(out: &mut String, prose: &mut String) {
+    if !prose.trim().is_empty() {
+        let _ = writeln!(
+            out,
+            "<div class=\"text\">{}</div>",
+            escape_html(prose.trim_end())
+        );
+    }
+    prose.clear();
+}
+
+fn render_diff_block(out: &mut String, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    out.push_str("<pre>");
+    for line in text.lines() {
+        let escaped = escape_html(line);
+        match DiffLineKind::classify(line) {
+            DiffLineKind::FileHeader => {
+                let _ = writeln!(out, "<span class=\"diff-file\">{escaped}</span>");
+            }
+            DiffLineKind::Hunk => {
+                let _ = writeln!(out, "<span class=\"diff-hunk\">{escaped}</span>");
+            }
+            DiffLineKind::Added => {
+                let _ = writeln!(out, "<span class=\"diff-add\">{escaped}</span>");
+            }
+            DiffLineKind::Removed => {
+                let _ = writeln!(out, "<span class=\"diff-del\">{escaped}</span>");
+            }
+            DiffLineKind::Context => {
+                let _ = writeln!(out, "{escaped}");
+            }
+        }
+    }
+    out.push_str("</pre>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ToolStatus;
+
+    #[test]
+    fn html_export_has_sections_and_escapes_content() {
+        let messages = vec![
+            Message::new(
+                Role::User,
+                "<script>alert('x')</script> key sk-123456789012345678901234",
+            ),
+            Message::new(
+                Role::Assistant,
+                "Here is the change:\n```diff\n--- a/lib.rs\n+++ b/lib.rs\n@@ -1 +1 @@\n-old\n+new\n```",
+            ),
+        ];
+        let mut entry = ToolLogEntry::new(0, "Lua <run>", "+added\n-removed");
+        entry.status = ToolStatus::Success;
+
+        let html = render_session_html(&messages, &[entry], &Redactor::default());
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<section id=\"transcript\">"));
+        assert!(html.contains("<section id=\"tool-logs\">"));
+        assert!(html.contains("class=\"message role-user\""));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"));
+        assert!(html.contains("[REDACTED]"));
+        assert!(html.contains("<span class=\"diff-add\">+new</span>"));
+        assert!(html.contains("<span class=\"diff-del\">-old</span>"));
+        assert!(
+            html.contains(
+                "<summary><span class=\"status-ok\">[ok]</span> Lua &lt;run&gt;</summary>"
+            )
+        );
+
+        let nested = Message::new(Role::Tool, "````md\n```\ninner\n```\n````\nafter");
+        let html = render_session_html(&[nested], &[], &Redactor::default());
+        assert!(html.contains("<pre><code>```\ninner\n```\n</code></pre>"));
+        assert!(html.contains("<div class=\"text\">after</div>"));
+    }
+}
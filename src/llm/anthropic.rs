@@ -0,0 +1,776 @@
+use std::{collections::HashMap, env};
+
+use anyhow::{Context, Result, anyhow, bail};
+use futures_util::StreamExt;
+use reqwest::{
+    Client, StatusCode,
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
+use serde_json::{Value, json};
+
+use crate::types::{Message, Role, ToolInvocation};
+
+use super::{
+    ChatRequest, ChatResponse, LlmClient, LlmTool, StreamEvent, StreamEventSender,
+    ToolCallProgress,
+    openai::{SourcePreview, normalize_history, truncate_payload},
+};
+
+pub const DEFAULT_BASE_URL: &str = "https://api.anthropic.com/v1";
+/// Messages API version sent as `anthropic-version`.
+pub const API_VERSION: &str = "2023-06-01";
+/// The Messages API requires `max_tokens`; this covers long tool scripts.
+pub const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+const API_KEY_HEADER: &str = "x-api-key";
+const VERSION_HEADER: &str = "anthropic-version";
+
+#[derive(Clone, Debug)]
+pub struct AnthropicConfig {
+    pub api_key: String,
+    pub model: String,
+    pub base_url: String,
+    pub max_tokens: u32,
+    /// Max characters of an error response body kept in error messages.
+    pub error_body_limit: usize,
+}
+
+pub struct AnthropicClient {
+    http: Client,
+    config: AnthropicConfig,
+}
+
+impl AnthropicClient {
+    pub fn new(config: AnthropicConfig) -> Result<Self> {
+        let http = Client::builder()
+            .default_headers(build_default_headers(&config)?)
+            .build()?;
+
+        Ok(Self { http, config })
+    }
+
+    /// Builds a `/v1/messages` body. The system prompt is a top-level text
+    /// block marked for prompt caching, since it repeats verbatim every turn,
+    /// and tools use `input_schema`.
+    fn build_payload(&self, request: &ChatRequest, stream: bool) -> Value {
+        let sampling = &request.sampling;
+        let mut payload = json!({
+            "model": self.config.model,
+            "max_tokens": sampling.max_tokens.unwrap_or(self.config.max_tokens),
+            "stream": stream,
+            "messages": serialize_history(&request.messages),
+        });
+        if let Some(temperature) = sampling.temperature {
+            payload["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = sampling.top_p {
+            payload["top_p"] = json!(top_p);
+        }
+
+        // Anthropic caches everything up to a marked block, in the order
+        // tools, system, messages, so one marker covers the whole prefix.
+        let cache_control = request.cache_prefix.then(|| json!({"type": "ephemeral"}));
+        if let Some(prompt) = &request.system_prompt {
+            let mut block = json!({"type": "text", "text": prompt});
+            if let Some(marker) = &cache_control {
+                block["cache_control"] = marker.clone();
+            }
+            payload["system"] = json!([block]);
+        }
+
+        if !request.tools.is_empty() {
+            let mut tools = request
+                .tools
+                .iter()
+                .map(LlmTool::to_anthropic_json)
+                .collect::<Vec<_>>();
+            if request.system_prompt.is_none()
+                && let (Some(marker), Some(last)) = (&cache_control, tools.last_mut())
+            {
+                last["cache_control"] = marker.clone();
+            }
+            payload["tools"] = Value::Array(tools);
+            if let Some(choice) = &request.tool_choice {
+                payload["tool_choice"] = choice.to_anthropic_json();
+            }
+        }
+
+        payload
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{path}", self.config.base_url.trim_end_matches('/'))
+    }
+
+    async fn get_json(&self, operation: &str, path: &str) -> Result<Value> {
+        let response = self.http.get(self.url(path)).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "<failed to read body>".into());
+            return Err(api_error(
+                operation,
+                status,
+                &text,
+                self.config.error_body_limit,
+            ));
+        }
+        Ok(response.json::<Value>().await?)
+    }
+}
+
+fn build_default_headers(config: &AnthropicConfig) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let mut key = HeaderValue::from_str(&config.api_key).context("invalid ANTHROPIC_API_KEY")?;
+    key.set_sensitive(true);
+    headers.insert(HeaderName::from_static(API_KEY_HEADER), key);
+    headers.insert(
+        HeaderName::from_static(VERSION_HEADER),
+        HeaderValue::from_static(API_VERSION),
+    );
+    Ok(headers)
+}
+
+/// Converts history into Messages API turns. Tool calls become `tool_use`
+/// blocks on the assistant turn and results become `tool_result` blocks on
+/// the following user turn. Consecutive turns with the same role are merged
+/// because the API requires user and assistant to alternate, and leading
+/// assistant turns (the welcome banner) are dropped since the first turn must
+/// come from the user.
+fn serialize_history(messages: &[Message]) -> Vec<Value> {
+    let mut turns: Vec<(&'static str, Vec<Value>)> = Vec::new();
+    for message in &normalize_history(messages) {
+        let (role, blocks) = content_blocks(message);
+        if blocks.is_empty() || (turns.is_empty() && role != "user") {
+            continue;
+        }
+        match turns.last_mut() {
+            Some((last_role, last_blocks)) if *last_role == role => last_blocks.extend(blocks),
+            _ => turns.push((role, blocks)),
+        }
+    }
+    turns
+        .into_iter()
+        .map(|(role, content)| json!({ "role": role, "content": content }))
+        .collect()
+}
+
+fn content_blocks(message: &Message) -> (&'static str, Vec<Value>) {
+    let mut blocks = Vec::new();
+    match message.role {
+        Role::Tool => {
+            if let Some(id) = &message.tool_call_id {
+                blocks.push(json!({
+                    "type": "tool_result",
+                    "tool_use_id": id,
+                    "content": message.content,
+                }));
+            }
+            ("user", blocks)
+        }
+        Role::User => {
+            push_text(&mut blocks, &message.content);
+            ("user", blocks)
+        }
+        Role::Assistant => {
+            push_text(&mut blocks, &message.content);
+            for call in &message.tool_calls {
+                let Some(id) = &call.call_id else {
+                    continue;
+                };
+                // `input` must be an object; arguments that never parsed as
+                // JSON are kept as a raw string under `arguments`.
+                let input = match &call.arguments {
+                    Value::Object(_) => call.arguments.clone(),
+                    other => json!({ "arguments": other }),
+                };
+                blocks.push(json!({
+                    "type": "tool_use",
+                    "id": id,
+                    "name": call.name,
+                    "input": input,
+                }));
+            }
+            ("assistant", blocks)
+        }
+    }
+}
+
+/// The API rejects empty text blocks, so blank content is left out.
+fn push_text(blocks: &mut Vec<Value>, text: &str) {
+    if !text.trim().is_empty() {
+        blocks.push(json!({ "type": "text", "text": text }));
+    }
+}
+
+fn log_payload(payload: &Value) {
+    if env::var("SELENAI_DEBUG_ANTHROPIC").is_ok()
+        && let Ok(pretty) = serde_json::to_string_pretty(payload)
+    {
+        eprintln!("[selenai][anthropic] payload:\n{}", pretty);
+    }
+}
+
+/// Builds the error for a non-success response, preferring the API's own
+/// `error.message` over the raw body.
+fn api_error(operation: &str, status: StatusCode, body: &str, limit: usize) -> anyhow::Error {
+    match parse_api_error(body) {
+        Some((label, message)) => anyhow!(
+            "Anthropic error ({}): {} [{} status {}]",
+            label,
+            truncate_payload(&message, limit),
+            operation,
+            status.as_u16()
+        ),
+        None => anyhow!(
+            "Anthropic {} failed (status {}): {}",
+            operation,
+            status,
+            truncate_payload(body, limit)
+        ),
+    }
+}
+
+/// Extracts `(type, message)` from an Anthropic error body or `error` event.
+fn parse_api_error(body: &str) -> Option<(String, String)> {
+    let value: Value = serde_json::from_str(body).ok()?;
+    error_fields(&value)
+}
+
+fn error_fields(value: &Value) -> Option<(String, String)> {
+    let error = value.get("error")?;
+    let message = error.get("message")?.as_str()?.trim();
+    if message.is_empty() {
+        return None;
+    }
+    let label = error
+        .get("type")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown");
+    Some((label.to_string(), message.to_string()))
+}
+
+#[async_trait::async_trait]
+impl LlmClient for AnthropicClient {
+    async fn chat(&self, request: ChatRequest) -> Result<ChatResponse> {
+        let payload = self.build_payload(&request, false);
+        log_payload(&payload);
+        let response = self
+            .http
+            .post(self.url("messages"))
+            .json(&payload)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "<failed to read body>".into());
+            return Err(api_error(
+                "chat",
+                status,
+                &text,
+                self.config.error_body_limit,
+            ));
+        }
+        let body = response.json::<Value>().await?;
+        parse_message(&body)
+    }
+
+    async fn chat_stream(&self, request: ChatRequest, sender: StreamEventSender) -> Result<()> {
+        let payload = self.build_payload(&request, true);
+        log_payload(&payload);
+        let response = self
+            .http
+            .post(self.url("messages"))
+            .json(&payload)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "<failed to read body>".into());
+            return Err(api_error(
+                "chat_stream",
+                status,
+                &text,
+                self.config.error_body_limit,
+            ));
+        }
+
+        let mut stream = response.bytes_stream();
+        let mut buffer = String::new();
+        let mut tool_uses: HashMap<usize, ToolUseState> = HashMap::new();
+
+        while let Some(chunk) = stream.next().await {
+            let bytes = chunk?;
+            let text = String::from_utf8_lossy(&bytes);
+            buffer.push_str(&text);
+
+            while let Some(pos) = buffer.find("\n\n") {
+                let mut event = buffer[..pos].to_string();
+                buffer.drain(..pos + 2);
+                event = event.replace("\r\n", "\n");
+
+                // `event:` lines repeat the `type` field of the data, so only
+                // the data lines are read.
+                for line in event.lines() {
+                    let Some(data) = line.trim().strip_prefix("data:") else {
+                        continue;
+                    };
+                    let data = data.trim();
+                    if data.is_empty() {
+                        continue;
+                    }
+                    let json: Value = serde_json::from_str(data)?;
+                    if handle_stream_event(&json, &sender, &mut tool_uses)? {
+                        let _ = sender.send(StreamEvent::Completed);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        finish_tool_uses(&mut tool_uses, &sender);
+        let _ = sender.send(StreamEvent::Completed);
+        Ok(())
+    }
+
+    /// Looks up the configured model via `GET /models/{model}`, which
+    /// validates the key and base URL without spending tokens.
+    async fn health_check(&self) -> Result<Option<String>> {
+        let body = self
+            .get_json("health_check", &format!("models/{}", self.config.model))
+            .await?;
+        Ok(body
+            .get("id")
+            .and_then(|id| id.as_str())
+            .map(str::to_string))
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let body = self.get_json("list_models", "models?limit=1000").await?;
+        let data = body
+            .get("data")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| anyhow!("missing `data` in Anthropic models response"))?;
+        let mut ids: Vec<String> = data
+            .iter()
+            .filter_map(|model| model.get("id").and_then(|id| id.as_str()))
+            .map(str::to_string)
+            .collect();
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
+
+/// Reads a non-streaming `/v1/messages` response. Any `tool_use` blocks win
+/// over text, matching how OpenAI tool-call responses are handled.
+fn parse_message(value: &Value) -> Result<ChatResponse> {
+    let content = value
+        .get("content")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| anyhow!("missing `content` in Anthropic response"))?;
+
+    let invocations = content
+        .iter()
+        .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("tool_use"))
+        .filter_map(parse_tool_use)
+        .collect::<Vec<_>>();
+    if !invocations.is_empty() {
+        return Ok(ChatResponse::ToolCalls(invocations));
+    }
+
+    let text = content
+        .iter()
+        .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("text"))
+        .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+        .collect::<String>();
+    Ok(ChatResponse::assistant_text(text))
+}
+
+fn parse_tool_use(block: &Value) -> Option<ToolInvocation> {
+    let name = block.get("name")?.as_str()?.to_string();
+    let arguments = block.get("input").cloned().unwrap_or_else(|| json!({}));
+    let call_id = block
+        .get("id")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    Some(ToolInvocation::from_parts(name, arguments, call_id))
+}
+
+/// Applies one SSE event. Returns true once `message_stop` arrives.
+fn handle_stream_event(
+    event: &Value,
+    sender: &StreamEventSender,
+    tool_uses: &mut HashMap<usize, ToolUseState>,
+) -> Result<bool> {
+    let kind = event
+        .get("type")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let index = event
+        .get("index")
+        .and_then(|v| v.as_u64())
+        .unwrap_or_default() as usize;
+    match kind {
+        "content_block_start" => {
+            let Some(block) = event.get("content_block") else {
+                return Ok(false);
+            };
+            if block.get("type").and_then(|t| t.as_str()) == Some("tool_use") {
+                let state = ToolUseState {
+                    name: block
+                        .get("name")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string),
+                    call_id: block.get("id").and_then(|v| v.as_str()).map(str::to_string),
+                    input_json: String::new(),
+                    preview: SourcePreview::default(),
+                };
+                let _ = sender.send(StreamEvent::ToolCallProgress(ToolCallProgress {
+                    index,
+                    name: state.name.clone(),
+                    received_chars: 0,
+                    source: None,
+                }));
+                tool_uses.insert(index, state);
+            } else if let Some(text) = block.get("text").and_then(|t| t.as_str())
+                && !text.is_empty()
+            {
+                let _ = sender.send(StreamEvent::Delta(text.to_string()));
+            }
+        }
+        "content_block_delta" => {
+            let Some(delta) = event.get("delta") else {
+                return Ok(false);
+            };
+            match delta.get("type").and_then(|t| t.as_str()) {
+                Some("text_delta") => {
+                    if let Some(text) = delta.get("text").and_then(|t| t.as_str())
+                        && !text.is_empty()
+                    {
+                        let _ = sender.send(StreamEvent::Delta(text.to_string()));
+                    }
+                }
+                Some("input_json_delta") => {
+                    let partial = delta
+                        .get("partial_json")
+                        .and_then(|t| t.as_str())
+                        .unwrap_or_default();
+                    if let Some(state) = tool_uses.get_mut(&index)
+                        && !partial.is_empty()
+                    {
+                        state.input_json.push_str(partial);
+                        let progress =
+                            state
+                                .preview
+                                .advance(index, state.name.clone(), &state.input_json);
+                        let _ = sender.send(StreamEvent::ToolCallProgress(progress));
+                    }
+                }
+                _ => {}
+            }
+        }
+        "content_block_stop" => {
+            if let Some(state) = tool_uses.remove(&index) {
+                emit_tool_use(state, sender);
+            }
+        }
+        "message_stop" => {
+            finish_tool_uses(tool_uses, sender);
+            return Ok(true);
+        }
+        "error" => {
+            let (label, message) =
+                error_fields(event).unwrap_or_else(|| ("unknown".into(), event.to_string()));
+            bail!("Anthropic stream error ({label}): {message}");
+        }
+        // `message_start`, `message_delta`, and `ping` carry nothing the UI shows.
+        _ => {}
+    }
+    Ok(false)
+}
+
+/// Emits tool uses whose block never closed (a cut-off stream), in index order.
+fn finish_tool_uses(tool_uses: &mut HashMap<usize, ToolUseState>, sender: &StreamEventSender) {
+    let mut pending: Vec<_> = tool_uses.drain().collect();
+    pending.sort_by_key(|(index, _)| *index);
+    for (_, state) in pending {
+        emit_tool_use(state, sender);
+    }
+}
+
+fn emit_tool_use(state: ToolUseState, sender: &StreamEventSender) {
+    let Some(name) = state.name else {
+        return;
+    };
+    // A tool with no parameters streams no input at all.
+    let arguments = if state.input_json.trim().is_empty() {
+        json!({})
+    } else {
+        serde_json::from_str(&state.input_json).unwrap_or_else(|_| json!(state.input_json))
+    };
+    let invocation = ToolInvocation::from_parts(name, arguments, state.call_id);
+    let _ = sender.send(StreamEvent::ToolCall(invocation));
+}
+
+struct ToolUseState {
+    name: Option<String>,
+    call_id: Option<String>,
+    input_json: String,
+    preview: SourcePreview,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{ToolChoice, test_server::serve_once};
+    use tokio::sync::mpsc;
+
+    fn client_for(base_url: String) -> AnthropicClient {
+        AnthropicClient::new(AnthropicConfig {
+            api_key: "test-key".into(),
+            model: "claude-test".into(),
+            base_url,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            error_body_limit: 500,
+        })
+        .expect("client")
+    }
+
+    fn tool_call(id: &str, source: &str) -> ToolInvocation {
+        ToolInvocation::from_parts(
+            "lua_run_script",
+            json!({ "source": source }),
+            Some(id.into()),
+        )
+    }
+
+    fn tool_result(id: &str, content: &str) -> Message {
+        Message {
+            tool_call_id: Some(id.into()),
+            ..Message::new(Role::Tool, content)
+        }
+    }
+
+    #[test]
+    fn payload_marks_the_system_prompt_for_caching() {
+        let client = client_for("https://example.test".into());
+        let messages = vec![Message::new(Role::User, "hi")];
+        let payload = client.build_payload(&ChatRequest::new(messages.clone()), false);
+        assert!(payload.get("system").is_none());
+
+        let request = ChatRequest::new(messages.clone()).with_system_prompt("be brief");
+        let payload = client.build_payload(&request, false);
+        assert_eq!(
+            payload["system"],
+            json!([{"type": "text", "text": "be brief"}])
+        );
+
+        let payload = client.build_payload(&request.with_cache_prefix(true), false);
+        assert_eq!(
+            payload["system"],
+            json!([{
+                "type": "text",
+                "text": "be brief",
+                "cache_control": {"type": "ephemeral"},
+            }])
+        );
+        assert!(payload["tools"].is_null());
+
+        let tool = LlmTool::new("lua_run_script", "Run Lua", json!({"type": "object"}));
+        let tools_only = ChatRequest::new(messages)
+            .with_tool(tool)
+            .with_cache_prefix(true);
+        let payload = client.build_payload(&tools_only, false);
+        assert_eq!(
+            payload["tools"][0]["cache_control"],
+            json!({"type": "ephemeral"})
+        );
+    }
+
+    #[test]
+    fn payload_uses_top_level_system_and_tool_blocks() {
+        let client = client_for("https://example.test".into());
+        let tool = LlmTool::new("lua_run_script", "Run Lua", json!({"type": "object"}));
+        let messages = vec![
+            Message::new(Role::Assistant, "Welcome to SelenAI"),
+            Message::new(Role::User, "count the files"),
+            Message {
+                tool_calls: vec![
+                    tool_call("toolu_1", "return 1"),
+                    tool_call("toolu_2", "return 2"),
+                ],
+                ..Message::new(Role::Assistant, "")
+            },
+            tool_result("toolu_1", "1"),
+            tool_result("toolu_2", "2"),
+            Message::new(Role::User, "thanks"),
+        ];
+        let request = ChatRequest::new(messages)
+            .with_system_prompt("system instructions")
+            .with_tool(tool)
+            .with_tool_choice(ToolChoice::Required);
+        let payload = client.build_payload(&request, true);
+
+        assert_eq!(payload["system"][0]["text"], "system instructions");
+        assert_eq!(payload["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert_eq!(payload["stream"], true);
+        assert_eq!(payload["tools"][0]["name"], "lua_run_script");
+        assert_eq!(
+            payload["tools"][0]["input_schema"],
+            json!({"type": "object"})
+        );
+        assert_eq!(payload["tool_choice"], json!({"type": "any"}));
+
+        let turns = payload["messages"].as_array().expect("messages");
+        let roles: Vec<_> = turns.iter().map(|t| t["role"].as_str().unwrap()).collect();
+        assert_eq!(roles, ["user", "assistant", "user"]);
+        assert_eq!(turns[0]["content"][0]["text"], "count the files");
+
+        let uses = turns[1]["content"].as_array().unwrap();
+        assert_eq!(uses.len(), 2, "empty assistant text is not sent");
+        assert_eq!(uses[0]["type"], "tool_use");
+        assert_eq!(uses[1]["id"], "toolu_2");
+        assert_eq!(uses[1]["input"]["source"], "return 2");
+
+        let results = turns[2]["content"].as_array().unwrap();
+        assert_eq!(results[0]["type"], "tool_result");
+        assert_eq!(results[0]["tool_use_id"], "toolu_1");
+        assert_eq!(results[1]["content"], "2");
+        assert_eq!(results[2]["text"], "thanks");
+    }
+
+    #[test]
+    fn parse_message_collects_tool_uses() {
+        let body = json!({
+            "content": [
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": "toolu_a", "name": "lua_run_script",
+                 "input": {"source": "return 1"}},
+                {"type": "tool_use", "id": "toolu_b", "name": "lua_run_script",
+                 "input": {"source": "return 2"}}
+            ],
+            "stop_reason": "tool_use"
+        });
+        let ChatResponse::ToolCalls(calls) = parse_message(&body).expect("parsed") else {
+            panic!("expected tool calls");
+        };
+        let ids: Vec<_> = calls.iter().map(|c| c.call_id.as_deref()).collect();
+        assert_eq!(ids, [Some("toolu_a"), Some("toolu_b")]);
+        assert_eq!(calls[1].arguments["source"], "return 2");
+
+        let text = json!({"content": [{"type": "text", "text": "Hello!"}]});
+        match parse_message(&text).expect("parsed") {
+            ChatResponse::Assistant(message) => assert_eq!(message.content, "Hello!"),
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn stream_events_produce_deltas_progress_and_tool_calls() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut state = HashMap::new();
+        let events = [
+            json!({"type": "message_start", "message": {"id": "msg_1"}}),
+            json!({"type": "content_block_start", "index": 0,
+                   "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 0,
+                   "delta": {"type": "text_delta", "text": "Checking"}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_start", "index": 1,
+                   "content_block": {"type": "tool_use", "id": "toolu_1",
+                                     "name": "lua_run_script", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 1,
+                   "delta": {"type": "input_json_delta", "partial_json": "{\"source\":\"ret"}}),
+            json!({"type": "content_block_delta", "index": 1,
+                   "delta": {"type": "input_json_delta", "partial_json": "urn 1\"}"}}),
+            json!({"type": "content_block_stop", "index": 1}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}}),
+        ];
+        for event in &events {
+            assert!(!handle_stream_event(event, &tx, &mut state).expect("event"));
+        }
+        let stop = json!({"type": "message_stop"});
+        assert!(handle_stream_event(&stop, &tx, &mut state).expect("stop"));
+
+        let received: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert!(matches!(&received[0], StreamEvent::Delta(text) if text == "Checking"));
+        let sources: Vec<_> = received
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::ToolCallProgress(progress) => Some(progress.source.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sources, [None, Some("ret".into()), Some("return 1".into())]);
+        match received.last() {
+            Some(StreamEvent::ToolCall(call)) => {
+                assert_eq!(call.call_id.as_deref(), Some("toolu_1"));
+                assert_eq!(call.arguments["source"], "return 1");
+            }
+            other => panic!("expected tool call, got {other:?}"),
+        }
+
+        let overloaded = json!({"type": "error",
+            "error": {"type": "overloaded_error", "message": "Overloaded"}});
+        let err = handle_stream_event(&overloaded, &tx, &mut state).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Anthropic stream error (overloaded_error): Overloaded"
+        );
+    }
+
+    #[tokio::test]
+    async fn health_check_sends_key_and_version_headers() {
+        let (base_url, server) = serve_once(
+            "200 OK",
+            r#"{"id":"claude-test","type":"model","display_name":"Claude Test"}"#,
+        );
+        let model = client_for(base_url)
+            .health_check()
+            .await
+            .expect("health check");
+        assert_eq!(model.as_deref(), Some("claude-test"));
+
+        let request = server.join().expect("server").to_ascii_lowercase();
+        assert!(
+            request.starts_with("get /v1/models/claude-test "),
+            "{request}"
+        );
+        assert!(request.contains("x-api-key: test-key"), "{request}");
+        assert!(
+            request.contains("anthropic-version: 2023-06-01"),
+            "{request}"
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_surfaces_api_errors() {
+        let (base_url, server) = serve_once(
+            "401 Unauthorized",
+            r#"{"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}"#,
+        );
+        let request = ChatRequest::new(vec![Message::new(Role::User, "ping")]);
+        let err = client_for(base_url)
+            .chat(request)
+            .await
+            .expect_err("should fail");
+        server.join().expect("server");
+        assert_eq!(
+            err.to_string(),
+            "Anthropic error (authentication_error): invalid x-api-key [chat status 401]"
+        );
+    }
+}
@@ -4,6 +4,8 @@ use tokio::sync::mpsc::UnboundedSender;
 
 use crate::types::{Message, Role, ToolInvocation};
 
+pub mod anthropic;
+pub mod ollama;
 pub mod openai;
 
 #[derive(Debug, Clone)]
@@ -12,6 +14,69 @@ pub struct ChatRequest {
     pub stream: bool,
     pub system_prompt: Option<String>,
     pub tools: Vec<LlmTool>,
+    /// Left to the provider (usually `auto`) when unset.
+    pub tool_choice: Option<ToolChoice>,
+    pub sampling: Sampling,
+    /// The system prompt and tool definitions repeat unchanged every turn;
+    /// providers with prompt caching mark that prefix for reuse when set.
+    pub cache_prefix: bool,
+}
+
+/// Whether the model may, must, or must not call the offered tools.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolChoice {
+    Auto,
+    /// Answer in text only; the tools stay defined so earlier calls in the
+    /// history remain valid.
+    None,
+    /// Call at least one tool.
+    Required,
+    /// Call this tool.
+    Tool(String),
+}
+
+impl ToolChoice {
+    /// Reads the `tool_choice` setting: `auto`, `none`, `required`, or the
+    /// name of a tool the model must call.
+    pub fn from_setting(value: &str) -> Self {
+        match value.trim() {
+            "auto" => ToolChoice::Auto,
+            "none" => ToolChoice::None,
+            "required" => ToolChoice::Required,
+            name => ToolChoice::Tool(name.to_string()),
+        }
+    }
+
+    pub fn to_openai_json(&self) -> serde_json::Value {
+        match self {
+            ToolChoice::Auto => "auto".into(),
+            ToolChoice::None => "none".into(),
+            ToolChoice::Required => "required".into(),
+            ToolChoice::Tool(name) => serde_json::json!({
+                "type": "function",
+                "function": { "name": name },
+            }),
+        }
+    }
+
+    /// Anthropic spells `required` as `any`.
+    pub fn to_anthropic_json(&self) -> serde_json::Value {
+        match self {
+            ToolChoice::Auto => serde_json::json!({ "type": "auto" }),
+            ToolChoice::None => serde_json::json!({ "type": "none" }),
+            ToolChoice::Required => serde_json::json!({ "type": "any" }),
+            ToolChoice::Tool(name) => serde_json::json!({ "type": "tool", "name": name }),
+        }
+    }
+}
+
+/// Optional sampling knobs; a `None` field is left out of the request so the
+/// provider default applies.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sampling {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<u32>,
 }
 
 impl ChatRequest {
@@ -21,9 +86,17 @@ impl ChatRequest {
             stream: false,
             system_prompt: None,
             tools: Vec::new(),
+            tool_choice: None,
+            sampling: Sampling::default(),
+            cache_prefix: false,
         }
     }
 
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
     pub fn with_stream(mut self, stream: bool) -> Self {
         self.stream = stream;
         self
@@ -39,6 +112,16 @@ impl ChatRequest {
         self
     }
 
+    pub fn with_tool_choice(mut self, choice: ToolChoice) -> Self {
+        self.tool_choice = Some(choice);
+        self
+    }
+
+    pub fn with_cache_prefix(mut self, cache: bool) -> Self {
+        self.cache_prefix = cache;
+        self
+    }
+
     pub fn latest_user_prompt(&self) -> Option<&str> {
         self.messages
             .iter()
@@ -63,10 +146,24 @@ impl ChatResponse {
 #[derive(Debug, Clone)]
 pub enum StreamEvent {
     Delta(String),
+    /// A tool call whose arguments are still arriving; superseded by `ToolCall`.
+    ToolCallProgress(ToolCallProgress),
     ToolCall(ToolInvocation),
     Completed,
 }
 
+/// Snapshot of a streaming tool call, sent each time more arguments arrive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolCallProgress {
+    /// Position of the call in the response; several calls can stream at once.
+    pub index: usize,
+    pub name: Option<String>,
+    /// Characters of raw JSON arguments received so far.
+    pub received_chars: usize,
+    /// Best-effort decode of the partial `source` argument, if it has started.
+    pub source: Option<String>,
+}
+
 pub type StreamEventSender = UnboundedSender<StreamEvent>;
 
 #[derive(Debug, Clone)]
@@ -99,6 +196,15 @@ impl LlmTool {
             }
         })
     }
+
+    /// Tool definition in the Anthropic Messages API shape.
+    pub fn to_anthropic_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "description": self.description,
+            "input_schema": self.parameters,
+        })
+    }
 }
 
 #[async_trait]
@@ -110,6 +216,21 @@ pub trait LlmClient: Send + Sync {
     fn supports_streaming(&self) -> bool {
         true
     }
+
+    /// Cheap connectivity probe used by `/ping`. Returns the model the
+    /// provider resolved, when it reports one. The default sends a one-word
+    /// chat turn.
+    async fn health_check(&self) -> Result<Option<String>> {
+        let request = ChatRequest::new(vec![Message::new(Role::User, "ping")]);
+        self.chat(request).await?;
+        Ok(None)
+    }
+
+    /// Model ids the provider offers for chat. Empty when the provider has no
+    /// listing endpoint.
+    async fn list_models(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
 }
 
 pub struct StubClient;
@@ -171,6 +292,50 @@ impl LlmClient for StubClient {
     }
 }
 
+/// Canned HTTP servers for the provider tests.
+#[cfg(test)]
+pub(crate) mod test_server {
+    use std::io::{Read, Write};
+    use std::thread::JoinHandle;
+
+    /// Serves one canned HTTP response on a local port and hands back the
+    /// base URL plus the raw request text it received.
+    pub(crate) fn serve_once(
+        status_line: &'static str,
+        body: &'static str,
+    ) -> (String, JoinHandle<String>) {
+        let (base_url, handle) = serve_sequence(vec![(status_line, "", body)]);
+        let handle =
+            std::thread::spawn(move || handle.join().expect("server").pop().expect("one request"));
+        (base_url, handle)
+    }
+
+    /// Like [`serve_once`], but answers one connection per
+    /// `(status line, extra header lines, body)` entry, in order.
+    pub(crate) fn serve_sequence(
+        responses: Vec<(&'static str, &'static str, &'static str)>,
+    ) -> (String, JoinHandle<Vec<String>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status_line, headers, body) in responses {
+                let (mut stream, _) = listener.accept().expect("accept");
+                let mut buf = [0u8; 4096];
+                let read = stream.read(&mut buf).expect("read");
+                let response = format!(
+                    "HTTP/1.1 {status_line}\r\ncontent-type: application/json\r\n{headers}content-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).expect("write");
+                requests.push(String::from_utf8_lossy(&buf[..read]).into_owned());
+            }
+            requests
+        });
+        (format!("http://{addr}/v1"), handle)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
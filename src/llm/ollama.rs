@@ -0,0 +1,443 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Result, anyhow, bail};
+use futures_util::StreamExt;
+use reqwest::{Client, StatusCode};
+use serde_json::{Value, json};
+
+use crate::types::{Message, Role, ToolInvocation};
+
+use super::{
+    ChatRequest, ChatResponse, LlmClient, LlmTool, StreamEvent, StreamEventSender, ToolChoice,
+    openai::{DEFAULT_ERROR_BODY_LIMIT, normalize_history, truncate_payload},
+};
+
+pub const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+#[derive(Clone, Debug)]
+pub struct OllamaConfig {
+    pub model: String,
+    pub base_url: String,
+}
+
+pub struct OllamaClient {
+    http: Client,
+    config: OllamaConfig,
+    /// Ollama does not number its tool calls, so ids are minted here to pair
+    /// each result with its call in later turns.
+    next_call_id: AtomicUsize,
+}
+
+impl OllamaClient {
+    pub fn new(config: OllamaConfig) -> Result<Self> {
+        Ok(Self {
+            http: Client::builder().build()?,
+            config,
+            next_call_id: AtomicUsize::new(1),
+        })
+    }
+
+    /// Builds an `/api/chat` body. Tools use the OpenAI function shape, which
+    /// Ollama accepts as-is.
+    fn build_payload(&self, request: &ChatRequest, stream: bool) -> Value {
+        let mut messages = Vec::new();
+        if let Some(prompt) = &request.system_prompt {
+            messages.push(json!({ "role": "system", "content": prompt }));
+        }
+        messages.extend(serialize_history(&request.messages));
+
+        let mut payload = json!({
+            "model": self.config.model,
+            "stream": stream,
+            "messages": messages,
+        });
+
+        // Ollama takes sampling under `options`, with `num_predict` as the
+        // token cap.
+        let sampling = &request.sampling;
+        let mut options = serde_json::Map::new();
+        if let Some(temperature) = sampling.temperature {
+            options.insert("temperature".into(), json!(temperature));
+        }
+        if let Some(top_p) = sampling.top_p {
+            options.insert("top_p".into(), json!(top_p));
+        }
+        if let Some(max_tokens) = sampling.max_tokens {
+            options.insert("num_predict".into(), json!(max_tokens));
+        }
+        if !options.is_empty() {
+            payload["options"] = Value::Object(options);
+        }
+
+        // Ollama has no `tool_choice`; `none` is honored by not offering tools.
+        if !request.tools.is_empty() && request.tool_choice != Some(ToolChoice::None) {
+            let tools = request
+                .tools
+                .iter()
+                .map(LlmTool::to_openai_json)
+                .collect::<Vec<_>>();
+            payload["tools"] = Value::Array(tools);
+        }
+
+        payload
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{path}", self.config.base_url.trim_end_matches('/'))
+    }
+
+    fn parse_tool_calls(&self, message: &Value) -> Vec<ToolInvocation> {
+        let Some(calls) = message.get("tool_calls").and_then(|v| v.as_array()) else {
+            return Vec::new();
+        };
+        calls
+            .iter()
+            .filter_map(|call| {
+                let function = call.get("function")?;
+                let name = function.get("name")?.as_str()?.to_string();
+                let arguments = match function.get("arguments") {
+                    // Some models send arguments as a JSON string, like OpenAI.
+                    Some(Value::String(raw)) => {
+                        serde_json::from_str(raw).unwrap_or_else(|_| json!(raw))
+                    }
+                    Some(other) => other.clone(),
+                    None => json!({}),
+                };
+                let call_id = call
+                    .get("id")
+                    .and_then(|v| v.as_str())
+                    .map(str::to_string)
+                    .unwrap_or_else(|| {
+                        let n = self.next_call_id.fetch_add(1, Ordering::Relaxed);
+                        format!("ollama_call_{n}")
+                    });
+                Some(ToolInvocation::from_parts(name, arguments, Some(call_id)))
+            })
+            .collect()
+    }
+
+    /// Applies one NDJSON line of a streaming reply. Returns true once the
+    /// line marked `done` arrives.
+    fn handle_stream_line(&self, line: &Value, sender: &StreamEventSender) -> Result<bool> {
+        if let Some(error) = line.get("error").and_then(|v| v.as_str()) {
+            bail!("Ollama stream error: {error}");
+        }
+        if let Some(message) = line.get("message") {
+            if let Some(text) = message.get("content").and_then(|v| v.as_str())
+                && !text.is_empty()
+            {
+                let _ = sender.send(StreamEvent::Delta(text.to_string()));
+            }
+            for call in self.parse_tool_calls(message) {
+                let _ = sender.send(StreamEvent::ToolCall(call));
+            }
+        }
+        Ok(line.get("done").and_then(|v| v.as_bool()).unwrap_or(false))
+    }
+}
+
+/// Converts history into `/api/chat` messages. Tool results name the tool
+/// they answer via `tool_name`, since Ollama has no call ids of its own.
+fn serialize_history(messages: &[Message]) -> Vec<Value> {
+    let normalized = normalize_history(messages);
+    let tool_names: HashMap<&str, &str> = normalized
+        .iter()
+        .flat_map(|message| &message.tool_calls)
+        .filter_map(|call| Some((call.call_id.as_deref()?, call.name.as_str())))
+        .collect();
+
+    normalized
+        .iter()
+        .map(|message| match message.role {
+            Role::Tool => {
+                let mut payload = json!({ "role": "tool", "content": message.content });
+                if let Some(name) = message
+                    .tool_call_id
+                    .as_deref()
+                    .and_then(|id| tool_names.get(id))
+                {
+                    payload["tool_name"] = json!(name);
+                }
+                payload
+            }
+            Role::Assistant => {
+                let mut payload = json!({ "role": "assistant", "content": message.content });
+                if !message.tool_calls.is_empty() {
+                    let calls = message
+                        .tool_calls
+                        .iter()
+                        .map(|call| {
+                            json!({
+                                "function": { "name": call.name, "arguments": call.arguments }
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    payload["tool_calls"] = Value::Array(calls);
+                }
+                payload
+            }
+            Role::User => json!({ "role": "user", "content": message.content }),
+        })
+        .collect()
+}
+
+fn log_payload(payload: &Value) {
+    if env::var("SELENAI_DEBUG_OLLAMA").is_ok()
+        && let Ok(pretty) = serde_json::to_string_pretty(payload)
+    {
+        eprintln!("[selenai][ollama] payload:\n{}", pretty);
+    }
+}
+
+/// Builds the error for a non-success response, preferring Ollama's own
+/// `{"error": "..."}` message over the raw body.
+fn api_error(operation: &str, status: StatusCode, body: &str) -> anyhow::Error {
+    let message = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|value| value.get("error")?.as_str().map(str::to_string));
+    match message {
+        Some(message) => anyhow!(
+            "Ollama error: {} [{} status {}]",
+            truncate_payload(&message, DEFAULT_ERROR_BODY_LIMIT),
+            operation,
+            status.as_u16()
+        ),
+        None => anyhow!(
+            "Ollama {} failed (status {}): {}",
+            operation,
+            status,
+            truncate_payload(body, DEFAULT_ERROR_BODY_LIMIT)
+        ),
+    }
+}
+
+async fn ensure_success(operation: &str, response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let text = response
+        .text()
+        .await
+        .unwrap_or_else(|_| "<failed to read body>".into());
+    Err(api_error(operation, status, &text))
+}
+
+#[async_trait::async_trait]
+impl LlmClient for OllamaClient {
+    async fn chat(&self, request: ChatRequest) -> Result<ChatResponse> {
+        let payload = self.build_payload(&request, false);
+        log_payload(&payload);
+        let response = self
+            .http
+            .post(self.url("api/chat"))
+            .json(&payload)
+            .send()
+            .await?;
+        let body = ensure_success("chat", response)
+            .await?
+            .json::<Value>()
+            .await?;
+        let message = body
+            .get("message")
+            .ok_or_else(|| anyhow!("missing `message` in Ollama response"))?;
+        let calls = self.parse_tool_calls(message);
+        if !calls.is_empty() {
+            return Ok(ChatResponse::ToolCalls(calls));
+        }
+        let content = message
+            .get("content")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        Ok(ChatResponse::assistant_text(content))
+    }
+
+    async fn chat_stream(&self, request: ChatRequest, sender: StreamEventSender) -> Result<()> {
+        let payload = self.build_payload(&request, true);
+        log_payload(&payload);
+        let response = self
+            .http
+            .post(self.url("api/chat"))
+            .json(&payload)
+            .send()
+            .await?;
+        let response = ensure_success("chat_stream", response).await?;
+
+        let mut stream = response.bytes_stream();
+        let mut buffer = String::new();
+        while let Some(chunk) = stream.next().await {
+            let bytes = chunk?;
+            buffer.push_str(&String::from_utf8_lossy(&bytes));
+
+            while let Some(pos) = buffer.find('\n') {
+                let line = buffer[..pos].trim().to_string();
+                buffer.drain(..pos + 1);
+                if line.is_empty() {
+                    continue;
+                }
+                let json: Value = serde_json::from_str(&line)?;
+                if self.handle_stream_line(&json, &sender)? {
+                    let _ = sender.send(StreamEvent::Completed);
+                    return Ok(());
+                }
+            }
+        }
+
+        let rest = buffer.trim();
+        if !rest.is_empty() {
+            let json: Value = serde_json::from_str(rest)?;
+            self.handle_stream_line(&json, &sender)?;
+        }
+        let _ = sender.send(StreamEvent::Completed);
+        Ok(())
+    }
+
+    /// Asks `/api/show` about the configured model, which confirms the
+    /// server is up and the model is pulled without running it.
+    async fn health_check(&self) -> Result<Option<String>> {
+        let response = self
+            .http
+            .post(self.url("api/show"))
+            .json(&json!({ "model": self.config.model }))
+            .send()
+            .await?;
+        ensure_success("health_check", response).await?;
+        Ok(Some(self.config.model.clone()))
+    }
+
+    /// Locally pulled models from `/api/tags`.
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let response = self.http.get(self.url("api/tags")).send().await?;
+        let body = ensure_success("list_models", response)
+            .await?
+            .json::<Value>()
+            .await?;
+        let models = body
+            .get("models")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| anyhow!("missing `models` in Ollama tags response"))?;
+        let mut names: Vec<String> = models
+            .iter()
+            .filter_map(|model| model.get("name").and_then(|n| n.as_str()))
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    fn test_client() -> OllamaClient {
+        OllamaClient::new(OllamaConfig {
+            model: "llama3.1".into(),
+            base_url: DEFAULT_BASE_URL.into(),
+        })
+        .expect("client")
+    }
+
+    #[test]
+    fn payload_maps_roles_tools_and_results() {
+        let client = test_client();
+        let call = ToolInvocation::from_parts(
+            "lua_run_script",
+            json!({ "source": "return 1" }),
+            Some("ollama_call_1".into()),
+        );
+        let messages = vec![
+            Message::new(Role::User, "count"),
+            Message {
+                tool_calls: vec![call],
+                ..Message::new(Role::Assistant, "")
+            },
+            Message {
+                tool_call_id: Some("ollama_call_1".into()),
+                ..Message::new(Role::Tool, "1")
+            },
+        ];
+        let tool = LlmTool::new("lua_run_script", "Run Lua", json!({"type": "object"}));
+        let request = ChatRequest::new(messages)
+            .with_system_prompt("system")
+            .with_tool(tool);
+        let payload = client.build_payload(&request, true);
+
+        let sent = payload["messages"].as_array().expect("messages");
+        let roles: Vec<_> = sent.iter().map(|m| m["role"].as_str().unwrap()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "tool"]);
+        assert_eq!(
+            sent[2]["tool_calls"][0]["function"]["arguments"]["source"],
+            "return 1"
+        );
+        assert_eq!(sent[3]["tool_name"], "lua_run_script");
+        assert_eq!(payload["tools"][0]["function"]["name"], "lua_run_script");
+        assert_eq!(payload["stream"], true);
+
+        let text_only = client.build_payload(&request.with_tool_choice(ToolChoice::None), true);
+        assert!(text_only.get("tools").is_none());
+    }
+
+    #[test]
+    fn stream_lines_emit_deltas_tool_calls_and_done() {
+        let client = test_client();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let lines = [
+            json!({"message": {"role": "assistant", "content": "Hel"}, "done": false}),
+            json!({"message": {"role": "assistant", "content": "lo"}, "done": false}),
+            json!({"message": {"role": "assistant", "content": "", "tool_calls": [
+                {"function": {"name": "lua_run_script", "arguments": {"source": "return 1"}}},
+                {"function": {"name": "lua_run_script", "arguments": "{\"source\":\"return 2\"}"}}
+            ]}, "done": false}),
+        ];
+        for line in &lines {
+            assert!(!client.handle_stream_line(line, &tx).expect("line"));
+        }
+        let done = json!({"message": {"role": "assistant", "content": ""}, "done": true});
+        assert!(client.handle_stream_line(&done, &tx).expect("done"));
+
+        let events: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert!(matches!(&events[0], StreamEvent::Delta(text) if text == "Hel"));
+        assert!(matches!(&events[1], StreamEvent::Delta(text) if text == "lo"));
+        let calls: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::ToolCall(call) => Some(call),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].arguments["source"], "return 2");
+        assert_ne!(calls[0].call_id, calls[1].call_id, "minted ids are unique");
+
+        let error = json!({"error": "model \"llama3.1\" not found"});
+        let err = client.handle_stream_line(&error, &tx).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Ollama stream error: model \"llama3.1\" not found"
+        );
+    }
+
+    #[test]
+    fn api_error_prefers_ollama_message() {
+        let err = api_error(
+            "chat",
+            StatusCode::NOT_FOUND,
+            r#"{"error":"model 'missing' not found"}"#,
+        );
+        assert_eq!(
+            err.to_string(),
+            "Ollama error: model 'missing' not found [chat status 404]"
+        );
+    }
+}
@@ -1,19 +1,34 @@
-use std::{collections::HashMap, env};
+use std::{
+    collections::HashMap,
+    env,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{Context, Result, anyhow};
 use futures_util::StreamExt;
 use reqwest::{
-    Client,
-    header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue},
+    Client, StatusCode,
+    header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue, RETRY_AFTER},
 };
 use serde_json::{Value, json};
+use tracing::warn;
 
-use crate::types::{Message, Role, ToolInvocation};
+use crate::{
+    config::{ExtraHeaders, is_sensitive_header},
+    types::{Message, Role, ToolInvocation},
+};
 
-use super::{ChatRequest, ChatResponse, LlmClient, LlmTool, StreamEvent, StreamEventSender};
+use super::{
+    ChatRequest, ChatResponse, LlmClient, LlmTool, StreamEvent, StreamEventSender, ToolCallProgress,
+};
 
 const ORG_HEADER: &str = "openai-organization";
 const PROJECT_HEADER: &str = "openai-project";
+pub const DEFAULT_ERROR_BODY_LIMIT: usize = 500;
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+/// Longest `Retry-After` honored, so a misbehaving server cannot stall a turn.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
 
 #[derive(Clone, Debug)]
 pub struct OpenAiConfig {
@@ -22,6 +37,18 @@ pub struct OpenAiConfig {
     pub base_url: String,
     pub organization: Option<String>,
     pub project: Option<String>,
+    /// End-user id sent as `user` for provider-side abuse monitoring.
+    pub user: Option<String>,
+    /// `parallel_tool_calls`; unset leaves OpenAI's default (true).
+    pub parallel_tool_calls: Option<bool>,
+    /// Max characters of an error response body kept in error messages.
+    pub error_body_limit: usize,
+    /// Extra default headers, applied after the built-in ones.
+    pub headers: ExtraHeaders,
+    /// Total tries for a chat request answered with 429/500/502/503.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubles on each later one.
+    pub retry_base_delay: Duration,
 }
 
 pub struct OpenAiClient {
@@ -38,7 +65,69 @@ impl OpenAiClient {
         Ok(Self { http, config })
     }
 
+    /// POSTs to `/chat/completions`, retrying transient failures with
+    /// jittered exponential backoff or the server's `Retry-After`. Retries
+    /// happen before any body is read, so a stream never repeats output.
+    async fn post_chat(&self, operation: &str, payload: &Value) -> Result<reqwest::Response> {
+        let url = self.url("chat/completions");
+        let max_attempts = self.config.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            let response = self.http.post(&url).json(payload).send().await?;
+            let status = response.status();
+            if status.is_success() {
+                return Ok(response);
+            }
+            if attempt < max_attempts && is_retryable(status) {
+                let delay = retry_after(response.headers())
+                    .unwrap_or_else(|| backoff_delay(self.config.retry_base_delay, attempt));
+                warn!(
+                    "OpenAI {operation} returned {status}; retrying in {}ms (attempt {}/{max_attempts})",
+                    delay.as_millis(),
+                    attempt + 1
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                continue;
+            }
+            let text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "<failed to read body>".into());
+            return Err(api_error(
+                operation,
+                status,
+                &text,
+                self.config.error_body_limit,
+            ));
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{path}", self.config.base_url.trim_end_matches('/'))
+    }
+
+    async fn get_json(&self, operation: &str, path: &str) -> Result<Value> {
+        let response = self.http.get(self.url(path)).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "<failed to read body>".into());
+            return Err(api_error(
+                operation,
+                status,
+                &text,
+                self.config.error_body_limit,
+            ));
+        }
+        Ok(response.json::<Value>().await?)
+    }
+
     fn build_payload(&self, request: &ChatRequest, stream: bool) -> Value {
+        // OpenAI caches repeated prefixes on its own, so `cache_prefix` needs no
+        // marker here; the system prompt and tools just have to come first.
         let mut messages = Vec::new();
 
         if let Some(prompt) = &request.system_prompt {
@@ -48,7 +137,7 @@ impl OpenAiClient {
             }));
         }
 
-        for message in &request.messages {
+        for message in &normalize_history(&request.messages) {
             if let Some(serialized) = serialize_message(message) {
                 messages.push(serialized);
             }
@@ -60,6 +149,20 @@ impl OpenAiClient {
             "messages": messages,
         });
 
+        let sampling = &request.sampling;
+        if let Some(temperature) = sampling.temperature {
+            payload["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = sampling.top_p {
+            payload["top_p"] = json!(top_p);
+        }
+        if let Some(max_tokens) = sampling.max_tokens {
+            payload["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(user) = &self.config.user {
+            payload["user"] = json!(user);
+        }
+
         if !request.tools.is_empty() {
             let tools = request
                 .tools
@@ -67,6 +170,13 @@ impl OpenAiClient {
                 .map(LlmTool::to_openai_json)
                 .collect::<Vec<_>>();
             payload["tools"] = Value::Array(tools);
+            if let Some(choice) = &request.tool_choice {
+                payload["tool_choice"] = choice.to_openai_json();
+            }
+            // Only valid alongside tools, so it is skipped when none are sent.
+            if let Some(parallel) = self.config.parallel_tool_calls {
+                payload["parallel_tool_calls"] = json!(parallel);
+            }
         }
 
         payload
@@ -91,9 +201,59 @@ fn build_default_headers(config: &OpenAiConfig) -> Result<HeaderMap> {
         headers.insert(name, HeaderValue::from_str(project)?);
     }
 
+    for (name, value) in config.headers.iter() {
+        let header = HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("invalid header name `{name}` in [openai.headers]"))?;
+        let mut header_value = HeaderValue::from_str(value)
+            .with_context(|| format!("invalid value for header `{name}` in [openai.headers]"))?;
+        header_value.set_sensitive(is_sensitive_header(name));
+        headers.insert(header, header_value);
+    }
+
     Ok(headers)
 }
 
+/// Reorders history so each tool result directly follows the assistant
+/// message that requested it. Orphaned results and unanswered calls (e.g.
+/// after trimming) are dropped, since OpenAI and Anthropic reject both with a
+/// 400.
+pub(super) fn normalize_history(messages: &[Message]) -> Vec<Message> {
+    let mut results: HashMap<&str, &Message> = HashMap::new();
+    for message in messages {
+        if let (Role::Tool, Some(id)) = (message.role, message.tool_call_id.as_deref()) {
+            results.entry(id).or_insert(message);
+        }
+    }
+
+    let mut normalized = Vec::with_capacity(messages.len());
+    for message in messages {
+        match message.role {
+            Role::Tool => {}
+            Role::Assistant if !message.tool_calls.is_empty() => {
+                let mut calls = Vec::new();
+                let mut answers = Vec::new();
+                for call in &message.tool_calls {
+                    if let Some(answer) = call.call_id.as_deref().and_then(|id| results.remove(id))
+                    {
+                        calls.push(call.clone());
+                        answers.push(answer.clone());
+                    }
+                }
+                if calls.is_empty() && message.content.trim().is_empty() {
+                    continue;
+                }
+                normalized.push(Message {
+                    tool_calls: calls,
+                    ..message.clone()
+                });
+                normalized.extend(answers);
+            }
+            _ => normalized.push(message.clone()),
+        }
+    }
+    normalized
+}
+
 fn map_role(role: Role) -> &'static str {
     match role {
         Role::User => "user",
@@ -145,13 +305,85 @@ fn log_payload(payload: &Value) {
     }
 }
 
-fn truncate_payload(text: &str) -> String {
-    const LIMIT: usize = 500;
-    if text.len() <= LIMIT {
-        text.to_string()
-    } else {
-        format!("{}…", &text[..LIMIT])
+/// Keeps the first `limit` characters, cutting on a char boundary.
+pub(super) fn truncate_payload(text: &str, limit: usize) -> String {
+    match text.char_indices().nth(limit) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Builds the error for a non-success response, preferring the API's own
+/// `error.message` over the raw body.
+fn api_error(operation: &str, status: StatusCode, body: &str, limit: usize) -> anyhow::Error {
+    match parse_api_error(body) {
+        Some((label, message)) => anyhow!(
+            "OpenAI error ({}): {} [{} status {}]",
+            label,
+            truncate_payload(&message, limit),
+            operation,
+            status.as_u16()
+        ),
+        None => anyhow!(
+            "OpenAI {} failed (status {}): {}",
+            operation,
+            status,
+            truncate_payload(body, limit)
+        ),
+    }
+}
+
+/// Rate limits and the gateway-style 5xx codes that usually clear on retry.
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+    )
+}
+
+/// Reads a `Retry-After` given in seconds; the HTTP-date form falls back to
+/// the normal backoff.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds: f64 = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    if !seconds.is_finite() || seconds < 0.0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(seconds).min(MAX_RETRY_AFTER))
+}
+
+/// `base * 2^(attempt - 1)` plus up to 25% jitter, so clients that failed
+/// together do not retry in lockstep.
+fn backoff_delay(base: Duration, attempt: u32) -> Duration {
+    let delay = base.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    delay + delay.mul_f64(f64::from(nanos % 1000) / 4000.0)
+}
+
+/// Extracts `(code or type, message)` from an OpenAI error body.
+fn parse_api_error(body: &str) -> Option<(String, String)> {
+    let value: Value = serde_json::from_str(body).ok()?;
+    let error = value.get("error")?;
+    let message = error.get("message")?.as_str()?.trim();
+    if message.is_empty() {
+        return None;
     }
+    let label = ["code", "type"]
+        .iter()
+        .find_map(|key| error.get(*key).and_then(|v| v.as_str()))
+        .unwrap_or("unknown");
+    Some((label.to_string(), message.to_string()))
 }
 
 #[async_trait::async_trait]
@@ -159,23 +391,7 @@ impl LlmClient for OpenAiClient {
     async fn chat(&self, request: ChatRequest) -> Result<ChatResponse> {
         let payload = self.build_payload(&request, false);
         log_payload(&payload);
-        let url = format!(
-            "{}/chat/completions",
-            self.config.base_url.trim_end_matches('/')
-        );
-        let response = self.http.post(url).json(&payload).send().await?;
-        let status = response.status();
-        if !status.is_success() {
-            let text = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "<failed to read body>".into());
-            return Err(anyhow!(
-                "OpenAI chat failed (status {}): {}",
-                status,
-                truncate_payload(&text)
-            ));
-        }
+        let response = self.post_chat("chat", &payload).await?;
         let body = response.json::<Value>().await?;
         parse_chat_response(&body)
     }
@@ -183,24 +399,7 @@ impl LlmClient for OpenAiClient {
     async fn chat_stream(&self, request: ChatRequest, sender: StreamEventSender) -> Result<()> {
         let payload = self.build_payload(&request, true);
         log_payload(&payload);
-        let url = format!(
-            "{}/chat/completions",
-            self.config.base_url.trim_end_matches('/')
-        );
-
-        let response = self.http.post(url).json(&payload).send().await?;
-        let status = response.status();
-        if !status.is_success() {
-            let text = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "<failed to read body>".into());
-            return Err(anyhow!(
-                "OpenAI chat_stream failed (status {}): {}",
-                status,
-                truncate_payload(&text)
-            ));
-        }
+        let response = self.post_chat("chat_stream", &payload).await?;
 
         let mut stream = response.bytes_stream();
         let mut buffer = String::new();
@@ -241,11 +440,63 @@ impl LlmClient for OpenAiClient {
         Ok(())
     }
 
+    /// Looks up the configured model via `GET /models/{model}`, which
+    /// validates the key and base URL without spending tokens.
+    async fn health_check(&self) -> Result<Option<String>> {
+        let body = self
+            .get_json("health_check", &format!("models/{}", self.config.model))
+            .await?;
+        Ok(body
+            .get("id")
+            .and_then(|id| id.as_str())
+            .map(str::to_string))
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let body = self.get_json("list_models", "models").await?;
+        parse_model_list(&body)
+    }
+
     fn supports_streaming(&self) -> bool {
         true
     }
 }
 
+/// Model families served by `/models` that cannot take chat completions.
+const NON_CHAT_MODEL_MARKERS: &[&str] = &[
+    "embedding",
+    "moderation",
+    "whisper",
+    "tts",
+    "dall-e",
+    "transcribe",
+    "davinci",
+    "babbage",
+    "-instruct",
+];
+
+/// Pulls sorted model ids out of a `/models` response, dropping families
+/// known not to support chat. Gateways with unfamiliar ids keep everything.
+fn parse_model_list(value: &Value) -> Result<Vec<String>> {
+    let data = value
+        .get("data")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| anyhow!("missing `data` in OpenAI models response"))?;
+    let mut ids: Vec<String> = data
+        .iter()
+        .filter_map(|model| model.get("id").and_then(|id| id.as_str()))
+        .filter(|id| {
+            !NON_CHAT_MODEL_MARKERS
+                .iter()
+                .any(|marker| id.contains(marker))
+        })
+        .map(str::to_string)
+        .collect();
+    ids.sort();
+    ids.dedup();
+    Ok(ids)
+}
+
 fn parse_chat_response(value: &Value) -> Result<ChatResponse> {
     let choices = value
         .get("choices")
@@ -321,8 +572,14 @@ fn handle_stream_chunk(
                             state.name.get_or_insert_with(|| name.to_string());
                         }
                         if let Some(arguments) = function.get("arguments").and_then(|v| v.as_str())
+                            && !arguments.is_empty()
                         {
                             state.arguments.push_str(arguments);
+                            let progress =
+                                state
+                                    .preview
+                                    .advance(index, state.name.clone(), &state.arguments);
+                            let _ = sender.send(StreamEvent::ToolCallProgress(progress));
                         }
                     }
                     if let Some(id) = entry.get("id").and_then(|v| v.as_str()) {
@@ -342,17 +599,141 @@ fn handle_stream_chunk(
     Ok(())
 }
 
+/// Emits every buffered tool call in the `index` order the API assigned, so a
+/// response with several calls runs them in the order the model wrote them.
 fn finalize_tool_calls(tool_calls: &mut HashMap<usize, ToolCallState>, sender: &StreamEventSender) {
-    for state in tool_calls.values() {
-        if let Some(name) = &state.name {
-            let arguments = serde_json::from_str(&state.arguments)
-                .unwrap_or_else(|_| json!(state.arguments.clone()));
-            let invocation =
-                ToolInvocation::from_parts(name.clone(), arguments, state.call_id.clone());
+    let mut pending: Vec<_> = tool_calls.drain().collect();
+    pending.sort_by_key(|(index, _)| *index);
+    for (_, state) in pending {
+        if let Some(name) = state.name {
+            let arguments =
+                serde_json::from_str(&state.arguments).unwrap_or_else(|_| json!(state.arguments));
+            let invocation = ToolInvocation::from_parts(name, arguments, state.call_id);
             let _ = sender.send(StreamEvent::ToolCall(invocation));
         }
     }
-    tool_calls.clear();
+}
+
+/// Decodes the `source` argument of a tool call while its JSON arrives in
+/// pieces, e.g. `{"source":"print(\"hi` yields `print("hi`. It keeps its place
+/// in the raw arguments so each chunk only scans the text it added.
+#[derive(Debug, Default)]
+pub(super) struct SourcePreview {
+    /// Bytes of the raw arguments seen so far, and their length in chars.
+    seen: usize,
+    received_chars: usize,
+    /// Bytes of the raw arguments the decoder has consumed.
+    scanned: usize,
+    step: SourceStep,
+    source: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum SourceStep {
+    #[default]
+    Key,
+    Colon,
+    Quote,
+    Value,
+    Done,
+}
+
+impl SourcePreview {
+    const KEY: &str = "\"source\"";
+
+    /// Scans what `raw` gained since the last call (it must only grow) and
+    /// returns the progress to report for call `index`.
+    pub(super) fn advance(
+        &mut self,
+        index: usize,
+        name: Option<String>,
+        raw: &str,
+    ) -> ToolCallProgress {
+        self.received_chars += raw[self.seen..].chars().count();
+        self.seen = raw.len();
+        loop {
+            let rest = &raw[self.scanned..];
+            match self.step {
+                SourceStep::Key => match rest.find(Self::KEY) {
+                    Some(at) => {
+                        self.scanned += at + Self::KEY.len();
+                        self.step = SourceStep::Colon;
+                    }
+                    None => {
+                        // Leave a tail that could hold the start of a split key.
+                        let mut skip = rest.len().saturating_sub(Self::KEY.len() - 1);
+                        while !rest.is_char_boundary(skip) {
+                            skip += 1;
+                        }
+                        self.scanned += skip;
+                        break;
+                    }
+                },
+                SourceStep::Colon | SourceStep::Quote => {
+                    let trimmed = rest.trim_start();
+                    self.scanned += rest.len() - trimmed.len();
+                    let Some(next) = trimmed.chars().next() else {
+                        break;
+                    };
+                    let (expected, then) = match self.step {
+                        SourceStep::Colon => (':', SourceStep::Quote),
+                        _ => ('"', SourceStep::Value),
+                    };
+                    if next == expected {
+                        self.scanned += 1;
+                        self.step = then;
+                    } else {
+                        self.step = SourceStep::Key;
+                    }
+                }
+                SourceStep::Value => {
+                    let (consumed, closed) = decode_string_piece(rest, &mut self.source);
+                    self.scanned += consumed;
+                    if closed {
+                        self.step = SourceStep::Done;
+                    }
+                    break;
+                }
+                SourceStep::Done => break,
+            }
+        }
+        ToolCallProgress {
+            index,
+            name,
+            received_chars: self.received_chars,
+            source: matches!(self.step, SourceStep::Value | SourceStep::Done)
+                .then(|| self.source.clone()),
+        }
+    }
+}
+
+/// Decodes JSON string content from `text` into `out`, stopping at the closing
+/// quote or before an escape that has not fully arrived. Returns the bytes
+/// consumed and whether the string closed.
+fn decode_string_piece(text: &str, out: &mut String) -> (usize, bool) {
+    let mut chars = text.char_indices();
+    while let Some((at, ch)) = chars.next() {
+        match ch {
+            '"' => return (at + 1, true),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => out.push('\n'),
+                Some((_, 't')) => out.push('\t'),
+                Some((_, 'r')) => out.push('\r'),
+                Some((_, 'u')) => {
+                    let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                    if hex.chars().count() < 4 {
+                        return (at, false);
+                    }
+                    let decoded = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+                    out.push(decoded.unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                Some((_, other)) => out.push(other),
+                None => return (at, false),
+            },
+            other => out.push(other),
+        }
+    }
+    (text.len(), false)
 }
 
 #[derive(Default)]
@@ -360,28 +741,54 @@ struct ToolCallState {
     name: Option<String>,
     arguments: String,
     call_id: Option<String>,
+    preview: SourcePreview,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        llm::StreamEvent,
+        llm::{
+            Sampling, StreamEvent, ToolChoice,
+            test_server::{serve_once, serve_sequence},
+        },
         types::{Message, Role},
     };
     use tokio::sync::mpsc;
 
     fn test_client() -> OpenAiClient {
+        client_for("https://example.test".into())
+    }
+
+    fn client_for(base_url: String) -> OpenAiClient {
         OpenAiClient::new(OpenAiConfig {
             api_key: "test-key".into(),
             model: "test-model".into(),
-            base_url: "https://example.test".into(),
+            base_url,
             organization: None,
             project: None,
+            user: None,
+            parallel_tool_calls: None,
+            error_body_limit: DEFAULT_ERROR_BODY_LIMIT,
+            headers: ExtraHeaders::default(),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retry_base_delay: Duration::from_millis(1),
         })
         .expect("client")
     }
 
+    #[test]
+    fn payload_includes_user_only_when_configured() {
+        let request = ChatRequest::new(vec![Message::new(Role::User, "ping")]);
+        let payload = test_client().build_payload(&request, false);
+        assert!(payload.get("user").is_none());
+
+        let mut client = test_client();
+        client.config.user = Some("team-42".into());
+        let payload = client.build_payload(&request, true);
+        assert_eq!(payload["user"], "team-42");
+    }
+
     #[test]
     fn payload_includes_system_prompt() {
         let client = test_client();
@@ -397,6 +804,107 @@ mod tests {
         assert_eq!(messages[1]["role"], "user");
     }
 
+    #[test]
+    fn payload_prefix_is_stable_across_turns() {
+        // OpenAI caches identical prompt prefixes automatically, so the system
+        // prompt and tools must serialize the same way as history grows.
+        let client = test_client();
+        let tool = LlmTool::new("lua_run_script", "Run Lua", json!({"type": "object"}));
+        let first = ChatRequest::new(vec![Message::new(Role::User, "one")])
+            .with_system_prompt("system")
+            .with_tool(tool.clone());
+        let mut second = first.clone();
+        second.messages.push(Message::new(Role::Assistant, "reply"));
+        second.messages.push(Message::new(Role::User, "two"));
+
+        let first = client.build_payload(&first, false);
+        let second = client.build_payload(&second, false);
+        assert_eq!(first["tools"], second["tools"]);
+        let first_messages = first["messages"].as_array().expect("messages");
+        let second_messages = second["messages"].as_array().expect("messages");
+        assert_eq!(first_messages[0]["role"], "system");
+        assert_eq!(first_messages[..], second_messages[..first_messages.len()]);
+    }
+
+    #[test]
+    fn payload_serializes_each_tool_choice() {
+        let client = test_client();
+        let tool = LlmTool::new("lua_run_script", "Run Lua", json!({"type": "object"}));
+        let request = ChatRequest::new(vec![Message::new(Role::User, "ping")]).with_tool(tool);
+        assert!(
+            client
+                .build_payload(&request, false)
+                .get("tool_choice")
+                .is_none()
+        );
+
+        let cases = [
+            (ToolChoice::Auto, json!("auto")),
+            (ToolChoice::None, json!("none")),
+            (ToolChoice::Required, json!("required")),
+            (
+                ToolChoice::Tool("lua_run_script".into()),
+                json!({"type": "function", "function": {"name": "lua_run_script"}}),
+            ),
+        ];
+        for (choice, expected) in cases {
+            let payload = client.build_payload(&request.clone().with_tool_choice(choice), false);
+            assert_eq!(payload["tool_choice"], expected);
+            // `none` still sends the definitions so earlier tool calls stay valid.
+            assert_eq!(payload["tools"][0]["function"]["name"], "lua_run_script");
+        }
+
+        let no_tools = ChatRequest::new(vec![Message::new(Role::User, "ping")])
+            .with_tool_choice(ToolChoice::Required);
+        assert!(
+            client
+                .build_payload(&no_tools, false)
+                .get("tool_choice")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn payload_includes_parallel_tool_calls_when_configured() {
+        let tool = LlmTool::new("lua_run_script", "Run Lua", json!({"type": "object"}));
+        let request = ChatRequest::new(vec![Message::new(Role::User, "ping")]).with_tool(tool);
+        let payload = test_client().build_payload(&request, false);
+        assert!(payload.get("parallel_tool_calls").is_none());
+
+        let mut client = test_client();
+        client.config.parallel_tool_calls = Some(false);
+        let payload = client.build_payload(&request, true);
+        assert_eq!(payload["parallel_tool_calls"], false);
+
+        let no_tools = ChatRequest::new(vec![Message::new(Role::User, "ping")]);
+        assert!(
+            client
+                .build_payload(&no_tools, false)
+                .get("parallel_tool_calls")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn payload_includes_only_configured_sampling() {
+        let client = test_client();
+        let request = ChatRequest::new(vec![Message::new(Role::User, "ping")]);
+        let payload = client.build_payload(&request, false);
+        for key in ["temperature", "top_p", "max_tokens"] {
+            assert!(payload.get(key).is_none(), "{key} should be omitted");
+        }
+
+        let request = request.with_sampling(Sampling {
+            temperature: Some(0.2),
+            top_p: None,
+            max_tokens: Some(512),
+        });
+        let payload = client.build_payload(&request, false);
+        assert_eq!(payload["temperature"], 0.2);
+        assert!(payload.get("top_p").is_none());
+        assert_eq!(payload["max_tokens"], 512);
+    }
+
     #[test]
     fn payload_includes_tools() {
         let client = test_client();
@@ -443,6 +951,13 @@ mod tests {
     fn payload_includes_tool_messages_with_id() {
         let client = test_client();
         let mut request = ChatRequest::new(vec![Message::new(Role::User, "ping")]);
+        let mut assistant = Message::new(Role::Assistant, "");
+        assistant.tool_calls.push(ToolInvocation::from_parts(
+            "lua_run_script",
+            json!({"source": "return 1"}),
+            Some("call_123".into()),
+        ));
+        request.messages.push(assistant);
         request
             .messages
             .push(Message::new_tool("call_123", "result output"));
@@ -451,10 +966,10 @@ mod tests {
             .get("messages")
             .and_then(|v| v.as_array())
             .expect("messages");
-        assert_eq!(messages.len(), 2);
-        assert_eq!(messages[1]["role"], "tool");
-        assert_eq!(messages[1]["tool_call_id"], "call_123");
-        assert_eq!(messages[1]["content"], "result output");
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2]["role"], "tool");
+        assert_eq!(messages[2]["tool_call_id"], "call_123");
+        assert_eq!(messages[2]["content"], "result output");
     }
 
     #[test]
@@ -469,15 +984,79 @@ mod tests {
         let mut assistant = Message::new(Role::Assistant, "Queuing tool run");
         assistant.tool_calls.push(invocation);
         request.messages.push(assistant);
+        request.messages.push(Message::new_tool("call_456", "1"));
         let payload = client.build_payload(&request, false);
         let messages = payload
             .get("messages")
             .and_then(|v| v.as_array())
             .expect("messages");
-        assert_eq!(messages.len(), 2);
+        assert_eq!(messages.len(), 3);
         assert!(messages[1].get("tool_calls").is_some());
     }
 
+    fn call_message(content: &str, ids: &[&str]) -> Message {
+        let mut message = Message::new(Role::Assistant, content);
+        for id in ids {
+            message.tool_calls.push(ToolInvocation::from_parts(
+                "lua_run_script",
+                json!({"source": "return 1"}),
+                Some(id.to_string()),
+            ));
+        }
+        message
+    }
+
+    fn roles_and_ids(messages: &[Message]) -> Vec<(Role, Option<&str>)> {
+        messages
+            .iter()
+            .map(|m| (m.role, m.tool_call_id.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn normalize_history_keeps_well_formed_sequence() {
+        let history = vec![
+            Message::new(Role::User, "run it"),
+            call_message("", &["a", "b"]),
+            Message::new_tool("a", "1"),
+            Message::new_tool("b", "2"),
+            Message::new(Role::Assistant, "done"),
+        ];
+        let normalized = normalize_history(&history);
+        assert_eq!(roles_and_ids(&normalized), roles_and_ids(&history));
+        assert_eq!(normalized[1].tool_calls.len(), 2);
+    }
+
+    #[test]
+    fn normalize_history_repairs_out_of_order_tool_results() {
+        let history = vec![
+            Message::new_tool("trimmed", "orphan from a dropped turn"),
+            Message::new(Role::User, "run it"),
+            call_message("Running", &["a", "never_answered"]),
+            Message::new(Role::Assistant, "Lua: note between call and result"),
+            Message::new_tool("a", "1"),
+            call_message("", &["lost"]),
+            Message::new(Role::User, "next"),
+        ];
+        let normalized = normalize_history(&history);
+        assert_eq!(
+            roles_and_ids(&normalized),
+            vec![
+                (Role::User, None),
+                (Role::Assistant, None),
+                (Role::Tool, Some("a")),
+                (Role::Assistant, None),
+                (Role::User, None),
+            ]
+        );
+        let ids: Vec<_> = normalized[1]
+            .tool_calls
+            .iter()
+            .map(|c| c.call_id.as_deref())
+            .collect();
+        assert_eq!(ids, vec![Some("a")]);
+    }
+
     #[test]
     fn parse_chat_response_returns_plain_text() {
         let body = serde_json::json!({
@@ -525,6 +1104,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_chat_response_collects_every_tool_call() {
+        let call = |id: &str, source: &str| {
+            serde_json::json!({
+                "id": id,
+                "type": "function",
+                "function": {
+                    "name": "lua_run_script",
+                    "arguments": serde_json::json!({ "source": source }).to_string()
+                }
+            })
+        };
+        let body = serde_json::json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [call("call_a", "return 1"), call("call_b", "return 2")]
+                }
+            }]
+        });
+        let ChatResponse::ToolCalls(invocations) = parse_chat_response(&body).expect("parsed")
+        else {
+            panic!("expected tool calls");
+        };
+        let ids: Vec<_> = invocations.iter().map(|i| i.call_id.as_deref()).collect();
+        assert_eq!(ids, [Some("call_a"), Some("call_b")]);
+        assert_eq!(invocations[1].arguments["source"], "return 2");
+    }
+
     #[test]
     fn handle_stream_chunk_emits_events() {
         let (tx, mut rx) = mpsc::unbounded_channel();
@@ -552,6 +1161,12 @@ mod tests {
             StreamEvent::Delta(text) => assert_eq!(text, "Hello"),
             other => panic!("expected delta, got {other:?}"),
         }
+        match rx.try_recv().expect("progress event") {
+            StreamEvent::ToolCallProgress(progress) => {
+                assert_eq!(progress.source.as_deref(), Some("return 1"));
+            }
+            other => panic!("expected tool call progress, got {other:?}"),
+        }
         let second = rx.try_recv().expect("tool call event");
         match second {
             StreamEvent::ToolCall(invocation) => {
@@ -564,14 +1179,127 @@ mod tests {
 
     #[test]
     fn truncate_payload_limits_length() {
-        let short = truncate_payload("hello");
+        let short = truncate_payload("hello", DEFAULT_ERROR_BODY_LIMIT);
         assert_eq!(short, "hello");
         let long = "a".repeat(600);
-        let truncated = truncate_payload(&long);
+        let truncated = truncate_payload(&long, DEFAULT_ERROR_BODY_LIMIT);
         assert!(truncated.ends_with('…'));
+        assert!(
+            !truncated.contains("\u{e2}\u{20ac}"),
+            "ellipsis must not be mojibake"
+        );
         assert_eq!(truncated.len(), 500 + "…".len());
     }
 
+    #[test]
+    fn truncate_payload_cuts_multibyte_text_on_char_boundary() {
+        let body = format!("{{\"error\":\"{}\"}}", "日本語のエラー".repeat(100));
+        assert!(body.len() > DEFAULT_ERROR_BODY_LIMIT);
+        let truncated = truncate_payload(&body, DEFAULT_ERROR_BODY_LIMIT);
+        assert_eq!(truncated.chars().count(), DEFAULT_ERROR_BODY_LIMIT + 1);
+        assert!(truncated.ends_with('…'));
+
+        let odd = truncate_payload("ééééé", 3);
+        assert_eq!(odd, "ééé…");
+    }
+
+    #[test]
+    fn api_error_surfaces_message_for_unauthorized() {
+        let body = r#"{
+            "error": {
+                "message": "Incorrect API key provided: sk-abc. You can find your API key at https://platform.openai.com/account/api-keys.",
+                "type": "invalid_request_error",
+                "param": null,
+                "code": "invalid_api_key"
+            }
+        }"#;
+        let err = api_error("chat", StatusCode::UNAUTHORIZED, body, 500).to_string();
+        assert!(
+            err.starts_with("OpenAI error (invalid_api_key): Incorrect API key provided"),
+            "{err}"
+        );
+        assert!(err.ends_with("[chat status 401]"), "{err}");
+        assert!(!err.contains("\"param\""));
+    }
+
+    #[test]
+    fn api_error_falls_back_to_type_when_code_is_null() {
+        let body = r#"{"error":{"message":"Invalid value for 'temperature'.","type":"invalid_request_error","param":"temperature","code":null}}"#;
+        let err = api_error("chat_stream", StatusCode::BAD_REQUEST, body, 500).to_string();
+        assert_eq!(
+            err,
+            "OpenAI error (invalid_request_error): Invalid value for 'temperature'. [chat_stream status 400]"
+        );
+    }
+
+    #[test]
+    fn api_error_keeps_raw_body_when_not_json() {
+        let err = api_error(
+            "chat",
+            StatusCode::BAD_GATEWAY,
+            "<html>bad gateway</html>",
+            500,
+        );
+        assert_eq!(
+            err.to_string(),
+            "OpenAI chat failed (status 502 Bad Gateway): <html>bad gateway</html>"
+        );
+    }
+
+    #[test]
+    fn parse_model_list_keeps_chat_models_sorted() {
+        let body = json!({
+            "object": "list",
+            "data": [
+                {"id": "gpt-4o-mini", "object": "model", "owned_by": "system"},
+                {"id": "text-embedding-3-small", "object": "model", "owned_by": "system"},
+                {"id": "gpt-4o", "object": "model", "owned_by": "system"},
+                {"id": "whisper-1", "object": "model", "owned_by": "openai-internal"},
+                {"id": "gpt-3.5-turbo-instruct", "object": "model", "owned_by": "system"},
+                {"id": "o3-mini", "object": "model", "owned_by": "system"},
+                {"id": "dall-e-3", "object": "model", "owned_by": "system"}
+            ]
+        });
+        let ids = parse_model_list(&body).expect("ids");
+        assert_eq!(ids, vec!["gpt-4o", "gpt-4o-mini", "o3-mini"]);
+
+        let err = parse_model_list(&json!({"object": "list"})).unwrap_err();
+        assert!(err.to_string().contains("missing `data`"));
+    }
+
+    #[test]
+    fn custom_headers_are_added_to_client_defaults() {
+        let mut config = test_client().config;
+        config.headers = ExtraHeaders(
+            [
+                ("HTTP-Referer", "https://example.com"),
+                ("X-Title", "SelenAI"),
+                ("X-Gateway-Token", "secret"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        );
+        let headers = build_default_headers(&config).expect("headers");
+        assert_eq!(headers["http-referer"], "https://example.com");
+        assert_eq!(headers["x-title"], "SelenAI");
+        assert!(headers["x-gateway-token"].is_sensitive());
+        assert!(!headers["x-title"].is_sensitive());
+        assert!(!format!("{headers:?}").contains("secret"));
+        OpenAiClient::new(config.clone()).expect("client");
+
+        config.headers = ExtraHeaders([("bad header".to_string(), "x".to_string())].into());
+        let err = build_default_headers(&config).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("invalid header name `bad header` in [openai.headers]")
+        );
+
+        config.headers = ExtraHeaders([("X-Ok".to_string(), "line\nbreak".to_string())].into());
+        let err = build_default_headers(&config).unwrap_err();
+        assert!(err.to_string().contains("invalid value for header `X-Ok`"));
+    }
+
     #[test]
     fn log_payload_respects_env_flag() {
         unsafe {
@@ -583,6 +1311,71 @@ mod tests {
         }
     }
 
+    #[test]
+    fn streamed_tool_arguments_report_partial_source() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut tool_state: HashMap<usize, ToolCallState> = HashMap::new();
+        let fragments = [
+            r#"{"sour"#,
+            r#"ce": "local x = \"a"#,
+            r#"\"\nreturn x"#,
+            r#""}"#,
+        ];
+        for (step, fragment) in fragments.iter().enumerate() {
+            let chunk = json!({
+                "choices": [{
+                    "delta": {
+                        "tool_calls": [{
+                            "index": 0,
+                            "id": (step == 0).then_some("call_7"),
+                            "function": {
+                                "name": (step == 0).then_some("lua_run_script"),
+                                "arguments": fragment
+                            }
+                        }]
+                    }
+                }]
+            });
+            handle_stream_chunk(&chunk, &tx, &mut tool_state).expect("stream chunk");
+        }
+
+        let mut progress = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            match event {
+                StreamEvent::ToolCallProgress(update) => progress.push(update),
+                other => panic!("expected progress, got {other:?}"),
+            }
+        }
+        let sources: Vec<Option<&str>> = progress.iter().map(|p| p.source.as_deref()).collect();
+        assert_eq!(
+            sources,
+            [
+                None,
+                Some("local x = \"a"),
+                Some("local x = \"a\"\nreturn x"),
+                Some("local x = \"a\"\nreturn x"),
+            ]
+        );
+        assert_eq!(progress[0].name.as_deref(), Some("lua_run_script"));
+        let raw_len = fragments.concat().chars().count();
+        assert_eq!(progress.last().unwrap().received_chars, raw_len);
+        assert_eq!(tool_state[&0].arguments, fragments.concat());
+    }
+
+    #[test]
+    fn source_preview_resumes_across_any_split() {
+        let raw =
+            "{\"reason\": \"x\", \"source\" : \"a\\\"b\\u00e9\\n\\u2713 \u{e9}\", \"more\": 1}";
+        let mut preview = SourcePreview::default();
+        let mut last = None;
+        for end in (1..=raw.len()).filter(|end| raw.is_char_boundary(*end)) {
+            last = preview.advance(0, None, &raw[..end]).source;
+        }
+        assert_eq!(last.as_deref(), Some("a\"b\u{e9}\n\u{2713} \u{e9}"));
+        assert_eq!(preview.received_chars, raw.chars().count());
+        assert_eq!(preview.step, SourceStep::Done);
+    }
+
     #[test]
     fn finalize_tool_calls_emits_pending_invocations() {
         let (tx, mut rx) = mpsc::unbounded_channel();
@@ -591,6 +1384,7 @@ mod tests {
             name: Some("lua_run_script".into()),
             arguments: r#"{"source":"return 1"}"#.into(),
             call_id: Some("call_1".into()),
+            ..Default::default()
         };
         map.insert(0, state);
         finalize_tool_calls(&mut map, &tx);
@@ -604,4 +1398,137 @@ mod tests {
         }
         assert!(map.is_empty());
     }
+
+    #[test]
+    fn finalize_tool_calls_emits_in_index_order() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut map: HashMap<usize, ToolCallState> = HashMap::new();
+        for index in (0..8).rev() {
+            map.insert(
+                index,
+                ToolCallState {
+                    name: Some("lua_run_script".into()),
+                    arguments: format!(r#"{{"source":"return {index}"}}"#),
+                    call_id: Some(format!("call_{index}")),
+                    ..Default::default()
+                },
+            );
+        }
+        finalize_tool_calls(&mut map, &tx);
+        let mut ids = Vec::new();
+        while let Ok(StreamEvent::ToolCall(invocation)) = rx.try_recv() {
+            ids.push(invocation.call_id.unwrap());
+        }
+        let expected: Vec<_> = (0..8).map(|index| format!("call_{index}")).collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[tokio::test]
+    async fn health_check_reports_resolved_model() {
+        let (base_url, server) = serve_once(
+            "200 OK",
+            r#"{"id":"test-model-2024-07-18","object":"model","owned_by":"system"}"#,
+        );
+        let model = client_for(base_url)
+            .health_check()
+            .await
+            .expect("health check");
+        assert_eq!(model.as_deref(), Some("test-model-2024-07-18"));
+
+        let request = server.join().expect("server");
+        assert!(
+            request.starts_with("GET /v1/models/test-model "),
+            "{request}"
+        );
+        assert!(
+            request
+                .to_ascii_lowercase()
+                .contains("authorization: bearer test-key"),
+            "{request}"
+        );
+    }
+
+    #[tokio::test]
+    async fn health_check_surfaces_auth_failure() {
+        let (base_url, server) = serve_once(
+            "401 Unauthorized",
+            r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error","code":"invalid_api_key"}}"#,
+        );
+        let err = client_for(base_url)
+            .health_check()
+            .await
+            .expect_err("should fail");
+        server.join().expect("server");
+        assert_eq!(
+            err.to_string(),
+            "OpenAI error (invalid_api_key): Incorrect API key provided [health_check status 401]"
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_retries_transient_failures() {
+        let (base_url, server) = serve_sequence(vec![
+            (
+                "429 Too Many Requests",
+                "retry-after: 0\r\n",
+                r#"{"error":{"message":"slow down","type":"rate_limit"}}"#,
+            ),
+            ("503 Service Unavailable", "", "busy"),
+            (
+                "200 OK",
+                "",
+                r#"{"choices":[{"message":{"role":"assistant","content":"pong"}}]}"#,
+            ),
+        ]);
+        let request = ChatRequest::new(vec![Message::new(Role::User, "ping")]);
+        let response = client_for(base_url).chat(request).await.expect("chat");
+        assert!(matches!(response, ChatResponse::Assistant(ref m) if m.content == "pong"));
+        assert_eq!(server.join().expect("server").len(), 3);
+    }
+
+    #[tokio::test]
+    async fn chat_stops_retrying_after_max_attempts() {
+        let (base_url, server) = serve_sequence(vec![
+            ("502 Bad Gateway", "", "bad gateway"),
+            ("502 Bad Gateway", "", "bad gateway"),
+        ]);
+        let mut client = client_for(base_url);
+        client.config.max_attempts = 2;
+        let request = ChatRequest::new(vec![Message::new(Role::User, "ping")]);
+        let err = client.chat(request).await.expect_err("should fail");
+        assert_eq!(server.join().expect("server").len(), 2);
+        assert_eq!(
+            err.to_string(),
+            "OpenAI chat failed (status 502 Bad Gateway): bad gateway"
+        );
+
+        let (base_url, server) = serve_sequence(vec![("400 Bad Request", "", "nope")]);
+        let request = ChatRequest::new(vec![Message::new(Role::User, "ping")]);
+        client_for(base_url)
+            .chat(request)
+            .await
+            .expect_err("not retried");
+        assert_eq!(server.join().expect("server").len(), 1);
+    }
+
+    #[test]
+    fn retry_delays_honor_retry_after_and_grow() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("2"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(2)));
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3600"));
+        assert_eq!(retry_after(&headers), Some(MAX_RETRY_AFTER));
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), None);
+
+        let base = Duration::from_millis(100);
+        for (attempt, floor) in [(1, 100), (2, 200), (3, 400)] {
+            let delay = backoff_delay(base, attempt);
+            let floor = Duration::from_millis(floor);
+            assert!(delay >= floor && delay <= floor.mul_f64(1.25), "{delay:?}");
+        }
+    }
 }
@@ -1,39 +1,149 @@
 use std::{
-    cell::RefCell,
+    cell::{OnceCell, RefCell},
+    collections::HashSet,
     ffi::OsString,
-    fs, io,
+    fs,
+    future::Future,
+    io::{self, Read, Write as _},
     path::{Component, Path, PathBuf},
-    process::{Command, Stdio},
+    process::{Child, Command, Stdio},
     rc::Rc,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result, bail};
-use mlua::{Lua, LuaOptions, StdLib, Table, UserData, UserDataMethods, Value, Variadic};
+use mlua::{
+    HookTriggers, Lua, LuaOptions, StdLib, Table, UserData, UserDataMethods, Value, Variadic,
+};
 use patch::{Line, Patch};
-use reqwest::{Method, blocking::Client, header::HeaderName, header::HeaderValue};
+use reqwest::{Client, Method, header::HeaderName, header::HeaderValue, redirect};
+use serde_json::Value as JsonValue;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use walk::{SearchOptions, WalkOptions};
+
+mod walk;
 
 const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024; // 10 MB
+/// Commits `rust.git_log` returns when `max_count` is not given.
+const DEFAULT_GIT_LOG_COUNT: u32 = 20;
+/// Table nesting rendered for results; deeper levels (and cycles) are
+/// summarized instead of recursing forever.
+const MAX_RENDER_DEPTH: usize = 16;
+/// Lua instructions between clock checks while a timeout is armed; small
+/// enough that tight numeric loops are still stopped promptly.
+const TIMEOUT_CHECK_INTERVAL: u32 = 4096;
+/// Characters of a value shown by `/lua vars` before it is cut off.
+const GLOBAL_PREVIEW_CHARS: usize = 60;
+/// Limit on a whole `rust.http_request` exchange when `timeout_ms` is not given.
+const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct LuaExecutor {
     lua: Lua,
     logs: Rc<RefCell<Vec<String>>>,
     stdout: Rc<RefCell<Vec<String>>>,
     stderr: Rc<RefCell<Vec<String>>>,
+    modified: Rc<RefCell<Vec<PathBuf>>>,
     workspace_root: PathBuf,
     allow_writes: bool,
-    http: Client,
+    write_scope: Option<PathBuf>,
+    extensions_dir: Option<PathBuf>,
+    prelude_file: Option<PathBuf>,
+    model: Option<String>,
+    command_timeout: Option<Duration>,
+    load_warnings: RefCell<Vec<String>>,
+    /// Globals present once the sandbox, preludes, and extensions loaded;
+    /// `/lua vars` hides these.
+    builtin_globals: RefCell<HashSet<String>>,
+    io: ExecutorIo,
+    /// Client for `follow_redirects = false`, built on first use because
+    /// reqwest fixes the redirect policy per client.
+    http_no_redirect: Rc<OnceCell<Client>>,
+}
+
+/// Shared async plumbing the synchronous HTTP helper runs on, handed in by
+/// the host so every executor reuses one connection pool and runtime.
+#[derive(Debug, Clone)]
+pub struct ExecutorIo {
+    pub http: Client,
+    /// Must belong to a multi-threaded runtime: `Handle::block_on` cannot
+    /// drive I/O for a current-thread runtime that nobody else is running.
+    pub runtime: Handle,
+}
+
+impl ExecutorIo {
+    /// Process-wide runtime and client for tests, which have no host app.
+    #[cfg(test)]
+    fn standalone() -> Result<Self> {
+        use std::sync::OnceLock;
+        use tokio::runtime::Runtime;
+
+        static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+        let runtime = match RUNTIME.get() {
+            Some(runtime) => runtime,
+            None => {
+                let runtime = Runtime::new().context("failed to start Lua I/O runtime")?;
+                RUNTIME.get_or_init(|| runtime)
+            }
+        };
+        Ok(Self {
+            http: Client::builder().build()?,
+            runtime: runtime.handle().clone(),
+        })
+    }
+}
+
+/// Knobs applied when building a [`LuaExecutor`].
+#[derive(Debug, Clone, Default)]
+pub struct ExecutorOptions {
+    pub allow_writes: bool,
+    /// Workspace-relative directory that writes are confined to.
+    pub write_scope: Option<PathBuf>,
+    /// Directory of user `*.lua` files loaded after the prelude.
+    pub extensions_dir: Option<PathBuf>,
+    /// Extra prelude evaluated right after the built-in one.
+    pub prelude_file: Option<PathBuf>,
+    /// Model id exposed to scripts as `selenai.model`.
+    pub model: Option<String>,
+    /// Default limit for `rust.run_command`; `None` lets commands run forever.
+    pub command_timeout: Option<Duration>,
 }
 
 #[derive(Debug, Clone)]
 pub struct LuaExecution {
     pub value: String,
+    /// JSON form of the returned value when the script returned a table.
+    pub structured: Option<JsonValue>,
+    /// Indented, multi-line form of the returned table (see `rust.pretty`).
+    pub pretty: Option<String>,
     pub logs: Vec<String>,
     pub stdout: Vec<String>,
     pub stderr: Vec<String>,
 }
 
 impl LuaExecutor {
+    /// Test shorthand for an executor with default options; the app always
+    /// goes through [`LuaExecutor::with_options`].
+    #[cfg(test)]
     pub fn new(root: impl Into<PathBuf>, allow_writes: bool) -> Result<Self> {
+        let io = ExecutorIo::standalone()?;
+        Self::with_options(
+            root,
+            ExecutorOptions {
+                allow_writes,
+                ..ExecutorOptions::default()
+            },
+            io,
+        )
+    }
+
+    pub fn with_options(
+        root: impl Into<PathBuf>,
+        options: ExecutorOptions,
+        io: ExecutorIo,
+    ) -> Result<Self> {
         let root = root.into();
         let canonical = if root.exists() {
             root.canonicalize()
@@ -41,9 +151,36 @@ impl LuaExecutor {
         } else {
             root
         };
+        let write_scope = options
+            .write_scope
+            .map(|scope| resolve_safe_path(&canonical, &scope))
+            .transpose()
+            .context("invalid write_scope")?;
+        let extensions_dir = options
+            .extensions_dir
+            .map(|dir| {
+                if dir.is_absolute() {
+                    canonicalize_with_missing(&dir)
+                        .with_context(|| format!("failed to access {}", dir.display()))
+                } else {
+                    resolve_safe_path(&canonical, &dir)
+                }
+            })
+            .transpose()
+            .context("invalid lua_extensions_dir")?;
+        let prelude_file = options
+            .prelude_file
+            .map(|file| {
+                if file.is_absolute() {
+                    Ok(file)
+                } else {
+                    resolve_safe_path(&canonical, &file)
+                }
+            })
+            .transpose()
+            .context("invalid lua_prelude_file")?;
+
 
-        let http = Client::builder().build()?;
-        
         // Initialize Persistent Lua VM
         let lua = Lua::new_with(StdLib::ALL_SAFE, LuaOptions::default())?;
         
@@ -69,9 +206,18 @@ impl LuaExecutor {
             logs,
             stdout,
             stderr,
+            modified: Rc::new(RefCell::new(Vec::new())),
             workspace_root: canonical,
-            allow_writes,
-            http,
+            allow_writes: options.allow_writes,
+            write_scope,
+            extensions_dir,
+            prelude_file,
+            model: options.model,
+            command_timeout: options.command_timeout,
+            load_warnings: RefCell::new(Vec::new()),
+            builtin_globals: RefCell::new(HashSet::new()),
+            io,
+            http_no_redirect: Rc::new(OnceCell::new()),
         };
         
         executor.init_lua()?;
@@ -86,6 +232,7 @@ impl LuaExecutor {
         let stderr = self.stderr.clone();
 
         let rust_api = self.build_rust_api(lua, logs.clone(), stderr.clone())?;
+        rust_api.set("ext", lua.create_table()?)?;
         let globals = lua.globals();
         let _ = globals.raw_set("os", Value::Nil);
         globals.set("print", self.make_print_fn(lua, stdout)?)?;
@@ -93,17 +240,187 @@ impl LuaExecutor {
         globals.set("rust", rust_api)?;
         globals.set("io", self.build_io_table(lua)?)?;
         globals.set("fs", self.build_fs_table(lua)?)?;
+        globals.set("json", build_json_table(lua)?)?;
+        let string: Table = globals.get("string")?;
+        string.set("trim", lua.create_function(trim_string)?)?;
         let package = self.build_package_table(lua)?;
         globals.set("package", package)?;
         globals.set("require", self.make_safe_require_fn(lua)?)?;
+        globals.set("selenai", self.build_selenai_table(lua)?)?;
         
         // Load Prelude
         let prelude = include_str!("prelude.lua");
         lua.load(prelude).set_name("prelude").exec()?;
+
+        self.load_warnings.borrow_mut().clear();
+        if let Some(file) = &self.prelude_file {
+            self.load_user_prelude(file);
+        }
+        if let Some(dir) = &self.extensions_dir {
+            self.load_extensions(dir);
+        }
+
+        let mut builtins = self.builtin_globals.borrow_mut();
+        builtins.clear();
+        for (name, _) in lua.globals().pairs::<String, Value>().flatten() {
+            builtins.insert(name);
+        }
         
         Ok(())
     }
 
+    /// Sets one user-defined global to nil and reports whether it was set.
+    /// Sandbox built-ins and prelude helpers are refused.
+    pub fn clear_global(&self, name: &str) -> Result<bool> {
+        if self.builtin_globals.borrow().contains(name) {
+            bail!("`{name}` is a built-in global; use /lua reset to restore the sandbox");
+        }
+        let globals = self.lua.globals();
+        let existed = !matches!(globals.raw_get::<_, Value>(name)?, Value::Nil);
+        globals.raw_set(name, Value::Nil)?;
+        Ok(existed)
+    }
+
+    /// Globals defined by scripts since the VM was created or reset, sorted by
+    /// name, each with its type and a short preview of the value.
+    pub fn list_user_globals(&self) -> Vec<(String, String)> {
+        let builtins = self.builtin_globals.borrow();
+        let mut globals: Vec<(String, String)> = self
+            .lua
+            .globals()
+            .pairs::<String, Value>()
+            .flatten()
+            .filter(|(name, _)| !builtins.contains(name))
+            .map(|(name, value)| (name, describe_global(value)))
+            .collect();
+        globals.sort();
+        globals
+    }
+
+    /// Read-only `selenai` table describing the environment scripts run in.
+    /// Writes raise an error; `pairs(selenai)` lists the fields.
+    fn build_selenai_table<'lua>(&self, lua: &'lua Lua) -> Result<Table<'lua>> {
+        let info = lua.create_table()?;
+        info.set(
+            "workspace_root",
+            self.workspace_root.to_string_lossy().into_owned(),
+        )?;
+        info.set("allow_writes", self.allow_writes)?;
+        info.set("model", self.model.clone())?;
+        info.set("version", env!("CARGO_PKG_VERSION"))?;
+
+        lua.load(
+            r#"
+            local info = ...
+            return setmetatable({}, {
+                __index = info,
+                __newindex = function(_, key)
+                    error("selenai." .. tostring(key) .. " is read-only", 2)
+                end,
+                __pairs = function()
+                    return next, info, nil
+                end,
+                __metatable = false,
+            })
+        "#,
+        )
+        .set_name("selenai")
+        .call(info)
+        .map_err(Into::into)
+    }
+
+    fn load_user_prelude(&self, file: &Path) {
+        let loaded = fs::read_to_string(file)
+            .with_context(|| format!("could not read {}", file.display()))
+            .and_then(|source| {
+                self.lua
+                    .load(&source)
+                    .set_name("user_prelude")
+                    .exec()
+                    .map_err(anyhow::Error::from)
+            });
+        if let Err(err) = loaded {
+            self.load_warnings.borrow_mut().push(format!(
+                "lua_prelude_file {} failed to load: {err:#}",
+                file.display()
+            ));
+        }
+    }
+
+    /// Runs each `*.lua` file in `dir` (sorted by name). Failures are recorded in
+    /// `load_warnings` so one broken extension does not disable the sandbox.
+    fn load_extensions(&self, dir: &Path) {
+        let mut warnings = self.load_warnings.borrow_mut();
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warnings.push(format!(
+                    "could not read extensions dir {}: {err}",
+                    dir.display()
+                ));
+                return;
+            }
+        };
+        let mut files: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "lua"))
+            .collect();
+        files.sort();
+
+        for path in files {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let loaded = resolve_safe_path(dir, &path)
+                .and_then(|resolved| {
+                    fs::read_to_string(&resolved)
+                        .with_context(|| format!("could not read {}", resolved.display()))
+                })
+                .and_then(|source| {
+                    self.lua
+                        .load(&source)
+                        .set_name(&name)
+                        .exec()
+                        .map_err(anyhow::Error::from)
+                });
+            if let Err(err) = loaded {
+                warnings.push(format!("extension {name} failed to load: {err:#}"));
+            }
+        }
+    }
+
+    /// Problems hit while loading user extensions during the last init/reset.
+    pub fn load_warnings(&self) -> Vec<String> {
+        self.load_warnings.borrow().clone()
+    }
+
+    pub fn workspace_root(&self) -> &Path {
+        &self.workspace_root
+    }
+
+    /// HTTP client and runtime to hand to a rebuilt executor.
+    pub fn io(&self) -> &ExecutorIo {
+        &self.io
+    }
+
+    /// Applies the checks the Lua write helpers use (write mode, workspace
+    /// containment, `write_scope`) to a path written on the host's behalf.
+    pub fn resolve_write_target(&self, path: &Path) -> Result<PathBuf> {
+        if !self.allow_writes {
+            bail!("write helpers are disabled (set allow_tool_writes = true)");
+        }
+        let resolved = resolve_safe_path(&self.workspace_root, path)?;
+        ensure_in_write_scope(self.write_scope.as_deref(), &resolved)?;
+        Ok(resolved)
+    }
+
+    /// Drains the absolute paths written since the last call.
+    pub fn take_modified_files(&self) -> Vec<PathBuf> {
+        std::mem::take(&mut *self.modified.borrow_mut())
+    }
+
     pub fn reset(&mut self) -> Result<()> {
         self.lua = Lua::new_with(StdLib::ALL_SAFE, LuaOptions::default())?;
         self.logs.borrow_mut().clear();
@@ -119,15 +436,39 @@ impl LuaExecutor {
         self.stderr.borrow_mut().clear();
 
         let value = self.lua.load(script).set_name("tool").eval::<Value>()?;
+        let structured = matches!(value, Value::Table(_)).then(|| value_to_json(&value, 0));
+        let pretty = matches!(value, Value::Table(_)).then(|| pretty_value(&value, 0));
         
         Ok(LuaExecution {
             value: render_value(value),
+            structured,
+            pretty,
             logs: collect_buffer(self.logs.clone()),
             stdout: collect_buffer(self.stdout.clone()),
             stderr: collect_buffer(self.stderr.clone()),
         })
     }
 
+    /// Runs `script` like [`Self::run_script`], aborting with "script exceeded
+    /// Ns timeout" once `timeout` has elapsed. Time spent blocked inside a
+    /// Rust helper is only noticed when control returns to Lua.
+    pub fn run_script_with_timeout(&self, script: &str, timeout: Duration) -> Result<LuaExecution> {
+        let started = Instant::now();
+        let triggers = HookTriggers::new().every_nth_instruction(TIMEOUT_CHECK_INTERVAL);
+        self.lua.set_hook(triggers, move |_, _| {
+            if started.elapsed() >= timeout {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "script exceeded {}s timeout",
+                    timeout.as_secs_f64()
+                )));
+            }
+            Ok(())
+        });
+        let result = self.run_script(script);
+        self.lua.remove_hook();
+        result
+    }
+
     pub fn preview_script(&self, script: &str) -> Result<String> {
         let lua = Lua::new_with(StdLib::ALL_SAFE, LuaOptions::default())?;
         // We use the 'logs' buffer to collect preview messages
@@ -159,6 +500,7 @@ impl LuaExecutor {
         
         globals.set("io", self.build_io_table(&lua)?)?;
         globals.set("fs", self.build_fs_table(&lua)?)?;
+        globals.set("json", build_json_table(&lua)?)?;
         let package = self.build_package_table(&lua)?;
         globals.set("package", package)?;
         globals.set("require", self.make_safe_require_fn(&lua)?)?;
@@ -185,16 +527,31 @@ impl LuaExecutor {
         // Read-only helpers are fine to be real
         table.set("read_file", self.make_read_fn(lua)?)?;
         table.set("list_dir", self.make_list_fn(lua)?)?;
+        table.set("walk_dir", self.make_walk_dir_fn(lua)?)?;
         table.set("http_request", self.make_http_fn(lua)?)?;
         table.set("git_status", self.make_git_status_fn(lua)?)?;
+        table.set("git_diff", self.make_git_diff_fn(lua)?)?;
+        table.set("git_log", self.make_git_log_fn(lua)?)?;
         table.set("search", self.make_search_fn(lua)?)?;
+        table.set("workspace_root", self.make_workspace_root_fn(lua)?)?;
+        table.set("join", self.make_join_fn(lua)?)?;
+        table.set("relpath", self.make_relpath_fn(lua)?)?;
+        table.set(
+            "pretty",
+            lua.create_function(|_, value: Value| Ok(pretty_value(&value, 0)))?,
+        )?;
         table.set("log", self.make_log_fn(lua, logs.clone())?)?; // log to our preview buffer
         table.set("eprint", self.make_eprint_fn(lua, stderr)?)?;
         table.set("mcp", self.make_mcp_table(lua)?)?;
         
         // Write helpers are replaced by preview versions
         table.set("write_file", self.make_preview_write_fn(lua, logs.clone())?)?;
+        table.set(
+            "delete_file",
+            self.make_preview_delete_fn(lua, logs.clone())?,
+        )?;
         table.set("patch_file", self.make_preview_patch_file_fn(lua, logs.clone())?)?;
+        table.set("preview_patch", self.make_preview_patch_fn(lua)?)?;
         table.set("run_command", self.make_preview_run_command_fn(lua, logs.clone())?)?;
         
         Ok(table)
@@ -212,6 +569,24 @@ impl LuaExecutor {
         Ok(fun)
     }
 
+    fn make_preview_delete_fn<'lua>(
+        &self,
+        lua: &'lua Lua,
+        logs: Rc<RefCell<Vec<String>>>,
+    ) -> Result<mlua::Function<'lua>> {
+        let fun = lua.create_function(move |_, (path, recursive): (String, Option<bool>)| {
+            let suffix = if recursive.unwrap_or(false) {
+                " (recursively)"
+            } else {
+                ""
+            };
+            logs.borrow_mut()
+                .push(format!("Would delete `{path}`{suffix}"));
+            Ok(())
+        })?;
+        Ok(fun)
+    }
+
     fn make_preview_patch_file_fn<'lua>(
         &self,
         lua: &'lua Lua,
@@ -267,8 +642,23 @@ impl LuaExecutor {
         lua: &'lua Lua,
         logs: Rc<RefCell<Vec<String>>>,
     ) -> Result<mlua::Function<'lua>> {
-        let fun = lua.create_function(move |lua_ctx, (cmd, args): (String, Vec<String>)| {
-            logs.borrow_mut().push(format!("Would run command: {} {}", cmd, args.join(" ")));
+        let fun = lua.create_function(move |lua_ctx, (cmd, args, opts): RunCommandArgs| {
+            let opts = RunCommandOptions::from_table(opts)?;
+            let mut line = format!("Would run command: {} {}", cmd, args.join(" "));
+            if let Some(cwd) = &opts.cwd {
+                line.push_str(&format!(" (in {cwd})"));
+            }
+            if !opts.env.is_empty() {
+                let names: Vec<&str> = opts.env.iter().map(|(name, _)| name.as_str()).collect();
+                line.push_str(&format!(" with env {}", names.join(", ")));
+            }
+            if let Some(stdin) = &opts.stdin {
+                line.push_str(&format!(" with {} bytes on stdin", stdin.len()));
+            }
+            if let Some(ms) = opts.timeout_ms {
+                line.push_str(&format!(" (timeout {ms} ms)"));
+            }
+            logs.borrow_mut().push(line);
             
             // Return dummy success result so script continues
             let result = lua_ctx.create_table()?;
@@ -289,12 +679,24 @@ impl LuaExecutor {
         let table = lua.create_table()?;
         table.set("read_file", self.make_read_fn(lua)?)?;
         table.set("list_dir", self.make_list_fn(lua)?)?;
+        table.set("walk_dir", self.make_walk_dir_fn(lua)?)?;
         table.set("write_file", self.make_write_fn(lua)?)?;
+        table.set("delete_file", self.make_delete_fn(lua)?)?;
         table.set("patch_file", self.make_patch_file_fn(lua)?)?;
+        table.set("preview_patch", self.make_preview_patch_fn(lua)?)?;
         table.set("http_request", self.make_http_fn(lua)?)?;
         table.set("run_command", self.make_run_command_fn(lua)?)?;
         table.set("git_status", self.make_git_status_fn(lua)?)?;
+        table.set("git_diff", self.make_git_diff_fn(lua)?)?;
+        table.set("git_log", self.make_git_log_fn(lua)?)?;
         table.set("search", self.make_search_fn(lua)?)?;
+        table.set("workspace_root", self.make_workspace_root_fn(lua)?)?;
+        table.set("join", self.make_join_fn(lua)?)?;
+        table.set("relpath", self.make_relpath_fn(lua)?)?;
+        table.set(
+            "pretty",
+            lua.create_function(|_, value: Value| Ok(pretty_value(&value, 0)))?,
+        )?;
         table.set("log", self.make_log_fn(lua, logs)?)?;
         table.set("eprint", self.make_eprint_fn(lua, stderr)?)?;
         table.set("mcp", self.make_mcp_table(lua)?)?;
@@ -343,6 +745,8 @@ impl LuaExecutor {
     fn make_io_open_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
         let root = self.workspace_root.clone();
         let allow_writes = self.allow_writes;
+        let scope = self.write_scope.clone();
+        let touched = self.modified.clone();
         let fun = lua.create_function(move |lua_ctx, (path, mode): (String, Option<String>)| {
             let mode_str = mode.unwrap_or_else(|| "r".to_string());
             let file_mode =
@@ -354,8 +758,12 @@ impl LuaExecutor {
             }
             let resolved =
                 resolve_safe_path(&root, Path::new(&path)).map_err(mlua::Error::external)?;
+            if file_mode.allows_write() {
+                ensure_in_write_scope(scope.as_deref(), &resolved)
+                    .map_err(mlua::Error::external)?;
+            }
 
-            if !file_mode.allows_write() {
+            if file_mode.allows_read() {
                  // Check size if reading
                 if let Ok(meta) = fs::metadata(&resolved) {
                      if meta.len() > MAX_FILE_SIZE {
@@ -367,6 +775,9 @@ impl LuaExecutor {
                 }
             }
 
+            if file_mode.allows_write() {
+                record_modified(&touched, &resolved);
+            }
             let handle = LuaFileHandle::open(resolved, file_mode)
                 .map_err(|err| mlua::Error::external(format!("{err:#}")))?;
             lua_ctx.create_userdata(handle)
@@ -418,11 +829,21 @@ impl LuaExecutor {
                     mlua::Error::external(format!("error listing {}: {e}", resolved.display()))
                 })?;
                 let meta = lua_ctx.create_table()?;
+                let file_type = entry.file_type().ok();
                 meta.set("name", entry.file_name().to_string_lossy().to_string())?;
-                meta.set(
-                    "is_dir",
-                    entry.file_type().map(|t| t.is_dir()).unwrap_or(false),
-                )?;
+                meta.set("is_dir", file_type.is_some_and(|t| t.is_dir()))?;
+                meta.set("is_symlink", file_type.is_some_and(|t| t.is_symlink()))?;
+                // Metadata describes the entry itself (not a symlink's target);
+                // when it cannot be read, `size` and `modified` stay nil.
+                if let Ok(info) = entry.metadata() {
+                    meta.set("size", info.len())?;
+                    let modified = info
+                        .modified()
+                        .ok()
+                        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                        .map(|age| age.as_secs());
+                    meta.set("modified", modified)?;
+                }
                 list.set(idx + 1, meta)?;
             }
 
@@ -431,9 +852,37 @@ impl LuaExecutor {
         Ok(fun)
     }
 
+    fn make_walk_dir_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
+        let root = self.workspace_root.clone();
+        let fun = lua.create_function(move |lua_ctx, (path, opts): (String, Option<Table>)| {
+            let resolved =
+                resolve_safe_path(&root, Path::new(&path)).map_err(mlua::Error::external)?;
+            let options = WalkOptions::from_table(opts)?;
+            let is_safe = |entry: &Path| resolve_safe_path(&root, entry).is_ok();
+            let (entries, truncated) = walk::walk_dir(&root, &resolved, &options, &is_safe)
+                .map_err(mlua::Error::external)?;
+
+            let list = lua_ctx.create_table()?;
+            for (idx, entry) in entries.into_iter().enumerate() {
+                let meta = lua_ctx.create_table()?;
+                meta.set("path", entry.path)?;
+                meta.set("is_dir", entry.is_dir)?;
+                meta.set("size", entry.size)?;
+                list.set(idx + 1, meta)?;
+            }
+            if truncated {
+                list.set("truncated", true)?;
+            }
+            Ok(list)
+        })?;
+        Ok(fun)
+    }
+
     fn make_write_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
         let root = self.workspace_root.clone();
         let allow = self.allow_writes;
+        let scope = self.write_scope.clone();
+        let touched = self.modified.clone();
         let fun = lua.create_function(move |_, (path, contents): (String, String)| {
             if !allow {
                 return Err(mlua::Error::external(
@@ -442,6 +891,7 @@ impl LuaExecutor {
             }
             let resolved =
                 resolve_safe_path(&root, Path::new(&path)).map_err(mlua::Error::external)?;
+            ensure_in_write_scope(scope.as_deref(), &resolved).map_err(mlua::Error::external)?;
             if let Some(parent) = resolved.parent() {
                 fs::create_dir_all(parent).map_err(|e| {
                     mlua::Error::external(format!(
@@ -453,76 +903,150 @@ impl LuaExecutor {
             fs::write(&resolved, contents).map_err(|e| {
                 mlua::Error::external(format!("could not write {}: {e}", resolved.display()))
             })?;
+            record_modified(&touched, &resolved);
             Ok(())
         })?;
         Ok(fun)
     }
 
-    fn make_patch_file_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
+    /// `rust.delete_file(path, recursive?)`: removes a file, or a directory
+    /// tree only when `recursive` is true. The workspace root itself is refused.
+    fn make_delete_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
         let root = self.workspace_root.clone();
         let allow = self.allow_writes;
-        let fun = lua.create_function(move |_, (path, diff): (String, String)| {
+        let scope = self.write_scope.clone();
+        let touched = self.modified.clone();
+        let fun = lua.create_function(move |_, (path, recursive): (String, Option<bool>)| {
             if !allow {
                 return Err(mlua::Error::external(
                     "write helpers are disabled (set allow_tool_writes = true)",
                 ));
             }
+            // A symlink is deleted itself, never the target it points to.
+            if let Ok(link) = resolve_safe_entry(&root, Path::new(&path))
+                && fs::symlink_metadata(&link).is_ok_and(|meta| meta.file_type().is_symlink())
+            {
+                ensure_in_write_scope(scope.as_deref(), &link).map_err(mlua::Error::external)?;
+                fs::remove_file(&link).map_err(|e| {
+                    mlua::Error::external(format!("could not delete {}: {e}", link.display()))
+                })?;
+                record_modified(&touched, &link);
+                return Ok(());
+            }
             let resolved =
                 resolve_safe_path(&root, Path::new(&path)).map_err(mlua::Error::external)?;
-
-            let meta = fs::metadata(&resolved).map_err(|e| {
-                mlua::Error::external(format!("could not get metadata for {}: {e}", resolved.display()))
-            })?;
-            if meta.len() > MAX_FILE_SIZE {
-                return Err(mlua::Error::external(format!(
-                    "file {} exceeds size limit ({} bytes)",
-                    path, MAX_FILE_SIZE
-                )));
+            ensure_in_write_scope(scope.as_deref(), &resolved).map_err(mlua::Error::external)?;
+            if resolved == root {
+                return Err(mlua::Error::external(
+                    "refusing to delete the workspace root",
+                ));
             }
-
-            let original = fs::read_to_string(&resolved).map_err(|e| {
-                mlua::Error::external(format!("could not read {}: {e}", resolved.display()))
+            let meta = fs::symlink_metadata(&resolved).map_err(|e| {
+                mlua::Error::external(format!("could not delete {}: {e}", resolved.display()))
             })?;
-
-            let patch = Patch::from_single(&diff).map_err(|e| {
-                mlua::Error::external(format!("failed to parse diff: {e}"))
+            let removed = if meta.is_dir() {
+                if !recursive.unwrap_or(false) {
+                    return Err(mlua::Error::external(format!(
+                        "{path} is a directory (use rust.delete_file(path, true) to remove it)"
+                    )));
+                }
+                fs::remove_dir_all(&resolved)
+            } else {
+                fs::remove_file(&resolved)
+            };
+            removed.map_err(|e| {
+                mlua::Error::external(format!("could not delete {}: {e}", resolved.display()))
             })?;
+            record_modified(&touched, &resolved);
+            Ok(())
+        })?;
+        Ok(fun)
+    }
 
-            let modified = apply_patch(&original, &patch).map_err(|e| {
-                 mlua::Error::external(format!("failed to apply patch: {e}"))
-            })?;
+    fn make_patch_file_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
+        let root = self.workspace_root.clone();
+        let allow = self.allow_writes;
+        let scope = self.write_scope.clone();
+        let touched = self.modified.clone();
+        let fun = lua.create_function(move |_, (path, diff): (String, String)| {
+            if !allow {
+                return Err(mlua::Error::external(
+                    "write helpers are disabled (set allow_tool_writes = true)",
+                ));
+            }
+            let resolved =
+                resolve_safe_path(&root, Path::new(&path)).map_err(mlua::Error::external)?;
+            ensure_in_write_scope(scope.as_deref(), &resolved).map_err(mlua::Error::external)?;
+
+            let modified = read_and_patch(&resolved, &path, &diff)?;
             
             fs::write(&resolved, modified).map_err(|e| {
                 mlua::Error::external(format!("could not write patched file {}: {e}", resolved.display()))
             })?;
+            record_modified(&touched, &resolved);
             
             Ok(())
         })?;
         Ok(fun)
     }
 
+    fn make_preview_patch_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
+        let root = self.workspace_root.clone();
+        let fun = lua.create_function(move |_, (path, diff): (String, String)| {
+            let resolved =
+                resolve_safe_path(&root, Path::new(&path)).map_err(mlua::Error::external)?;
+            read_and_patch(&resolved, &path, &diff)
+        })?;
+        Ok(fun)
+    }
+
     fn make_run_command_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
         let root = self.workspace_root.clone();
         let allow = self.allow_writes;
-        let fun = lua.create_function(move |lua_ctx, (cmd, args): (String, Vec<String>)| {
+        let default_timeout = self.command_timeout;
+        let fun = lua.create_function(move |lua_ctx, (cmd, args, opts): RunCommandArgs| {
             if !allow {
                 return Err(mlua::Error::external(
                     "write helpers (including run_command) are disabled",
                 ));
             }
 
-            let output = Command::new(&cmd)
+            let opts = RunCommandOptions::from_table(opts)?;
+            let dir = match &opts.cwd {
+                Some(cwd) => {
+                    resolve_safe_path(&root, Path::new(cwd)).map_err(mlua::Error::external)?
+                }
+                None => root.clone(),
+            };
+            let stdin = if opts.stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            };
+            let child = Command::new(&cmd)
                 .args(&args)
-                .current_dir(&root)
+                .current_dir(&dir)
+                .envs(opts.env.iter().map(|(name, value)| (name, value)))
+                .stdin(stdin)
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
-                .output()
+                .spawn()
+                .map_err(|e| mlua::Error::external(format!("failed to run {cmd}: {e}")))?;
+            let timeout = match opts.timeout_ms {
+                Some(0) => None,
+                Some(ms) => Some(Duration::from_millis(ms)),
+                None => default_timeout,
+            };
+            let output = collect_child_output(child, opts.stdin, timeout)
                 .map_err(|e| mlua::Error::external(format!("failed to run {cmd}: {e}")))?;
 
             let result = lua_ctx.create_table()?;
-            result.set("status", output.status.code().unwrap_or(-1))?;
-            result.set("stdout", String::from_utf8_lossy(&output.stdout).to_string())?;
-            result.set("stderr", String::from_utf8_lossy(&output.stderr).to_string())?;
+            result.set("status", output.status)?;
+            result.set("stdout", output.stdout)?;
+            result.set("stderr", output.stderr)?;
+            if output.timed_out {
+                result.set("timed_out", true)?;
+            }
             Ok(result)
         })?;
         Ok(fun)
@@ -545,34 +1069,77 @@ impl LuaExecutor {
         Ok(fun)
     }
 
+    /// `rust.git_diff(opts?)`: read-only, so it works without `allow_writes`.
+    fn make_git_diff_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
+        let root = self.workspace_root.clone();
+        let fun = lua.create_function(move |lua_ctx, opts: Option<Table>| {
+            let opts = GitQueryOptions::from_table(opts)?;
+            // External diff drivers and textconv filters come from repo config
+            // and could run arbitrary programs, so plain diffs only.
+            let mut args: Vec<String> = ["diff", "--no-ext-diff", "--no-textconv"]
+                .map(String::from)
+                .to_vec();
+            if opts.staged {
+                args.push("--cached".to_string());
+            }
+            if opts.stat {
+                args.push("--stat".to_string());
+            }
+            opts.push_target_and_paths(&mut args);
+            git_output_table(lua_ctx, &root, &args)
+        })?;
+        Ok(fun)
+    }
+
+    /// `rust.git_log(opts?)`: one line per commit unless `oneline = false`.
+    fn make_git_log_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
+        let root = self.workspace_root.clone();
+        let fun = lua.create_function(move |lua_ctx, opts: Option<Table>| {
+            let opts = GitQueryOptions::from_table(opts)?;
+            let max_count = opts.max_count.unwrap_or(DEFAULT_GIT_LOG_COUNT);
+            let mut args = vec!["log".to_string(), format!("--max-count={max_count}")];
+            if opts.oneline {
+                args.push("--oneline".to_string());
+            }
+            if opts.stat {
+                args.push("--stat".to_string());
+            }
+            opts.push_target_and_paths(&mut args);
+            git_output_table(lua_ctx, &root, &args)
+        })?;
+        Ok(fun)
+    }
+
     fn make_search_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
         let root = self.workspace_root.clone();
-        let fun = lua.create_function(move |lua_ctx, (pattern, dir): (String, Option<String>)| {
-            let target_dir = if let Some(d) = dir {
-                resolve_safe_path(&root, Path::new(&d)).map_err(mlua::Error::external)?
-            } else {
-                root.clone()
+        let fun = lua.create_function(move |lua_ctx, (pattern, dir, opts): SearchArgs| {
+            let target = match dir {
+                Some(d) => {
+                    resolve_safe_path(&root, Path::new(&d)).map_err(mlua::Error::external)?
+                }
+                None => root.clone(),
             };
-
-            let output = Command::new("grep")
-                .args(["-r", "-n", &pattern, "."])
-                .current_dir(&target_dir)
-                .output()
-                .map_err(|e| mlua::Error::external(format!("grep failed: {e}")))?;
+            let options = SearchOptions::from_table(opts)?;
+            let hits =
+                walk::search(&root, &target, &pattern, &options).map_err(mlua::Error::external)?;
 
             let result = lua_ctx.create_table()?;
-            result.set("status", output.status.code().unwrap_or(-1))?;
-            result.set("stdout", String::from_utf8_lossy(&output.stdout).to_string())?;
-            result.set("stderr", String::from_utf8_lossy(&output.stderr).to_string())?;
+            for (idx, hit) in hits.into_iter().enumerate() {
+                let entry = lua_ctx.create_table()?;
+                entry.set("file", hit.file)?;
+                entry.set("line_number", hit.line_number)?;
+                entry.set("text", hit.text)?;
+                result.set(idx + 1, entry)?;
+            }
             Ok(result)
         })?;
         Ok(fun)
     }
 
-
-
     fn make_http_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
-        let client = self.http.clone();
+        let follow = self.io.http.clone();
+        let no_redirect = Rc::clone(&self.http_no_redirect);
+        let runtime = self.io.runtime.clone();
         let fun = lua.create_function(move |lua_ctx, opts: Table| {
             let url: String = opts
                 .get("url")
@@ -582,8 +1149,35 @@ impl LuaExecutor {
             let method: Method = method.parse().map_err(|_| {
                 mlua::Error::external("http_request method must be a valid HTTP method")
             })?;
+            let timeout = match opts.get::<_, Option<u64>>("timeout_ms")? {
+                Some(0) => {
+                    return Err(mlua::Error::external(
+                        "http_request timeout_ms must be positive",
+                    ));
+                }
+                Some(ms) => Duration::from_millis(ms),
+                None => DEFAULT_HTTP_TIMEOUT,
+            };
+            let follow_redirects = opts
+                .get::<_, Option<bool>>("follow_redirects")?
+                .unwrap_or(true);
 
-            let mut request = client.request(method, &url);
+            let client = if follow_redirects {
+                follow.clone()
+            } else {
+                no_redirect_client(&no_redirect).map_err(mlua::Error::external)?
+            };
+            let mut request = client.request(method, &url).timeout(timeout);
+
+            if let Some(query) = opts.get::<_, Option<Table>>("query")? {
+                let mut pairs = query
+                    .pairs::<String, String>()
+                    .collect::<mlua::Result<Vec<_>>>()
+                    .map_err(|e| mlua::Error::external(format!("invalid query entry: {e}")))?;
+                // Lua tables have no order; sorting keeps the URL reproducible.
+                pairs.sort();
+                request = request.query(&pairs);
+            }
 
             if let Ok(headers) = opts.get::<_, Table>("headers") {
                 for pair in headers.pairs::<String, String>() {
@@ -603,22 +1197,46 @@ impl LuaExecutor {
                 request = request.body(body);
             }
 
-            let response = request
-                .send()
-                .map_err(|e| mlua::Error::external(format!("http_request failed: {e}")))?;
+            let describe = |err: reqwest::Error| {
+                if err.is_timeout() {
+                    format!(
+                        "http_request to {url} timed out after {} ms",
+                        timeout.as_millis()
+                    )
+                } else {
+                    format!("http_request to {url} failed: {err}")
+                }
+            };
+            let (status, headers, body) = block_on_io(&runtime, async move {
+                let response = request.send().await.map_err(describe)?;
+                let status = response.status().as_u16();
+                let headers = response
+                    .headers()
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        value
+                            .to_str()
+                            .ok()
+                            .map(|text| (name.as_str().to_string(), text.to_string()))
+                    })
+                    .collect::<Vec<_>>();
+                let body = response.text().await.map_err(|err| {
+                    if err.is_timeout() {
+                        describe(err)
+                    } else {
+                        format!("failed to read body: {err}")
+                    }
+                })?;
+                Ok::<_, String>((status, headers, body))
+            })
+            .map_err(mlua::Error::external)?
+            .map_err(mlua::Error::external)?;
 
             let header_table = lua_ctx.create_table()?;
-            for (name, value) in response.headers().iter() {
-                if let Ok(text) = value.to_str() {
-                    header_table.set(name.as_str(), text)?;
-                }
+            for (name, value) in headers {
+                header_table.set(name, value)?;
             }
 
-            let status = response.status().as_u16();
-            let body = response
-                .text()
-                .map_err(|e| mlua::Error::external(format!("failed to read body: {e}")))?;
-
             let result = lua_ctx.create_table()?;
             result.set("status", status)?;
             result.set("body", body)?;
@@ -628,6 +1246,30 @@ impl LuaExecutor {
         Ok(fun)
     }
 
+    fn make_workspace_root_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
+        let root = self.workspace_root.to_string_lossy().into_owned();
+        let fun = lua.create_function(move |_, ()| Ok(root.clone()))?;
+        Ok(fun)
+    }
+
+    fn make_join_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
+        let fun = lua.create_function(|_, segments: Variadic<String>| {
+            let joined = join_segments(&segments).map_err(mlua::Error::external)?;
+            Ok(joined.to_string_lossy().into_owned())
+        })?;
+        Ok(fun)
+    }
+
+    fn make_relpath_fn<'lua>(&self, lua: &'lua Lua) -> Result<mlua::Function<'lua>> {
+        let root = self.workspace_root.clone();
+        let fun = lua.create_function(move |_, path: String| {
+            let relative =
+                workspace_relpath(&root, Path::new(&path)).map_err(mlua::Error::external)?;
+            Ok(relative.to_string_lossy().into_owned())
+        })?;
+        Ok(fun)
+    }
+
     fn make_log_fn<'lua>(
         &self,
         lua: &'lua Lua,
@@ -828,6 +1470,51 @@ fn collect_buffer(buffer: Rc<RefCell<Vec<String>>>) -> Vec<String> {
         .unwrap_or_else(|rc| rc.borrow().clone())
 }
 
+fn no_redirect_client(cell: &OnceCell<Client>) -> Result<Client> {
+    if let Some(client) = cell.get() {
+        return Ok(client.clone());
+    }
+    let client = Client::builder()
+        .redirect(redirect::Policy::none())
+        .build()
+        .context("failed to build HTTP client")?;
+    Ok(cell.get_or_init(|| client).clone())
+}
+
+/// Runs `future` on the host runtime from synchronous Lua code.
+/// `Handle::block_on` panics when called from inside another runtime, so
+/// when a script runs on a Tokio worker we hop out via `block_in_place`
+/// (or a scoped thread for current-thread runtimes, which cannot).
+fn block_on_io<F>(runtime: &Handle, future: F) -> Result<F::Output>
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    if runtime.runtime_flavor() != RuntimeFlavor::MultiThread {
+        bail!("Lua I/O needs a multi-threaded Tokio runtime");
+    }
+    let output = match Handle::try_current() {
+        Err(_) => runtime.block_on(future),
+        Ok(current) if current.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| runtime.block_on(future))
+        }
+        Ok(_) => thread::scope(|scope| {
+            scope
+                .spawn(|| runtime.block_on(future))
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        }),
+    };
+    Ok(output)
+}
+
+fn record_modified(buffer: &RefCell<Vec<PathBuf>>, path: &Path) {
+    let mut buffer = buffer.borrow_mut();
+    if !buffer.iter().any(|existing| existing == path) {
+        buffer.push(path.to_path_buf());
+    }
+}
+
 fn ensure_single_component(value: &str, kind: &str) -> Result<()> {
     let mut components = Path::new(value).components();
     match components.next() {
@@ -843,9 +1530,16 @@ fn resolve_safe_path(root: &Path, path: &Path) -> Result<PathBuf> {
         root.join(path)
     };
 
-    let normalized = canonicalize_with_missing(&candidate)
-        .with_context(|| format!("failed to access {}", candidate.display()))?;
-
+    // Symlinks are only followed by `canonicalize`, so the deepest existing
+    // ancestor is resolved first and checked before the missing tail is added.
+    let access_error =
+        |err: io::Error| anyhow::anyhow!("failed to access {}: {err}", candidate.display());
+    let (existing, missing) = split_missing_tail(&candidate).map_err(access_error)?;
+    let mut normalized = canonicalize_existing(&existing).map_err(access_error)?;
+    if !normalized.starts_with(root) {
+        bail!("path {} escapes workspace root", normalized.display());
+    }
+    normalized.extend(missing);
     if !normalized.starts_with(root) {
         bail!("path {} escapes workspace root", normalized.display());
     }
@@ -853,72 +1547,683 @@ fn resolve_safe_path(root: &Path, path: &Path) -> Result<PathBuf> {
     Ok(normalized)
 }
 
-fn canonicalize_with_missing(path: &Path) -> io::Result<PathBuf> {
-    match path.canonicalize() {
-        Ok(value) => Ok(value),
-        Err(err) if err.kind() == io::ErrorKind::NotFound => {
-            let mut segments = Vec::<OsString>::new();
-            let mut current = path;
-            while !current.exists() {
-                let parent = current.parent().ok_or_else(|| {
-                    io::Error::new(
-                        io::ErrorKind::NotFound,
-                        "unable to canonicalize path without parent",
-                    )
-                })?;
-                let name = current.file_name().ok_or_else(|| {
-                    io::Error::new(
-                        io::ErrorKind::NotFound,
-                        "unable to canonicalize unnamed path segment",
-                    )
-                })?;
-                segments.push(name.to_os_string());
-                current = parent;
+/// Like `resolve_safe_path`, but only the parent directory is resolved, so a
+/// final component that is a symlink names the link rather than its target.
+fn resolve_safe_entry(root: &Path, path: &Path) -> Result<PathBuf> {
+    let candidate = if path.is_absolute() {
+        PathBuf::from(path)
+    } else {
+        root.join(path)
+    };
+    let normalized = normalize_lexically(&candidate)?;
+    let (Some(parent), Some(name)) = (normalized.parent(), normalized.file_name()) else {
+        return resolve_safe_path(root, &normalized);
+    };
+    Ok(resolve_safe_path(root, parent)?.join(name))
+}
+
+/// Collapses `.`, `..`, and repeated separators without touching the
+/// filesystem. A `..` that would climb above the start of the path is an error
+/// rather than being silently dropped.
+fn normalize_lexically(path: &Path) -> Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => normalized.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if depth == 0 {
+                    bail!("path {} escapes its base via `..`", path.display());
+                }
+                normalized.pop();
+                depth -= 1;
             }
-            let mut normalized = current.canonicalize()?;
-            while let Some(segment) = segments.pop() {
+            Component::Normal(segment) => {
                 normalized.push(segment);
+                depth += 1;
             }
-            Ok(normalized)
         }
-        Err(err) => Err(err),
     }
-}
-
-fn render_value(value: Value) -> String {
-    match value {
-        Value::Nil => "nil".into(),
-        Value::Boolean(b) => b.to_string(),
-        Value::Integer(i) => i.to_string(),
-        Value::Number(n) => format!("{n}"),
-        Value::String(s) => s.to_string_lossy().into_owned(),
-        Value::Table(t) => table_to_string(&t),
-        Value::Function(_) => "<function>".into(),
-        Value::Thread(_) => "<thread>".into(),
-        Value::UserData(_) => "<userdata>".into(),
-        Value::Error(err) => format!("{err}"),
-        other => format!("{other:?}"),
+    if normalized.as_os_str().is_empty() {
+        normalized.push(".");
     }
+    Ok(normalized)
 }
 
-fn table_to_string(table: &Table) -> String {
-    let mut items = Vec::new();
-    for pair in table.clone().pairs::<Value, Value>() {
-        match pair {
-            Ok((key, value)) => {
-                items.push(format!("{}: {}", render_value(key), render_value(value)));
-            }
-            Err(err) => {
-                return format!("{{error iterating table: {err}}}");
-            }
+/// Backs `rust.join`: only the first segment may be absolute, so a later
+/// `/etc` cannot replace everything joined so far.
+fn join_segments(segments: &[String]) -> Result<PathBuf> {
+    if segments.is_empty() {
+        bail!("join expects at least one path segment");
+    }
+    let mut joined = PathBuf::new();
+    for (index, segment) in segments.iter().enumerate() {
+        let segment = Path::new(segment);
+        if index > 0 && segment.has_root() {
+            bail!("join segment {} must be relative", segment.display());
         }
+        joined.push(segment);
     }
-    format!("{{{}}}", items.join(", "))
+    normalize_lexically(&joined)
 }
 
-fn apply_patch(original: &str, patch: &Patch) -> Result<String> {
-    let mut lines: Vec<&str> = original.lines().collect();
-    let mut offset: isize = 0;
+/// Backs `rust.relpath`: absolute paths must sit under `root`, relative
+/// ones are only normalized.
+fn workspace_relpath(root: &Path, path: &Path) -> Result<PathBuf> {
+    let normalized = normalize_lexically(path)?;
+    if !normalized.has_root() {
+        return Ok(normalized);
+    }
+    let relative = normalized
+        .strip_prefix(root)
+        .map_err(|_| anyhow::anyhow!("path {} is outside the workspace root", path.display()))?;
+    if relative.as_os_str().is_empty() {
+        Ok(PathBuf::from("."))
+    } else {
+        Ok(relative.to_path_buf())
+    }
+}
+
+fn ensure_in_write_scope(scope: Option<&Path>, resolved: &Path) -> Result<()> {
+    match scope {
+        Some(scope) if !resolved.starts_with(scope) => bail!(
+            "path {} is outside the write scope {}",
+            resolved.display(),
+            scope.display()
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn canonicalize_with_missing(path: &Path) -> io::Result<PathBuf> {
+    let (existing, missing) = split_missing_tail(path)?;
+    let mut normalized = canonicalize_existing(&existing)?;
+    normalized.extend(missing);
+    Ok(normalized)
+}
+
+/// Splits `path` into its deepest ancestor that exists and the names below
+/// it that do not. A symlink counts as existing even when its target is
+/// missing, so a dangling link is never mistaken for a plain new file name.
+fn split_missing_tail(path: &Path) -> io::Result<(PathBuf, Vec<OsString>)> {
+    let mut missing = Vec::<OsString>::new();
+    let mut current = path;
+    while fs::symlink_metadata(current).is_err() {
+        let parent = current.parent().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "unable to canonicalize path without parent",
+            )
+        })?;
+        let name = current.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "unable to canonicalize unnamed path segment",
+            )
+        })?;
+        missing.push(name.to_os_string());
+        current = parent;
+    }
+    missing.reverse();
+    Ok((current.to_path_buf(), missing))
+}
+
+/// `canonicalize` with a clearer error for a symlink whose target is gone.
+fn canonicalize_existing(path: &Path) -> io::Result<PathBuf> {
+    path.canonicalize().map_err(|err| {
+        let dangling = err.kind() == io::ErrorKind::NotFound
+            && fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink());
+        if dangling {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is a symlink to a missing target", path.display()),
+            )
+        } else {
+            err
+        }
+    })
+}
+
+/// "number 42", "string \"hi\"", "table {a: 1}", or just "function".
+fn describe_global(value: Value) -> String {
+    // Match Lua's `type()`, which reports integers as numbers.
+    let kind = match value {
+        Value::Integer(_) => "number",
+        ref other => other.type_name(),
+    };
+    let preview = match value {
+        Value::Function(_) | Value::Thread(_) | Value::UserData(_) => return kind.to_string(),
+        Value::String(s) => format!("{:?}", s.to_string_lossy()),
+        other => render_value(other),
+    };
+    let preview: String = match preview.char_indices().nth(GLOBAL_PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}...", &preview[..end]),
+        None => preview,
+    };
+    format!("{kind} {preview}")
+}
+
+fn render_value(value: Value) -> String {
+    render_value_at(value, 0)
+}
+
+fn render_value_at(value: Value, depth: usize) -> String {
+    match value {
+        Value::Nil => "nil".into(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Number(n) => format!("{n}"),
+        Value::String(s) => s.to_string_lossy().into_owned(),
+        Value::Table(_) if depth >= MAX_RENDER_DEPTH => "{...}".into(),
+        Value::Table(t) => table_to_string(&t, depth),
+        Value::Function(_) => "<function>".into(),
+        Value::Thread(_) => "<thread>".into(),
+        Value::UserData(_) => "<userdata>".into(),
+        Value::Error(err) => format!("{err}"),
+        other => format!("{other:?}"),
+    }
+}
+
+/// Converts a Lua value to JSON. Sequences become arrays, other tables become
+/// objects keyed by the rendered key.
+fn value_to_json(value: &Value, depth: usize) -> JsonValue {
+    match value {
+        Value::Nil => JsonValue::Null,
+        Value::Boolean(b) => JsonValue::Bool(*b),
+        Value::Integer(i) => JsonValue::from(*i),
+        Value::Number(n) => serde_json::Number::from_f64(*n)
+            .map(JsonValue::Number)
+            .unwrap_or_else(|| JsonValue::String(n.to_string())),
+        Value::Table(_) if depth >= MAX_RENDER_DEPTH => {
+            JsonValue::String(format!("<table nested deeper than {MAX_RENDER_DEPTH}>"))
+        }
+        Value::Table(t) => table_to_json(t, depth),
+        other => JsonValue::String(render_value(other.clone())),
+    }
+}
+
+fn table_to_json(table: &Table, depth: usize) -> JsonValue {
+    let pairs: Vec<(Value, Value)> = table
+        .clone()
+        .pairs::<Value, Value>()
+        .filter_map(|pair| pair.ok())
+        .collect();
+    let len = table.raw_len();
+    if len > 0 && pairs.len() == len {
+        let items = (1..=len)
+            .map(|idx| {
+                table
+                    .raw_get::<_, Value>(idx)
+                    .map(|item| value_to_json(&item, depth + 1))
+                    .unwrap_or(JsonValue::Null)
+            })
+            .collect();
+        return JsonValue::Array(items);
+    }
+    let map = pairs
+        .into_iter()
+        .map(|(key, item)| (render_value(key), value_to_json(&item, depth + 1)))
+        .collect();
+    JsonValue::Object(map)
+}
+
+/// `json.encode(value, pretty?)` and `json.decode(text)`. Encoding follows
+/// the same rules as structured tool results; decoding maps objects and
+/// arrays to tables and `null` to `nil`.
+fn build_json_table(lua: &Lua) -> Result<Table<'_>> {
+    let table = lua.create_table()?;
+    let encode = lua.create_function(|_, (value, pretty): (Value, Option<bool>)| {
+        let json = value_to_json(&value, 0);
+        let encoded = if pretty.unwrap_or(false) {
+            serde_json::to_string_pretty(&json)
+        } else {
+            serde_json::to_string(&json)
+        };
+        encoded.map_err(|e| mlua::Error::external(format!("json.encode: {e}")))
+    })?;
+    // Bad input is expected from remote bodies, so decode follows the Lua
+    // convention of returning `nil, err` rather than raising.
+    let decode = lua.create_function(|lua_ctx, text: String| {
+        match serde_json::from_str::<JsonValue>(&text) {
+            Ok(json) => Ok((json_to_value(lua_ctx, &json)?, None)),
+            Err(e) => Ok((Value::Nil, Some(format!("json.decode: {e}")))),
+        }
+    })?;
+    table.set("encode", encode)?;
+    table.set("decode", decode)?;
+    Ok(table)
+}
+
+/// `rust.run_command(cmd, args, opts?)` arguments.
+type RunCommandArgs<'lua> = (String, Vec<String>, Option<Table<'lua>>);
+
+/// `rust.search(pattern, dir?, opts?)` arguments.
+type SearchArgs<'lua> = (String, Option<String>, Option<Table<'lua>>);
+
+/// The optional third argument of `rust.run_command`.
+#[derive(Default)]
+struct RunCommandOptions {
+    /// Written to the child's stdin, which is otherwise closed.
+    stdin: Option<String>,
+    /// Variables set on top of the inherited environment, sorted by name.
+    env: Vec<(String, String)>,
+    /// Working directory relative to the workspace root.
+    cwd: Option<String>,
+    /// Overrides the configured limit; 0 disables it.
+    timeout_ms: Option<u64>,
+}
+
+impl RunCommandOptions {
+    fn from_table(opts: Option<Table>) -> mlua::Result<Self> {
+        let Some(opts) = opts else {
+            return Ok(Self::default());
+        };
+        let mut env = Vec::new();
+        if let Some(vars) = opts.get::<_, Option<Table>>("env")? {
+            for pair in vars.pairs::<String, String>() {
+                env.push(pair.map_err(|e| {
+                    mlua::Error::external(format!("invalid run_command env entry: {e}"))
+                })?);
+            }
+            env.sort();
+        }
+        Ok(Self {
+            stdin: opts.get("stdin")?,
+            env,
+            cwd: opts.get("cwd")?,
+            timeout_ms: opts.get("timeout_ms")?,
+        })
+    }
+}
+
+/// Options shared by `rust.git_diff` and `rust.git_log`.
+struct GitQueryOptions {
+    /// Revision or range such as `HEAD~3` or `main..feature`.
+    target: Option<String>,
+    /// Limit output to these workspace paths.
+    paths: Vec<String>,
+    /// `git_diff` only: compare the index instead of the working tree.
+    staged: bool,
+    stat: bool,
+    /// `git_log` only.
+    max_count: Option<u32>,
+    oneline: bool,
+}
+
+impl Default for GitQueryOptions {
+    fn default() -> Self {
+        Self {
+            target: None,
+            paths: Vec::new(),
+            staged: false,
+            stat: false,
+            max_count: None,
+            oneline: true,
+        }
+    }
+}
+
+impl GitQueryOptions {
+    fn from_table(opts: Option<Table>) -> mlua::Result<Self> {
+        let Some(opts) = opts else {
+            return Ok(Self::default());
+        };
+        let target: Option<String> = opts.get("target")?;
+        // A leading dash would be read as a flag (`--output=<file>` writes).
+        if let Some(target) = &target
+            && target.starts_with('-')
+        {
+            return Err(mlua::Error::external(format!(
+                "git target {target:?} cannot start with '-'"
+            )));
+        }
+        Ok(Self {
+            target: target.filter(|target| !target.is_empty()),
+            paths: opts
+                .get::<_, Option<Vec<String>>>("paths")?
+                .unwrap_or_default(),
+            staged: opts.get::<_, Option<bool>>("staged")?.unwrap_or(false),
+            stat: opts.get::<_, Option<bool>>("stat")?.unwrap_or(false),
+            max_count: opts.get("max_count")?,
+            oneline: opts.get::<_, Option<bool>>("oneline")?.unwrap_or(true),
+        })
+    }
+
+    /// Appends the target and, after `--`, the paths so neither is parsed as
+    /// an option.
+    fn push_target_and_paths(&self, args: &mut Vec<String>) {
+        args.extend(self.target.clone());
+        if !self.paths.is_empty() {
+            args.push("--".to_string());
+            args.extend(self.paths.iter().cloned());
+        }
+    }
+}
+
+/// Runs git in `root` and returns `{status, stdout, stderr}`.
+fn git_output_table<'lua>(
+    lua: &'lua Lua,
+    root: &Path,
+    args: &[String],
+) -> mlua::Result<Table<'lua>> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(root)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| mlua::Error::external(format!("git {} failed: {e}", args[0])))?;
+    let result = lua.create_table()?;
+    result.set("status", output.status.code().unwrap_or(-1))?;
+    result.set(
+        "stdout",
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    )?;
+    result.set(
+        "stderr",
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )?;
+    Ok(result)
+}
+
+/// What `rust.run_command` hands back to Lua.
+struct CommandOutput {
+    /// Exit code, or -1 when the child was killed or died from a signal.
+    status: i32,
+    stdout: String,
+    stderr: String,
+    timed_out: bool,
+}
+
+/// Feeds `stdin` and drains both pipes on helper threads while waiting for
+/// `child`. Past `timeout` the child is killed and the output gathered so far
+/// is returned, with a "timed out" note appended to stderr.
+fn collect_child_output(
+    mut child: Child,
+    stdin: Option<String>,
+    timeout: Option<Duration>,
+) -> io::Result<CommandOutput> {
+    // Writing from another thread keeps a child that fills its output pipes
+    // before reading all of stdin from deadlocking us.
+    let writer = match (stdin, child.stdin.take()) {
+        (Some(input), Some(mut pipe)) => {
+            Some(thread::spawn(move || pipe.write_all(input.as_bytes())))
+        }
+        _ => None,
+    };
+    let stdout = PipeReader::spawn(child.stdout.take());
+    let stderr = PipeReader::spawn(child.stderr.take());
+
+    let status = match timeout {
+        None => Some(child.wait()?),
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break Some(status);
+                }
+                if Instant::now() >= deadline {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break None;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    };
+    if let Some(writer) = writer {
+        // A child that exits without reading all of stdin is not an error.
+        let _ = writer.join();
+    }
+
+    // Grandchildren can keep the pipes open after a kill, so a timed-out run
+    // only waits briefly for the readers before taking what they have.
+    let grace = status.is_none().then(|| Duration::from_millis(200));
+    let stdout = stdout.finish(grace);
+    let mut stderr = stderr.finish(grace);
+    let timed_out = status.is_none();
+    if let Some(timeout) = timeout.filter(|_| timed_out) {
+        if !stderr.is_empty() && !stderr.ends_with('\n') {
+            stderr.push('\n');
+        }
+        stderr.push_str(&format!("timed out after {} ms", timeout.as_millis()));
+    }
+    Ok(CommandOutput {
+        status: status.and_then(|status| status.code()).unwrap_or(-1),
+        stdout,
+        stderr,
+        timed_out,
+    })
+}
+
+/// Collects a child pipe on a background thread into a shared buffer, so the
+/// bytes read so far are available even if the pipe never closes.
+struct PipeReader {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl PipeReader {
+    fn spawn(pipe: Option<impl Read + Send + 'static>) -> Self {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let handle = pipe.map(|mut pipe| {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || {
+                let mut chunk = [0u8; 8192];
+                while let Ok(read) = pipe.read(&mut chunk) {
+                    if read == 0 {
+                        break;
+                    }
+                    let mut buffer = buffer.lock().unwrap_or_else(|e| e.into_inner());
+                    buffer.extend_from_slice(&chunk[..read]);
+                }
+            })
+        });
+        Self { buffer, handle }
+    }
+
+    /// Waits for the pipe to close (at most `grace`, when given) and returns
+    /// its contents as lossy UTF-8.
+    fn finish(self, grace: Option<Duration>) -> String {
+        if let Some(handle) = self.handle {
+            match grace {
+                None => {
+                    let _ = handle.join();
+                }
+                Some(grace) => {
+                    let deadline = Instant::now() + grace;
+                    while !handle.is_finished() && Instant::now() < deadline {
+                        thread::sleep(Duration::from_millis(5));
+                    }
+                }
+            }
+        }
+        let bytes = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+/// `string.trim`: strips Unicode whitespace from valid UTF-8, and only ASCII
+/// whitespace from raw byte strings.
+fn trim_string<'lua>(lua: &'lua Lua, text: mlua::String<'lua>) -> mlua::Result<mlua::String<'lua>> {
+    let bytes = text.as_bytes();
+    match std::str::from_utf8(bytes) {
+        Ok(s) => lua.create_string(s.trim()),
+        Err(_) => lua.create_string(bytes.trim_ascii()),
+    }
+}
+
+/// Inverse of [`value_to_json`]: arrays become sequences (1-based), objects
+/// become keyed tables, and `null` becomes `nil`.
+fn json_to_value<'lua>(lua: &'lua Lua, json: &JsonValue) -> mlua::Result<Value<'lua>> {
+    Ok(match json {
+        JsonValue::Null => Value::Nil,
+        JsonValue::Bool(b) => Value::Boolean(*b),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        JsonValue::String(s) => Value::String(lua.create_string(s)?),
+        JsonValue::Array(items) => {
+            let table = lua.create_table_with_capacity(items.len(), 0)?;
+            for (idx, item) in items.iter().enumerate() {
+                table.raw_set(idx + 1, json_to_value(lua, item)?)?;
+            }
+            Value::Table(table)
+        }
+        JsonValue::Object(map) => {
+            let table = lua.create_table_with_capacity(0, map.len())?;
+            for (key, item) in map {
+                table.raw_set(key.as_str(), json_to_value(lua, item)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+/// Lua-literal rendering with one entry per line and nested tables indented
+/// two spaces per level. Sequences list values; other tables sort their keys.
+fn pretty_value(value: &Value, depth: usize) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s.to_string_lossy()),
+        Value::Table(_) if depth >= MAX_RENDER_DEPTH => "{...}".into(),
+        Value::Table(table) => pretty_table(table, depth),
+        other => render_value(other.clone()),
+    }
+}
+
+fn pretty_table(table: &Table, depth: usize) -> String {
+    let mut pairs: Vec<(Value, Value)> = table
+        .clone()
+        .pairs::<Value, Value>()
+        .filter_map(|pair| pair.ok())
+        .collect();
+    if pairs.is_empty() {
+        return "{}".into();
+    }
+    let is_sequence = pairs.len() == table.raw_len()
+        && pairs
+            .iter()
+            .all(|(key, _)| matches!(key, Value::Integer(_)));
+    let mut entries: Vec<(String, String)> = Vec::new();
+    if is_sequence {
+        pairs.sort_by_key(|(key, _)| key.as_i64().unwrap_or_default());
+        for (_, value) in &pairs {
+            entries.push((String::new(), pretty_value(value, depth + 1)));
+        }
+    } else {
+        for (key, value) in &pairs {
+            let key = match key {
+                Value::String(s) => {
+                    let text = s.to_string_lossy().into_owned();
+                    if is_lua_identifier(&text) {
+                        format!("{text} = ")
+                    } else {
+                        format!("[{text:?}] = ")
+                    }
+                }
+                other => format!("[{}] = ", render_value(other.clone())),
+            };
+            entries.push((key, pretty_value(value, depth + 1)));
+        }
+        entries.sort();
+    }
+
+    let indent = "  ".repeat(depth + 1);
+    let mut out = String::from("{\n");
+    for (key, value) in entries {
+        out.push_str(&format!("{indent}{key}{value},\n"));
+    }
+    out.push_str(&"  ".repeat(depth));
+    out.push('}');
+    out
+}
+
+fn is_lua_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// Renders entries in the prelude's `sorted_pairs` order (numeric keys
+/// first, then the rest by text) so output is stable across runs.
+fn table_to_string(table: &Table, depth: usize) -> String {
+    let mut items = Vec::new();
+    for pair in table.clone().pairs::<Value, Value>() {
+        match pair {
+            Ok((key, value)) => {
+                let number = match key {
+                    Value::Integer(i) => Some(i as f64),
+                    Value::Number(n) => Some(n),
+                    _ => None,
+                };
+                let key = render_value(key);
+                let value = render_value_at(value, depth + 1);
+                items.push((number, key, value));
+            }
+            Err(err) => {
+                return format!("{{error iterating table: {err}}}");
+            }
+        }
+    }
+    items.sort_by(|a, b| match (a.0, b.0) {
+        (Some(x), Some(y)) => x.total_cmp(&y),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.1.cmp(&b.1),
+    });
+    let items: Vec<String> = items
+        .into_iter()
+        .map(|(_, key, value)| format!("{key}: {value}"))
+        .collect();
+    format!("{{{}}}", items.join(", "))
+}
+
+/// Computes the contents `rust.patch_file` would write, without touching disk.
+fn read_and_patch(resolved: &Path, path: &str, diff: &str) -> mlua::Result<String> {
+    let meta = fs::metadata(resolved).map_err(|e| {
+        mlua::Error::external(format!(
+            "could not get metadata for {}: {e}",
+            resolved.display()
+        ))
+    })?;
+    if meta.len() > MAX_FILE_SIZE {
+        return Err(mlua::Error::external(format!(
+            "file {} exceeds size limit ({} bytes)",
+            path, MAX_FILE_SIZE
+        )));
+    }
+
+    let original = fs::read_to_string(resolved).map_err(|e| {
+        mlua::Error::external(format!("could not read {}: {e}", resolved.display()))
+    })?;
+
+    let patch = Patch::from_single(diff)
+        .map_err(|e| mlua::Error::external(format!("failed to parse diff: {e}")))?;
+
+    apply_patch(&original, &patch)
+        .map_err(|e| mlua::Error::external(format!("failed to apply patch: {e}")))
+}
+
+/// Applies each hunk of `patch` to `original` by line position, tracking how
+/// earlier hunks shifted later ones. Context and removed lines must match the
+/// file exactly. Untouched lines keep their own `\n` or `\r\n` ending, added
+/// lines use the file's style (CRLF if any line has it), and the result ends
+/// with a newline exactly when the original did (new, empty files get one).
+pub(crate) fn apply_patch(original: &str, patch: &Patch) -> Result<String> {
+    let mut lines: Vec<(&str, &str)> = original
+        .split_inclusive('\n')
+        .map(split_line_ending)
+        .collect();
+    let eol = if original.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let trailing_newline = original.is_empty() || original.ends_with('\n');
+    let mut offset: isize = 0;
 
     for hunk in &patch.hunks {
         let start = hunk.old_range.start as isize + offset - 1;
@@ -931,21 +2236,55 @@ fn apply_patch(original: &str, patch: &Patch) -> Result<String> {
              bail!("patch application out of bounds (line {})", start + 1);
         }
         
+        let mut cursor = start;
         let mut new_block = Vec::new();
         for line in &hunk.lines {
-             match line {
-                 Line::Context(s) | Line::Add(s) => new_block.push(*s),
-                 Line::Remove(_) => {}
-             }
+            match line {
+                Line::Context(expected) | Line::Remove(expected) => {
+                    let found = lines.get(cursor).copied();
+                    if found.map(|(content, _)| content) != Some(*expected) {
+                        bail!(
+                            "patch does not match file at line {}: expected {:?}, found {:?}",
+                            cursor + 1,
+                            expected,
+                            found.map_or("<end of file>", |(content, _)| content)
+                        );
+                    }
+                    if let (Line::Context(_), Some(kept)) = (line, found) {
+                        new_block.push(kept);
+                    }
+                    cursor += 1;
+                }
+                Line::Add(added) => new_block.push((*added, eol)),
+            }
         }
         
-        lines.splice(start..start+old_count, new_block.clone());
-        
         let new_count = new_block.len();
+        lines.splice(start..start + old_count, new_block);
+
         offset += (new_count as isize) - (old_count as isize);
     }
-    
-    Ok(lines.join("\n"))
+
+    let mut out = String::with_capacity(original.len());
+    let last = lines.len().saturating_sub(1);
+    for (idx, (content, ending)) in lines.into_iter().enumerate() {
+        out.push_str(content);
+        if idx < last || trailing_newline {
+            out.push_str(if ending.is_empty() { eol } else { ending });
+        }
+    }
+    Ok(out)
+}
+
+/// Splits a line from `split_inclusive('\n')` into its content and ending.
+fn split_line_ending(line: &str) -> (&str, &str) {
+    if let Some(content) = line.strip_suffix("\r\n") {
+        (content, "\r\n")
+    } else if let Some(content) = line.strip_suffix('\n') {
+        (content, "\n")
+    } else {
+        (line, "")
+    }
 }
 
 #[cfg(test)]
@@ -967,40 +2306,708 @@ mod tests {
             return data
         "#,
         )?;
-        assert_eq!(output.value, "alpha\nbeta");
+        assert_eq!(output.value, "alpha\nbeta");
+        Ok(())
+    }
+
+    #[test]
+    fn returned_tables_carry_structured_json() -> Result<()> {
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let output = executor.run_script(
+            r#"return { name = "selenai", files = { "a.rs", "b.rs" }, ok = true, ratio = 0.5 }"#,
+        )?;
+        assert_eq!(
+            output.structured,
+            Some(serde_json::json!({
+                "name": "selenai",
+                "files": ["a.rs", "b.rs"],
+                "ok": true,
+                "ratio": 0.5
+            }))
+        );
+        assert!(executor.run_script("return 42")?.structured.is_none());
+
+        let cyclic = executor.run_script("local t = {} t.self = t return t")?;
+        assert!(cyclic.structured.is_some());
+        assert!(cyclic.value.contains("{...}"));
+        Ok(())
+    }
+
+    #[test]
+    fn io_open_respects_write_flag() -> Result<()> {
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let err = executor.run_script(
+            r#"
+            local f = io.open("new.txt", "w")
+        "#,
+        );
+        assert!(
+            err.unwrap_err()
+                .to_string()
+                .contains("write helpers are disabled")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn io_open_allows_creating_files_when_enabled() -> Result<()> {
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), true)?;
+        let output = executor.run_script(
+            r#"
+            local f = io.open("dir/example.txt", "w")
+            f:write("demo")
+            f:close()
+            return rust.read_file("dir/example.txt")
+        "#,
+        )?;
+        assert_eq!(output.value, "demo");
+        Ok(())
+    }
+
+    #[test]
+    fn io_open_read_write_modes_seek_and_overwrite() -> Result<()> {
+        let tmp = tempdir()?;
+        fs::write(tmp.path().join("data.txt"), "version = 1\nname = demo\n")?;
+        let executor = LuaExecutor::new(tmp.path(), true)?;
+        let output = executor.run_script(
+            r#"
+            local f = io.open("data.txt", "r+")
+            local first = f:read("*l")
+            local pos = f:seek("set", 10)
+            f:write("2")
+            local size = f:seek("end")
+            f:write("extra = true\n")
+            f:seek("set")
+            local all = f:read("*a")
+            f:close()
+
+            local g = io.open("fresh.txt", "w+")
+            g:write("abc")
+            g:seek("set", 1)
+            local rest = g:read("*a")
+            g:close()
+            return table.concat({ first, pos, size, rest }, "|") .. "\n" .. all
+        "#,
+        )?;
+        let expected = "version = 1|10|24|bc\nversion = 2\nname = demo\nextra = true\n";
+        assert_eq!(output.value, expected);
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("data.txt"))?,
+            "version = 2\nname = demo\nextra = true\n"
+        );
+        assert_eq!(fs::read_to_string(tmp.path().join("fresh.txt"))?, "abc");
+
+        let err = executor
+            .run_script(r#"local f = io.open("data.txt", "r+"); f:seek("cur", -1)"#)
+            .unwrap_err();
+        assert!(err.to_string().contains("cannot seek"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn file_read_supports_numbers_and_byte_counts() -> Result<()> {
+        let tmp = tempdir()?;
+        fs::write(tmp.path().join("nums.txt"), "  42 -3.5e1 0x1F rest\nabcdé")?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let output = executor.run_script(
+            r#"
+            local f = io.open("nums.txt", "r")
+            local a, b, c = f:read("*n"), f:read("*n"), f:read("*n")
+            local missing = f:read("*n")
+            local word = f:read(5)
+            f:read("*l")
+            local head = f:read(4)
+            local tail = f:read(10)
+            local eof_count, eof_zero = f:read(3), f:read(0)
+            f:close()
+            return table.concat({
+                math.type(a), a, b, c, tostring(missing), word, head, #tail,
+                tostring(eof_count), tostring(eof_zero),
+            }, "|")
+        "#,
+        )?;
+        assert_eq!(output.value, "integer|42|-35.0|31|nil| rest|abcd|2|nil|nil");
+
+        let err = executor
+            .run_script(r#"io.open("nums.txt"):read("*x")"#)
+            .unwrap_err();
+        assert!(err.to_string().contains("\"*n\", or a byte count"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn selenai_global_describes_environment_and_is_read_only() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut executor = LuaExecutor::with_options(
+            tmp.path(),
+            ExecutorOptions {
+                allow_writes: true,
+                model: Some("gpt-4o-mini".into()),
+                ..ExecutorOptions::default()
+            },
+            ExecutorIo::standalone()?,
+        )?;
+        let root = tmp.path().canonicalize()?;
+        let value = |executor: &LuaExecutor, script: &str| -> Result<String> {
+            Ok(executor.run_script(script)?.value)
+        };
+        assert_eq!(
+            value(&executor, "return selenai.workspace_root")?,
+            root.to_string_lossy()
+        );
+        assert_eq!(value(&executor, "return selenai.allow_writes")?, "true");
+        assert_eq!(value(&executor, "return selenai.model")?, "gpt-4o-mini");
+        assert_eq!(
+            value(&executor, "return selenai.version")?,
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(
+            value(
+                &executor,
+                "local n = 0 for _ in pairs(selenai) do n = n + 1 end return n"
+            )?,
+            "4"
+        );
+
+        let err = executor
+            .run_script("selenai.allow_writes = false")
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("selenai.allow_writes is read-only")
+        );
+        assert!(
+            executor
+                .run_script("return setmetatable(selenai, {})")
+                .is_err()
+        );
+
+        executor.run_script("selenai = nil")?;
+        executor.reset()?;
+        assert_eq!(value(&executor, "return selenai.allow_writes")?, "true");
+
+        let read_only = LuaExecutor::new(tmp.path(), false)?;
+        assert_eq!(value(&read_only, "return selenai.allow_writes")?, "false");
+        assert_eq!(value(&read_only, "return selenai.model")?, "nil");
+        Ok(())
+    }
+
+    #[test]
+    fn write_scope_limits_writes_but_not_reads() -> Result<()> {
+        let tmp = tempdir()?;
+        fs::create_dir_all(tmp.path().join("src"))?;
+        fs::write(tmp.path().join("Cargo.toml"), "[package]\n")?;
+        let executor = LuaExecutor::with_options(
+            tmp.path(),
+            ExecutorOptions {
+                allow_writes: true,
+                write_scope: Some(PathBuf::from("src")),
+                ..ExecutorOptions::default()
+            },
+            ExecutorIo::standalone()?,
+        )?;
+
+        executor.run_script(r#"rust.write_file("src/lib.rs", "// ok")"#)?;
+        assert_eq!(fs::read_to_string(tmp.path().join("src/lib.rs"))?, "// ok");
+        assert_eq!(
+            executor.take_modified_files(),
+            vec![tmp.path().canonicalize()?.join("src/lib.rs")]
+        );
+
+        for script in [
+            r#"rust.write_file("Cargo.toml", "oops")"#,
+            r#"local f = io.open("notes.txt", "w"); f:write("x"); f:close()"#,
+        ] {
+            let err = executor.run_script(script).unwrap_err();
+            assert!(
+                format!("{err:#}").contains("outside the write scope"),
+                "unexpected error: {err:#}"
+            );
+        }
+        assert!(!tmp.path().join("notes.txt").exists());
+
+        let read = executor.run_script(r#"return rust.read_file("Cargo.toml")"#)?;
+        assert!(read.value.contains("[package]"));
+        Ok(())
+    }
+
+    #[test]
+    fn extensions_register_helpers_and_skip_broken_files() -> Result<()> {
+        let tmp = tempdir()?;
+        let ext_dir = tmp.path().join(".selenai/lua");
+        fs::create_dir_all(&ext_dir)?;
+        fs::write(
+            ext_dir.join("greet.lua"),
+            "function rust.ext.greet(name) return \"hi \" .. name end",
+        )?;
+        fs::write(ext_dir.join("broken.lua"), "function (")?;
+        fs::write(ext_dir.join("notes.txt"), "not lua")?;
+        fs::write(
+            ext_dir.join("sandbox.lua"),
+            "function rust.ext.has_os() return os ~= nil end",
+        )?;
+
+        let executor = LuaExecutor::with_options(
+            tmp.path(),
+            ExecutorOptions {
+                extensions_dir: Some(PathBuf::from(".selenai/lua")),
+                ..ExecutorOptions::default()
+            },
+            ExecutorIo::standalone()?,
+        )?;
+        let result = executor.run_script(r#"return rust.ext.greet("bob")"#)?;
+        assert_eq!(result.value, "hi bob");
+
+        let warnings = executor.load_warnings();
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(warnings[0].contains("broken.lua"));
+
+        let sandboxed = executor.run_script("return rust.ext.has_os()")?;
+        assert_eq!(
+            sandboxed.value, "false",
+            "extensions share the sandbox restrictions"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn custom_prelude_functions_are_callable() -> Result<()> {
+        let tmp = tempdir()?;
+        fs::write(
+            tmp.path().join("prelude.lua"),
+            "function pipe(x, ...) for _, f in ipairs({...}) do x = f(x) end return x end",
+        )?;
+        let executor = LuaExecutor::with_options(
+            tmp.path(),
+            ExecutorOptions {
+                prelude_file: Some(PathBuf::from("prelude.lua")),
+                ..ExecutorOptions::default()
+            },
+            ExecutorIo::standalone()?,
+        )?;
+        let result = executor.run_script(
+            "return pipe(2, function(x) return x * 10 end, function(x) return x + 1 end)",
+        )?;
+        assert_eq!(result.value, "21");
+        assert!(executor.load_warnings().is_empty());
+
+        let missing = LuaExecutor::with_options(
+            tmp.path(),
+            ExecutorOptions {
+                prelude_file: Some(PathBuf::from("missing.lua")),
+                ..ExecutorOptions::default()
+            },
+            ExecutorIo::standalone()?,
+        )?;
+        let warnings = missing.load_warnings();
+        assert!(warnings[0].contains("lua_prelude_file"), "{warnings:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn path_helpers_join_relativize_and_reject_escapes() -> Result<()> {
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let root = executor.workspace_root().to_string_lossy().into_owned();
+        let check: (String, String, String, String, String) = executor
+            .lua
+            .load(
+                r#"
+                return rust.workspace_root(),
+                    rust.join("src//lua_tool", "./mod.rs"),
+                    rust.join(rust.workspace_root(), "a/../b"),
+                    rust.relpath(rust.join(rust.workspace_root(), "src", "main.rs")),
+                    rust.relpath(rust.workspace_root())
+                "#,
+            )
+            .eval()?;
+        assert_eq!(check.0, root);
+        assert_eq!(check.1, "src/lua_tool/mod.rs");
+        assert_eq!(check.2, format!("{root}/b"));
+        assert_eq!(check.3, "src/main.rs");
+        assert_eq!(check.4, ".");
+
+        let escape = executor
+            .lua
+            .load(r#"return rust.join("src", "../../etc")"#)
+            .eval::<String>()
+            .unwrap_err();
+        assert!(escape.to_string().contains("escapes"), "{escape}");
+        let absolute = executor
+            .lua
+            .load(r#"return rust.join("src", "/etc/passwd")"#)
+            .eval::<String>()
+            .unwrap_err();
+        assert!(
+            absolute.to_string().contains("must be relative"),
+            "{absolute}"
+        );
+        let outside = executor
+            .lua
+            .load(r#"return rust.relpath("/etc/passwd")"#)
+            .eval::<String>()
+            .unwrap_err();
+        assert!(
+            outside.to_string().contains("outside the workspace"),
+            "{outside}"
+        );
+
+        fs::write(tmp.path().join("note.txt"), "hi")?;
+        let joined: String = executor
+            .lua
+            .load(r#"return rust.read_file(rust.join("sub", "..", "note.txt"))"#)
+            .eval()?;
+        assert_eq!(joined, "hi");
+        Ok(())
+    }
+
+    #[test]
+    fn pretty_renders_nested_tables_across_indented_lines() -> Result<()> {
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let script =
+            r#"return { name = "selenai", files = { "a.rs", "b.rs" }, ["two words"] = {} }"#;
+        let expected = r#"{
+  ["two words"] = {},
+  files = {
+    "a.rs",
+    "b.rs",
+  },
+  name = "selenai",
+}"#;
+
+        let output = executor.run_script(script)?;
+        assert_eq!(output.pretty.as_deref(), Some(expected));
+        let via_helper: String = executor
+            .lua
+            .load(format!("return rust.pretty((function() {script} end)())"))
+            .eval()?;
+        assert_eq!(via_helper, expected);
+        assert_eq!(executor.run_script("return 42")?.pretty, None);
+        Ok(())
+    }
+
+    #[test]
+    fn run_script_with_timeout_interrupts_infinite_loops() -> Result<()> {
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let started = Instant::now();
+        let spin = "local n = 0 while true do n = n + 1 end";
+        let err = executor
+            .run_script_with_timeout(spin, Duration::from_millis(200))
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("script exceeded 0.2s timeout"),
+            "{err:#}"
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let output = executor.run_script_with_timeout("return 1 + 1", Duration::from_secs(5))?;
+        assert_eq!(output.value, "2");
+        // The hook is removed afterwards, so untimed runs are unaffected.
+        assert_eq!(executor.run_script("return 'ok'")?.value, "ok");
+        Ok(())
+    }
+
+    #[test]
+    fn json_table_round_trips_values() -> Result<()> {
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let checks: (String, i64, String, bool, bool, f64, String) = executor
+            .lua
+            .load(
+                r#"
+                local doc = json.decode('{"name":"selenai","tags":["a","b"],"n":3,"ok":true,"gone":null,"ratio":0.5}')
+                return doc.name, #doc.tags, doc.tags[2], doc.ok, doc.gone == nil, doc.ratio,
+                    json.encode({ list = { 1, 2 }, flag = false })
+                "#,
+            )
+            .eval()?;
+        assert_eq!(checks.0, "selenai");
+        assert_eq!(checks.1, 2);
+        assert_eq!(checks.2, "b");
+        assert!(checks.3);
+        assert!(checks.4);
+        assert_eq!(checks.5, 0.5);
+        let encoded: JsonValue = serde_json::from_str(&checks.6)?;
+        assert_eq!(encoded, serde_json::json!({"list": [1, 2], "flag": false}));
+
+        let pretty: String = executor
+            .lua
+            .load(r#"return json.encode({ a = 1 }, true)"#)
+            .eval()?;
+        assert_eq!(pretty, "{\n  \"a\": 1\n}");
+        let (value, err): (Value, Option<String>) = executor
+            .lua
+            .load(r#"return json.decode("{not json")"#)
+            .eval()?;
+        assert!(matches!(value, Value::Nil));
+        let err = err.expect("decode error");
+        assert!(err.starts_with("json.decode:"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn json_encodes_nested_tables_and_round_trips() -> Result<()> {
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let encoded: String = executor
+            .lua
+            .load(r#"return json.encode({ user = { name = "ada", roles = { "admin", "dev" } } })"#)
+            .eval()?;
+        let parsed: JsonValue = serde_json::from_str(&encoded)?;
+        assert_eq!(
+            parsed,
+            serde_json::json!({"user": {"name": "ada", "roles": ["admin", "dev"]}})
+        );
+
+        let round_trip: String = executor
+            .lua
+            .load(
+                r#"
+                local doc, err = json.decode('{"items":[{"id":1},{"id":2}],"meta":{"next":null}}')
+                assert(err == nil, err)
+                return json.encode(json.decode(json.encode(doc)))
+                "#,
+            )
+            .eval()?;
+        let parsed: JsonValue = serde_json::from_str(&round_trip)?;
+        assert_eq!(
+            parsed,
+            serde_json::json!({"items": [{"id": 1}, {"id": 2}], "meta": {}})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sorted_pairs_yields_stable_key_order() -> Result<()> {
+        let script = |inserts: &str| {
+            format!(
+                r#"
+                local t = {{}}
+                {inserts}
+                local seen = {{}}
+                for k, v in sorted_pairs(t) do table.insert(seen, tostring(k) .. "=" .. v) end
+                return table.concat(seen, ",")
+                "#
+            )
+        };
+        let forward = r#"t.beta = "b"; t[10] = "ten"; t.alpha = "a"; t[2] = "two"; t["5x"] = "s""#;
+        let reverse = r#"t["5x"] = "s"; t[2] = "two"; t.alpha = "a"; t[10] = "ten"; t.beta = "b""#;
+        let mut orders = Vec::new();
+        for inserts in [forward, reverse, forward] {
+            let tmp = tempdir()?;
+            let executor = LuaExecutor::new(tmp.path(), false)?;
+            orders.push(executor.run_script(&script(inserts))?.value);
+        }
+        assert_eq!(orders[0], "2=two,10=ten,5x=s,alpha=a,beta=b");
+        assert!(orders.iter().all(|order| *order == orders[0]), "{orders:?}");
+
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let rendered = executor.run_script(r#"return { b = 1, [3] = "x", a = 2, [1] = "y" }"#)?;
+        assert_eq!(rendered.value, "{1: y, 3: x, a: 2, b: 1}");
+        Ok(())
+    }
+
+    #[test]
+    fn prelude_table_helpers_merge_and_sort_keys() -> Result<()> {
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let run = |script: &str| executor.run_script(script).map(|out| out.value);
+
+        assert_eq!(
+            run(r#"return table.concat(table.keys({ zeta = 1, alpha = 2, mid = 3 }), ",")"#)?,
+            "alpha,mid,zeta"
+        );
+        assert_eq!(
+            run(r#"return table.concat(table.keys({ 10, 20, 30 }), ",")"#)?,
+            "1,2,3"
+        );
+        assert_eq!(
+            run(r#"return table.concat(table.values({ b = "two", a = "one" }), ",")"#)?,
+            "one,two"
+        );
+        assert_eq!(
+            run(r#"
+                local m = table.merge({ model = "a", stream = true }, { model = "b", debug = 1 })
+                return m.model .. "," .. tostring(m.stream) .. "," .. m.debug
+                "#)?,
+            "b,true,1"
+        );
+        assert_eq!(
+            run(r#"return table.contains({ "x", "y" }, "y") and not table.contains({}, 1)"#)?,
+            "true"
+        );
+        assert_eq!(
+            run(r#"
+                local evens = table.filter({ 1, 2, 3, 4 }, function(n) return n % 2 == 0 end)
+                return table.reduce(evens, function(acc, n) return acc + n end, 0)
+                    + table.reduce({ 5, 6 }, function(acc, n) return acc * n end)
+                "#)?,
+            "36"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn prelude_string_helpers_split_trim_and_match() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut executor = LuaExecutor::new(tmp.path(), false)?;
+        let checks: (String, String, String, bool, bool, bool, String) = executor
+            .lua
+            .load(
+                r#"
+                local parts = string.split("a::b::::c", "::")
+                local plain = string.split("1.2.3", ".")
+                return table.concat(parts, "|"), table.concat(plain, "|"),
+                    string.trim("\u{3000}\u{a0} hi there\t\u{2003}\n"),
+                    ("selenai.toml"):starts_with("selenai"), ("selenai.toml"):ends_with(".toml"),
+                    string.starts_with("lua", "luau"),
+                    table.concat(string.lines("one\r\ntwo\n\nfour\n"), "|")
+                "#,
+            )
+            .eval()?;
+        assert_eq!(checks.0, "a|b||c");
+        assert_eq!(checks.1, "1|2|3");
+        assert_eq!(checks.2, "hi there");
+        assert!(checks.3 && checks.4);
+        assert!(!checks.5);
+        assert_eq!(checks.6, "one|two||four");
+
+        executor.reset()?;
+        let err = executor
+            .run_script(r#"return string.split("abc", "")"#)
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("separator must be a non-empty string"));
+        Ok(())
+    }
+
+    #[test]
+    fn list_user_globals_skips_builtins() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut executor = LuaExecutor::new(tmp.path(), false)?;
+        assert!(executor.list_user_globals().is_empty());
+
+        executor.run_script(
+            r#"
+            x = 42
+            greeting = "hello"
+            function helper() end
+            config = { depth = 2 }
+            print = print
+        "#,
+        )?;
+        let globals = executor.list_user_globals();
+        let names: Vec<_> = globals.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["config", "greeting", "helper", "x"]);
+        assert_eq!(globals[3].1, "number 42");
+        assert_eq!(globals[1].1, "string \"hello\"");
+        assert_eq!(globals[2].1, "function");
+        assert!(globals[0].1.starts_with("table "), "{}", globals[0].1);
+
+        executor.run_script(&format!(
+            "long = string.rep('x', {})",
+            GLOBAL_PREVIEW_CHARS * 2
+        ))?;
+        let long = executor.list_user_globals();
+        assert!(
+            long.iter()
+                .any(|(name, desc)| name == "long" && desc.ends_with("..."))
+        );
+
+        executor.reset()?;
+        assert!(executor.list_user_globals().is_empty());
         Ok(())
     }
 
     #[test]
-    fn io_open_respects_write_flag() -> Result<()> {
+    fn clear_global_removes_only_the_named_variable() -> Result<()> {
         let tmp = tempdir()?;
         let executor = LuaExecutor::new(tmp.path(), false)?;
-        let err = executor.run_script(
-            r#"
-            local f = io.open("new.txt", "w")
-        "#,
+        executor.run_script("stale = 1; keep = 2")?;
+
+        assert!(executor.clear_global("stale")?);
+        assert!(!executor.clear_global("stale")?);
+        let names: Vec<_> = executor
+            .list_user_globals()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["keep"]);
+        assert_eq!(executor.run_script("return keep")?.value, "2");
+
+        let err = executor.clear_global("rust").unwrap_err();
+        assert!(err.to_string().contains("built-in"), "{err}");
+        assert_eq!(
+            executor.run_script("return type(rust.read_file)")?.value,
+            "function"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn delete_file_honors_write_mode_and_directories() -> Result<()> {
+        let tmp = tempdir()?;
+        fs::write(tmp.path().join("old.txt"), "bye")?;
+        fs::create_dir_all(tmp.path().join("build/out"))?;
+        fs::write(tmp.path().join("build/out/a.o"), "obj")?;
+
+        let read_only = LuaExecutor::new(tmp.path(), false)?;
+        let err = read_only
+            .run_script(r#"rust.delete_file("old.txt")"#)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("write helpers are disabled"),
+            "{err}"
         );
+        assert!(tmp.path().join("old.txt").exists());
+
+        let executor = LuaExecutor::new(tmp.path(), true)?;
+        executor.run_script(r#"rust.delete_file("old.txt")"#)?;
+        assert!(!tmp.path().join("old.txt").exists());
+        let missing = executor
+            .run_script(r#"rust.delete_file("old.txt")"#)
+            .unwrap_err();
         assert!(
-            err.unwrap_err()
-                .to_string()
-                .contains("write helpers are disabled")
+            missing.to_string().contains("could not delete"),
+            "{missing}"
         );
+
+        let dir = executor
+            .run_script(r#"rust.delete_file("build")"#)
+            .unwrap_err();
+        assert!(dir.to_string().contains("is a directory"), "{dir}");
+        executor.run_script(r#"rust.delete_file("build", true)"#)?;
+        assert!(!tmp.path().join("build").exists());
+        let root = executor
+            .run_script(r#"rust.delete_file(".", true)"#)
+            .unwrap_err();
+        assert!(root.to_string().contains("workspace root"), "{root}");
+        assert_eq!(executor.take_modified_files().len(), 2);
+
+        fs::write(tmp.path().join("keep.txt"), "stay")?;
+        let preview = executor.preview_script(r#"rust.delete_file("keep.txt")"#)?;
+        assert_eq!(preview, "Would delete `keep.txt`");
+        assert!(tmp.path().join("keep.txt").exists());
         Ok(())
     }
 
+    #[cfg(unix)]
     #[test]
-    fn io_open_allows_creating_files_when_enabled() -> Result<()> {
+    fn delete_file_removes_a_symlink_but_not_its_target() -> Result<()> {
         let tmp = tempdir()?;
+        fs::create_dir_all(tmp.path().join("data/nested"))?;
+        fs::write(tmp.path().join("data/nested/keep.txt"), "keep")?;
+        std::os::unix::fs::symlink(tmp.path().join("data"), tmp.path().join("data_link"))?;
+
         let executor = LuaExecutor::new(tmp.path(), true)?;
-        let output = executor.run_script(
-            r#"
-            local f = io.open("dir/example.txt", "w")
-            f:write("demo")
-            f:close()
-            return rust.read_file("dir/example.txt")
-        "#,
-        )?;
-        assert_eq!(output.value, "demo");
+        executor.run_script(r#"rust.delete_file("data_link", true)"#)?;
+        assert!(fs::symlink_metadata(tmp.path().join("data_link")).is_err());
+        assert!(tmp.path().join("data/nested/keep.txt").exists());
         Ok(())
     }
 
@@ -1015,6 +3022,42 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn write_file_through_symlink_out_of_root_is_rejected() -> Result<()> {
+        let tmp = tempdir()?;
+        let outside = tempdir()?;
+        std::os::unix::fs::symlink(std::env::temp_dir(), tmp.path().join("tmp_link"))?;
+        std::os::unix::fs::symlink(
+            outside.path().join("missing.txt"),
+            tmp.path().join("dangling.txt"),
+        )?;
+        let executor = LuaExecutor::new(tmp.path(), true)?;
+
+        let escape_name = format!("selenai-escape-{}.txt", std::process::id());
+        let script = format!(r#"rust.write_file("tmp_link/{escape_name}", "x")"#);
+        let err = executor.run_script(&script).unwrap_err();
+        assert!(err.to_string().contains("escapes workspace root"), "{err}");
+        assert!(!std::env::temp_dir().join(&escape_name).exists());
+
+        let err = executor
+            .run_script(r#"rust.write_file("dangling.txt", "x")"#)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("symlink to a missing target"),
+            "{err:#}"
+        );
+        let err = executor
+            .run_script(r#"rust.write_file("dangling.txt/nested.txt", "x")"#)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("symlink to a missing target"),
+            "{err:#}"
+        );
+        assert!(!outside.path().join("missing.txt").exists());
+        Ok(())
+    }
+
     #[test]
     fn resolve_safe_path_rejects_escape() {
         let tmp = tempdir().expect("tempdir");
@@ -1027,8 +3070,10 @@ mod tests {
         );
     }
 
-    #[test]
-    fn http_request_helper_handles_basic_request() -> Result<()> {
+    /// Answers one connection with `response`; the thread returns the request head.
+    fn serve_once(
+        response: &'static [u8],
+    ) -> Result<(std::net::SocketAddr, std::thread::JoinHandle<String>)> {
         use std::{
             io::{Read, Write},
             net::TcpListener,
@@ -1038,14 +3083,21 @@ mod tests {
         let listener = TcpListener::bind("127.0.0.1:0")?;
         let addr = listener.local_addr()?;
         let handle = thread::spawn(move || {
-            if let Ok((mut stream, _)) = listener.accept() {
-                let mut buffer = [0u8; 1024];
-                let _ = stream.read(&mut buffer);
-                let response =
-                    b"HTTP/1.1 200 OK\r\nX-Test-Header: Pong\r\nContent-Length: 4\r\n\r\npong";
-                let _ = stream.write_all(response);
-            }
+            let Ok((mut stream, _)) = listener.accept() else {
+                return String::new();
+            };
+            let mut buffer = [0u8; 1024];
+            let read = stream.read(&mut buffer).unwrap_or(0);
+            let _ = stream.write_all(response);
+            String::from_utf8_lossy(&buffer[..read]).into_owned()
         });
+        Ok((addr, handle))
+    }
+
+    #[test]
+    fn http_request_helper_handles_basic_request() -> Result<()> {
+        let (addr, handle) =
+            serve_once(b"HTTP/1.1 200 OK\r\nX-Test-Header: Pong\r\nContent-Length: 4\r\n\r\npong")?;
 
         let tmp = tempdir()?;
         let executor = LuaExecutor::new(tmp.path(), false)?;
@@ -1057,12 +3109,81 @@ mod tests {
                 headers = {{ ["X-Demo"] = "value" }},
                 body = "ping"
             }}
-            return resp.status .. ":" .. resp.body
+            return resp.status .. ":" .. resp.body .. ":" .. resp.headers["x-test-header"]
         "#,
             addr = addr
         );
         let output = executor.run_script(&script)?;
-        assert_eq!(output.value.trim(), "200:pong");
+        assert_eq!(output.value.trim(), "200:pong:Pong");
+        handle.join().expect("server thread");
+        Ok(())
+    }
+
+    #[test]
+    fn http_request_encodes_query_parameters() -> Result<()> {
+        let (addr, handle) = serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")?;
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let script = format!(
+            r#"
+            return rust.http_request{{
+                url = "http://{addr}/search?page=2",
+                query = {{ q = "a&b c/é", limit = 10 }},
+            }}.body
+        "#
+        );
+        assert_eq!(executor.run_script(&script)?.value, "ok");
+        let request = handle.join().expect("server thread");
+        let request_line = request.lines().next().unwrap_or_default();
+        assert_eq!(
+            request_line,
+            "GET /search?page=2&limit=10&q=a%26b+c%2F%C3%A9 HTTP/1.1"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn http_request_honors_timeout_and_redirect_options() -> Result<()> {
+        let (addr, handle) =
+            serve_once(b"HTTP/1.1 302 Found\r\nLocation: /elsewhere\r\nContent-Length: 0\r\n\r\n")?;
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let script = format!(
+            r#"
+            local resp = rust.http_request{{ url = "http://{addr}/old", follow_redirects = false }}
+            return resp.status .. " " .. resp.headers["location"]
+        "#
+        );
+        assert_eq!(executor.run_script(&script)?.value, "302 /elsewhere");
+        handle.join().expect("server thread");
+
+        // Accepts the connection but never answers.
+        let silent = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = silent.local_addr()?;
+        let started = Instant::now();
+        let script =
+            format!(r#"rust.http_request{{ url = "http://{addr}/slow", timeout_ms = 200 }}"#);
+        let err = executor.run_script(&script).unwrap_err();
+        assert!(
+            err.to_string().contains(&format!(
+                "http_request to http://{addr}/slow timed out after 200 ms"
+            )),
+            "{err}"
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+        drop(silent);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn http_request_helper_runs_inside_tokio_runtime() -> Result<()> {
+        let (addr, handle) = serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")?;
+
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::new(tmp.path(), false)?;
+        let script = format!(r#"return rust.http_request{{ url = "http://{addr}/" }}.body"#);
+        let output = executor.run_script(&script)?;
+        assert_eq!(output.value, "ok");
         handle.join().expect("server thread");
         Ok(())
     }
@@ -1089,6 +3210,183 @@ mod tests {
             "expected both entries in output: {}",
             output.value
         );
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("one.txt", tmp.path().join("link.txt"))?;
+        let output = executor.run_script(
+            r#"
+            local rows = {}
+            for _, e in ipairs(rust.list_dir(".")) do
+                if e.name ~= "dir" then
+                    table.insert(rows, table.concat({
+                        e.name, e.size, tostring(e.is_symlink), e.modified,
+                    }, ":"))
+                end
+            end
+            table.sort(rows)
+            return table.concat(rows, " ")
+        "#,
+        )?;
+        let now = std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_secs();
+        let mut rows = Vec::new();
+        for row in output.value.split(' ') {
+            let (entry, modified) = row.rsplit_once(':').expect("modified column");
+            let modified: u64 = modified.parse()?;
+            assert!(now.abs_diff(modified) < 3600, "{row}");
+            rows.push(entry);
+        }
+        let expected: &[&str] = if cfg!(unix) {
+            &["link.txt:7:true", "one.txt:1:false"]
+        } else {
+            &["one.txt:1:false"]
+        };
+        assert_eq!(rows, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn search_returns_structured_hits_and_respects_gitignore() -> Result<()> {
+        let tmp = tempdir()?;
+        let root = tmp.path();
+        fs::create_dir_all(root.join("src/gen"))?;
+        fs::create_dir_all(root.join("target"))?;
+        fs::write(root.join(".gitignore"), "target/\n*.bak\n")?;
+        fs::write(root.join("src/.gitignore"), "gen/\n")?;
+        fs::write(root.join(".selenai-ignore"), "src/c.rs\n")?;
+        fs::write(root.join("src/c.rs"), "// TODO: hidden from search only\n")?;
+        fs::write(root.join("src/a.rs"), "fn a() {}\n// TODO: a\n")?;
+        fs::write(root.join("src/b.rs"), "// todo: b\n// TODO: b again\n")?;
+        fs::write(root.join("src/old.bak"), "TODO: backup\n")?;
+        fs::write(root.join("src/gen/out.rs"), "TODO: generated\n")?;
+        fs::write(root.join("target/build.rs"), "TODO: build\n")?;
+        fs::write(root.join("src/blob.bin"), b"TODO\0binary")?;
+        let executor = LuaExecutor::new(root, false)?;
+
+        let output = executor.run_script(
+            r#"
+            local rows = {}
+            for _, hit in ipairs(rust.search("TODO")) do
+                table.insert(rows, hit.file .. ":" .. hit.line_number .. ":" .. hit.text)
+            end
+            return table.concat(rows, "\n")
+            "#,
+        )?;
+        assert_eq!(
+            output.value,
+            "src/a.rs:2:// TODO: a\nsrc/b.rs:2:// TODO: b again"
+        );
+
+        let output = executor.run_script(
+            r#"
+            local hits = rust.search("todo", "src", { case_insensitive = true, max_results = 2 })
+            return #hits .. " " .. hits[2].file .. ":" .. hits[2].line_number
+            "#,
+        )?;
+        assert_eq!(output.value, "2 src/b.rs:1");
+
+        let output = executor.run_script(r#"return #rust.search("TODO", "src/gen")"#)?;
+        assert_eq!(output.value, "0", "ignored by src/.gitignore");
+
+        let err = executor
+            .run_script(r#"rust.search("(", "src")"#)
+            .expect_err("bad regex");
+        assert!(
+            format!("{err:#}").contains("invalid search pattern"),
+            "{err:#}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn git_diff_and_log_work_without_writes() -> Result<()> {
+        let tmp = tempdir()?;
+        let root = tmp.path();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(root)
+                .output()
+                .expect("git runs")
+                .status;
+            assert!(status.success(), "git {args:?} failed");
+        };
+        git(&["init", "-q"]);
+        fs::write(root.join("a.txt"), "one\n")?;
+        fs::write(root.join("b.txt"), "uno\n")?;
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "first commit"]);
+        fs::write(root.join("a.txt"), "one\ntwo\n")?;
+        fs::write(root.join("b.txt"), "uno\ndos\n")?;
+        git(&["add", "b.txt"]);
+        let executor = LuaExecutor::new(root, false)?;
+
+        let output = executor.run_script(
+            r#"
+            local work = rust.git_diff()
+            local staged = rust.git_diff({ staged = true, paths = { "b.txt" } })
+            local log = rust.git_log({ max_count = 1 })
+            return table.concat({
+                work.status, tostring(work.stdout:find("+two", 1, true) ~= nil),
+                tostring(work.stdout:find("dos", 1, true) ~= nil),
+                tostring(staged.stdout:find("+dos", 1, true) ~= nil),
+                log.stdout:match("first commit") or "missing",
+            }, "|")
+            "#,
+        )?;
+        assert_eq!(output.value, "0|true|false|true|first commit");
+
+        let err = executor
+            .run_script(r#"rust.git_diff({ target = "--output=pwned" })"#)
+            .expect_err("flag-like target");
+        assert!(
+            format!("{err:#}").contains("cannot start with '-'"),
+            "{err:#}"
+        );
+        assert!(!root.join("pwned").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn walk_dir_lists_recursively_within_limits() -> Result<()> {
+        let tmp = tempdir()?;
+        let root = tmp.path();
+        fs::create_dir_all(root.join("src/nested"))?;
+        fs::create_dir_all(root.join("target/debug"))?;
+        fs::write(root.join(".gitignore"), "target/\n")?;
+        fs::write(root.join("src/lib.rs"), "12345")?;
+        fs::write(root.join("src/nested/deep.rs"), "")?;
+        let outside = tempdir()?;
+        fs::write(outside.path().join("secret.txt"), "nope")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(outside.path(), root.join("src/escape"))?;
+        let executor = LuaExecutor::new(root, false)?;
+
+        let list = |opts: &str| -> Result<String> {
+            let output = executor.run_script(&format!(
+                r#"
+                local rows = {{}}
+                for _, e in ipairs(rust.walk_dir(".", {opts})) do
+                    table.insert(rows, e.path .. (e.is_dir and "/" or ":" .. e.size))
+                end
+                local entries = rust.walk_dir(".", {opts})
+                return table.concat(rows, " ") .. (entries.truncated and " ..." or "")
+                "#
+            ))?;
+            Ok(output.value)
+        };
+        let expected = ".gitignore:8 src/ src/lib.rs:5 src/nested/ src/nested/deep.rs:0";
+        assert_eq!(list("nil")?, expected);
+        assert_eq!(list("{ max_depth = 1 }")?, ".gitignore:8 src/");
+        assert_eq!(list("{ max_entries = 2 }")?, ".gitignore:8 src/ ...");
+        let all = list("{ respect_gitignore = false }")?;
+        assert!(all.contains("target/debug/"), "{all}");
+
+        let output = executor.run_script(r#"return #rust.walk_dir("src/nested")"#)?;
+        assert_eq!(output.value, "1");
+        assert!(executor.run_script(r#"rust.walk_dir("../")"#).is_err());
         Ok(())
     }
 
@@ -1114,6 +3412,14 @@ mod tests {
         assert!(matches!(FileMode::parse("r").unwrap(), FileMode::Read));
         assert!(matches!(FileMode::parse("w").unwrap(), FileMode::Write));
         assert!(matches!(FileMode::parse("a").unwrap(), FileMode::Append));
+        assert!(matches!(
+            FileMode::parse("r+").unwrap(),
+            FileMode::ReadWrite
+        ));
+        assert!(matches!(
+            FileMode::parse("w+b").unwrap(),
+            FileMode::ReadWriteTruncate
+        ));
         assert!(FileMode::parse("invalid").is_err());
     }
 
@@ -1138,9 +3444,116 @@ mod tests {
             rust.patch_file("code.rs", "{}")
             return rust.read_file("code.rs")
         "#, diff_lua);
-        
+
         let output = executor.run_script(&script)?;
-        assert_eq!(output.value, "fn main() {\n    println!(\"new\");\n}");
+        assert_eq!(output.value, "fn main() {\n    println!(\"new\");\n}\n");
+        Ok(())
+    }
+
+    fn patched(original: &str, diff: &str) -> Result<String> {
+        let patch = Patch::from_single(diff).map_err(|e| anyhow::anyhow!("{e}"))?;
+        apply_patch(original, &patch)
+    }
+
+    #[test]
+    fn apply_patch_handles_multiple_hunks_with_offsets() -> Result<()> {
+        let original = "a\nb\nc\nd\ne\nf\ng\nh";
+        let diff = "--- f\n+++ f\n@@ -1,3 +1,4 @@\n a\n+a2\n b\n c\n@@ -6,3 +7,2 @@\n f\n-g\n h\n";
+        assert_eq!(patched(original, diff)?, "a\na2\nb\nc\nd\ne\nf\nh");
+        Ok(())
+    }
+
+    #[test]
+    fn apply_patch_handles_add_only_hunks() -> Result<()> {
+        let with_context = "--- f\n+++ f\n@@ -1,2 +1,3 @@\n one\n+inserted\n two\n";
+        assert_eq!(
+            patched("one\ntwo\nthree", with_context)?,
+            "one\ninserted\ntwo\nthree"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn apply_patch_handles_remove_only_hunks() -> Result<()> {
+        let diff = "--- f\n+++ f\n@@ -1,3 +1,2 @@\n one\n-two\n three\n";
+        assert_eq!(patched("one\ntwo\nthree", diff)?, "one\nthree");
+        Ok(())
+    }
+
+    #[test]
+    fn apply_patch_edits_start_and_end_of_file() -> Result<()> {
+        let replace_first = "--- f\n+++ f\n@@ -1,2 +1,2 @@\n-one\n+ONE\n two\n";
+        assert_eq!(patched("one\ntwo", replace_first)?, "ONE\ntwo");
+
+        let append = "--- f\n+++ f\n@@ -2 +2,2 @@\n two\n+footer\n";
+        assert_eq!(patched("one\ntwo\n", append)?, "one\ntwo\nfooter\n");
+        Ok(())
+    }
+
+    #[test]
+    fn apply_patch_preserves_trailing_newline_and_line_endings() -> Result<()> {
+        let diff = "--- f\n+++ f\n@@ -1,2 +1,3 @@\n one\n-two\n+TWO\n+extra\n";
+        assert_eq!(patched("one\ntwo\n", diff)?, "one\nTWO\nextra\n");
+        assert_eq!(patched("one\ntwo", diff)?, "one\nTWO\nextra");
+        assert_eq!(patched("one\r\ntwo\r\n", diff)?, "one\r\nTWO\r\nextra\r\n");
+
+        let append = "--- f\n+++ f\n@@ -1 +1,2 @@\n one\n+two\n";
+        assert_eq!(patched("one", append)?, "one\ntwo");
+        Ok(())
+    }
+
+    #[test]
+    fn apply_patch_rejects_hunks_past_end_of_file() {
+        let diff = "--- f\n+++ f\n@@ -3,2 +3,2 @@\n three\n-four\n+FOUR\n";
+        let err = patched("one\ntwo", diff).unwrap_err();
+        assert!(err.to_string().contains("out of bounds"), "{err:#}");
+    }
+
+    #[test]
+    fn apply_patch_rejects_stale_context() {
+        let original = "fn main() {\n    println!(\"changed\");\n}";
+        let diff = "--- f\n+++ f\n@@ -1,3 +1,3 @@\n fn main() {\n-    println!(\"old\");\n+    println!(\"new\");\n }\n";
+        let err = patched(original, diff).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("line 2"), "{message}");
+        assert!(message.contains("println!(\\\"old\\\")"), "{message}");
+
+        let context_diff = "--- f\n+++ f\n@@ -1,2 +1,3 @@\n alpha\n+inserted\n gamma\n";
+        assert!(patched("alpha\nbeta", context_diff).is_err());
+    }
+
+    #[test]
+    fn preview_patch_matches_patch_file_without_writing() -> Result<()> {
+        let tmp = tempdir()?;
+        let file = tmp.path().join("code.rs");
+        let original = "fn main() {\n    println!(\"old\");\n}\n";
+        fs::write(&file, original)?;
+
+        let executor = LuaExecutor::new(tmp.path(), true)?;
+        let script = r#"
+            local diff = "--- code.rs\n+++ code.rs\n@@ -1,3 +1,3 @@\n fn main() {\n-    println!(\"old\");\n+    println!(\"new\");\n }\n"
+            return rust.preview_patch("code.rs", diff)
+        "#;
+        let preview = executor.run_script(script)?.value;
+        assert_eq!(
+            fs::read_to_string(&file)?,
+            original,
+            "preview must not write"
+        );
+
+        let apply_script = script.replace(
+            r#"return rust.preview_patch("code.rs", diff)"#,
+            r#"rust.patch_file("code.rs", diff) return rust.read_file("code.rs")"#,
+        );
+        let applied = executor.run_script(&apply_script)?;
+        assert_eq!(preview, applied.value);
+        assert!(preview.contains("println!(\"new\")"));
+
+        let read_only = LuaExecutor::new(tmp.path(), false)?;
+        let err = read_only
+            .run_script(r#"return rust.preview_patch("code.rs", "--- code.rs\n+++ code.rs\n@@ -1 +1 @@\n-nope\n+x\n")"#)
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("does not match"), "{err:#}");
         Ok(())
     }
 
@@ -1160,6 +3573,93 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn run_command_accepts_stdin_env_and_cwd() -> Result<()> {
+        let tmp = tempdir()?;
+        fs::create_dir(tmp.path().join("sub"))?;
+        let executor = LuaExecutor::new(tmp.path(), true)?;
+
+        let output = executor.run_script(
+            r#"
+            local piped = rust.run_command("cat", {}, { stdin = "line one\nline two\n" })
+            local env = rust.run_command("sh", { "-c", 'printf %s "$SELENAI_TEST_VAR"' },
+                { env = { SELENAI_TEST_VAR = "from lua" } })
+            local here = rust.run_command("sh", { "-c", "basename \"$PWD\"" }, { cwd = "sub" })
+            local plain = rust.run_command("cat", {})
+            return piped.stdout .. "|" .. env.stdout .. "|" .. here.stdout .. "|" .. plain.stdout
+            "#,
+        )?;
+        assert_eq!(output.value, "line one\nline two\n|from lua|sub\n|");
+
+        let err = executor
+            .run_script(r#"rust.run_command("pwd", {}, { cwd = "../outside" })"#)
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("escapes workspace"), "{err:#}");
+
+        let preview = executor.preview_script(
+            r#"
+            local opts = { stdin = "12345", cwd = "sub", env = { B = "1", A = "2" } }
+            rust.run_command("git", {"apply"}, opts)
+            "#,
+        )?;
+        let expected = "Would run command: git apply (in sub) with env A, B with 5 bytes on stdin";
+        assert!(preview.contains(expected), "{preview}");
+        Ok(())
+    }
+
+    #[test]
+    fn run_command_times_out_and_keeps_partial_output() -> Result<()> {
+        let tmp = tempdir()?;
+        let executor = LuaExecutor::with_options(
+            tmp.path(),
+            ExecutorOptions {
+                allow_writes: true,
+                command_timeout: Some(Duration::from_millis(300)),
+                ..ExecutorOptions::default()
+            },
+            ExecutorIo::standalone()?,
+        )?;
+        let slow = r#"printf 'started'; printf 'warming up' >&2; sleep 5; printf 'never'"#;
+
+        let started = Instant::now();
+        let output = executor.run_script(&format!(
+            r#"
+            local res = rust.run_command("sh", {{ "-c", "{slow}" }})
+            return table.concat({{ res.status, res.stdout, res.stderr, tostring(res.timed_out) }}, "|")
+            "#
+        ))?;
+        assert!(
+            started.elapsed() < Duration::from_secs(3),
+            "{:?}",
+            started.elapsed()
+        );
+        assert_eq!(
+            output.value,
+            "-1|started|warming up\ntimed out after 300 ms|true"
+        );
+
+        let output = executor.run_script(&format!(
+            r#"
+            local res = rust.run_command("sh", {{ "-c", "{slow}" }}, {{ timeout_ms = 100 }})
+            return res.stderr
+            "#
+        ))?;
+        assert!(
+            output.value.ends_with("timed out after 100 ms"),
+            "{}",
+            output.value
+        );
+
+        let output = executor.run_script(
+            r#"
+            local res = rust.run_command("sh", { "-c", "sleep 0.5; printf ok" }, { timeout_ms = 0 })
+            return res.status .. "|" .. res.stdout .. "|" .. tostring(res.timed_out)
+            "#,
+        )?;
+        assert_eq!(output.value, "0|ok|nil");
+        Ok(())
+    }
+
     #[test]
     fn run_command_blocked_if_read_only() -> Result<()> {
         let tmp = tempdir()?;
@@ -1229,6 +3729,10 @@ enum FileMode {
     Read,
     Write,
     Append,
+    /// `r+`: the file must exist; reads and writes share the cursor.
+    ReadWrite,
+    /// `w+`: like `r+`, but the file starts out empty.
+    ReadWriteTruncate,
 }
 
 impl FileMode {
@@ -1238,12 +3742,21 @@ impl FileMode {
             "r" | "rb" => Ok(FileMode::Read),
             "w" | "wb" => Ok(FileMode::Write),
             "a" | "ab" => Ok(FileMode::Append),
-            _ => Err("unsupported io mode (expected r, w, or a)"),
+            "r+" | "r+b" | "rb+" => Ok(FileMode::ReadWrite),
+            "w+" | "w+b" | "wb+" => Ok(FileMode::ReadWriteTruncate),
+            _ => Err("unsupported io mode (expected r, w, a, r+, or w+)"),
         }
     }
 
+    fn allows_read(self) -> bool {
+        matches!(
+            self,
+            FileMode::Read | FileMode::ReadWrite | FileMode::ReadWriteTruncate
+        )
+    }
+
     fn allows_write(self) -> bool {
-        matches!(self, FileMode::Write | FileMode::Append)
+        !matches!(self, FileMode::Read)
     }
 }
 
@@ -1260,9 +3773,9 @@ struct LuaFileHandle {
 impl LuaFileHandle {
     fn open(path: PathBuf, mode: FileMode) -> Result<Self> {
         let buffer = match mode {
-            FileMode::Read => fs::read_to_string(&path)
+            FileMode::Read | FileMode::ReadWrite => fs::read_to_string(&path)
                 .with_context(|| format!("could not read {}", path.display()))?,
-            FileMode::Write => String::new(),
+            FileMode::Write | FileMode::ReadWriteTruncate => String::new(),
             FileMode::Append => fs::read_to_string(&path).unwrap_or_default(),
         };
         Ok(Self {
@@ -1283,7 +3796,7 @@ impl LuaFileHandle {
     }
 
     fn ensure_can_read(&self) -> Result<()> {
-        if self.mode == FileMode::Write || self.mode == FileMode::Append {
+        if !self.mode.allows_read() {
             bail!("file opened without read access");
         }
         Ok(())
@@ -1296,35 +3809,159 @@ impl LuaFileHandle {
         Ok(())
     }
 
-    fn read_all(&mut self) -> String {
-        let slice = self.buffer[self.cursor..].to_string();
+    /// Unread bytes. Reads work on bytes, since a count read may stop inside
+    /// a multi-byte character.
+    fn remaining(&self) -> &[u8] {
+        &self.buffer.as_bytes()[self.cursor..]
+    }
+
+    fn read_all(&mut self) -> Vec<u8> {
+        let slice = self.remaining().to_vec();
         self.cursor = self.buffer.len();
         slice
     }
 
-    fn read_line(&mut self) -> Option<String> {
+    fn read_line(&mut self) -> Option<Vec<u8>> {
         if self.cursor >= self.buffer.len() {
             return None;
         }
-        let remaining = &self.buffer[self.cursor..];
-        if let Some(pos) = remaining.find('\n') {
-            let line = &remaining[..pos];
-            self.cursor += pos + 1;
-            Some(line.trim_end_matches('\r').to_string())
-        } else {
-            let line = remaining.trim_end_matches('\r').to_string();
-            self.cursor = self.buffer.len();
-            Some(line)
+        let remaining = self.remaining();
+        let (line, consumed) = match remaining.iter().position(|&byte| byte == b'\n') {
+            Some(pos) => (&remaining[..pos], pos + 1),
+            None => (remaining, remaining.len()),
+        };
+        let line = line.strip_suffix(b"\r").unwrap_or(line).to_vec();
+        self.cursor += consumed;
+        Some(line)
+    }
+
+    /// Up to `count` bytes, or `None` at end of file (`read(0)` included).
+    fn read_bytes(&mut self, count: usize) -> Option<Vec<u8>> {
+        if self.cursor >= self.buffer.len() {
+            return None;
+        }
+        let remaining = self.remaining();
+        let bytes = remaining[..count.min(remaining.len())].to_vec();
+        self.cursor += bytes.len();
+        Some(bytes)
+    }
+
+    /// Parses a number after any leading whitespace, like Lua's `read("*n")`:
+    /// decimal integers and floats with an optional exponent, or `0x` hex
+    /// integers. Leaves the cursor untouched and returns `None` when no number
+    /// starts there.
+    fn read_number<'lua>(&mut self) -> Option<Value<'lua>> {
+        let bytes = self.remaining();
+        let start = bytes
+            .iter()
+            .take_while(|byte| byte.is_ascii_whitespace())
+            .count();
+        let digits_from = |from: usize, hex: bool| {
+            let is_digit = |byte: &&u8| {
+                if hex {
+                    byte.is_ascii_hexdigit()
+                } else {
+                    byte.is_ascii_digit()
+                }
+            };
+            from + bytes[from..].iter().take_while(is_digit).count()
+        };
+        let mut end = start;
+        if matches!(bytes.get(end), Some(b'+' | b'-')) {
+            end += 1;
+        }
+        let sign_end = end;
+        if bytes.get(end) == Some(&b'0') && matches!(bytes.get(end + 1), Some(b'x' | b'X')) {
+            let hex_end = digits_from(end + 2, true);
+            if hex_end == end + 2 {
+                return None;
+            }
+            let text = std::str::from_utf8(&bytes[end + 2..hex_end]).ok()?;
+            let magnitude = i64::from_str_radix(text, 16).ok()?;
+            let negative = bytes[start] == b'-';
+            self.cursor += hex_end;
+            return Some(Value::Integer(if negative {
+                -magnitude
+            } else {
+                magnitude
+            }));
+        }
+        end = digits_from(end, false);
+        let mut has_digits = end > sign_end;
+        let mut is_float = false;
+        if bytes.get(end) == Some(&b'.') {
+            is_float = true;
+            let fraction_end = digits_from(end + 1, false);
+            has_digits |= fraction_end > end + 1;
+            end = fraction_end;
+        }
+        if !has_digits {
+            return None;
         }
+        if matches!(bytes.get(end), Some(b'e' | b'E')) {
+            let mut exponent = end + 1;
+            if matches!(bytes.get(exponent), Some(b'+' | b'-')) {
+                exponent += 1;
+            }
+            let exponent_end = digits_from(exponent, false);
+            if exponent_end > exponent {
+                is_float = true;
+                end = exponent_end;
+            }
+        }
+        let text = std::str::from_utf8(&bytes[start..end]).ok()?;
+        let value = match text.parse::<i64>() {
+            Ok(integer) if !is_float => Value::Integer(integer),
+            _ => Value::Number(text.parse().ok()?),
+        };
+        self.cursor += end;
+        Some(value)
     }
 
+    /// Appends in `a` mode; otherwise overwrites from the cursor, extending
+    /// the buffer past its end as needed. A character cut in half by the
+    /// overwrite is replaced whole.
     fn write_data(&mut self, data: &str) -> Result<()> {
         self.ensure_can_write()?;
-        self.buffer.push_str(data);
+        if !self.buffer.is_char_boundary(self.cursor) {
+            bail!(
+                "cannot write into the middle of a UTF-8 character (offset {})",
+                self.cursor
+            );
+        }
+        if self.mode == FileMode::Append {
+            self.buffer.push_str(data);
+            self.cursor = self.buffer.len();
+        } else {
+            let mut end = (self.cursor + data.len()).min(self.buffer.len());
+            while !self.buffer.is_char_boundary(end) {
+                end += 1;
+            }
+            self.buffer.replace_range(self.cursor..end, data);
+            self.cursor += data.len();
+        }
         self.dirty = true;
         Ok(())
     }
 
+    /// Moves the cursor like Lua's `file:seek`, returning the new byte
+    /// offset. Positions past the end are clamped to it.
+    fn seek(&mut self, whence: &str, offset: i64) -> Result<usize> {
+        let base = match whence {
+            "set" => 0,
+            "cur" => self.cursor as i64,
+            "end" => self.buffer.len() as i64,
+            other => bail!("seek whence `{other}` not supported (use set, cur, or end)"),
+        };
+        let target = base
+            .checked_add(offset)
+            .filter(|target| *target >= 0)
+            .with_context(|| format!("cannot seek to {offset} from `{whence}`"))?;
+        let target = (target as usize).min(self.buffer.len());
+        self.cursor = target;
+        Ok(target)
+    }
+
     fn flush(&mut self) -> Result<()> {
         if self.closed {
             return Ok(());
@@ -1351,21 +3988,36 @@ impl Drop for LuaFileHandle {
 
 impl UserData for LuaFileHandle {
     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
-        methods.add_method_mut("read", |lua_ctx, this, mode: Option<String>| {
+        methods.add_method_mut("read", |lua_ctx, this, mode: Option<Value>| {
             this.ensure_open().map_err(mlua::Error::external)?;
             this.ensure_can_read().map_err(mlua::Error::external)?;
-            let spec = mode.unwrap_or_else(|| "*l".into());
+            let bytes_or_nil = |bytes: Option<Vec<u8>>| match bytes {
+                Some(bytes) => Ok(Value::String(lua_ctx.create_string(&bytes)?)),
+                None => Ok(Value::Nil),
+            };
+            let count = match &mode {
+                Some(Value::Integer(count)) => Some(*count),
+                Some(Value::Number(count)) if count.fract() == 0.0 => Some(*count as i64),
+                _ => None,
+            };
+            if let Some(count) = count {
+                let count = usize::try_from(count).map_err(|_| {
+                    mlua::Error::external(format!("io.read count must be >= 0 (got {count})"))
+                })?;
+                return bytes_or_nil(this.read_bytes(count));
+            }
+            let spec = match &mode {
+                None => "*l".to_string(),
+                Some(Value::String(spec)) => spec.to_str()?.to_string(),
+                Some(other) => other.type_name().to_string(),
+            };
             match spec.as_str() {
-                "*a" => {
-                    let data = this.read_all();
-                    Ok(Value::String(lua_ctx.create_string(&data)?))
-                }
-                "*l" => match this.read_line() {
-                    Some(line) => Ok(Value::String(lua_ctx.create_string(&line)?)),
-                    None => Ok(Value::Nil),
-                },
+                "*a" => bytes_or_nil(Some(this.read_all())),
+                "*l" => bytes_or_nil(this.read_line()),
+                "*n" => Ok(this.read_number().unwrap_or(Value::Nil)),
                 other => Err(mlua::Error::external(format!(
-                    "io.read mode `{other}` not supported (use \"*a\" or \"*l\")"
+                    "io.read mode `{other}` not supported \
+                     (use \"*a\", \"*l\", \"*n\", or a byte count)"
                 ))),
             }
         });
@@ -1376,6 +4028,16 @@ impl UserData for LuaFileHandle {
             Ok(true)
         });
 
+        methods.add_method_mut(
+            "seek",
+            |_, this, (whence, offset): (Option<String>, Option<i64>)| {
+                this.ensure_open().map_err(mlua::Error::external)?;
+                let whence = whence.unwrap_or_else(|| "cur".into());
+                this.seek(&whence, offset.unwrap_or(0))
+                    .map_err(mlua::Error::external)
+            },
+        );
+
         methods.add_method_mut("close", |_, this, ()| {
             this.flush().map_err(mlua::Error::external)?;
             Ok(true)
@@ -1,6 +1,30 @@
 -- SelenAI Lua Prelude
 -- Injected into every persistent session at startup.
 
+-- Numbers first in numeric order, then every other key by tostring.
+local function sorted_keys(t)
+    local keys = {}
+    for k in pairs(t) do table.insert(keys, k) end
+    table.sort(keys, function(a, b)
+        local a_num, b_num = type(a) == "number", type(b) == "number"
+        if a_num ~= b_num then return a_num end
+        if a_num then return a < b end
+        return tostring(a) < tostring(b)
+    end)
+    return keys
+end
+
+-- Like `pairs`, but in a stable sorted key order (see `sorted_keys`).
+function sorted_pairs(t)
+    local keys = sorted_keys(t)
+    local i = 0
+    return function()
+        i = i + 1
+        local k = keys[i]
+        if k ~= nil then return k, t[k] end
+    end
+end
+
 function repr(x, indent)
     indent = indent or 0
     if x == nil then return "nil" end
@@ -34,13 +58,7 @@ function repr(x, indent)
         end
         return "{" .. table.concat(parts, ", ") .. "}"
     else
-        -- sort keys for stability
-        local keys = {}
-        for k in pairs(x) do table.insert(keys, k) end
-        table.sort(keys, function(a, b) return tostring(a) < tostring(b) end)
-        
-        for _, k in ipairs(keys) do
-            local v = x[k]
+        for k, v in sorted_pairs(x) do
             local k_str = type(k) == "string" and k or "[" .. repr(k) .. "]"
             table.insert(parts, space .. k_str .. " = " .. repr(v, next_indent))
         end
@@ -67,6 +85,84 @@ function filter(tbl, func)
     return new_tbl
 end
 
+-- Table helpers. `keys` and `values` use `sorted_pairs` order so output is
+-- deterministic.
+function table.keys(t)
+    return sorted_keys(t)
+end
+
+function table.values(t)
+    return map(sorted_keys(t), function(k) return t[k] end)
+end
+
+-- Shallow merge into a new table; keys in `b` win over `a`.
+function table.merge(a, b)
+    local merged = {}
+    for k, v in pairs(a or {}) do merged[k] = v end
+    for k, v in pairs(b or {}) do merged[k] = v end
+    return merged
+end
+
+function table.contains(t, value)
+    for _, v in pairs(t) do
+        if v == value then return true end
+    end
+    return false
+end
+
+table.map = map
+table.filter = filter
+
+-- Folds a list left to right; without `init`, starts from the first item.
+function table.reduce(tbl, func, init)
+    local acc = init
+    local start = 1
+    if acc == nil then
+        acc = tbl[1]
+        start = 2
+    end
+    for i = start, #tbl do
+        acc = func(acc, tbl[i])
+    end
+    return acc
+end
+
+-- String helpers (`string.trim` is Rust-backed so it also strips Unicode
+-- whitespace). Separators and prefixes are plain text, not patterns.
+function string.split(s, sep)
+    assert(type(sep) == "string" and sep ~= "", "string.split: separator must be a non-empty string")
+    local parts = {}
+    local start = 1
+    while true do
+        local i, j = string.find(s, sep, start, true)
+        if not i then break end
+        table.insert(parts, string.sub(s, start, i - 1))
+        start = j + 1
+    end
+    table.insert(parts, string.sub(s, start))
+    return parts
+end
+
+function string.starts_with(s, prefix)
+    return string.sub(s, 1, #prefix) == prefix
+end
+
+function string.ends_with(s, suffix)
+    return suffix == "" or string.sub(s, -#suffix) == suffix
+end
+
+function string.lines(s)
+    local lines = string.split(s, "\n")
+    -- A trailing newline ends the last line rather than starting a new one.
+    if lines[#lines] == "" then
+        table.remove(lines)
+    end
+    for i, line in ipairs(lines) do
+        lines[i] = string.gsub(line, "\r$", "")
+    end
+    return lines
+end
+
 -- Overwrite global print to use repr for tables automatically?
 -- The host 'print' uses 'render_value' which calls 'table_to_string'.
 -- 'table_to_string' in rust is basic. 
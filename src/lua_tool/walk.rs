@@ -0,0 +1,387 @@
+//! Recursive workspace helpers: `rust.search` (a portable regex search) and
+//! `rust.walk_dir`. Both skip `.git` and whatever the workspace's `.gitignore`
+//! and `.selenai-ignore` files exclude.
+
+use std::{
+    fs::{self, FileType},
+    ops::ControlFlow,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use ignore::WalkBuilder;
+use mlua::Table;
+use regex::{Regex, RegexBuilder};
+
+use super::MAX_FILE_SIZE;
+
+/// Per-directory ignore file that hides paths from searches without touching
+/// what git tracks. It is read after `.gitignore`, so it can override it.
+const SEARCH_IGNORE_FILE: &str = ".selenai-ignore";
+
+/// Bytes inspected for a NUL when deciding whether a file is binary.
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+/// Entries `rust.walk_dir` returns unless `max_entries` says otherwise.
+const DEFAULT_WALK_ENTRIES: usize = 1_000;
+/// Ceiling for `max_entries`, so a script cannot flood the context window.
+const MAX_WALK_ENTRIES: usize = 10_000;
+
+/// The optional third argument of `rust.search`.
+#[derive(Debug, Default)]
+pub(crate) struct SearchOptions {
+    pub case_insensitive: bool,
+    /// Stop after this many matching lines.
+    pub max_results: Option<usize>,
+}
+
+impl SearchOptions {
+    pub fn from_table(opts: Option<Table>) -> mlua::Result<Self> {
+        let Some(opts) = opts else {
+            return Ok(Self::default());
+        };
+        Ok(Self {
+            case_insensitive: opts
+                .get::<_, Option<bool>>("case_insensitive")?
+                .unwrap_or(false),
+            max_results: opts.get("max_results")?,
+        })
+    }
+}
+
+/// One matching line. `file` is relative to the workspace root and always
+/// uses `/` separators.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct SearchHit {
+    pub file: String,
+    pub line_number: usize,
+    pub text: String,
+}
+
+/// The optional second argument of `rust.walk_dir`.
+#[derive(Debug)]
+pub(crate) struct WalkOptions {
+    /// Levels below the start directory to list; 1 matches `rust.list_dir`.
+    pub max_depth: Option<usize>,
+    /// Capped at `MAX_WALK_ENTRIES`.
+    pub max_entries: usize,
+    pub respect_gitignore: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            max_entries: DEFAULT_WALK_ENTRIES,
+            respect_gitignore: true,
+        }
+    }
+}
+
+impl WalkOptions {
+    pub fn from_table(opts: Option<Table>) -> mlua::Result<Self> {
+        let Some(opts) = opts else {
+            return Ok(Self::default());
+        };
+        let max_entries: Option<usize> = opts.get("max_entries")?;
+        Ok(Self {
+            max_depth: opts.get("max_depth")?,
+            max_entries: max_entries
+                .unwrap_or(DEFAULT_WALK_ENTRIES)
+                .min(MAX_WALK_ENTRIES),
+            respect_gitignore: opts
+                .get::<_, Option<bool>>("respect_gitignore")?
+                .unwrap_or(true),
+        })
+    }
+}
+
+/// One `rust.walk_dir` entry; `path` is workspace-relative with `/`
+/// separators and `size` is 0 for directories.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct WalkEntry {
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+/// Something met while walking, before any filtering by the caller.
+struct Visit<'a> {
+    path: &'a Path,
+    rel: &'a str,
+    file_type: FileType,
+}
+
+/// Searches every text file under `start` (a directory or a single file
+/// inside `root`) for `pattern`, in path order. Binary files, files over
+/// `MAX_FILE_SIZE`, symlinks, and ignored paths are skipped; ignore files
+/// between `root` and `start` apply too.
+pub(crate) fn search(
+    root: &Path,
+    start: &Path,
+    pattern: &str,
+    options: &SearchOptions,
+) -> Result<Vec<SearchHit>> {
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(options.case_insensitive)
+        .build()
+        .with_context(|| format!("invalid search pattern {pattern:?}"))?;
+    let limit = options.max_results.unwrap_or(usize::MAX);
+    let mut hits = Vec::new();
+    if limit == 0 {
+        return Ok(hits);
+    }
+
+    walk(root, start, true, None, &mut |visit| {
+        if !visit.file_type.is_file() {
+            return ControlFlow::Continue(());
+        }
+        scan_file(&regex, visit.path, visit.rel, limit, &mut hits);
+        if hits.len() >= limit {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })?;
+    Ok(hits)
+}
+
+/// Lists `start` recursively in path order (each directory before its
+/// contents), up to `max_entries`. Every entry is re-checked with `is_safe`
+/// so a symlink that points outside the workspace is left out; symlinked
+/// directories are listed but never entered. The flag reports truncation.
+pub(crate) fn walk_dir(
+    root: &Path,
+    start: &Path,
+    options: &WalkOptions,
+    is_safe: &dyn Fn(&Path) -> bool,
+) -> Result<(Vec<WalkEntry>, bool)> {
+    let mut entries = Vec::new();
+    let mut truncated = false;
+    walk(
+        root,
+        start,
+        options.respect_gitignore,
+        options.max_depth,
+        &mut |visit| {
+            if !is_safe(visit.path) {
+                return ControlFlow::Continue(());
+            }
+            if entries.len() >= options.max_entries {
+                truncated = true;
+                return ControlFlow::Break(());
+            }
+            // Symlinks report what they point at, which `is_safe` vetted.
+            let meta = fs::metadata(visit.path).ok();
+            let is_dir = meta.as_ref().is_some_and(|meta| meta.is_dir());
+            entries.push(WalkEntry {
+                path: visit.rel.to_string(),
+                is_dir,
+                size: meta.filter(|_| !is_dir).map_or(0, |meta| meta.len()),
+            });
+            ControlFlow::Continue(())
+        },
+    )?;
+    Ok((entries, truncated))
+}
+
+/// Visits everything under `start` (or `start` itself when it is a file)
+/// depth-first in name order, skipping `.git` and, with `respect_ignores`,
+/// whatever `.gitignore` and `.selenai-ignore` files exclude. The walk begins
+/// at `root` so ignore files between `root` and `start` apply, but only the
+/// directories leading to `start` are entered on the way. Directories below
+/// `max_depth` and symlinks are never entered.
+fn walk(
+    root: &Path,
+    start: &Path,
+    respect_ignores: bool,
+    max_depth: Option<usize>,
+    visit: &mut dyn FnMut(Visit<'_>) -> ControlFlow<()>,
+) -> Result<()> {
+    if !start.is_file() {
+        fs::read_dir(start).with_context(|| format!("could not read dir {}", start.display()))?;
+    }
+    let start_depth = start
+        .strip_prefix(root)
+        .map_or(0, |rel| rel.components().count());
+    let mut builder = WalkBuilder::new(root);
+    builder
+        .standard_filters(false)
+        .git_ignore(respect_ignores)
+        .require_git(false)
+        .max_depth(max_depth.map(|depth| start_depth + depth))
+        .sort_by_file_name(|a, b| a.cmp(b));
+    if respect_ignores {
+        builder.add_custom_ignore_filename(SEARCH_IGNORE_FILE);
+    }
+    let scope = start.to_path_buf();
+    builder.filter_entry(move |entry| {
+        entry.file_name() != ".git"
+            && (scope.starts_with(entry.path()) || entry.path().starts_with(&scope))
+    });
+
+    // Unreadable entries are skipped rather than failing the whole walk.
+    for entry in builder.build().flatten() {
+        let path = entry.path();
+        let Some(file_type) = entry.file_type() else {
+            continue;
+        };
+        let below_start = path != start && path.starts_with(start);
+        if !below_start && (path != start || file_type.is_dir()) {
+            continue;
+        }
+        let rel = workspace_rel(root, path);
+        if visit(Visit {
+            path,
+            rel: &rel,
+            file_type,
+        })
+        .is_break()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Appends matching lines of a text file to `hits`, up to `limit`. Binary,
+/// oversized, and unreadable files are skipped rather than failing the whole
+/// search; the size is checked before anything is read.
+fn scan_file(regex: &Regex, path: &Path, rel: &str, limit: usize, hits: &mut Vec<SearchHit>) {
+    if fs::metadata(path).map_or(true, |meta| meta.len() > MAX_FILE_SIZE) {
+        return;
+    }
+    let Ok(bytes) = fs::read(path) else {
+        return;
+    };
+    if bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0) {
+        return;
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    for (idx, line) in text.lines().enumerate() {
+        if hits.len() >= limit {
+            break;
+        }
+        if regex.is_match(line) {
+            hits.push(SearchHit {
+                file: rel.to_string(),
+                line_number: idx + 1,
+                text: line.to_string(),
+            });
+        }
+    }
+}
+
+/// `path` relative to `root`, with `/` separators on every platform.
+fn workspace_rel(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn listed(root: &Path, start: &Path) -> Vec<String> {
+        let (entries, _) = walk_dir(root, start, &WalkOptions::default(), &|_| true).expect("walk");
+        entries.into_iter().map(|entry| entry.path).collect()
+    }
+
+    #[test]
+    fn gitignore_patterns_follow_git_matching() -> Result<()> {
+        let tmp = tempdir()?;
+        let root = tmp.path().canonicalize()?;
+        for dir in [
+            "target",
+            "crates/a/target",
+            "docs/a",
+            "src/x/gen",
+            "gen",
+            "build",
+        ] {
+            fs::create_dir_all(root.join(dir))?;
+        }
+        for file in [
+            "app.log",
+            "keep.log",
+            "app.log.txt",
+            "docs/a/index.html",
+            "docs/index.html",
+            "file7.txt",
+            "src/build",
+            "notes.md",
+        ] {
+            fs::write(root.join(file), "x")?;
+        }
+        fs::write(
+            root.join(".gitignore"),
+            "target/\n*.log\n!keep.log\n/build\ndocs/**/*.html\n",
+        )?;
+        fs::write(root.join("src/.gitignore"), "gen\n")?;
+        fs::write(root.join(SEARCH_IGNORE_FILE), "file[0-9].txt\n!app.log\n")?;
+
+        let paths = listed(&root, &root);
+        for hidden in [
+            "target",
+            "crates/a/target",
+            "build",
+            "docs/a/index.html",
+            "src/x/gen",
+        ] {
+            assert!(
+                !paths.iter().any(|path| path == hidden),
+                "{hidden} in {paths:?}"
+            );
+        }
+        for shown in [
+            "keep.log",
+            "app.log.txt",
+            "src/build",
+            "gen",
+            "notes.md",
+            ".gitignore",
+        ] {
+            assert!(
+                paths.iter().any(|path| path == shown),
+                "{shown} missing: {paths:?}"
+            );
+        }
+        assert!(
+            !paths.iter().any(|path| path == "file7.txt"),
+            ".selenai-ignore applies"
+        );
+        assert!(
+            paths.iter().any(|path| path == "app.log"),
+            ".selenai-ignore wins"
+        );
+
+        assert!(
+            listed(&root, &root.join("src/x")).is_empty(),
+            "parent rules apply"
+        );
+        assert!(
+            listed(&root, &root.join("target")).is_empty(),
+            "ignored start"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn search_skips_files_over_the_size_limit() -> Result<()> {
+        let tmp = tempdir()?;
+        let root = tmp.path().canonicalize()?;
+        fs::write(root.join("small.txt"), "needle\n")?;
+        let mut big = b"needle\n".to_vec();
+        big.resize(MAX_FILE_SIZE as usize + 1, b'.');
+        fs::write(root.join("big.txt"), big)?;
+
+        let hits = search(&root, &root, "needle", &SearchOptions::default())?;
+        let files: Vec<_> = hits.iter().map(|hit| hit.file.as_str()).collect();
+        assert_eq!(files, ["small.txt"]);
+        Ok(())
+    }
+}
@@ -1,5 +1,4 @@
 use std::collections::HashMap;
-use std::path::Path;
 use std::fs;
 use anyhow::Result;
 use serde::Deserialize;
@@ -11,8 +10,7 @@ pub struct MacroConfig {
 
 impl MacroConfig {
     pub fn load() -> Result<Self> {
-        let home = std::env::var("HOME").unwrap_or_else(|_| ".".into());
-        let path = Path::new(&home).join(".config/selenai/macros.toml");
+        let path = crate::config::user_config_dir().join("macros.toml");
         if !path.exists() {
             return Ok(Self::default());
         }
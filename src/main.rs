@@ -1,22 +1,38 @@
 mod app;
 mod config;
+mod export;
 mod llm;
 mod lua_tool;
 mod macros;
 mod session;
 mod tui;
 mod types;
+mod watch;
 
-use std::{env, io};
+use std::{
+    env,
+    io::{self, IsTerminal},
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
 fn main() -> Result<()> {
     load_env_file()?;
+    if env::args().nth(1).as_deref() == Some("init") {
+        return init_workspace();
+    }
+    if env::args().skip(1).any(|arg| arg == "--print-config") {
+        return print_config();
+    }
     init_tracing();
+    let plain_reason = app::plain_mode_reason(
+        env::args().skip(1).any(|arg| arg == "--plain"),
+        io::stdin().is_terminal(),
+        io::stdout().is_terminal(),
+    );
     let mut app = app::App::new()?;
-    app.run()
+    app.run(plain_reason)
 }
 
 fn load_env_file() -> Result<()> {
@@ -27,6 +43,29 @@ fn load_env_file() -> Result<()> {
     }
 }
 
+/// `selenai init`: scaffolds `selenai.toml` and `.selenai-ignore` in the
+/// current directory without overwriting either.
+fn init_workspace() -> Result<()> {
+    let workspace = env::current_dir().context("failed to get current dir")?;
+    for line in config::init_workspace(&workspace)? {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// `--print-config`: prints the merged config (defaults, then the file, then
+/// `SELENAI_*` overrides) and exits without starting the TUI.
+fn print_config() -> Result<()> {
+    let workspace = env::current_dir().context("failed to get current dir")?;
+    let (config, warnings) = config::AppConfig::load(&workspace)?;
+    for warning in warnings {
+        eprintln!("warning: {warning}");
+    }
+    let path = config::config_path_from_env();
+    print!("{}", config.to_effective_toml(&path, &workspace)?);
+    Ok(())
+}
+
 fn init_tracing() {
     let val = env::var("SELENAI_TRACE").unwrap_or_default();
     if val.is_empty() {
@@ -1,27 +1,51 @@
 use std::{
+    collections::HashMap,
     fs::{self, File},
     io::{BufWriter, Write},
-    path::{Path, PathBuf},
-    sync::OnceLock,
+    path::{Component, Path, PathBuf},
+    process::Command,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local};
 use regex::Regex;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::types::{Message, ToolLogEntry};
 
+/// Naming used when `session_dir_template` is not configured.
+pub const DEFAULT_SESSION_DIR_TEMPLATE: &str = "session-{unix}-{pid}";
+
 pub struct SessionRecorder {
     session_dir: PathBuf,
+    redactor: Redactor,
 }
 
 impl SessionRecorder {
+    /// Test shorthand using the default directory naming.
+    #[cfg(test)]
     pub fn new(log_root: impl AsRef<Path>, allow_tool_writes: bool) -> Result<Self> {
+        Self::with_template(
+            log_root,
+            allow_tool_writes,
+            DEFAULT_SESSION_DIR_TEMPLATE,
+            "",
+        )
+    }
+
+    /// Creates the session directory from a template such as `{date}/{time}-{project}`.
+    pub fn with_template(
+        log_root: impl AsRef<Path>,
+        allow_tool_writes: bool,
+        template: &str,
+        project: &str,
+    ) -> Result<Self> {
         let log_root = log_root.as_ref();
         fs::create_dir_all(log_root)
             .with_context(|| format!("failed to create log directory {}", log_root.display()))?;
-        let session_dir = create_unique_session_dir(log_root)?;
+        let name = generate_session_dir_name(template, project, Local::now(), std::process::id())?;
+        let session_dir = create_unique_session_dir(log_root, &name)?;
         fs::create_dir_all(&session_dir).with_context(|| {
             format!(
                 "failed to create session directory {}",
@@ -29,13 +53,45 @@ impl SessionRecorder {
             )
         })?;
         write_metadata(&session_dir, allow_tool_writes)?;
-        Ok(Self { session_dir })
+        Ok(Self {
+            session_dir,
+            redactor: Redactor::default(),
+        })
+    }
+
+    /// Masks transcripts and tool logs with `redactor` instead of the
+    /// built-in patterns alone.
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = redactor;
+        self
     }
 
     pub fn session_dir(&self) -> &Path {
         &self.session_dir
     }
 
+    /// Stores a human-friendly `title` in `metadata.json` for `/sessions`.
+    pub fn set_title(&self, title: &str) -> Result<()> {
+        self.update_metadata(|metadata| metadata.title = Some(title.to_string()))
+    }
+
+    /// Remembers the copy-mode toggle so resuming this session restores it.
+    pub fn set_copy_mode(&self, enabled: bool) -> Result<()> {
+        self.update_metadata(|metadata| metadata.copy_mode = Some(enabled))
+    }
+
+    fn update_metadata(&self, update: impl FnOnce(&mut SessionMetadata)) -> Result<()> {
+        let file = self.session_dir.join("metadata.json");
+        let data = fs::read(&file)
+            .with_context(|| format!("failed to read metadata {}", file.display()))?;
+        let mut metadata: SessionMetadata = serde_json::from_slice(&data)
+            .with_context(|| format!("invalid metadata {}", file.display()))?;
+        update(&mut metadata);
+        fs::write(&file, serde_json::to_vec_pretty(&metadata)?)
+            .with_context(|| format!("failed to write metadata {}", file.display()))?;
+        Ok(())
+    }
+
     pub fn persist(&self, messages: &[Message], tool_logs: &[ToolLogEntry]) -> Result<()> {
         self.write_jsonl("transcript.jsonl", messages)?;
         self.write_jsonl("tool_logs.jsonl", tool_logs)?;
@@ -49,7 +105,7 @@ impl SessionRecorder {
         let mut writer = BufWriter::new(file);
         for item in items {
             let json = serde_json::to_string(item)?;
-            let redacted = redact_secrets(&json);
+            let redacted = self.redactor.redact(&json);
             writer.write_all(redacted.as_bytes())?;
             writer.write_all(b"\n")?;
         }
@@ -58,27 +114,266 @@ impl SessionRecorder {
     }
 }
 
-static SECRET_REGEX: OnceLock<Vec<Regex>> = OnceLock::new();
+/// A past session discovered under the log root.
+#[derive(Debug, Clone)]
+pub struct SessionSummary {
+    /// Directory path relative to the log root, e.g. `session-1717000000-42`.
+    pub id: String,
+    pub dir: PathBuf,
+    /// `None` when `metadata.json` is missing or unreadable.
+    pub started_unix_ms: Option<u128>,
+    /// `None` when no transcript was persisted (crashed or still running).
+    pub message_count: Option<usize>,
+    /// Set from the first prompt; absent for older or untitled sessions.
+    pub title: Option<String>,
+    /// Last copy-mode toggle; absent when it was never changed.
+    pub copy_mode: Option<bool>,
+}
+
+/// How deep to look for session directories (templates may nest them).
+const MAX_SESSION_DEPTH: usize = 3;
+
+/// Lists sessions under `log_root`, newest first. Unreadable entries are kept
+/// with missing fields instead of failing the whole listing.
+pub fn list_sessions(log_root: &Path) -> Result<Vec<SessionSummary>> {
+    let mut sessions = Vec::new();
+    if log_root.exists() {
+        collect_sessions(log_root, log_root, 0, &mut sessions)?;
+    }
+    sessions.sort_by(|a, b| {
+        b.started_unix_ms
+            .cmp(&a.started_unix_ms)
+            .then_with(|| b.id.cmp(&a.id))
+    });
+    Ok(sessions)
+}
+
+fn collect_sessions(
+    root: &Path,
+    dir: &Path,
+    depth: usize,
+    out: &mut Vec<SessionSummary>,
+) -> Result<()> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("failed to read log directory {}", dir.display()))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let metadata_path = path.join("metadata.json");
+        let transcript_path = path.join("transcript.jsonl");
+        if metadata_path.exists() || transcript_path.exists() {
+            let metadata = fs::read(&metadata_path)
+                .ok()
+                .and_then(|data| serde_json::from_slice::<SessionMetadata>(&data).ok());
+            let started_unix_ms = metadata.as_ref().map(|meta| meta.started_unix_ms);
+            let copy_mode = metadata.as_ref().and_then(|meta| meta.copy_mode);
+            let title = metadata.and_then(|meta| meta.title);
+            let message_count = fs::read_to_string(&transcript_path)
+                .ok()
+                .map(|data| data.lines().filter(|line| !line.trim().is_empty()).count());
+            out.push(SessionSummary {
+                id: path
+                    .strip_prefix(root)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .into_owned(),
+                dir: path,
+                started_unix_ms,
+                message_count,
+                title,
+                copy_mode,
+            });
+        } else if depth + 1 < MAX_SESSION_DEPTH {
+            collect_sessions(root, &path, depth + 1, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Loads a persisted transcript, skipping lines that no longer parse.
+pub fn load_transcript(session_dir: &Path) -> Result<Vec<Message>> {
+    let path = session_dir.join("transcript.jsonl");
+    let data = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read transcript {}", path.display()))?;
+    Ok(data
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Net line counts for a file written during the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: String,
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// Computes +/- counts for `paths` against `HEAD`. Files git does not track are
+/// reported as fully added; tracked files without a net diff are skipped.
+pub fn collect_file_changes(workspace_root: &Path, paths: &[PathBuf]) -> Vec<FileChange> {
+    let relative: Vec<String> = paths
+        .iter()
+        .map(|path| {
+            path.strip_prefix(workspace_root)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    let numstat = git_output(
+        workspace_root,
+        &["diff", "--numstat", "HEAD", "--"],
+        &relative,
+    )
+    .unwrap_or_default();
+    let mut counts = HashMap::new();
+    for line in numstat.lines() {
+        let mut parts = line.splitn(3, '\t');
+        if let (Some(added), Some(removed), Some(path)) = (parts.next(), parts.next(), parts.next())
+        {
+            counts.insert(
+                path.to_string(),
+                (added.parse().unwrap_or(0), removed.parse().unwrap_or(0)),
+            );
+        }
+    }
+    let tracked = git_output(workspace_root, &["ls-files", "--"], &relative).unwrap_or_default();
+    let tracked: Vec<&str> = tracked.lines().collect();
+
+    relative
+        .into_iter()
+        .filter_map(|path| {
+            let (added, removed) = match counts.get(&path) {
+                Some(&counts) => counts,
+                None if tracked.contains(&path.as_str()) => return None,
+                None => {
+                    let contents = fs::read_to_string(workspace_root.join(&path)).ok()?;
+                    (contents.lines().count(), 0)
+                }
+            };
+            Some(FileChange {
+                path,
+                added,
+                removed,
+            })
+        })
+        .collect()
+}
+
+fn git_output(root: &Path, args: &[&str], paths: &[String]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .args(paths)
+        .current_dir(root)
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Renders a `git diff --stat` style recap, or an empty string when nothing changed.
+pub fn format_change_summary(changes: &[FileChange]) -> String {
+    if changes.is_empty() {
+        return String::new();
+    }
+    let width = changes.iter().map(|c| c.path.len()).max().unwrap_or(0);
+    let mut out = String::from("Files changed this session:\n");
+    for change in changes {
+        out.push_str(&format!(
+            " {:<width$} | +{} -{}\n",
+            change.path, change.added, change.removed
+        ));
+    }
+    let added: usize = changes.iter().map(|c| c.added).sum();
+    let removed: usize = changes.iter().map(|c| c.removed).sum();
+    out.push_str(&format!(
+        "{} file{} changed, {added} insertion{}(+), {removed} deletion{}(-)\n",
+        changes.len(),
+        plural(changes.len()),
+        plural(added),
+        plural(removed)
+    ));
+    out
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
+
+/// Patterns that are always redacted; `[security] secret_patterns` adds to these.
+const BUILTIN_SECRET_PATTERNS: &[&str] = &[r"sk-[a-zA-Z0-9-]{20,}"];
+
+/// The secret patterns every redaction path (transcripts, HTML export, live
+/// tool output, input history) applies. `Default` has only the built-ins.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::new(&[]).0
+    }
+}
+
+impl Redactor {
+    /// Compiles the built-in patterns followed by `extra`. A pattern that is
+    /// not a valid regex is skipped and described in the returned warnings.
+    pub fn new(extra: &[String]) -> (Self, Vec<String>) {
+        let mut patterns = Vec::new();
+        let mut warnings = Vec::new();
+        let builtins = BUILTIN_SECRET_PATTERNS.iter().copied();
+        for pattern in builtins.chain(extra.iter().map(String::as_str)) {
+            match Regex::new(pattern) {
+                Ok(regex) => patterns.push(regex),
+                Err(err) => warnings.push(format!(
+                    "skipping invalid secret pattern {pattern:?}: {err}"
+                )),
+            }
+        }
+        (Self { patterns }, warnings)
+    }
 
-fn get_secret_regexes() -> &'static [Regex] {
-    SECRET_REGEX.get_or_init(|| {
-        vec![
-            Regex::new(r"sk-[a-zA-Z0-9-]{20,}").expect("invalid regex"),
-        ]
-    })
+    pub fn redact(&self, text: &str) -> String {
+        let mut result = text.to_string();
+        for re in &self.patterns {
+            result = re.replace_all(&result, "[REDACTED]").to_string();
+        }
+        result
+    }
 }
 
-fn redact_secrets(text: &str) -> String {
-    let mut result = text.to_string();
-    for re in get_secret_regexes() {
-        result = re.replace_all(&result, "[REDACTED]").to_string();
+/// Escapes text for embedding in HTML element content or attribute values.
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
     }
-    result
+    escaped
 }
 
-fn create_unique_session_dir(root: &Path) -> Result<PathBuf> {
-    let base = generate_session_dir_name();
-    let mut candidate = root.join(&base);
+/// Wraps `code` in a Markdown fence longer than any backtick run inside it, so
+/// embedded ``` sequences cannot close the block early.
+pub(crate) fn fence_code_block(lang: &str, code: &str) -> String {
+    let longest_run = code.split(|ch| ch != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{fence}{lang}\n{code}\n{fence}")
+}
+
+fn create_unique_session_dir(root: &Path, base: &str) -> Result<PathBuf> {
+    let mut candidate = root.join(base);
     let mut counter = 1;
     while candidate.exists() {
         counter += 1;
@@ -87,18 +382,72 @@ fn create_unique_session_dir(root: &Path) -> Result<PathBuf> {
     Ok(candidate)
 }
 
-fn generate_session_dir_name() -> String {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0));
-    format!("session-{}-{}", now.as_secs(), std::process::id())
+fn generate_session_dir_name(
+    template: &str,
+    project: &str,
+    now: DateTime<Local>,
+    pid: u32,
+) -> Result<String> {
+    let project = sanitize_component(project);
+    let name = template
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+        .replace("{time}", &now.format("%H-%M").to_string())
+        .replace("{unix}", &now.timestamp().to_string())
+        .replace("{pid}", &pid.to_string())
+        .replace("{project}", &project);
+    let path = Path::new(&name);
+    if name.trim().is_empty()
+        || !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        bail!("session_dir_template `{template}` must expand to a relative path inside log_dir");
+    }
+    Ok(name)
 }
 
-#[derive(Serialize)]
+fn sanitize_component(value: &str) -> String {
+    let cleaned: String = value
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == '.' {
+                ch
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if cleaned.trim_matches('.').is_empty() {
+        "workspace".to_string()
+    } else {
+        cleaned
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 struct SessionMetadata {
     version: u8,
     started_unix_ms: u128,
     allow_tool_writes: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    copy_mode: Option<bool>,
+}
+
+/// Longest session title derived from a prompt, in characters.
+const MAX_TITLE_CHARS: usize = 60;
+
+/// One-line title from a prompt: first non-empty line, whitespace collapsed,
+/// truncated with an ellipsis.
+pub fn title_from_prompt(prompt: &str) -> Option<String> {
+    let line = prompt.lines().find(|line| !line.trim().is_empty())?;
+    let collapsed = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.chars().count() <= MAX_TITLE_CHARS {
+        return Some(collapsed);
+    }
+    let cut: String = collapsed.chars().take(MAX_TITLE_CHARS - 1).collect();
+    Some(format!("{}…", cut.trim_end()))
 }
 
 fn write_metadata(path: &Path, allow_tool_writes: bool) -> Result<()> {
@@ -106,6 +455,8 @@ fn write_metadata(path: &Path, allow_tool_writes: bool) -> Result<()> {
         version: 1,
         started_unix_ms: unix_timestamp_ms(),
         allow_tool_writes,
+        title: None,
+        copy_mode: None,
     };
     let data = serde_json::to_vec_pretty(&metadata)?;
     let file = path.join("metadata.json");
@@ -138,6 +489,46 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn set_title_is_recorded_and_listed() -> Result<()> {
+        let root = tempdir()?;
+        let recorder = SessionRecorder::new(root.path(), false)?;
+        let title = title_from_prompt("\n  Fix   the flaky\tparser test\nmore detail").unwrap();
+        assert_eq!(title, "Fix the flaky parser test");
+        recorder.set_title(&title)?;
+
+        let contents = fs::read_to_string(recorder.session_dir().join("metadata.json"))?;
+        let json: serde_json::Value = serde_json::from_str(&contents)?;
+        assert_eq!(json["title"], "Fix the flaky parser test");
+        assert_eq!(json["allow_tool_writes"], false);
+
+        let sessions = list_sessions(root.path())?;
+        assert_eq!(
+            sessions[0].title.as_deref(),
+            Some("Fix the flaky parser test")
+        );
+
+        let long = title_from_prompt(&"word ".repeat(40)).unwrap();
+        assert_eq!(long.chars().count(), MAX_TITLE_CHARS);
+        assert!(long.ends_with('…'));
+        assert_eq!(title_from_prompt("  \n "), None);
+        Ok(())
+    }
+
+    #[test]
+    fn copy_mode_is_remembered_per_session() -> Result<()> {
+        let root = tempdir()?;
+        let recorder = SessionRecorder::new(root.path(), false)?;
+        assert_eq!(list_sessions(root.path())?[0].copy_mode, None);
+
+        recorder.set_title("keep me")?;
+        recorder.set_copy_mode(true)?;
+        let sessions = list_sessions(root.path())?;
+        assert_eq!(sessions[0].copy_mode, Some(true));
+        assert_eq!(sessions[0].title.as_deref(), Some("keep me"));
+        Ok(())
+    }
+
     #[test]
     fn persist_writes_transcript_and_tool_logs() -> Result<()> {
         let root = tempdir()?;
@@ -177,4 +568,163 @@ mod tests {
         assert!(content.contains("[REDACTED]"), "redaction placeholder should appear");
         Ok(())
     }
+
+    #[test]
+    fn custom_secret_patterns_apply_to_transcripts_and_export() -> Result<()> {
+        let (redactor, warnings) =
+            Redactor::new(&["corp-[".to_string(), r"corp-token-[0-9]{6}".to_string()]);
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(
+            warnings[0].contains("invalid secret pattern \"corp-[\""),
+            "{warnings:?}"
+        );
+        let secret = "corp-token-424242";
+        assert_eq!(
+            redactor.redact(&format!("token={secret}")),
+            "token=[REDACTED]"
+        );
+        assert_eq!(
+            redactor.redact("sk-123456789012345678901234"),
+            "[REDACTED]",
+            "built-ins still apply"
+        );
+        assert_eq!(
+            Redactor::default().redact(secret),
+            secret,
+            "nothing global changed"
+        );
+
+        let root = tempdir()?;
+        let recorder = SessionRecorder::new(root.path(), false)?.with_redactor(redactor.clone());
+        let messages = vec![Message::new(Role::Tool, format!("printed {secret}"))];
+        let logs = vec![ToolLogEntry::new(1, "env", format!("detail {secret}"))];
+        recorder.persist(&messages, &logs)?;
+        for file in ["transcript.jsonl", "tool_logs.jsonl"] {
+            let content = fs::read_to_string(recorder.session_dir().join(file))?;
+            assert!(!content.contains(secret), "{file}: {content}");
+        }
+        let html = crate::export::render_session_html(&messages, &logs, &redactor);
+        assert!(!html.contains(secret));
+        Ok(())
+    }
+
+    #[test]
+    fn change_summary_formats_like_diff_stat() {
+        assert_eq!(format_change_summary(&[]), "");
+        let changes = vec![
+            FileChange {
+                path: "src/lib.rs".into(),
+                added: 3,
+                removed: 1,
+            },
+            FileChange {
+                path: "notes.txt".into(),
+                added: 1,
+                removed: 0,
+            },
+        ];
+        assert_eq!(
+            format_change_summary(&changes),
+            "Files changed this session:\n \
+             src/lib.rs | +3 -1\n \
+             notes.txt  | +1 -0\n\
+             2 files changed, 4 insertions(+), 1 deletion(-)\n"
+        );
+    }
+
+    #[test]
+    fn untracked_files_count_as_additions() -> Result<()> {
+        let root = tempdir()?;
+        let path = root.path().join("new.txt");
+        fs::write(&path, "one\ntwo\n")?;
+        let changes = collect_file_changes(root.path(), &[path]);
+        assert_eq!(
+            changes,
+            vec![FileChange {
+                path: "new.txt".into(),
+                added: 2,
+                removed: 0,
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn session_dir_template_substitutes_placeholders() -> Result<()> {
+        use chrono::TimeZone;
+
+        let now = Local.with_ymd_and_hms(2024, 6, 1, 14, 30, 5).unwrap();
+        let name = generate_session_dir_name("{date}/{time}-{project}-{pid}", "my app", now, 42)?;
+        assert_eq!(name, "2024-06-01/14-30-my_app-42");
+
+        let default = generate_session_dir_name(DEFAULT_SESSION_DIR_TEMPLATE, "", now, 7)?;
+        assert_eq!(default, format!("session-{}-7", now.timestamp()));
+
+        assert!(generate_session_dir_name("../{date}", "demo", now, 1).is_err());
+        assert!(generate_session_dir_name("/tmp/{date}", "demo", now, 1).is_err());
+
+        let root = tempdir()?;
+        let first = SessionRecorder::with_template(root.path(), false, "{project}", "demo")?;
+        let second = SessionRecorder::with_template(root.path(), false, "{project}", "demo")?;
+        assert_eq!(first.session_dir(), root.path().join("demo"));
+        assert_eq!(second.session_dir(), root.path().join("demo-2"));
+        Ok(())
+    }
+
+    #[test]
+    fn list_sessions_sorts_newest_first_and_tolerates_corruption() -> Result<()> {
+        let root = tempdir()?;
+        let older = root.path().join("old");
+        let newer = root.path().join("2024-06-01/14-30-demo");
+        let broken = root.path().join("broken");
+        for dir in [&older, &newer, &broken] {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            older.join("metadata.json"),
+            r#"{"version":1,"started_unix_ms":1000,"allow_tool_writes":false}"#,
+        )?;
+        fs::write(
+            newer.join("metadata.json"),
+            r#"{"version":1,"started_unix_ms":2000,"allow_tool_writes":true}"#,
+        )?;
+        fs::write(
+            newer.join("transcript.jsonl"),
+            "{\"role\":\"User\",\"content\":\"hi\",\"tool_calls\":[]}\nnot json\n",
+        )?;
+        fs::write(broken.join("metadata.json"), "{truncated")?;
+
+        let sessions = list_sessions(root.path())?;
+        let ids: Vec<&str> = sessions.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["2024-06-01/14-30-demo", "old", "broken"]);
+        assert_eq!(sessions[0].message_count, Some(2));
+        assert_eq!(sessions[1].message_count, None);
+        assert_eq!(sessions[2].started_unix_ms, None);
+
+        let messages = load_transcript(&newer)?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "hi");
+        Ok(())
+    }
+
+    #[test]
+    fn escape_html_handles_each_special_character() {
+        assert_eq!(escape_html("&"), "&amp;");
+        assert_eq!(escape_html("<"), "&lt;");
+        assert_eq!(escape_html(">"), "&gt;");
+        assert_eq!(escape_html("\""), "&quot;");
+        assert_eq!(escape_html("'"), "&#39;");
+        assert_eq!(escape_html("plain `code`"), "plain `code`");
+        assert_eq!(
+            escape_html("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn fence_code_block_outgrows_embedded_backticks() {
+        assert_eq!(fence_code_block("lua", "print(1)"), "```lua\nprint(1)\n```");
+        assert_eq!(fence_code_block("", "a ``` b"), "````\na ``` b\n````");
+        assert_eq!(fence_code_block("md", "`````"), "``````md\n`````\n``````");
+    }
 }
@@ -1,13 +1,21 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+    time::SystemTime,
+};
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
 use ratatui::{
     Frame,
     prelude::*,
     style::{Color, Style},
-    widgets::{Block, Borders, Paragraph, Wrap},
+    widgets::{Block, Borders, Paragraph, Wrap, block::Title},
 };
 
 use crate::{
     app::{AppState, FocusTarget},
-    types::{Role, ToolStatus},
+    types::{DiffLineKind, Role, RolePrefixes, ToolIcons, ToolStatus, format_relative_age},
 };
 
 const SELENAI_BANNER: &[&str] = &[
@@ -20,6 +28,15 @@ const SELENAI_BANNER: &[&str] = &[
     r"             SYSTEM ONLINE :: V0.01 :: 🚀",
 ];
 
+const ASCII_BANNER: &[&str] = &[
+    r"   ____       _                 _    ___",
+    r"  / ___|  ___| | ___ _ __      / \  |_ _|",
+    r"  \___ \ / _ \ |/ _ \ '_ \    / _ \  | |",
+    r"   ___) |  __/ |  __/ | | |  / ___ \ | |",
+    r"  |____/ \___|_|\___|_| |_| /_/   \_\___|",
+    r"             SYSTEM ONLINE :: V0.01",
+];
+
 const MAX_DISPLAY_LINES: usize = 2000;
 
 pub fn render_chat(frame: &mut Frame, area: Rect, state: &AppState) {
@@ -32,11 +49,35 @@ pub fn render_chat(frame: &mut Frame, area: Rect, state: &AppState) {
     let required_height = state.chat_scroll.saturating_add(inner_height);
     let mut collected_blocks: Vec<Vec<Line>> = Vec::new();
     let mut current_height: u16 = 0;
-    
+
+    let now = state.show_timestamps.then(SystemTime::now);
+    state.chat_heights.forget_removed(&state.messages);
     // Iterate backwards through messages
     for message in state.messages.iter().rev() {
-        let lines = message_to_lines(message);
-        let height = estimate_wrapped_height(&lines, inner_width);
+        let mut lines = message_to_lines(
+            message,
+            now,
+            &state.role_prefixes,
+            state.chat_compact,
+            state.message_spacing,
+        );
+        if state.chat_hard_wrap {
+            lines = hard_wrap_long_tokens(lines, inner_width as usize);
+        }
+        let key = HeightKey {
+            width: inner_width,
+            content_len: message.content.len(),
+            age: now
+                .and_then(|now| message.age(now))
+                .map(format_relative_age),
+            trim: state.chat_wrap_trim,
+            hard_wrap: state.chat_hard_wrap,
+            compact: state.chat_compact,
+            spacing: state.message_spacing,
+        };
+        let height = state.chat_heights.get_or_measure(message.id, key, || {
+            estimate_wrapped_height(&lines, inner_width, state.chat_wrap_trim)
+        });
         collected_blocks.push(lines);
         current_height = current_height.saturating_add(height);
         
@@ -51,7 +92,12 @@ pub fn render_chat(frame: &mut Frame, area: Rect, state: &AppState) {
             .fg(Color::Cyan)
             .add_modifier(Modifier::BOLD);
         let mut banner_lines = Vec::new();
-        for text in SELENAI_BANNER {
+        let banner = if state.glyphs.unicode {
+            SELENAI_BANNER
+        } else {
+            ASCII_BANNER
+        };
+        for text in banner {
             banner_lines.push(Line::from(Span::styled(*text, banner_style)));
         }
         banner_lines.push(Line::default());
@@ -59,6 +105,8 @@ pub fn render_chat(frame: &mut Frame, area: Rect, state: &AppState) {
         // Only add if we really are at the start (checked by loop completion)
         // Actually, if we broke early, current_height >= required. 
         // If we didn't break, we processed all messages.
+        let height = estimate_wrapped_height(&banner_lines, inner_width, state.chat_wrap_trim);
+        current_height = current_height.saturating_add(height);
         collected_blocks.push(banner_lines);
     }
 
@@ -73,41 +121,112 @@ pub fn render_chat(frame: &mut Frame, area: Rect, state: &AppState) {
         lines
     };
 
-    let mut title = "Conversation".to_string();
-    let total_lines = estimate_wrapped_height(&lines, inner_width);
+    // Sum of the block heights above; the collected lines are not re-wrapped.
+    let total_lines = current_height.max(1);
     let baseline = total_lines.saturating_sub(inner_height);
     let offset_from_bottom = state.chat_scroll.min(baseline);
     let scroll_top = baseline.saturating_sub(offset_from_bottom);
 
-    if total_lines > inner_height {
-        let percent = if baseline == 0 {
-            100
-        } else {
-            let ratio = scroll_top as f64 / baseline as f64;
-            (ratio * 100.0).round() as u16
-        };
-        title = format!("Conversation ({percent:>3}%)");
-    }
-    
+    let percent = (total_lines > inner_height).then(|| scroll_percent(scroll_top, baseline));
+    let stats = state.show_pane_stats.then(|| {
+        format!(
+            "{}, {}",
+            count_label(state.messages.len(), "message"),
+            count_label(total_lines as usize, "line")
+        )
+    });
+    let title = pane_title("Conversation", stats, percent);
+
     let block = base_block(&title, state.focus == FocusTarget::Chat, state.copy_mode);
     let paragraph = Paragraph::new(lines)
-        .wrap(Wrap { trim: false })
+        .wrap(Wrap {
+            trim: state.chat_wrap_trim,
+        })
         .scroll((scroll_top, 0))
         .block(block);
 
     frame.render_widget(paragraph, area);
 }
 
-fn message_to_lines(message: &crate::types::Message) -> Vec<Line<'static>> {
+/// Splits lines containing a token wider than `width` into rows of exactly
+/// `width` columns, so a long URL fills the pane from where it starts instead
+/// of leaving a mostly empty row before it. Other lines are left to `Wrap`.
+fn hard_wrap_long_tokens(lines: Vec<Line<'static>>, width: usize) -> Vec<Line<'static>> {
+    if width == 0 {
+        return lines;
+    }
+    let mut wrapped = Vec::with_capacity(lines.len());
+    for line in lines {
+        let text: String = line
+            .spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        if !text.split_whitespace().any(|token| token.width() > width) {
+            wrapped.push(line);
+            continue;
+        }
+        let mut row: Vec<Span<'static>> = Vec::new();
+        let mut row_width = 0;
+        for span in &line.spans {
+            let mut chunk = String::new();
+            for ch in span.content.chars() {
+                let ch_width = ch.width().unwrap_or(0);
+                if row_width + ch_width > width && row_width > 0 {
+                    if !chunk.is_empty() {
+                        row.push(Span::styled(std::mem::take(&mut chunk), span.style));
+                    }
+                    wrapped.push(Line::from(std::mem::take(&mut row)).style(line.style));
+                    row_width = 0;
+                }
+                chunk.push(ch);
+                row_width += ch_width;
+            }
+            if !chunk.is_empty() {
+                row.push(Span::styled(chunk, span.style));
+            }
+        }
+        wrapped.push(Line::from(row).style(line.style));
+    }
+    wrapped
+}
+
+/// Messages up to this many characters on a single line fit beside their
+/// prefix in compact mode.
+const COMPACT_MAX_CHARS: usize = 120;
+
+/// Renders a chat message followed by `spacing` blank lines; `now` enables
+/// the relative-age suffix. With `compact`, a short one-line message follows
+/// its prefix on the same row instead of getting a header row of its own.
+fn message_to_lines(
+    message: &crate::types::Message,
+    now: Option<SystemTime>,
+    prefixes: &RolePrefixes,
+    compact: bool,
+    spacing: usize,
+) -> Vec<Line<'static>> {
     let mut lines = Vec::new();
-    lines.push(Line::from(vec![Span::styled(
-        message.role.display_name(),
+    let mut header = vec![Span::styled(
+        prefixes.prefix(message.role).to_string(),
         Style::default()
             .fg(role_color(message.role))
             .add_modifier(Modifier::BOLD),
-    )]));
-    append_multiline(&mut lines, &message.content);
-    lines.push(Line::default());
+    )];
+    if let Some(age) = now.and_then(|now| message.age(now)) {
+        header.push(Span::styled(
+            format!(" ({})", format_relative_age(age)),
+            Style::default().add_modifier(Modifier::DIM),
+        ));
+    }
+    let content = message.content.trim_end();
+    if compact && !content.contains('\n') && content.chars().count() <= COMPACT_MAX_CHARS {
+        header.push(Span::raw(format!(" {content}")));
+        lines.push(Line::from(header));
+    } else {
+        lines.push(Line::from(header));
+        append_multiline(&mut lines, &message.content);
+    }
+    lines.extend(std::iter::repeat_n(Line::default(), spacing));
     lines
 }
 
@@ -122,8 +241,8 @@ pub fn render_tool_logs(frame: &mut Frame, area: Rect, state: &AppState) {
 
     // Iterate backwards through logs
     for entry in state.tool_logs.iter().rev() {
-        let lines = tool_entry_to_lines(entry);
-        let height = estimate_wrapped_height(&lines, inner_width);
+        let lines = tool_entry_to_lines(entry, &state.tool_icons);
+        let height = estimate_wrapped_height(&lines, inner_width, false);
         collected_blocks.push(lines);
         current_height = current_height.saturating_add(height);
 
@@ -133,29 +252,26 @@ pub fn render_tool_logs(frame: &mut Frame, area: Rect, state: &AppState) {
     }
 
     if current_height < required_height && state.tool_logs.is_empty() {
-        collected_blocks.push(vec![Line::from(
+        let lines = vec![Line::from(
             "Tool log will appear here. Try `/lua rust.list_dir(\".\")`.",
-        )]);
+        )];
+        current_height = estimate_wrapped_height(&lines, inner_width, false);
+        collected_blocks.push(lines);
     }
 
     collected_blocks.reverse();
     let lines: Vec<Line> = collected_blocks.into_iter().flatten().collect();
 
-    let mut title = "Tool Activity".to_string();
-    let total_lines = estimate_wrapped_height(&lines, inner_width);
+    let total_lines = current_height;
     let baseline = total_lines.saturating_sub(inner_height);
     let offset_from_bottom = state.tool_scroll.min(baseline);
     let scroll_top = baseline.saturating_sub(offset_from_bottom);
 
-    if total_lines > inner_height {
-        let percent = if baseline == 0 {
-            100
-        } else {
-            let ratio = scroll_top as f64 / baseline as f64;
-            (ratio * 100.0).round() as u16
-        };
-        title = format!("Tool Activity ({percent:>3}%)");
-    }
+    let percent = (total_lines > inner_height).then(|| scroll_percent(scroll_top, baseline));
+    let stats = state
+        .show_pane_stats
+        .then(|| count_label(state.tool_logs.len(), "entry"));
+    let title = pane_title("Tool Activity", stats, percent);
 
     let block = base_block(
         &title,
@@ -171,21 +287,31 @@ pub fn render_tool_logs(frame: &mut Frame, area: Rect, state: &AppState) {
     frame.render_widget(paragraph, area);
 }
 
-fn tool_entry_to_lines(entry: &crate::types::ToolLogEntry) -> Vec<Line<'static>> {
+fn tool_entry_to_lines(
+    entry: &crate::types::ToolLogEntry,
+    icons: &ToolIcons,
+) -> Vec<Line<'static>> {
     let mut lines = Vec::new();
-    let (icon, style) = match entry.status {
-        ToolStatus::Pending => ("⏳", Style::default().fg(Color::Yellow)),
-        ToolStatus::Success => ("✅", Style::default().fg(Color::Green)),
-        ToolStatus::Error => ("❌", Style::default().fg(Color::Red)),
+    let style = match entry.status {
+        ToolStatus::Pending => Style::default().fg(Color::Yellow),
+        ToolStatus::Success => Style::default().fg(Color::Green),
+        ToolStatus::Error => Style::default().fg(Color::Red),
     };
-    
-    lines.push(Line::from(vec![
-        Span::styled(format!("{icon} "), style),
+
+    let mut header = vec![
+        Span::styled(format!("{} ", icons.icon(entry.status)), style),
         Span::styled(
             entry.title.clone(),
             Style::default().add_modifier(Modifier::BOLD),
         ),
-    ]));
+    ];
+    if let Some(ms) = entry.duration_ms {
+        header.push(Span::styled(
+            format!(" ({})", format_duration_ms(ms)),
+            Style::default().fg(Color::DarkGray),
+        ));
+    }
+    lines.push(Line::from(header));
 
     if !entry.detail.is_empty() {
         let mut added = 0;
@@ -198,16 +324,12 @@ fn tool_entry_to_lines(entry: &crate::types::ToolLogEntry) -> Vec<Line<'static>>
                 break;
             }
 
-            let style = if line_str.starts_with("+++") || line_str.starts_with("---") {
-                Style::default().add_modifier(Modifier::BOLD)
-            } else if line_str.starts_with('+') {
-                Style::default().fg(Color::Green)
-            } else if line_str.starts_with('-') {
-                Style::default().fg(Color::Red)
-            } else if line_str.starts_with("@@") {
-                Style::default().fg(Color::Cyan)
-            } else {
-                Style::default()
+            let style = match DiffLineKind::classify(line_str) {
+                DiffLineKind::FileHeader => Style::default().add_modifier(Modifier::BOLD),
+                DiffLineKind::Added => Style::default().fg(Color::Green),
+                DiffLineKind::Removed => Style::default().fg(Color::Red),
+                DiffLineKind::Hunk => Style::default().fg(Color::Cyan),
+                DiffLineKind::Context => Style::default(),
             };
 
             lines.push(Line::styled(line_str.to_string(), style));
@@ -218,17 +340,32 @@ fn tool_entry_to_lines(entry: &crate::types::ToolLogEntry) -> Vec<Line<'static>>
     lines
 }
 
+/// Most input lines shown at once; longer drafts scroll to keep the cursor
+/// in view.
+const MAX_INPUT_LINES: u16 = 8;
+
+/// Input pane height: one row per line of the draft (capped) plus the rows
+/// taken by the border or, in copy mode, the title.
+pub fn input_height(state: &AppState) -> u16 {
+    let lines = state.input.line_count().min(MAX_INPUT_LINES as usize) as u16;
+    lines.max(1) + 2
+}
+
 pub fn render_input(frame: &mut Frame, area: Rect, state: &AppState) {
     let border_padding = if state.copy_mode { 0 } else { 2 };
     let inner_width = area.width.saturating_sub(border_padding).max(1);
+    let inner_height = area.height.saturating_sub(2).max(1);
     
     let mut text = state.input.buffer();
     let show_placeholder = text.is_empty();
     if show_placeholder {
         text.push_str("Type a message, or `/lua <code>` to run Lua.");
     }
-    
-    let block = base_block("Input", state.focus == FocusTarget::Input, state.copy_mode);
+
+    let mut block = base_block("Input", state.focus == FocusTarget::Input, state.copy_mode);
+    if let Some(counter) = input_limit_counter(state) {
+        block = block.title(Title::from(counter).alignment(Alignment::Right));
+    }
     
     // Horizontal scrolling logic
     let cursor_visual_x = state.input.cursor_display_offset();
@@ -243,9 +380,12 @@ pub fn render_input(frame: &mut Frame, area: Rect, state: &AppState) {
         }
     }
 
+    let cursor_line = state.input.cursor_line();
+    let scroll_y = cursor_line.saturating_sub(inner_height - 1);
+
     let paragraph = Paragraph::new(text)
         .block(block)
-        .scroll((0, scroll_x));
+        .scroll((scroll_y, scroll_x));
         
     frame.render_widget(paragraph, area);
 
@@ -257,13 +397,32 @@ pub fn render_input(frame: &mut Frame, area: Rect, state: &AppState) {
         
         // If the cursor is mathematically "visible", we draw it.
         if relative_cursor_x < inner_width {
-             let cursor_x = area.x + 1 + relative_cursor_x;
-             let cursor_y = area.y + 1;
-             frame.set_cursor(cursor_x, cursor_y);
+            let cursor_x = area.x + 1 + relative_cursor_x;
+            let cursor_y = area.y + 1 + (cursor_line - scroll_y);
+            frame.set_cursor(cursor_x, cursor_y);
         }
     }
 }
 
+/// Dim `len/max` shown once the input passes 80% of `max_input_chars`, turning
+/// yellow with the number of refused characters after a capped paste.
+fn input_limit_counter(state: &AppState) -> Option<Line<'static>> {
+    let max = state.input.max_chars()?;
+    let len = state.input.char_count();
+    let dropped = state.input.dropped();
+    if dropped > 0 {
+        let text = format!(" {len}/{max}, {dropped} chars dropped ");
+        return Some(Line::from(Span::styled(
+            text,
+            Style::default().fg(Color::Yellow),
+        )));
+    }
+    (len * 5 >= max * 4).then(|| {
+        let style = Style::default().add_modifier(Modifier::DIM);
+        Line::from(Span::styled(format!(" {len}/{max} "), style))
+    })
+}
+
 fn role_color(role: Role) -> Color {
     match role {
         Role::User => Color::Cyan,
@@ -272,6 +431,37 @@ fn role_color(role: Role) -> Color {
     }
 }
 
+fn scroll_percent(scroll_top: u16, baseline: u16) -> u16 {
+    if baseline == 0 {
+        100
+    } else {
+        let ratio = scroll_top as f64 / baseline as f64;
+        (ratio * 100.0).round() as u16
+    }
+}
+
+/// Pane title such as "Conversation (3 messages, 42 lines,  80%)"; the
+/// parenthesized part is dropped when there is nothing to show.
+fn pane_title(name: &str, stats: Option<String>, percent: Option<u16>) -> String {
+    let mut parts: Vec<String> = stats.into_iter().collect();
+    if let Some(percent) = percent {
+        parts.push(format!("{percent:>3}%"));
+    }
+    if parts.is_empty() {
+        name.to_string()
+    } else {
+        format!("{name} ({})", parts.join(", "))
+    }
+}
+
+fn count_label(count: usize, noun: &str) -> String {
+    match (count, noun) {
+        (1, _) => format!("1 {noun}"),
+        (_, "entry") => format!("{count} entries"),
+        _ => format!("{count} {noun}s"),
+    }
+}
+
 fn base_block<'a>(title: &'a str, focused: bool, copy_mode: bool) -> Block<'a> {
     if copy_mode {
         Block::default().title(title)
@@ -291,6 +481,14 @@ fn base_block<'a>(title: &'a str, focused: bool, copy_mode: bool) -> Block<'a> {
     }
 }
 
+fn format_duration_ms(ms: u64) -> String {
+    if ms < 1000 {
+        format!("{ms}ms")
+    } else {
+        format!("{:.1}s", ms as f64 / 1000.0)
+    }
+}
+
 fn append_multiline(lines: &mut Vec<Line>, text: &str) {
     let mut segments = text.split('\n').peekable();
     let mut count = 0;
@@ -310,29 +508,149 @@ fn append_multiline(lines: &mut Vec<Line>, text: &str) {
     }
 }
 
-fn estimate_wrapped_height(lines: &[Line], width: u16) -> u16 {
+fn estimate_wrapped_height(lines: &[Line], width: u16, trim: bool) -> u16 {
     if width == 0 {
         return lines.len() as u16;
     }
-    let usable_width = width as usize;
     let mut total: u32 = 0;
     for line in lines {
-        let height = estimate_line_height(line, usable_width) as u32;
+        let height = estimate_line_height(line, width, trim) as u32;
         total = total.saturating_add(height);
     }
     total.min(u16::MAX as u32) as u16
 }
 
-fn estimate_line_height(line: &Line, width: usize) -> u16 {
+/// Rows `line` takes when `Paragraph` wraps it at `width`. This follows the
+/// word wrapper ratatui renders with (which it does not expose), grapheme by
+/// grapheme, so wide (CJK) characters, joined emoji, and long words match what
+/// is drawn; a width-based guess drifts on those and throws off scrolling.
+fn estimate_line_height(line: &Line, width: u16, trim: bool) -> u16 {
     if width == 0 {
         return 0;
     }
-    let line_width = line.width();
-    if line_width == 0 {
-        return 1;
+    let mut rows: usize = 0;
+    // The row being filled, the word being read, and the whitespace before it,
+    // as grapheme counts and column widths.
+    let (mut row_len, mut row_width) = (0usize, 0u16);
+    let (mut word_len, mut word_width) = (0usize, 0u16);
+    let mut spaces: VecDeque<u16> = VecDeque::new();
+    let mut space_width: u16 = 0;
+    let mut after_word = false;
+    for grapheme in line.styled_graphemes(Style::default()) {
+        let symbol = grapheme.symbol;
+        let is_space =
+            symbol == "\u{200b}" || (symbol.chars().all(char::is_whitespace) && symbol != "\u{a0}");
+        let symbol_width = symbol.width() as u16;
+        // Graphemes wider than the whole row are never drawn.
+        if symbol_width > width {
+            continue;
+        }
+
+        if after_word && is_space
+            || row_len == 0
+                && trim
+                && (word_width + symbol_width > width || space_width + symbol_width > width)
+            || row_len == 0 && !trim && word_width + space_width + symbol_width > width
+        {
+            if row_len > 0 || !trim {
+                row_len += spaces.len();
+                row_width += space_width;
+            }
+            row_len += word_len;
+            row_width += word_width;
+            spaces.clear();
+            space_width = 0;
+            word_len = 0;
+            word_width = 0;
+        }
+
+        if row_width >= width || row_width + space_width + word_width >= width && symbol_width > 0 {
+            let mut remaining = width.saturating_sub(row_width);
+            rows += 1;
+            row_len = 0;
+            row_width = 0;
+            // Whitespace that still fit on the finished row is dropped with it.
+            let mut first = spaces.pop_front();
+            while let Some(space) = first {
+                space_width -= space;
+                if space > remaining {
+                    break;
+                }
+                remaining -= space;
+                first = spaces.pop_front();
+            }
+            if is_space && first.is_none() {
+                continue;
+            }
+        }
+
+        if is_space {
+            space_width += symbol_width;
+            spaces.push_back(symbol_width);
+        } else {
+            word_width += symbol_width;
+            word_len += 1;
+        }
+        after_word = !is_space;
+    }
+
+    if word_len > 0 || !spaces.is_empty() {
+        if row_len == 0 && word_len == 0 {
+            rows += 1;
+        } else if !trim || row_len > 0 {
+            row_len += spaces.len();
+        }
+        row_len += word_len;
+    }
+    if row_len > 0 {
+        rows += 1;
+    }
+    rows.clamp(1, u16::MAX as usize) as u16
+}
+
+/// Wrapped heights of chat messages from earlier frames, so a redraw only
+/// re-wraps messages whose text, age label, or pane width changed.
+#[derive(Debug, Clone, Default)]
+pub struct ChatHeights {
+    rows: RefCell<HashMap<u64, (HeightKey, u16)>>,
+}
+
+/// Everything besides the message id that decides how a message wraps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HeightKey {
+    width: u16,
+    /// Messages only change by growing (streaming) or being replaced, so the
+    /// length stands in for the text.
+    content_len: usize,
+    age: Option<String>,
+    trim: bool,
+    hard_wrap: bool,
+    compact: bool,
+    spacing: usize,
+}
+
+impl ChatHeights {
+    /// The cached height of message `id`, measured again when `key` changed.
+    fn get_or_measure(&self, id: u64, key: HeightKey, measure: impl FnOnce() -> u16) -> u16 {
+        let mut rows = self.rows.borrow_mut();
+        if let Some((cached_key, height)) = rows.get(&id)
+            && *cached_key == key
+        {
+            return *height;
+        }
+        let height = measure();
+        rows.insert(id, (key, height));
+        height
+    }
+
+    /// Drops entries of messages that are no longer in the history.
+    fn forget_removed(&self, messages: &[crate::types::Message]) {
+        let mut rows = self.rows.borrow_mut();
+        if rows.len() > messages.len() {
+            let ids: HashSet<u64> = messages.iter().map(|message| message.id).collect();
+            rows.retain(|id, _| ids.contains(id));
+        }
     }
-    let rows = line_width.div_ceil(width);
-    rows.min(u16::MAX as usize) as u16
 }
 
 #[cfg(test)]
@@ -348,11 +666,251 @@ mod tests {
         assert_eq!(lines[1], Line::from("two"));
     }
 
+    #[test]
+    fn message_header_shows_age_only_when_enabled() {
+        let message = crate::types::Message::new(Role::User, "hi");
+        let later = message.created_at + std::time::Duration::from_secs(125);
+
+        let plain = message_to_lines(&message, None, &RolePrefixes::default(), false, 1);
+        assert_eq!(
+            plain[0],
+            Line::from(Span::styled("You", plain[0].spans[0].style))
+        );
+
+        let timed = message_to_lines(&message, Some(later), &RolePrefixes::default(), false, 1);
+        assert_eq!(timed[0].spans.len(), 2);
+        assert_eq!(timed[0].spans[1].content, " (2m ago)");
+    }
+
+    #[test]
+    fn compact_mode_inlines_only_short_single_line_messages() {
+        let prefixes = RolePrefixes {
+            assistant: "\u{2726} Assistant".into(),
+            ..RolePrefixes::default()
+        };
+        let short = crate::types::Message::new(Role::Assistant, "Done.");
+        let lines = message_to_lines(&short, None, &prefixes, true, 1);
+        assert_eq!(lines.len(), 2);
+        let row: String = lines[0]
+            .spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        assert_eq!(row, "\u{2726} Assistant Done.");
+
+        let block = crate::types::Message::new(Role::Assistant, "Step one.\nStep two.");
+        let lines = message_to_lines(&block, None, &prefixes, true, 1);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].spans[0].content, "\u{2726} Assistant");
+        assert_eq!(lines[0].spans.len(), 1);
+        assert_eq!(lines[1], Line::from("Step one."));
+
+        let expanded = message_to_lines(&short, None, &prefixes, false, 1);
+        assert_eq!(expanded.len(), 3);
+        assert_eq!(expanded[1], Line::from("Done."));
+    }
+
     #[test]
     fn estimate_wrapped_height_accounts_for_width() {
         let lines = vec![Line::from("abcdef")];
-        assert_eq!(estimate_wrapped_height(&lines, 3), 2);
-        assert_eq!(estimate_wrapped_height(&lines, 10), 1);
+        assert_eq!(estimate_wrapped_height(&lines, 3, false), 2);
+        assert_eq!(estimate_wrapped_height(&lines, 10, false), 1);
+    }
+
+    /// Rows `Paragraph` actually fills when rendering `lines` at `width`.
+    fn rendered_height(lines: &[Line<'static>], width: u16, trim: bool) -> u16 {
+        let area = Rect::new(0, 0, width, 40);
+        let mut buffer = Buffer::empty(area);
+        Paragraph::new(lines.to_vec())
+            .wrap(Wrap { trim })
+            .render(area, &mut buffer);
+        (0..area.height)
+            .rev()
+            .find(|&y| (0..width).any(|x| buffer.get(x, y).symbol() != " "))
+            .map_or(0, |y| y + 1)
+    }
+
+    #[test]
+    fn zero_spacing_drops_blank_separators_and_keeps_heights_exact() {
+        let prefixes = RolePrefixes::default();
+        let messages = [
+            crate::types::Message::new(Role::User, "Where is the config loaded?"),
+            crate::types::Message::new(Role::Assistant, "In src/config.rs.\nSee AppConfig::load."),
+        ];
+        let lines: Vec<Line<'static>> = messages
+            .iter()
+            .flat_map(|message| message_to_lines(message, None, &prefixes, false, 0))
+            .collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|line| line.width() > 0));
+        for width in [12, 20, 80] {
+            assert_eq!(
+                estimate_wrapped_height(&lines, width, false),
+                rendered_height(&lines, width, false),
+                "width {width}"
+            );
+        }
+
+        let spaced = message_to_lines(&messages[0], None, &prefixes, false, 1);
+        assert_eq!(spaced.last(), Some(&Line::default()));
+    }
+
+    #[test]
+    fn wide_character_height_matches_rendered_height() {
+        let cases = [
+            "漢字かな交じり文の折り返しテスト",
+            "see 日本語のとても長いエラーメッセージ",
+            "ok 👨\u{200d}👩\u{200d}👧 family",
+        ];
+        for text in cases {
+            let lines = vec![Line::from(text)];
+            for width in [5, 7, 10, 16] {
+                assert_eq!(
+                    estimate_wrapped_height(&lines, width, false),
+                    rendered_height(&lines, width, false),
+                    "{text:?} at width {width}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn spacing_and_trim_heights_match_rendered_height() {
+        let cases = [
+            "  indented words that wrap around",
+            "gaps    between     words here",
+            "a\u{a0}non\u{a0}breaking\u{a0}run and more",
+            "zero\u{200b}width\u{200b}spaces\u{200b}split",
+            "end with spaces      x",
+            "tab\tseparated\twords",
+        ];
+        for text in cases {
+            let lines = vec![Line::from(text)];
+            for width in [3, 5, 8, 12] {
+                for trim in [false, true] {
+                    assert_eq!(
+                        estimate_wrapped_height(&lines, width, trim),
+                        rendered_height(&lines, width, trim),
+                        "{text:?} at width {width}, trim {trim}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chat_heights_are_reused_until_the_message_changes() {
+        use ratatui::{Terminal, backend::TestBackend};
+
+        fn title(state: &AppState) -> String {
+            let mut terminal = Terminal::new(TestBackend::new(40, 12)).unwrap();
+            terminal
+                .draw(|frame| render_chat(frame, frame.size(), state))
+                .unwrap();
+            let buffer = terminal.backend().buffer();
+            (0..40).map(|x| buffer.get(x, 0).symbol()).collect()
+        }
+
+        let mut state = AppState::default();
+        state.push_message(crate::types::Message::new(Role::User, "hi"));
+        state.show_pane_stats = true;
+        let id = state.messages[1].id;
+        let before = title(&state);
+
+        // A stale entry with a matching key is trusted as is...
+        if let Some((_, height)) = state.chat_heights.rows.borrow_mut().get_mut(&id) {
+            *height = 50;
+        }
+        assert_ne!(title(&state), before);
+        // ...and measured again once the text grows.
+        state.append_to_message(1, " there");
+        assert_eq!(title(&state), before);
+
+        state.remove_message(1);
+        title(&state);
+        assert!(!state.chat_heights.rows.borrow().contains_key(&id));
+    }
+
+    #[test]
+    fn long_unbroken_line_height_matches_wrapping() {
+        let url = format!("https://example.com/{}", "x".repeat(480));
+        let lines = vec![Line::from(format!("see {url}"))];
+        // "see" keeps its own row, then the 500-column URL fills 7 rows of 80.
+        assert_eq!(estimate_wrapped_height(&lines, 80, false), 8);
+
+        let hard = hard_wrap_long_tokens(lines, 80);
+        assert_eq!(hard.len(), 7);
+        assert!(hard.iter().all(|line| line.width() <= 80));
+        assert_eq!(estimate_wrapped_height(&hard, 80, false), 7);
+
+        let prose = vec![Line::from("aaa bbb ccc")];
+        assert_eq!(estimate_wrapped_height(&prose, 7, false), 2);
+        assert_eq!(hard_wrap_long_tokens(prose.clone(), 7), prose);
+    }
+
+    #[test]
+    fn pane_titles_show_counts_only_when_enabled() {
+        use ratatui::{Terminal, backend::TestBackend};
+
+        fn top_row(state: &AppState, render: fn(&mut Frame, Rect, &AppState)) -> String {
+            let mut terminal = Terminal::new(TestBackend::new(60, 12)).unwrap();
+            terminal
+                .draw(|frame| render(frame, frame.size(), state))
+                .unwrap();
+            let buffer = terminal.backend().buffer();
+            (0..60).map(|x| buffer.get(x, 0).symbol()).collect()
+        }
+
+        let mut state = AppState::default();
+        state.push_message(crate::types::Message::new(Role::User, "hi"));
+        state.tool_logs.push(crate::types::ToolLogEntry {
+            id: 1,
+            title: "Tool".to_string(),
+            status: ToolStatus::Success,
+            detail: String::new(),
+            duration_ms: None,
+        });
+        assert!(!top_row(&state, render_chat).contains("messages"));
+
+        state.show_pane_stats = true;
+        let chat = top_row(&state, render_chat);
+        assert!(chat.contains("Conversation (2 messages, "), "{chat}");
+        let tools = top_row(&state, render_tool_logs);
+        assert!(tools.contains("Tool Activity (1 entry)"), "{tools}");
+
+        assert_eq!(
+            pane_title("Tool Activity", None, Some(5)),
+            "Tool Activity (  5%)"
+        );
+        assert_eq!(count_label(3, "entry"), "3 entries");
+    }
+
+    #[test]
+    fn input_grows_with_lines_and_keeps_cursor_visible() {
+        use ratatui::{Terminal, backend::TestBackend};
+
+        let mut state = AppState::default();
+        assert_eq!(input_height(&state), 3);
+        state.input.insert_str("one\ntwo\nthree");
+        assert_eq!(input_height(&state), 5);
+        let long: Vec<String> = (1..=12).map(|n| format!("line {n}")).collect();
+        state.input.clear();
+        state.input.insert_str(&long.join("\n"));
+        assert_eq!(input_height(&state), MAX_INPUT_LINES + 2);
+
+        let height = input_height(&state);
+        let mut terminal = Terminal::new(TestBackend::new(30, height)).unwrap();
+        terminal
+            .draw(|frame| render_input(frame, frame.size(), &state))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        let row = |y: u16| {
+            (0..30)
+                .map(|x| buffer.get(x, y).symbol())
+                .collect::<String>()
+        };
+        assert!(row(height - 2).contains("line 12"), "{}", row(height - 2));
+        assert!(row(1).contains("line 5"), "{}", row(1));
     }
 
     #[test]
@@ -362,8 +920,9 @@ mod tests {
             title: "Test Tool".to_string(),
             status: ToolStatus::Success,
             detail: "Details here".to_string(),
+            duration_ms: None,
         };
-        let lines = tool_entry_to_lines(&entry);
+        let lines = tool_entry_to_lines(&entry, &ToolIcons::default());
         assert!(!lines.is_empty());
         assert!(lines[0].spans.iter().any(|s| s.content.contains("✅")));
         assert!(lines[0].spans.iter().any(|s| s.content == "Test Tool"));
@@ -377,8 +936,9 @@ mod tests {
             title: "Multi".to_string(),
             status: ToolStatus::Pending,
             detail: "Line 1\nLine 2".to_string(),
+            duration_ms: None,
         };
-        let lines = tool_entry_to_lines(&entry);
+        let lines = tool_entry_to_lines(&entry, &ToolIcons::default());
         // Line 0: Header
         // Line 1: "Line 1"
         // Line 2: "Line 2"
@@ -387,4 +947,24 @@ mod tests {
         assert_eq!(lines[1], Line::from("Line 1"));
         assert_eq!(lines[2], Line::from("Line 2"));
     }
+
+    #[test]
+    fn tool_entry_header_uses_icon_for_each_status_and_mode() {
+        let cases = [
+            (ToolStatus::Pending, "⏳", "[pending]"),
+            (ToolStatus::Success, "✅", "[ok]"),
+            (ToolStatus::Error, "❌", "[error]"),
+        ];
+        for (status, unicode, ascii) in cases {
+            let mut entry = crate::types::ToolLogEntry::new(1, "Tool", "");
+            entry.status = status;
+            entry.duration_ms = Some(1500);
+            for (icons, expected) in [(ToolIcons::unicode(), unicode), (ToolIcons::ascii(), ascii)]
+            {
+                let header = &tool_entry_to_lines(&entry, &icons)[0];
+                assert_eq!(header.spans[0].content, format!("{expected} "));
+                assert_eq!(header.spans[2].content, " (1.5s)");
+            }
+        }
+    }
 }
@@ -1,5 +1,7 @@
 mod components;
 
+pub use components::ChatHeights;
+
 use ratatui::{
     Frame,
     layout::{Constraint, Direction, Layout},
@@ -9,38 +11,147 @@ use ratatui::{
 
 use crate::app::{AppState, FocusTarget};
 
-pub fn draw(frame: &mut Frame, state: &AppState) {
+/// Where each part of the screen goes. The status line has its own row
+/// between the panes and the input box, so it never covers a border.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AppLayout {
+    chat: Rect,
+    tools: Rect,
+    status: Rect,
+    input: Rect,
+}
+
+fn calculate_layout(area: Rect, state: &AppState) -> AppLayout {
     let vertical = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Min(10), Constraint::Length(3)])
-        .split(frame.size());
+        .constraints([
+            Constraint::Min(3),
+            Constraint::Length(1),
+            Constraint::Length(components::input_height(state)),
+        ])
+        .split(area);
 
     let horizontal = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
         .split(vertical[0]);
 
-    components::render_chat(frame, horizontal[0], state);
-    components::render_tool_logs(frame, horizontal[1], state);
-    components::render_input(frame, vertical[1], state);
+    AppLayout {
+        chat: horizontal[0],
+        tools: horizontal[1],
+        status: vertical[1],
+        input: vertical[2],
+    }
+}
 
-    render_focus_hint(frame, vertical[1], state.focus);
+pub fn draw(frame: &mut Frame, state: &AppState) {
+    let layout = calculate_layout(frame.size(), state);
+
+    components::render_chat(frame, layout.chat, state);
+    components::render_tool_logs(frame, layout.tools, state);
+    render_status_line(frame, layout.status, state);
+    components::render_input(frame, layout.input, state);
 }
 
-fn render_focus_hint(frame: &mut Frame, area: Rect, focus: FocusTarget) {
-    let hint = match focus {
-        FocusTarget::Chat => "Focus: chat • Tab to move • Up/Down to scroll",
-        FocusTarget::Tool => "Focus: tools • Tab to move • Up/Down to scroll",
-        FocusTarget::Input => "Focus: input • /review • /config • @macro • /lua",
-    };
+/// One right-aligned row: a spinner while a response streams, the write mode,
+/// focus hints, and the approximate conversation size.
+fn render_status_line(frame: &mut Frame, area: Rect, state: &AppState) {
+    let paragraph = Paragraph::new(status_line(state)).alignment(Alignment::Right);
+    frame.render_widget(paragraph, area);
+}
 
-    let info_area = Rect {
-        x: area.x,
-        y: area.y.saturating_sub(1),
-        width: area.width,
-        height: 1,
+fn status_line(state: &AppState) -> Line<'static> {
+    let hint = Style::default().fg(Color::DarkGray);
+    let separator = Span::styled(state.glyphs.separator(), hint);
+    let mut spans = Vec::new();
+    if state.busy {
+        spans.push(Span::styled(
+            format!(
+                "{} Responding (Esc cancels)",
+                state.glyphs.spinner(state.spinner_tick)
+            ),
+            Style::default().fg(Color::Cyan),
+        ));
+        spans.push(separator.clone());
+    }
+    spans.push(if state.writes_enabled {
+        Span::styled("Writes enabled", Style::default().fg(Color::Yellow))
+    } else {
+        Span::styled("Read-only", Style::default().fg(Color::Green))
+    });
+
+    let parts: &[&str] = match state.focus {
+        FocusTarget::Chat => &["Focus: chat", "Tab to move", "Up/Down to scroll"],
+        FocusTarget::Tool => &["Focus: tools", "Tab to move", "Up/Down to scroll"],
+        FocusTarget::Input => &["Focus: input", "/help", "/review", "@macro", "/lua"],
     };
+    for part in parts {
+        spans.push(separator.clone());
+        spans.push(Span::styled(*part, hint));
+    }
+    spans.push(separator);
+    spans.push(Span::styled(
+        format!("~{} tokens", compact_count(state.estimated_tokens())),
+        hint,
+    ));
+    Line::from(spans)
+}
+
+/// `950`, `1.2k`, `34k`: short enough for the status line.
+fn compact_count(count: usize) -> String {
+    match count {
+        0..=999 => count.to_string(),
+        1_000..=9_999 => format!("{:.1}k", count as f64 / 1_000.0),
+        _ => format!("{}k", count / 1_000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Message, Role};
+
+    #[test]
+    fn status_line_gets_its_own_row_above_the_input() {
+        let mut state = AppState::default();
+        state.input.set("one\ntwo\nthree");
+        for (width, height) in [(120, 40), (80, 24), (40, 10), (20, 6)] {
+            let area = Rect::new(0, 0, width, height);
+            let layout = calculate_layout(area, &state);
+            assert_eq!(layout.status.height, 1, "{width}x{height}");
+            assert_eq!(layout.status.bottom(), layout.input.y, "{width}x{height}");
+            assert!(!layout.status.intersects(layout.input), "{width}x{height}");
+            assert!(!layout.status.intersects(layout.chat), "{width}x{height}");
+            assert!(!layout.status.intersects(layout.tools), "{width}x{height}");
+            assert_eq!(layout.input.bottom(), area.bottom(), "{width}x{height}");
+        }
+    }
+
+    #[test]
+    fn status_line_shows_mode_spinner_and_token_estimate() {
+        let mut state = AppState::default();
+        state.truncate_messages(0);
+        state.push_message(Message::new(Role::User, "x".repeat(4_800)));
+        let text = |state: &AppState| -> String {
+            status_line(state)
+                .spans
+                .iter()
+                .map(|span| span.content.as_ref())
+                .collect()
+        };
+
+        let idle = text(&state);
+        assert!(idle.starts_with("Read-only"), "{idle}");
+        assert!(idle.contains("Focus: input"), "{idle}");
+        assert!(idle.ends_with("~1.2k tokens"), "{idle}");
 
-    let paragraph = Paragraph::new(hint).alignment(Alignment::Right);
-    frame.render_widget(paragraph, info_area);
+        state.busy = true;
+        state.writes_enabled = true;
+        state.spinner_tick = 1;
+        let busy = text(&state);
+        assert!(busy.starts_with("⠙ Responding"), "{busy}");
+        assert!(busy.contains("Writes enabled"), "{busy}");
+        assert_eq!(compact_count(950), "950");
+        assert_eq!(compact_count(34_567), "34k");
+    }
 }
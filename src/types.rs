@@ -1,6 +1,10 @@
-use std::fmt;
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value as JsonValue;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -22,30 +26,100 @@ impl Role {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
+    /// Stable identity that survives removals elsewhere in the history.
+    #[serde(
+        default = "next_message_id",
+        deserialize_with = "deserialize_message_id"
+    )]
+    pub id: u64,
     pub role: Role,
     pub content: String,
     pub tool_call_id: Option<String>,
     pub tool_calls: Vec<ToolInvocation>,
+    /// Serialized as unix milliseconds; older transcripts without it load as
+    /// the epoch, which renders as "unknown".
+    #[serde(default = "unknown_time", with = "unix_ms")]
+    pub created_at: SystemTime,
 }
 
 impl Message {
     pub fn new(role: Role, content: impl Into<String>) -> Self {
         Self {
+            id: next_message_id(),
             role,
             content: content.into(),
             tool_call_id: None,
             tool_calls: Vec::new(),
+            created_at: SystemTime::now(),
         }
     }
 
     pub fn new_tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
         Self {
+            id: next_message_id(),
             role: Role::Tool,
             content: content.into(),
             tool_call_id: Some(tool_call_id.into()),
             tool_calls: Vec::new(),
+            created_at: SystemTime::now(),
         }
     }
+
+    /// Time since creation, or `None` when the timestamp is unknown.
+    pub fn age(&self, now: SystemTime) -> Option<Duration> {
+        if self.created_at == UNIX_EPOCH {
+            return None;
+        }
+        Some(now.duration_since(self.created_at).unwrap_or_default())
+    }
+}
+
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_message_id() -> u64 {
+    NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Keeps loaded ids and freshly minted ones from colliding after a resume.
+fn deserialize_message_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let id = u64::deserialize(deserializer)?;
+    NEXT_MESSAGE_ID.fetch_max(id.saturating_add(1), Ordering::Relaxed);
+    Ok(id)
+}
+
+fn unknown_time() -> SystemTime {
+    UNIX_EPOCH
+}
+
+mod unix_ms {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let millis = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        serializer.serialize_u64(millis)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let millis = u64::deserialize(deserializer)?;
+        Ok(UNIX_EPOCH + Duration::from_millis(millis))
+    }
+}
+
+/// Compact relative age such as "just now", "45s ago", "2m ago", "3h ago".
+pub fn format_relative_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..5 => "just now".to_string(),
+        5..60 => format!("{secs}s ago"),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -113,6 +187,9 @@ pub struct ToolLogEntry {
     pub title: String,
     pub status: ToolStatus,
     pub detail: String,
+    /// Wall-clock execution time, once the run has finished.
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
 }
 
 impl ToolLogEntry {
@@ -122,6 +199,149 @@ impl ToolLogEntry {
             title: title.into(),
             status: ToolStatus::Pending,
             detail: detail.into(),
+            duration_ms: None,
+        }
+    }
+}
+
+/// Decorative characters with ASCII fallbacks for terminals (and grep'd logs)
+/// that cannot show emoji or box-drawing glyphs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Glyphs {
+    pub unicode: bool,
+}
+
+impl Glyphs {
+    /// Separator between items on a single status line.
+    pub fn separator(self) -> &'static str {
+        if self.unicode { " • " } else { " | " }
+    }
+
+    pub fn dash(self) -> &'static str {
+        if self.unicode { "—" } else { "-" }
+    }
+
+    /// Frame `tick` of the busy spinner on the status line.
+    pub fn spinner(self, tick: usize) -> &'static str {
+        const UNICODE: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+        const ASCII: [&str; 4] = ["|", "/", "-", "\\"];
+        if self.unicode {
+            UNICODE[tick % UNICODE.len()]
+        } else {
+            ASCII[tick % ASCII.len()]
+        }
+    }
+
+    /// Greeting prefix, including its trailing space when present.
+    pub fn wave(self) -> &'static str {
+        if self.unicode { "👋 " } else { "" }
+    }
+
+    pub fn tool_icons(self) -> ToolIcons {
+        if self.unicode {
+            ToolIcons::unicode()
+        } else {
+            ToolIcons::ascii()
+        }
+    }
+}
+
+impl Default for Glyphs {
+    fn default() -> Self {
+        Self { unicode: true }
+    }
+}
+
+/// Glyphs shown in the tool log header for each status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolIcons {
+    pub pending: String,
+    pub success: String,
+    pub error: String,
+}
+
+impl ToolIcons {
+    pub fn unicode() -> Self {
+        Self {
+            pending: "⏳".into(),
+            success: "✅".into(),
+            error: "❌".into(),
+        }
+    }
+
+    pub fn ascii() -> Self {
+        Self {
+            pending: "[pending]".into(),
+            success: "[ok]".into(),
+            error: "[error]".into(),
+        }
+    }
+
+    pub fn icon(&self, status: ToolStatus) -> &str {
+        match status {
+            ToolStatus::Pending => &self.pending,
+            ToolStatus::Success => &self.success,
+            ToolStatus::Error => &self.error,
+        }
+    }
+}
+
+impl Default for ToolIcons {
+    fn default() -> Self {
+        Self::unicode()
+    }
+}
+
+/// Labels shown before chat messages, one per role.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RolePrefixes {
+    pub user: String,
+    pub assistant: String,
+    pub tool: String,
+}
+
+impl RolePrefixes {
+    pub fn prefix(&self, role: Role) -> &str {
+        match role {
+            Role::User => &self.user,
+            Role::Assistant => &self.assistant,
+            Role::Tool => &self.tool,
+        }
+    }
+}
+
+impl Default for RolePrefixes {
+    fn default() -> Self {
+        Self {
+            user: Role::User.display_name().into(),
+            assistant: Role::Assistant.display_name().into(),
+            tool: Role::Tool.display_name().into(),
+        }
+    }
+}
+
+/// How a line inside unified-diff output should be highlighted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffLineKind {
+    FileHeader,
+    Hunk,
+    Added,
+    Removed,
+    Context,
+}
+
+impl DiffLineKind {
+    pub fn classify(line: &str) -> Self {
+        if line.starts_with("+++") || line.starts_with("---") {
+            DiffLineKind::FileHeader
+        } else if line.starts_with('+') {
+            DiffLineKind::Added
+        } else if line.starts_with('-') {
+            DiffLineKind::Removed
+        } else if line.starts_with("@@") {
+            DiffLineKind::Hunk
+        } else {
+            DiffLineKind::Context
         }
     }
 }
@@ -130,6 +350,67 @@ impl ToolLogEntry {
 mod tests {
     use super::*;
 
+    #[test]
+    fn message_timestamp_round_trips_as_unix_ms() {
+        let mut message = Message::new(Role::User, "hi");
+        message.created_at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let json = serde_json::to_value(&message).expect("serialize");
+        assert_eq!(json["created_at"], 1_700_000_000_123u64);
+        let back: Message = serde_json::from_value(json).expect("deserialize");
+        assert_eq!(back.created_at, message.created_at);
+
+        let legacy: Message = serde_json::from_str(
+            r#"{"role":"User","content":"old","tool_call_id":null,"tool_calls":[]}"#,
+        )
+        .expect("legacy transcript line");
+        assert_eq!(legacy.created_at, UNIX_EPOCH);
+        assert_eq!(legacy.age(SystemTime::now()), None);
+    }
+
+    #[test]
+    fn message_ids_are_unique_and_survive_resume() {
+        let first = Message::new(Role::User, "a");
+        let second = Message::new_tool("call", "b");
+        assert_ne!(first.id, second.id);
+
+        let json =
+            r#"{"id":900000,"role":"User","content":"old","tool_call_id":null,"tool_calls":[]}"#;
+        let loaded: Message = serde_json::from_str(json).expect("deserialize");
+        assert_eq!(loaded.id, 900000);
+        assert!(Message::new(Role::User, "fresh").id > 900000);
+
+        let legacy: Message = serde_json::from_str(
+            r#"{"role":"User","content":"old","tool_call_id":null,"tool_calls":[]}"#,
+        )
+        .expect("legacy");
+        assert_ne!(legacy.id, loaded.id);
+    }
+
+    #[test]
+    fn relative_age_picks_coarse_unit() {
+        assert_eq!(format_relative_age(Duration::from_secs(2)), "just now");
+        assert_eq!(format_relative_age(Duration::from_secs(45)), "45s ago");
+        assert_eq!(format_relative_age(Duration::from_secs(150)), "2m ago");
+        assert_eq!(format_relative_age(Duration::from_secs(7300)), "2h ago");
+        assert_eq!(format_relative_age(Duration::from_secs(90_000)), "1d ago");
+    }
+
+    #[test]
+    fn ascii_glyphs_are_ascii() {
+        let glyphs = Glyphs { unicode: false };
+        let icons = glyphs.tool_icons();
+        for text in [
+            glyphs.separator(),
+            glyphs.dash(),
+            glyphs.wave(),
+            &icons.pending,
+            &icons.success,
+            &icons.error,
+        ] {
+            assert!(text.is_ascii(), "{text:?}");
+        }
+    }
+
     #[test]
     fn role_display_names_are_human_readable() {
         assert_eq!(Role::User.display_name(), "You");
@@ -0,0 +1,112 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Quiet period after the last change before a rerun fires, so editors that
+/// write a file in several steps trigger a single run.
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Watches one Lua file for `/watch-lua` and reports debounced changes.
+pub struct LuaFileWatch {
+    path: PathBuf,
+    events: mpsc::Receiver<()>,
+    last_change: Option<Instant>,
+    /// Kept alive for the lifetime of the watch; `None` in tests.
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl LuaFileWatch {
+    /// Starts watching `path`. The parent directory is watched so editors that
+    /// save by renaming a temp file over the original are still noticed.
+    pub fn start(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let parent = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+        let file_name = path.file_name().map(|name| name.to_os_string());
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            let Ok(event) = res else {
+                return;
+            };
+            let touches_file = event
+                .paths
+                .iter()
+                .any(|changed| changed.file_name().map(|n| n.to_os_string()) == file_name);
+            if touches_file && !event.kind.is_access() {
+                let _ = tx.send(());
+            }
+        })
+        .context("failed to create file watcher")?;
+        watcher
+            .watch(&parent, RecursiveMode::NonRecursive)
+            .with_context(|| format!("failed to watch {}", parent.display()))?;
+        Ok(Self {
+            path,
+            events: rx,
+            last_change: None,
+            _watcher: Some(watcher),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Drains pending change events and returns true once the file has been
+    /// quiet for [`DEBOUNCE`] since the last one.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        while self.events.try_recv().is_ok() {
+            self.last_change = Some(now);
+        }
+        match self.last_change {
+            Some(changed) if now.duration_since(changed) >= DEBOUNCE => {
+                self.last_change = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+impl LuaFileWatch {
+    /// A watch driven by a test-controlled channel instead of the OS.
+    pub fn from_channel(path: impl Into<PathBuf>, events: mpsc::Receiver<()>) -> Self {
+        Self {
+            path: path.into(),
+            events,
+            last_change: None,
+            _watcher: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_debounces_bursts_of_changes() {
+        let (tx, rx) = mpsc::channel();
+        let mut watch = LuaFileWatch::from_channel("tool.lua", rx);
+        let start = Instant::now();
+        assert!(!watch.poll(start));
+
+        tx.send(()).unwrap();
+        tx.send(()).unwrap();
+        assert!(!watch.poll(start));
+        tx.send(()).unwrap();
+        assert!(!watch.poll(start + DEBOUNCE / 2));
+        assert!(!watch.poll(start + DEBOUNCE));
+        assert!(watch.poll(start + DEBOUNCE / 2 + DEBOUNCE));
+        assert!(!watch.poll(start + DEBOUNCE * 4));
+    }
+}